@@ -1,17 +1,151 @@
-use anyhow::Result;
-use clap::{Parser, Subcommand};
+use std::fmt::{self, Write as _};
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use librustbelt::{
-    analyzer::RustAnalyzerish, builder::RustAnalyzerishBuilder, entities::CursorCoordinates,
+    analyzer::RustAnalyzerish,
+    builder::RustAnalyzerishBuilder,
+    entities::{CargoOptions, CursorCoordinates, DiagnosticsOptions},
+    flycheck::FlycheckConfig,
+    journal::EditJournal,
 };
+use serde::{Serialize, Serializer};
+use serde_json::Value;
+
+use crate::progress;
 
 // Unified command wrapper for both CLI and REPL use
 #[derive(Parser)]
 #[command(no_binary_name = true)]
 pub struct CommandWrapper {
+    /// Output format
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+    #[command(flatten)]
+    pub cargo: CargoArgs,
     #[command(subcommand)]
     pub command: AnalyzerCommand,
 }
 
+/// Cargo configuration to analyze workspaces with
+#[derive(Args, Debug, Clone, Default)]
+pub struct CargoArgs {
+    /// Comma separated list of features to enable
+    #[arg(long, global = true, value_delimiter = ',')]
+    pub features: Vec<String>,
+    /// Enable all features
+    #[arg(long, global = true)]
+    pub all_features: bool,
+    /// Disable the default features
+    #[arg(long, global = true)]
+    pub no_default_features: bool,
+    /// Target triple to analyze for, instead of the host's
+    #[arg(long, global = true)]
+    pub target: Option<String>,
+    /// Extra cfg option for every crate, as `name` or `name=value`, or
+    /// `!name` to disable one (repeatable)
+    #[arg(long = "cfg", global = true)]
+    pub cfgs: Vec<String>,
+    /// Extra environment variable for cargo, build scripts and `env!`, as
+    /// `KEY=VALUE` (repeatable)
+    #[arg(long = "env", global = true, value_parser = parse_env_var)]
+    pub env: Vec<(String, String)>,
+}
+
+impl From<CargoArgs> for CargoOptions {
+    fn from(args: CargoArgs) -> Self {
+        CargoOptions {
+            features: args.features,
+            all_features: args.all_features,
+            no_default_features: args.no_default_features,
+            target: args.target,
+            cfgs: args.cfgs,
+            env: args.env.into_iter().collect(),
+        }
+    }
+}
+
+/// How to run cargo for flycheck diagnostics
+#[derive(Args, Debug, Clone, Default)]
+pub struct FlycheckArgs {
+    /// Cargo subcommand flycheck runs, e.g. `check` (default) or `clippy`
+    #[arg(long)]
+    pub flycheck_command: Option<String>,
+    /// Extra argument for the flycheck command, e.g. `--all-targets`
+    /// (repeatable)
+    #[arg(long = "flycheck-arg", allow_hyphen_values = true)]
+    pub flycheck_args: Vec<String>,
+    /// Run flycheck with `--offline`
+    #[arg(long)]
+    pub flycheck_offline: bool,
+    /// Command to run instead of cargo, printing compiler messages as JSON
+    /// like `cargo check --message-format=json`
+    #[arg(long)]
+    pub flycheck_override_command: Option<String>,
+}
+
+impl From<FlycheckArgs> for FlycheckConfig {
+    fn from(args: FlycheckArgs) -> Self {
+        let defaults = FlycheckConfig::default();
+        FlycheckConfig {
+            command: args.flycheck_command.unwrap_or(defaults.command),
+            extra_args: args.flycheck_args,
+            offline: args.flycheck_offline,
+            override_command: args
+                .flycheck_override_command
+                .map(|command| command.split_whitespace().map(str::to_string).collect()),
+        }
+    }
+}
+
+/// Which diagnostics to report, on top of the workspace's `rustbelt.toml`
+#[derive(Args, Debug, Clone, Default)]
+pub struct DiagnosticsArgs {
+    /// Diagnostic code to leave out, e.g. `inactive-code` (repeatable)
+    #[arg(long = "disable-diagnostic")]
+    pub disabled: Vec<String>,
+    /// Diagnostic code disabled in `rustbelt.toml` to report again
+    /// (repeatable)
+    #[arg(long = "enable-diagnostic")]
+    pub enabled: Vec<String>,
+    /// Report style lints, such as non-idiomatic naming
+    #[arg(long)]
+    pub style_lints: bool,
+    /// Leave out experimental diagnostics
+    #[arg(long)]
+    pub no_experimental: bool,
+}
+
+impl From<DiagnosticsArgs> for DiagnosticsOptions {
+    fn from(args: DiagnosticsArgs) -> Self {
+        DiagnosticsOptions {
+            disabled: args.disabled,
+            enabled: args.enabled,
+            style_lints: args.style_lints.then_some(true),
+            experimental: args.no_experimental.then_some(false),
+        }
+    }
+}
+
+fn parse_env_var(var: &str) -> Result<(String, String), String> {
+    var.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected KEY=VALUE, got `{var}`"))
+}
+
+/// How analyzer command results are printed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable text
+    Text,
+    /// A single pretty-printed JSON document
+    Json,
+    /// One compact JSON value per line (one per item for list results)
+    Jsonl,
+}
+
 // Base commands without workspace path - used by both CLI and REPL
 #[derive(Subcommand)]
 #[command(no_binary_name = true)]
@@ -106,6 +240,9 @@ pub enum AnalyzerCommand {
         /// Optional symbol name to search for near the coordinates
         #[arg(long)]
         symbol: Option<String>,
+        /// Print the change as a unified diff instead of applying it
+        #[arg(long)]
+        preview: bool,
     },
 
     /// Rename a symbol at a specific position
@@ -121,6 +258,9 @@ pub enum AnalyzerCommand {
         /// Optional symbol name to search for near the coordinates
         #[arg(long)]
         symbol: Option<String>,
+        /// Print the rename as a unified diff instead of applying it
+        #[arg(long)]
+        preview: bool,
     },
 
     /// Analyze a symbol completely - type, definition, implementations, callers, reference count
@@ -146,6 +286,14 @@ pub enum AnalyzerCommand {
     GetDiagnostics {
         /// Path to the Rust source file
         file_path: String,
+        /// Also run `cargo check` (or `--flycheck-command`) for the file's
+        /// package, for errors and lints rust-analyzer misses
+        #[arg(long)]
+        flycheck: bool,
+        #[command(flatten)]
+        flycheck_args: FlycheckArgs,
+        #[command(flatten)]
+        diagnostics_args: DiagnosticsArgs,
     },
 
     /// Expand a macro at a specific position to see what it generates
@@ -193,6 +341,9 @@ pub enum AnalyzerCommand {
         /// Only show matches without applying changes
         #[arg(long)]
         dry_run: bool,
+        /// Print the replacements as a unified diff instead of applying them
+        #[arg(long, conflicts_with = "dry_run")]
+        preview: bool,
     },
 
     /// Search for code patterns using SSR syntax (without replacement)
@@ -203,13 +354,48 @@ pub enum AnalyzerCommand {
         #[arg(long)]
         context_file: Option<String>,
     },
+
+    /// Undo the last edit written to disk (rename, assist or SSR)
+    Undo {
+        /// Workspace root, or any file inside the workspace (defaults to the
+        /// current directory)
+        #[arg(long)]
+        workspace_path: Option<String>,
+    },
+
+    /// Redo the last undone edit
+    Redo {
+        /// Workspace root, or any file inside the workspace (defaults to the
+        /// current directory)
+        #[arg(long)]
+        workspace_path: Option<String>,
+    },
+
+    /// List the edits recorded in the undo/redo journal
+    EditHistory {
+        /// Workspace root, or any file inside the workspace (defaults to the
+        /// current directory)
+        #[arg(long)]
+        workspace_path: Option<String>,
+    },
 }
 
 // For REPL use - reuses existing analyzer connection
 pub async fn execute_analyzer_command_with_instance(
     command: AnalyzerCommand,
-    analyzer: &mut RustAnalyzerish,
+    analyzer: &RustAnalyzerish,
+    format: OutputFormat,
+    out: &mut impl Write,
 ) -> Result<()> {
+    render(format, run_analyzer_command(command, analyzer).await, out)
+}
+
+// Run a command on a loaded workspace
+async fn run_analyzer_command(
+    command: AnalyzerCommand,
+    analyzer: &RustAnalyzerish,
+) -> Result<CommandOutput> {
+    let mut text = String::new();
     match command {
         AnalyzerCommand::TypeHint {
             file_path,
@@ -224,20 +410,23 @@ pub async fn execute_analyzer_command_with_instance(
                 symbol,
             };
 
-            match analyzer.get_type_hint(&cursor).await {
-                Ok(Some(type_info)) => {
-                    println!("Type Hint:\n-----\n{}\n------", type_info);
+            let result = analyzer
+                .get_type_hint(&cursor)
+                .await
+                .context("Error getting type hint")?;
+            match &result {
+                Some(type_info) => {
+                    writeln!(text, "Type Hint:\n-----\n{}\n------", type_info)?;
                 }
-                Ok(None) => {
-                    println!(
+                None => {
+                    writeln!(
+                        text,
                         "No type information available at {}:{}:{}",
                         file_path, line, column
-                    );
-                }
-                Err(e) => {
-                    println!("Error getting type hint: {}", e);
+                    )?;
                 }
             }
+            CommandOutput::new(&result, text)
         }
         AnalyzerCommand::GetDefinition {
             file_path,
@@ -252,20 +441,26 @@ pub async fn execute_analyzer_command_with_instance(
                 symbol,
             };
 
-            match analyzer.get_definition(&cursor).await {
-                Ok(Some(definitions)) => {
-                    println!("Found {} definition(s):", definitions.len());
+            let result = analyzer
+                .get_definition(&cursor)
+                .await
+                .context("Error getting definitions")?;
+            match &result {
+                Some(definitions) => {
+                    writeln!(text, "Found {} definition(s):", definitions.len())?;
                     for def in definitions {
-                        println!("  {}", def);
+                        writeln!(text, "  {}", def)?;
                     }
                 }
-                Ok(None) => {
-                    println!("No definitions found at {}:{}:{}", file_path, line, column);
-                }
-                Err(e) => {
-                    println!("Error getting definitions: {}", e);
+                None => {
+                    writeln!(
+                        text,
+                        "No definitions found at {}:{}:{}",
+                        file_path, line, column
+                    )?;
                 }
             }
+            CommandOutput::new(&result, text)
         }
         AnalyzerCommand::GetCompletions {
             file_path,
@@ -280,26 +475,33 @@ pub async fn execute_analyzer_command_with_instance(
                 symbol,
             };
 
-            match analyzer.get_completions(&cursor).await {
-                Ok(Some(completions)) => {
-                    println!(
+            let result = analyzer
+                .get_completions(&cursor)
+                .await
+                .context("Error getting completions")?;
+            match &result {
+                Some(completions) => {
+                    writeln!(
+                        text,
                         "Available completions at {}:{}:{} ({} items):",
                         file_path,
                         line,
                         column,
                         completions.len()
-                    );
+                    )?;
                     for completion in completions {
-                        println!("  {}", completion);
+                        writeln!(text, "  {}", completion)?;
                     }
                 }
-                Ok(None) => {
-                    println!("No completions found at {}:{}:{}", file_path, line, column);
-                }
-                Err(e) => {
-                    println!("Error getting completions: {}", e);
+                None => {
+                    writeln!(
+                        text,
+                        "No completions found at {}:{}:{}",
+                        file_path, line, column
+                    )?;
                 }
             }
+            CommandOutput::new(&result, text)
         }
         AnalyzerCommand::FindReferences {
             file_path,
@@ -314,40 +516,41 @@ pub async fn execute_analyzer_command_with_instance(
                 symbol,
             };
 
-            match analyzer.find_references(&cursor).await {
-                Ok(Some(references)) => {
-                    println!("Found {} reference(s):", references.len());
+            let result = analyzer
+                .find_references(&cursor)
+                .await
+                .context("Error finding references")?;
+            match &result {
+                Some(references) => {
+                    writeln!(text, "Found {} reference(s):", references.len())?;
                     for reference in references {
-                        println!("  {}", reference);
+                        writeln!(text, "  {}", reference)?;
                     }
                 }
-                Ok(None) => {
-                    println!("No references found at {}:{}:{}", file_path, line, column);
-                }
-                Err(e) => {
-                    println!("Error finding references: {}", e);
+                None => {
+                    writeln!(
+                        text,
+                        "No references found at {}:{}:{}",
+                        file_path, line, column
+                    )?;
                 }
             }
+            CommandOutput::new(&result, text)
         }
         AnalyzerCommand::ViewInlayHints {
             file_path,
             start_line,
             end_line,
         } => {
-            match analyzer
+            let annotated_content = analyzer
                 .view_inlay_hints(&file_path, start_line, end_line)
                 .await
-            {
-                Ok(annotated_content) => {
-                    println!("File with inlay hints:");
-                    println!("=====================================");
-                    println!("{}", annotated_content);
-                    println!("=====================================");
-                }
-                Err(e) => {
-                    println!("Error viewing inlay hints: {}", e);
-                }
-            }
+                .context("Error viewing inlay hints")?;
+            writeln!(text, "File with inlay hints:")?;
+            writeln!(text, "=====================================")?;
+            writeln!(text, "{}", annotated_content)?;
+            writeln!(text, "=====================================")?;
+            CommandOutput::new(&annotated_content, text)
         }
         AnalyzerCommand::GetAssists {
             file_path,
@@ -362,26 +565,37 @@ pub async fn execute_analyzer_command_with_instance(
                 symbol,
             };
 
-            match analyzer.get_assists(&cursor).await {
-                Ok(Some(assists)) => {
-                    println!(
+            let result = analyzer
+                .get_assists(&cursor)
+                .await
+                .context("Error getting assists")?;
+            match &result {
+                Some(assists) => {
+                    writeln!(
+                        text,
                         "Available assists at {}:{}:{} ({} items):",
                         file_path,
                         line,
                         column,
                         assists.len()
-                    );
+                    )?;
                     for assist in assists {
-                        println!("  {} ({}): {}", assist.label, assist.id, assist.target);
+                        writeln!(
+                            text,
+                            "  {} ({}): {}",
+                            assist.label, assist.id, assist.target
+                        )?;
                     }
                 }
-                Ok(None) => {
-                    println!("No assists available at {}:{}:{}", file_path, line, column);
-                }
-                Err(e) => {
-                    println!("Error getting assists: {}", e);
+                None => {
+                    writeln!(
+                        text,
+                        "No assists available at {}:{}:{}",
+                        file_path, line, column
+                    )?;
                 }
             }
+            CommandOutput::new(&result, text)
         }
         AnalyzerCommand::ApplyAssist {
             file_path,
@@ -389,6 +603,7 @@ pub async fn execute_analyzer_command_with_instance(
             column,
             assist_id,
             symbol,
+            preview,
         } => {
             let cursor = CursorCoordinates {
                 file_path: file_path.clone(),
@@ -397,24 +612,43 @@ pub async fn execute_analyzer_command_with_instance(
                 symbol,
             };
 
-            match analyzer.apply_assist(&cursor, &assist_id).await {
-                Ok(Some(source_change)) => {
-                    println!("Successfully applied assist '{}':", assist_id);
+            if preview {
+                let result = analyzer
+                    .preview_assist(&cursor, &assist_id)
+                    .await
+                    .context("Error previewing assist")?;
+                match &result {
+                    Some(diff) => write!(text, "{}", diff)?,
+                    None => writeln!(
+                        text,
+                        "Assist '{}' not available at this position",
+                        assist_id
+                    )?,
+                }
+                return CommandOutput::new(&result, text);
+            }
+
+            let result = analyzer
+                .apply_assist(&cursor, &assist_id)
+                .await
+                .with_context(|| format!("Error applying assist '{}'", assist_id))?;
+            match &result {
+                Some(source_change) => {
+                    writeln!(text, "Successfully applied assist '{}':", assist_id)?;
                     for file_change in &source_change.file_changes {
-                        println!("  Modified file: {}", file_change.file_path);
-                        println!("    {} edits applied", file_change.edits.len());
+                        writeln!(text, "  Modified file: {}", file_change.file_path)?;
+                        writeln!(text, "    {} edits applied", file_change.edits.len())?;
                     }
                 }
-                Ok(None) => {
-                    println!(
+                None => {
+                    writeln!(
+                        text,
                         "Assist '{}' not available at {}:{}:{}",
                         assist_id, file_path, line, column
-                    );
-                }
-                Err(e) => {
-                    println!("Error applying assist '{}': {}", assist_id, e);
+                    )?;
                 }
             }
+            CommandOutput::new(&result, text)
         }
         AnalyzerCommand::RenameSymbol {
             file_path,
@@ -422,6 +656,7 @@ pub async fn execute_analyzer_command_with_instance(
             column,
             new_name,
             symbol,
+            preview,
         } => {
             let cursor = CursorCoordinates {
                 file_path: file_path.clone(),
@@ -430,26 +665,47 @@ pub async fn execute_analyzer_command_with_instance(
                 symbol,
             };
 
-            match analyzer.rename_symbol(&cursor, &new_name).await {
-                Ok(Some(changes)) => {
-                    println!(
+            if preview {
+                let result = analyzer
+                    .preview_rename(&cursor, &new_name)
+                    .await
+                    .context("Error previewing rename")?;
+                match &result {
+                    Some(diff) => write!(text, "{}", diff)?,
+                    None => writeln!(text, "Symbol cannot be renamed at this position")?,
+                }
+                return CommandOutput::new(&result, text);
+            }
+
+            let result = analyzer
+                .rename_symbol(&cursor, &new_name)
+                .await
+                .context("Error renaming symbol")?;
+            match &result {
+                Some(changes) => {
+                    writeln!(
+                        text,
                         "Rename successful! {} file(s) changed:",
                         changes.file_changes.len()
-                    );
+                    )?;
                     for change in &changes.file_changes {
-                        println!("  {}: {} edit(s)", change.file_path, change.edits.len());
+                        writeln!(
+                            text,
+                            "  {}: {} edit(s)",
+                            change.file_path,
+                            change.edits.len()
+                        )?;
                     }
                 }
-                Ok(None) => {
-                    println!(
+                None => {
+                    writeln!(
+                        text,
                         "No symbol found to rename at {}:{}:{}",
                         file_path, line, column
-                    );
-                }
-                Err(e) => {
-                    println!("Error renaming symbol: {}", e);
+                    )?;
                 }
             }
+            CommandOutput::new(&result, text)
         }
         AnalyzerCommand::AnalyzeSymbol {
             file_path,
@@ -464,46 +720,52 @@ pub async fn execute_analyzer_command_with_instance(
                 symbol,
             };
 
-            match analyzer.analyze_symbol(&cursor).await {
-                Ok(analysis) => {
-                    println!("{}", analysis);
-                }
-                Err(e) => {
-                    println!("Error analyzing symbol: {}", e);
-                }
-            }
+            let analysis = analyzer
+                .analyze_symbol(&cursor)
+                .await
+                .context("Error analyzing symbol")?;
+            writeln!(text, "{}", analysis)?;
+            CommandOutput::new(&analysis, text)
         }
         AnalyzerCommand::GetFileOutline { file_path } => {
-            match analyzer.get_file_outline(&file_path).await {
-                Ok(items) => {
-                    if items.is_empty() {
-                        println!("No structure items found in file.");
-                    } else {
-                        for item in items {
-                            println!("{}", item);
-                        }
-                    }
-                }
-                Err(e) => {
-                    println!("Error getting file outline: {}", e);
+            let items = analyzer
+                .get_file_outline(&file_path)
+                .await
+                .context("Error getting file outline")?;
+            if items.is_empty() {
+                writeln!(text, "No structure items found in file.")?;
+            } else {
+                for item in &items {
+                    writeln!(text, "{}", item)?;
                 }
             }
+            CommandOutput::new(&items, text)
         }
-        AnalyzerCommand::GetDiagnostics { file_path } => {
-            match analyzer.get_diagnostics(&file_path).await {
-                Ok(diagnostics) => {
-                    if diagnostics.is_empty() {
-                        println!("No diagnostics — code looks clean.");
-                    } else {
-                        for diag in diagnostics {
-                            println!("{}\n", diag);
-                        }
-                    }
-                }
-                Err(e) => {
-                    println!("Error getting diagnostics: {}", e);
+        AnalyzerCommand::GetDiagnostics {
+            file_path,
+            flycheck,
+            flycheck_args,
+            diagnostics_args,
+        } => {
+            let options = DiagnosticsOptions::from(diagnostics_args);
+            let result = if flycheck || flycheck_args.flycheck_command.is_some() {
+                analyzer
+                    .get_diagnostics_with_flycheck(&file_path, &flycheck_args.into(), &options)
+                    .await
+            } else {
+                analyzer
+                    .get_diagnostics_with_options(&file_path, &options)
+                    .await
+            };
+            let diagnostics = result.context("Error getting diagnostics")?;
+            if diagnostics.is_empty() {
+                writeln!(text, "No diagnostics — code looks clean.")?;
+            } else {
+                for diag in &diagnostics {
+                    writeln!(text, "{}\n", diag)?;
                 }
             }
+            CommandOutput::new(&diagnostics, text)
         }
         AnalyzerCommand::ExpandMacro {
             file_path,
@@ -518,34 +780,34 @@ pub async fn execute_analyzer_command_with_instance(
                 symbol,
             };
 
-            match analyzer.expand_macro(&cursor).await {
-                Ok(Some(expansion)) => {
-                    println!("{}", expansion);
-                }
-                Ok(None) => {
-                    println!("No macro found at this position to expand.");
+            let result = analyzer
+                .expand_macro(&cursor)
+                .await
+                .context("Error expanding macro")?;
+            match &result {
+                Some(expansion) => {
+                    writeln!(text, "{}", expansion)?;
                 }
-                Err(e) => {
-                    println!("Error expanding macro: {}", e);
+                None => {
+                    writeln!(text, "No macro found at this position to expand.")?;
                 }
             }
+            CommandOutput::new(&result, text)
         }
         AnalyzerCommand::SearchSymbols { query, limit } => {
-            match analyzer.search_symbols(&query, limit).await {
-                Ok(results) => {
-                    if results.is_empty() {
-                        println!("No symbols found matching '{}'", query);
-                    } else {
-                        println!("Found {} symbol(s):", results.len());
-                        for result in results {
-                            println!("{}", result);
-                        }
-                    }
-                }
-                Err(e) => {
-                    println!("Error searching symbols: {}", e);
+            let results = analyzer
+                .search_symbols(&query, limit)
+                .await
+                .context("Error searching symbols")?;
+            if results.is_empty() {
+                writeln!(text, "No symbols found matching '{}'", query)?;
+            } else {
+                writeln!(text, "Found {} symbol(s):", results.len())?;
+                for result in &results {
+                    writeln!(text, "{}", result)?;
                 }
             }
+            CommandOutput::new(&results, text)
         }
         AnalyzerCommand::GetSignatureHelp {
             file_path,
@@ -560,67 +822,234 @@ pub async fn execute_analyzer_command_with_instance(
                 symbol,
             };
 
-            match analyzer.get_signature_help(&cursor).await {
-                Ok(Some(sig_info)) => {
-                    println!("{}", sig_info);
-                }
-                Ok(None) => {
-                    println!("No signature help available at this position.");
+            let result = analyzer
+                .get_signature_help(&cursor)
+                .await
+                .context("Error getting signature help")?;
+            match &result {
+                Some(sig_info) => {
+                    writeln!(text, "{}", sig_info)?;
                 }
-                Err(e) => {
-                    println!("Error getting signature help: {}", e);
+                None => {
+                    writeln!(text, "No signature help available at this position.")?;
                 }
             }
+            CommandOutput::new(&result, text)
         }
         AnalyzerCommand::Ssr {
             pattern,
             context_file,
             dry_run,
+            preview,
         } => {
-            match analyzer
+            if preview {
+                let diff = analyzer
+                    .preview_ssr(&pattern, context_file.as_deref())
+                    .await
+                    .context("SSR error")?;
+                write!(text, "{}", diff)?;
+                return CommandOutput::new(&diff, text);
+            }
+
+            let result = analyzer
                 .ssr(&pattern, context_file.as_deref(), dry_run)
                 .await
-            {
-                Ok(result) => {
-                    println!("{}", result);
-                }
-                Err(e) => {
-                    println!("SSR error: {}", e);
-                }
-            }
+                .context("SSR error")?;
+            writeln!(text, "{}", result)?;
+            CommandOutput::new(&result, text)
         }
         AnalyzerCommand::SsrSearch {
             pattern,
             context_file,
-        } => match analyzer.ssr_search(&pattern, context_file.as_deref()).await {
-            Ok(matches) => {
-                if matches.is_empty() {
-                    println!("No matches found for pattern: {}", pattern);
-                } else {
-                    println!("Found {} match(es):\n", matches.len());
-                    for m in matches {
-                        println!("{}", m);
-                    }
+        } => {
+            let matches = analyzer
+                .ssr_search(&pattern, context_file.as_deref())
+                .await
+                .context("SSR search error")?;
+            if matches.is_empty() {
+                writeln!(text, "No matches found for pattern: {}", pattern)?;
+            } else {
+                writeln!(text, "Found {} match(es):\n", matches.len())?;
+                for m in &matches {
+                    writeln!(text, "{}", m)?;
                 }
             }
-            Err(e) => {
-                println!("SSR search error: {}", e);
+            CommandOutput::new(&matches, text)
+        }
+        AnalyzerCommand::Undo { .. }
+        | AnalyzerCommand::Redo { .. }
+        | AnalyzerCommand::EditHistory { .. } => {
+            run_journal_command(command, analyzer.edit_journal()).await
+        }
+    }
+}
+
+// Undo/redo commands only need the journal, not a loaded workspace
+pub(crate) async fn execute_journal_command(
+    command: AnalyzerCommand,
+    journal: &EditJournal,
+    format: OutputFormat,
+    out: &mut impl Write,
+) -> Result<()> {
+    render(format, run_journal_command(command, journal).await, out)
+}
+
+// Run a command on the edit journal
+async fn run_journal_command(
+    command: AnalyzerCommand,
+    journal: &EditJournal,
+) -> Result<CommandOutput> {
+    let mut text = String::new();
+    match command {
+        AnalyzerCommand::Undo { .. } => {
+            let result = journal.undo().await.context("Undo error")?;
+            match &result {
+                Some(record) => write!(text, "Undid edit {}", record)?,
+                None => writeln!(text, "Nothing to undo.")?,
+            }
+            CommandOutput::new(&result, text)
+        }
+        AnalyzerCommand::Redo { .. } => {
+            let result = journal.redo().await.context("Redo error")?;
+            match &result {
+                Some(record) => write!(text, "Redid edit {}", record)?,
+                None => writeln!(text, "Nothing to redo.")?,
             }
-        },
+            CommandOutput::new(&result, text)
+        }
+        AnalyzerCommand::EditHistory { .. } => {
+            let records = journal.history().await.context("Edit history error")?;
+            if records.is_empty() {
+                writeln!(text, "No edits recorded.")?;
+            } else {
+                writeln!(text, "{} edit(s) recorded:\n", records.len())?;
+                for record in &records {
+                    write!(text, "{}", record)?;
+                }
+            }
+            CommandOutput::new(&records, text)
+        }
+        _ => unreachable!("not a journal command"),
     }
-    Ok(())
 }
 
 // For CLI use - creates new analyzer instance for single command
 pub(crate) async fn execute_analyzer_command(
     command: AnalyzerCommand,
     workspace_path: &str,
+    format: OutputFormat,
+    cargo_options: CargoOptions,
 ) -> Result<()> {
-    let mut analyzer = RustAnalyzerishBuilder::from_file(workspace_path)?.build()?;
-    execute_analyzer_command_with_instance(command, &mut analyzer).await
+    if command.is_journal_command() {
+        let journal = EditJournal::for_file(workspace_path)?;
+        return execute_journal_command(command, &journal, format, &mut std::io::stdout()).await;
+    }
+
+    let analyzer = RustAnalyzerishBuilder::from_file(workspace_path)?
+        .with_cargo_options(cargo_options)
+        .with_progress(progress::progress_bar())
+        .build()
+        .inspect_err(|_| progress::clear())?;
+    execute_analyzer_command_with_instance(command, &analyzer, format, &mut std::io::stdout()).await
+}
+
+/// Result of a command, serialized as its JSON and displayed as its text
+/// rendering
+struct CommandOutput {
+    value: Value,
+    text: String,
+}
+
+impl CommandOutput {
+    fn new(result: &impl Serialize, text: String) -> Result<Self> {
+        Ok(Self {
+            value: serde_json::to_value(result)?,
+            text,
+        })
+    }
 }
 
-pub(crate) fn extract_workspace_path(command: &AnalyzerCommand) -> String {
+impl Serialize for CommandOutput {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.value.serialize(serializer)
+    }
+}
+
+impl fmt::Display for CommandOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+// Print the output of a command. Errors are printed as text, but returned
+// with the JSON formats so the exit status reflects them
+fn render(format: OutputFormat, output: Result<CommandOutput>, out: &mut impl Write) -> Result<()> {
+    if format == OutputFormat::Text {
+        match output {
+            Ok(output) => write!(out, "{}", output)?,
+            Err(e) => writeln!(out, "{:#}", e)?,
+        }
+        return Ok(());
+    }
+    match (format, serde_json::to_value(output?)?) {
+        (OutputFormat::Jsonl, Value::Array(items)) => {
+            for item in items {
+                writeln!(out, "{}", serde_json::to_string(&item)?)?;
+            }
+        }
+        (OutputFormat::Jsonl, Value::Null) => {}
+        (OutputFormat::Jsonl, value) => writeln!(out, "{}", serde_json::to_string(&value)?)?,
+        (_, value) => writeln!(out, "{}", serde_json::to_string_pretty(&value)?)?,
+    }
+    Ok(())
+}
+
+impl AnalyzerCommand {
+    /// Whether the command only needs the edit journal
+    pub(crate) fn is_journal_command(&self) -> bool {
+        matches!(
+            self,
+            AnalyzerCommand::Undo { .. }
+                | AnalyzerCommand::Redo { .. }
+                | AnalyzerCommand::EditHistory { .. }
+        )
+    }
+
+    /// Make the paths of the command absolute, relative to `cwd`
+    ///
+    /// Commands forwarded to the daemon are resolved against the directory
+    /// the client was started in, not the daemon's.
+    pub(crate) fn resolve_paths(&mut self, cwd: &Path) {
+        let path = match self {
+            AnalyzerCommand::TypeHint { file_path, .. }
+            | AnalyzerCommand::GetDefinition { file_path, .. }
+            | AnalyzerCommand::GetCompletions { file_path, .. }
+            | AnalyzerCommand::FindReferences { file_path, .. }
+            | AnalyzerCommand::ViewInlayHints { file_path, .. }
+            | AnalyzerCommand::GetAssists { file_path, .. }
+            | AnalyzerCommand::ApplyAssist { file_path, .. }
+            | AnalyzerCommand::RenameSymbol { file_path, .. }
+            | AnalyzerCommand::AnalyzeSymbol { file_path, .. }
+            | AnalyzerCommand::GetFileOutline { file_path, .. }
+            | AnalyzerCommand::GetDiagnostics { file_path, .. }
+            | AnalyzerCommand::ExpandMacro { file_path, .. }
+            | AnalyzerCommand::GetSignatureHelp { file_path, .. } => Some(file_path),
+            AnalyzerCommand::Undo { workspace_path }
+            | AnalyzerCommand::Redo { workspace_path }
+            | AnalyzerCommand::EditHistory { workspace_path } => workspace_path.as_mut(),
+            AnalyzerCommand::Ssr { context_file, .. }
+            | AnalyzerCommand::SsrSearch { context_file, .. } => context_file.as_mut(),
+            AnalyzerCommand::SearchSymbols { .. } => None,
+        };
+        if let Some(path) = path {
+            *path = cwd.join(&*path).display().to_string();
+        }
+    }
+}
+
+/// Path used to find the workspace of a command, falling back to `cwd` for
+/// commands without one
+pub(crate) fn extract_workspace_path(command: &AnalyzerCommand, cwd: &Path) -> String {
     match command {
         AnalyzerCommand::TypeHint { file_path, .. }
         | AnalyzerCommand::GetDefinition { file_path, .. }
@@ -635,16 +1064,28 @@ pub(crate) fn extract_workspace_path(command: &AnalyzerCommand) -> String {
         | AnalyzerCommand::GetDiagnostics { file_path, .. }
         | AnalyzerCommand::ExpandMacro { file_path, .. }
         | AnalyzerCommand::GetSignatureHelp { file_path, .. } => file_path.clone(),
-        AnalyzerCommand::SearchSymbols { .. } => std::env::current_dir()
-            .map(|p| p.display().to_string())
-            .unwrap_or_else(|_| ".".to_string()),
-        AnalyzerCommand::Ssr { context_file, .. }
-        | AnalyzerCommand::SsrSearch { context_file, .. } => {
-            context_file.clone().unwrap_or_else(|| {
-                std::env::current_dir()
-                    .map(|p| p.display().to_string())
-                    .unwrap_or_else(|_| ".".to_string())
-            })
+        AnalyzerCommand::SearchSymbols { .. }
+        | AnalyzerCommand::Undo {
+            workspace_path: None,
         }
+        | AnalyzerCommand::Redo {
+            workspace_path: None,
+        }
+        | AnalyzerCommand::EditHistory {
+            workspace_path: None,
+        } => cwd.display().to_string(),
+        AnalyzerCommand::Undo {
+            workspace_path: Some(path),
+        }
+        | AnalyzerCommand::Redo {
+            workspace_path: Some(path),
+        }
+        | AnalyzerCommand::EditHistory {
+            workspace_path: Some(path),
+        } => path.clone(),
+        AnalyzerCommand::Ssr { context_file, .. }
+        | AnalyzerCommand::SsrSearch { context_file, .. } => context_file
+            .clone()
+            .unwrap_or_else(|| cwd.display().to_string()),
     }
 }
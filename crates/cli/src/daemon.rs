@@ -0,0 +1,424 @@
+//! Analyzer daemon
+//!
+//! Loading a workspace takes seconds to minutes, so running every `rustbelt
+//! analyzer` invocation in a fresh process is slow. The daemon keeps a
+//! [`WorkspaceRegistry`] alive and serves analyzer commands over a Unix
+//! socket. `rustbelt analyzer` forwards its arguments to the daemon, spawning
+//! it first if it is not running, and the daemon exits after it has been idle
+//! for a while.
+//!
+//! The protocol is a single newline-delimited JSON request and response per
+//! connection, preceded by progress messages while a workspace loads if the
+//! client asked for them. Requests are served one at a time.
+//!
+//! Only the user running the daemon may talk to it: the socket lives in a
+//! directory private to the user, and connections from other users are
+//! refused. Commands run with the environment the daemon was started with;
+//! the client's environment, such as `RUST_LOG` or cargo variables, is
+//! ignored. Use the cargo `--env` option, or `--no-daemon` to run a command
+//! with the current environment.
+
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use clap::Parser;
+use librustbelt::{
+    EditJournal, ProgressCallback, RustAnalyzerish, WorkspaceRegistry, entities::LoadProgress,
+};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc;
+use tokio::time::{Instant, sleep, timeout, timeout_at};
+use tracing::{debug, info, warn};
+
+use crate::command::{
+    CommandWrapper, execute_analyzer_command_with_instance, execute_journal_command,
+    extract_workspace_path,
+};
+use crate::progress;
+
+/// Time a client has to send its request once connected
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time a client waits for a freshly spawned daemon to accept connections
+const SPAWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Daemon settings
+pub struct DaemonConfig {
+    pub socket_path: PathBuf,
+    /// Exit after this long without requests
+    pub idle_timeout: Duration,
+    pub memory_budget_mb: Option<u64>,
+    pub cache_dir: Option<PathBuf>,
+}
+
+/// An analyzer command line, as passed to `rustbelt analyzer`
+#[derive(Debug, Serialize, Deserialize)]
+struct DaemonRequest {
+    /// Directory relative paths in `args` are resolved against
+    cwd: PathBuf,
+    args: Vec<String>,
+    /// Send workspace loading progress before the response
+    #[serde(default)]
+    progress: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct DaemonResponse {
+    /// Everything the command printed
+    output: String,
+    /// Set if the command failed
+    error: Option<String>,
+}
+
+/// A line sent by the daemon
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum DaemonMessage {
+    Progress { progress: LoadProgress },
+    Response(DaemonResponse),
+}
+
+/// Where the registry reports loading progress: the connection being
+/// served, if its client asked for progress
+type ProgressSink = Arc<Mutex<Option<mpsc::UnboundedSender<LoadProgress>>>>;
+
+/// Socket used when none is configured
+///
+/// Prefers `$XDG_RUNTIME_DIR`, which is private to the user, and falls back
+/// to a directory of the temporary directory that only the user can access.
+pub fn default_socket_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("rustbelt.sock"),
+        None => private_socket_dir().join("rustbelt.sock"),
+    }
+}
+
+/// Per-user directory for the socket when `$XDG_RUNTIME_DIR` is unset
+fn private_socket_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("rustbelt-{}", current_uid()))
+}
+
+fn current_uid() -> u32 {
+    // SAFETY: geteuid has no preconditions and always succeeds
+    unsafe { libc::geteuid() }
+}
+
+/// Make sure no other user controls the socket at `socket_path`
+///
+/// Creates the private socket directory if the socket goes there. Refuses
+/// symlinks, sockets of other users and directories other users can replace
+/// the socket in.
+fn check_socket_path(socket_path: &Path) -> Result<()> {
+    let uid = current_uid();
+    let dir = match socket_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    if dir == private_socket_dir() {
+        match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to create {}", dir.display()));
+            }
+        }
+        let metadata = std::fs::symlink_metadata(dir)
+            .with_context(|| format!("Failed to inspect {}", dir.display()))?;
+        ensure!(
+            metadata.is_dir() && metadata.uid() == uid && metadata.mode() & 0o077 == 0,
+            "Refusing socket directory {}: it must be a directory only you can access",
+            dir.display()
+        );
+    } else {
+        let metadata = std::fs::metadata(dir)
+            .with_context(|| format!("Failed to inspect {}", dir.display()))?;
+        // Others can't remove our files from sticky directories like /tmp
+        let sticky = metadata.mode() & 0o1000 != 0;
+        ensure!(
+            (metadata.uid() == uid || metadata.uid() == 0)
+                && (metadata.mode() & 0o022 == 0 || sticky),
+            "Refusing socket directory {}: other users can write to it",
+            dir.display()
+        );
+    }
+
+    match std::fs::symlink_metadata(socket_path) {
+        Ok(metadata) => ensure!(
+            !metadata.file_type().is_symlink() && metadata.uid() == uid,
+            "Refusing socket {}: it is a symlink or belongs to another user",
+            socket_path.display()
+        ),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to inspect {}", socket_path.display()));
+        }
+    }
+    Ok(())
+}
+
+/// Make sure the other end of a connection runs as the current user
+fn check_peer(stream: &UnixStream) -> Result<()> {
+    let peer = stream
+        .peer_cred()
+        .context("Failed to get the peer credentials")?;
+    ensure!(
+        peer.uid() == current_uid(),
+        "Refusing connection from user {}",
+        peer.uid()
+    );
+    Ok(())
+}
+
+/// Serve analyzer commands until the daemon has been idle for
+/// `config.idle_timeout`
+pub async fn run_daemon(config: DaemonConfig) -> Result<()> {
+    let listener = bind(&config.socket_path).await?;
+    info!(
+        "rustbelt daemon listening on {}",
+        config.socket_path.display()
+    );
+
+    let progress = ProgressSink::default();
+    let sink = progress.clone();
+    let mut registry = WorkspaceRegistry::new()
+        .with_memory_budget_mb(config.memory_budget_mb)
+        .with_cache_dir(config.cache_dir)
+        .with_progress(Some(ProgressCallback::new(move |update| {
+            if let Some(sender) = sink.lock().unwrap().as_ref() {
+                let _ = sender.send(update);
+            }
+        })));
+    loop {
+        let deadline = Instant::now() + config.idle_timeout;
+        let stream = match timeout_at(deadline, listener.accept()).await {
+            Ok(Ok((stream, _))) => stream,
+            Ok(Err(e)) => {
+                warn!("Failed to accept connection: {e}");
+                continue;
+            }
+            Err(_) => {
+                info!("Idle for {:?}, shutting down", config.idle_timeout);
+                break;
+            }
+        };
+        if let Err(e) = handle_connection(stream, &mut registry, &progress).await {
+            warn!("Failed to serve request: {e:#}");
+        }
+    }
+
+    std::fs::remove_file(&config.socket_path).ok();
+    Ok(())
+}
+
+/// Bind the socket, replacing a stale one left by a daemon that died
+async fn bind(socket_path: &Path) -> Result<UnixListener> {
+    check_socket_path(socket_path)?;
+    if socket_path.exists() {
+        if UnixStream::connect(socket_path).await.is_ok() {
+            bail!(
+                "A rustbelt daemon is already listening on {}",
+                socket_path.display()
+            );
+        }
+        std::fs::remove_file(socket_path)
+            .with_context(|| format!("Failed to remove stale socket {}", socket_path.display()))?;
+    }
+    let listener = UnixListener::bind(socket_path)
+        .with_context(|| format!("Failed to bind {}", socket_path.display()))?;
+    std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("Failed to restrict {}", socket_path.display()))?;
+    Ok(listener)
+}
+
+async fn handle_connection(
+    stream: UnixStream,
+    registry: &mut WorkspaceRegistry,
+    progress: &ProgressSink,
+) -> Result<()> {
+    check_peer(&stream)?;
+    let (reader, writer) = stream.into_split();
+    let mut line = String::new();
+    timeout(REQUEST_TIMEOUT, BufReader::new(reader).read_line(&mut line))
+        .await
+        .context("Timed out waiting for the request")??;
+    let request: DaemonRequest = serde_json::from_str(&line).context("Malformed request")?;
+    debug!("Serving {:?} from {}", request.args, request.cwd.display());
+
+    // Workspaces load on this task, so progress is written by another one
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    *progress.lock().unwrap() = request.progress.then_some(sender);
+    let forward = tokio::spawn(async move {
+        let mut writer = writer;
+        while let Some(progress) = receiver.recv().await {
+            write_message(&mut writer, &DaemonMessage::Progress { progress }).await?;
+        }
+        anyhow::Ok(writer)
+    });
+
+    let mut output = Vec::new();
+    let error = serve_request(request, registry, &mut output)
+        .await
+        .err()
+        .map(|e| format!("{e:#}"));
+    let response = DaemonResponse {
+        output: String::from_utf8_lossy(&output).into_owned(),
+        error,
+    };
+
+    // Dropping the sender ends the forwarding task
+    progress.lock().unwrap().take();
+    let mut writer = forward.await??;
+    write_message(&mut writer, &DaemonMessage::Response(response)).await?;
+    writer.shutdown().await?;
+    Ok(())
+}
+
+async fn write_message(writer: &mut OwnedWriteHalf, message: &DaemonMessage) -> Result<()> {
+    let mut payload = serde_json::to_string(message)?;
+    payload.push('\n');
+    writer.write_all(payload.as_bytes()).await?;
+    Ok(())
+}
+
+async fn serve_request(
+    request: DaemonRequest,
+    registry: &mut WorkspaceRegistry,
+    output: &mut Vec<u8>,
+) -> Result<()> {
+    let wrapper = CommandWrapper::try_parse_from(&request.args)?;
+    let mut command = wrapper.command;
+    command.resolve_paths(&request.cwd);
+    let workspace_path = extract_workspace_path(&command, &request.cwd);
+
+    if command.is_journal_command() {
+        let journal = EditJournal::for_file(&workspace_path)?;
+        return execute_journal_command(command, &journal, wrapper.format, output).await;
+    }
+
+    // Commands carry their cargo configuration, workspaces loaded with another
+    // one are reloaded
+    let analyzer = registry.get_or_load_with(&workspace_path, &wrapper.cargo.into())?;
+    RustAnalyzerish::run_blocking(execute_analyzer_command_with_instance(
+        command,
+        &analyzer,
+        wrapper.format,
+        output,
+    ))
+    .await
+}
+
+/// Run an analyzer command line on the daemon, spawning it if needed
+///
+/// Prints the command output and returns an error if the command failed.
+/// Connection failures, including the daemon closing the connection without
+/// replying, are reported with [`DaemonUnavailable`] so callers can fall back
+/// to running the command in-process.
+pub async fn run_client(socket_path: &Path, args: Vec<String>) -> Result<()> {
+    let stream = connect_or_spawn(socket_path)
+        .await
+        .map_err(DaemonUnavailable)?;
+    let request = DaemonRequest {
+        cwd: std::env::current_dir()?,
+        args,
+        progress: progress::enabled(),
+    };
+
+    let (reader, mut writer) = stream.into_split();
+    let mut payload = serde_json::to_string(&request)?;
+    payload.push('\n');
+    writer.write_all(payload.as_bytes()).await?;
+
+    let mut lines = BufReader::new(reader).lines();
+    let response = loop {
+        let Some(line) = lines.next_line().await? else {
+            // The daemon went away before replying, e.g. it was stopped
+            progress::clear();
+            return Err(
+                DaemonUnavailable(anyhow!("the connection was closed without a response")).into(),
+            );
+        };
+        let message = serde_json::from_str(&line);
+        match message.context("Malformed response from the rustbelt daemon") {
+            Ok(DaemonMessage::Progress { progress }) => progress::draw(&progress),
+            Ok(DaemonMessage::Response(response)) => break response,
+            Err(e) => {
+                progress::clear();
+                return Err(e);
+            }
+        }
+    };
+    progress::clear();
+
+    print!("{}", response.output);
+    match response.error {
+        Some(error) => bail!(error),
+        None => Ok(()),
+    }
+}
+
+/// The daemon could not be reached or started
+#[derive(Debug)]
+pub struct DaemonUnavailable(anyhow::Error);
+
+impl std::fmt::Display for DaemonUnavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "rustbelt daemon unavailable: {:#}", self.0)
+    }
+}
+
+impl std::error::Error for DaemonUnavailable {}
+
+async fn connect_or_spawn(socket_path: &Path) -> Result<UnixStream> {
+    check_socket_path(socket_path)?;
+    let stream = connect_or_spawn_unchecked(socket_path).await?;
+    check_peer(&stream)?;
+    Ok(stream)
+}
+
+async fn connect_or_spawn_unchecked(socket_path: &Path) -> Result<UnixStream> {
+    if let Ok(stream) = UnixStream::connect(socket_path).await {
+        return Ok(stream);
+    }
+
+    spawn_daemon(socket_path)?;
+    let deadline = Instant::now() + SPAWN_TIMEOUT;
+    loop {
+        match UnixStream::connect(socket_path).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) if Instant::now() >= deadline => {
+                return Err(e)
+                    .with_context(|| format!("Failed to connect to {}", socket_path.display()));
+            }
+            Err(_) => sleep(Duration::from_millis(50)).await,
+        }
+    }
+}
+
+/// Start a detached daemon listening on `socket_path`
+///
+/// The daemon gets its own process group so it survives the terminal
+/// signals sent to the client.
+fn spawn_daemon(socket_path: &Path) -> Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    let exe = std::env::current_exe().context("Failed to locate the rustbelt executable")?;
+    std::process::Command::new(exe)
+        .arg("daemon")
+        .arg("--socket")
+        .arg(socket_path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()
+        .context("Failed to spawn the rustbelt daemon")?;
+    Ok(())
+}
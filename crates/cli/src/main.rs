@@ -3,11 +3,20 @@
 //! Command-line interface for rustbelt providing both MCP mcp
 //! functionality and standalone CLI tools.
 
+use std::path::PathBuf;
+use std::time::Duration;
+
 use clap::{Parser, Subcommand};
-use command::{CommandWrapper, execute_analyzer_command, extract_workspace_path};
-use rustbelt_server::VERSION;
+use command::{
+    CargoArgs, CommandWrapper, FlycheckArgs, execute_analyzer_command, extract_workspace_path,
+};
+use daemon::{DaemonConfig, DaemonUnavailable};
+use rustbelt_server::cancellation::ToolTimeouts;
+use rustbelt_server::{ServerConfig, VERSION};
 
 mod command;
+mod daemon;
+mod progress;
 mod repl;
 
 #[derive(Parser)]
@@ -32,14 +41,93 @@ enum Commands {
         /// Port for TCP mode
         #[arg(long, default_value = "3001")]
         port: u16,
+        /// Memory budget in MB for loaded workspaces; least recently used
+        /// workspaces are unloaded when it is exceeded
+        #[arg(long)]
+        memory_budget_mb: Option<u64>,
+        /// Cache workspace metadata in this directory, so restarts skip
+        /// `cargo metadata` and building build scripts for unchanged
+        /// workspaces
+        #[arg(long, env = "RUSTBELT_CACHE_DIR")]
+        cache_dir: Option<PathBuf>,
+        /// Load projects under this manifest's directory from it instead of
+        /// discovering theirs: a Cargo.toml, rust-project.json or `.rs` file
+        #[arg(long)]
+        manifest_path: Option<PathBuf>,
+        /// Stop tool calls querying a workspace after this many seconds
+        #[arg(long, env = "RUSTBELT_TOOL_TIMEOUT")]
+        tool_timeout: Option<u64>,
+        /// Deadline of a single tool, as TOOL=SECONDS, overriding
+        /// --tool-timeout (can be repeated)
+        #[arg(long = "tool-timeout-for", value_parser = parse_tool_timeout)]
+        tool_timeouts: Vec<(String, u64)>,
+        /// How get_diagnostics runs cargo for flycheck diagnostics
+        #[command(flatten)]
+        flycheck: FlycheckArgs,
+        /// Cargo configuration workspaces are loaded with
+        #[command(flatten)]
+        cargo: CargoArgs,
+    },
+    /// Start the language server on stdio
+    Lsp {
+        /// Memory budget in MB for loaded workspaces; least recently used
+        /// workspaces are unloaded when it is exceeded
+        #[arg(long)]
+        memory_budget_mb: Option<u64>,
+        /// Cache workspace metadata in this directory, so restarts skip
+        /// `cargo metadata` and building build scripts for unchanged
+        /// workspaces
+        #[arg(long, env = "RUSTBELT_CACHE_DIR")]
+        cache_dir: Option<PathBuf>,
+        /// Load projects under this manifest's directory from it instead of
+        /// discovering theirs: a Cargo.toml, rust-project.json or `.rs` file
+        #[arg(long)]
+        manifest_path: Option<PathBuf>,
+        /// Cargo configuration workspaces are loaded with
+        #[command(flatten)]
+        cargo: CargoArgs,
     },
     /// Connect to a workspace for interactive queries
     Repl {
         /// Path to the workspace directory
         workspace_path: String,
+        /// Cargo configuration the workspace is loaded with
+        #[command(flatten)]
+        cargo: CargoArgs,
+    },
+    /// Keep analyzers loaded in the background and serve analyzer tasks
+    /// over a Unix socket
+    Daemon {
+        /// Socket to listen on (defaults to $XDG_RUNTIME_DIR/rustbelt.sock)
+        #[arg(long, env = "RUSTBELT_SOCKET")]
+        socket: Option<PathBuf>,
+        /// Exit after this many seconds without requests
+        #[arg(long, env = "RUSTBELT_DAEMON_IDLE_TIMEOUT", default_value = "900")]
+        idle_timeout: u64,
+        /// Memory budget in MB for loaded workspaces; least recently used
+        /// workspaces are unloaded when it is exceeded
+        #[arg(long)]
+        memory_budget_mb: Option<u64>,
+        /// Cache workspace metadata in this directory, so restarts skip
+        /// `cargo metadata` and building build scripts for unchanged
+        /// workspaces
+        #[arg(long, env = "RUSTBELT_CACHE_DIR")]
+        cache_dir: Option<PathBuf>,
     },
     /// Run an analyzer task
-    Analyzer(#[command(flatten)] CommandWrapper),
+    ///
+    /// Tasks run on the rustbelt daemon, which is started on first use and
+    /// keeps workspaces loaded between invocations.
+    Analyzer {
+        /// Run the task in this process instead of on the daemon
+        #[arg(long, global = true)]
+        no_daemon: bool,
+        /// Socket of the daemon (defaults to $XDG_RUNTIME_DIR/rustbelt.sock)
+        #[arg(long, global = true, env = "RUSTBELT_SOCKET")]
+        socket: Option<PathBuf>,
+        #[command(flatten)]
+        wrapper: CommandWrapper,
+    },
 }
 
 #[tokio::main]
@@ -47,35 +135,156 @@ async fn main() -> anyhow::Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Mcp { tcp, host, port } => {
+        Commands::Mcp {
+            tcp,
+            host,
+            port,
+            memory_budget_mb,
+            cache_dir,
+            manifest_path,
+            tool_timeout,
+            tool_timeouts,
+            flycheck,
+            cargo,
+        } => {
+            let config = ServerConfig {
+                memory_budget_mb,
+                cache_dir,
+                manifest_path,
+                cargo_options: cargo.into(),
+                tool_timeouts: ToolTimeouts {
+                    default: tool_timeout.map(Duration::from_secs),
+                    tools: tool_timeouts
+                        .into_iter()
+                        .map(|(tool, secs)| (tool, Duration::from_secs(secs)))
+                        .collect(),
+                },
+                flycheck: flycheck.into(),
+            };
             if tcp {
                 // Run in TCP mode
                 // Only initialize logging for TCP mode
                 tracing_subscriber::fmt::init();
                 let addr = format!("{host}:{port}");
-                rustbelt_server::serve_tcp(addr).await?;
+                rustbelt_server::serve_tcp(addr, config).await?;
             } else {
                 // Run in stdio mode - recommended for MCP clients (default)
                 // No logging as it would interfere with JSON-RPC communication
-                rustbelt_server::serve_stdio().await?;
+                rustbelt_server::serve_stdio(config).await?;
             }
         }
-        Commands::Repl { workspace_path } => {
-            // Initialize logging for debugging
-            tracing_subscriber::fmt::init();
+        Commands::Lsp {
+            memory_budget_mb,
+            cache_dir,
+            manifest_path,
+            cargo,
+        } => {
+            // Log on stderr, stdout carries the LSP messages
+            tracing_subscriber::fmt()
+                .with_writer(std::io::stderr)
+                .init();
 
-            repl::run_repl(&workspace_path).await?;
+            let config = rustbelt_lsp::ServerConfig {
+                memory_budget_mb,
+                cache_dir,
+                manifest_path,
+                cargo_options: cargo.into(),
+            };
+            rustbelt_lsp::serve_stdio(config).await?;
         }
-        Commands::Analyzer(command_wrapper) => {
+        Commands::Repl {
+            workspace_path,
+            cargo,
+        } => {
             // Initialize logging for debugging
             tracing_subscriber::fmt::init();
 
-            let analyzer_command = command_wrapper.command;
+            repl::run_repl(&workspace_path, cargo.into()).await?;
+        }
+        Commands::Daemon {
+            socket,
+            idle_timeout,
+            memory_budget_mb,
+            cache_dir,
+        } => {
+            tracing_subscriber::fmt()
+                .with_writer(std::io::stderr)
+                .init();
+
+            daemon::run_daemon(DaemonConfig {
+                socket_path: socket.unwrap_or_else(daemon::default_socket_path),
+                idle_timeout: Duration::from_secs(idle_timeout),
+                memory_budget_mb,
+                cache_dir,
+            })
+            .await?;
+        }
+        Commands::Analyzer {
+            no_daemon,
+            socket,
+            wrapper,
+        } => {
+            // Initialize logging for debugging, on stderr so it doesn't mix
+            // with JSON output
+            tracing_subscriber::fmt()
+                .with_writer(std::io::stderr)
+                .init();
+
+            if !no_daemon {
+                let socket = socket.unwrap_or_else(daemon::default_socket_path);
+                match daemon::run_client(&socket, analyzer_args()).await {
+                    Err(e) if e.is::<DaemonUnavailable>() => {
+                        tracing::warn!("{e}, running the task in-process");
+                    }
+                    result => return result,
+                }
+            }
+
+            // Resolve paths like the daemon does, so output is the same
+            // either way
+            let cwd = std::env::current_dir()?;
+            let mut analyzer_command = wrapper.command;
+            analyzer_command.resolve_paths(&cwd);
             // For analyzer commands, we need to determine the workspace path
-            let workspace_path = extract_workspace_path(&analyzer_command);
-            execute_analyzer_command(analyzer_command, &workspace_path).await?;
+            let workspace_path = extract_workspace_path(&analyzer_command, &cwd);
+            execute_analyzer_command(
+                analyzer_command,
+                &workspace_path,
+                wrapper.format,
+                wrapper.cargo.into(),
+            )
+            .await?;
         }
     }
 
     Ok(())
 }
+
+/// Arguments following `analyzer` on the command line, minus the ones only
+/// meaningful to the client
+fn analyzer_args() -> Vec<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != "analyzer").skip(1);
+    let mut forwarded = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--no-daemon" => {}
+            "--socket" => {
+                args.next();
+            }
+            _ if arg.starts_with("--socket=") => {}
+            _ => forwarded.push(arg),
+        }
+    }
+    forwarded
+}
+
+/// Parse a `TOOL=SECONDS` tool deadline
+fn parse_tool_timeout(timeout: &str) -> Result<(String, u64), String> {
+    let (tool, secs) = timeout
+        .split_once('=')
+        .ok_or_else(|| format!("expected TOOL=SECONDS, got `{timeout}`"))?;
+    let secs = secs
+        .parse()
+        .map_err(|_| format!("expected a number of seconds, got `{secs}`"))?;
+    Ok((tool.to_string(), secs))
+}
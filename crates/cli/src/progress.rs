@@ -0,0 +1,53 @@
+//! Progress bar for workspace loading
+//!
+//! Loading a workspace takes a while the first time, so the REPL and the
+//! analyzer commands draw the loading progress on stderr. Nothing is drawn
+//! when stderr isn't a terminal, to keep logs and pipes clean.
+
+use std::io::{IsTerminal, Write};
+
+use librustbelt::{ProgressCallback, entities::LoadProgress};
+
+const BAR_WIDTH: usize = 24;
+const MESSAGE_WIDTH: usize = 60;
+
+/// Whether progress should be drawn at all
+pub fn enabled() -> bool {
+    std::io::stderr().is_terminal()
+}
+
+/// Progress callback drawing the bar, if stderr is a terminal
+pub fn progress_bar() -> Option<ProgressCallback> {
+    enabled().then(|| ProgressCallback::new(|progress| draw(&progress)))
+}
+
+/// Redraw the bar for a progress update, or clear it once loading is done
+pub fn draw(progress: &LoadProgress) {
+    // Locking stderr keeps updates from indexing threads from interleaving
+    let mut stderr = std::io::stderr().lock();
+    if *progress == LoadProgress::Ready {
+        let _ = write!(stderr, "\r\x1b[2K");
+    } else {
+        let percent = progress.percent();
+        let filled = (percent / 100.0 * BAR_WIDTH as f64).round() as usize;
+        let mut message = progress.to_string();
+        if let Some((end, _)) = message.char_indices().nth(MESSAGE_WIDTH) {
+            message.truncate(end);
+            message.push('…');
+        }
+        let _ = write!(
+            stderr,
+            "\r\x1b[2K[{}{}] {percent:>3.0}% {message}",
+            "#".repeat(filled.min(BAR_WIDTH)),
+            " ".repeat(BAR_WIDTH.saturating_sub(filled)),
+        );
+    }
+    let _ = stderr.flush();
+}
+
+/// Clear the bar, e.g. when loading failed before reaching `Ready`
+pub fn clear() {
+    if enabled() {
+        draw(&LoadProgress::Ready);
+    }
+}
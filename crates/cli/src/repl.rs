@@ -2,16 +2,24 @@ use std::path::Path;
 
 use anyhow::Result;
 use clap::Parser;
-use librustbelt::{builder::RustAnalyzerishBuilder, entities::CursorCoordinates};
+use librustbelt::{
+    builder::RustAnalyzerishBuilder,
+    entities::{CargoOptions, CursorCoordinates},
+};
 use rustyline::{Config, DefaultEditor};
 
 use crate::command::{CommandWrapper, execute_analyzer_command_with_instance};
+use crate::progress;
 
-pub async fn run_repl(workspace_path: &str) -> Result<()> {
+pub async fn run_repl(workspace_path: &str, cargo_options: CargoOptions) -> Result<()> {
     println!("Connecting to workspace: {}", workspace_path);
 
     // Initialize a standalone analyzer for the workspace
-    let mut analyzer = RustAnalyzerishBuilder::from_file(workspace_path)?.build()?;
+    let analyzer = RustAnalyzerishBuilder::from_file(workspace_path)?
+        .with_cargo_options(cargo_options)
+        .with_progress(progress::progress_bar())
+        .build()
+        .inspect_err(|_| progress::clear())?;
 
     // Configure rustyline with history support
     let config = Config::builder()
@@ -74,10 +82,19 @@ pub async fn run_repl(workspace_path: &str) -> Result<()> {
                     _ => {
                         // Try to parse as an analyzer command using clap
                         match CommandWrapper::try_parse_from(parts) {
+                            Ok(wrapper)
+                                if !CargoOptions::from(wrapper.cargo.clone()).is_default() =>
+                            {
+                                println!(
+                                    "Cargo options can only be set when starting the REPL, e.g. `rustbelt repl <path> --features x`."
+                                );
+                            }
                             Ok(wrapper) => {
                                 match execute_analyzer_command_with_instance(
                                     wrapper.command,
-                                    &mut analyzer,
+                                    &analyzer,
+                                    wrapper.format,
+                                    &mut std::io::stdout(),
                                 )
                                 .await
                                 {
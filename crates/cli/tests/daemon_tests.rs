@@ -0,0 +1,121 @@
+//! Integration tests for the analyzer daemon
+//!
+//! These tests run `rustbelt analyzer` against a private socket, so the
+//! daemon they spawn doesn't interfere with one the user may be running.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::time::{Duration, Instant};
+
+fn workspace_root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent() // crates
+        .unwrap()
+        .parent() // workspace root
+        .unwrap()
+}
+
+fn sample_project() -> PathBuf {
+    workspace_root().join("crates/librustbelt/tests/sample-project")
+}
+
+fn rustbelt_binary() -> PathBuf {
+    let output = Command::new("cargo")
+        .current_dir(workspace_root())
+        .args(["build"])
+        .output()
+        .expect("Failed to build rustbelt");
+    if !output.status.success() {
+        panic!(
+            "Failed to build rustbelt: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let profile = if cfg!(debug_assertions) {
+        "debug"
+    } else {
+        "release"
+    };
+    workspace_root()
+        .join("target")
+        .join(profile)
+        .join("rustbelt")
+}
+
+fn run_analyzer(binary: &Path, socket: &Path, args: &[&str]) -> Output {
+    Command::new(binary)
+        .current_dir(sample_project())
+        .env("RUSTBELT_SOCKET", socket)
+        .env("RUSTBELT_DAEMON_IDLE_TIMEOUT", "5")
+        .arg("analyzer")
+        .args(args)
+        .output()
+        .expect("Failed to run rustbelt analyzer")
+}
+
+#[test]
+fn test_analyzer_commands_run_on_daemon() {
+    let binary = rustbelt_binary();
+    let socket = std::env::temp_dir().join(format!("rustbelt-test-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&socket);
+
+    // Relative paths are resolved against the client's directory
+    let type_hint = ["type-hint", "src/main.rs", "31", "13"];
+    let direct = run_analyzer(
+        &binary,
+        &socket,
+        &[&["--no-daemon"], &type_hint[..]].concat(),
+    );
+    assert!(direct.status.success());
+    assert!(!socket.exists(), "--no-daemon should not spawn the daemon");
+
+    // The first call spawns the daemon, the second reuses the loaded workspace
+    for _ in 0..2 {
+        let output = run_analyzer(&binary, &socket, &type_hint);
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(output.stdout, direct.stdout);
+        assert!(socket.exists());
+    }
+
+    // Failures are reported through the exit status
+    let output = run_analyzer(&binary, &socket, &["type-hint", "missing.rs", "1", "1"]);
+    assert!(!output.status.success());
+
+    // The daemon exits and removes its socket once idle
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while socket.exists() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(200));
+    }
+    assert!(!socket.exists(), "Daemon did not shut down when idle");
+}
+
+#[test]
+fn test_daemon_refuses_symlinked_socket() {
+    let binary = rustbelt_binary();
+    let target = std::env::temp_dir().join(format!("rustbelt-target-{}", std::process::id()));
+    let socket = std::env::temp_dir().join(format!("rustbelt-link-{}.sock", std::process::id()));
+    std::fs::write(&target, "").unwrap();
+    let _ = std::fs::remove_file(&socket);
+    std::os::unix::fs::symlink(&target, &socket).unwrap();
+
+    let output = Command::new(&binary)
+        .args(["daemon", "--socket"])
+        .arg(&socket)
+        .output()
+        .expect("Failed to run rustbelt daemon");
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("symlink"),
+        "Unexpected output: {output:?}"
+    );
+    // The daemon must not have replaced the link's target
+    assert!(target.is_file());
+
+    let _ = std::fs::remove_file(&socket);
+    let _ = std::fs::remove_file(&target);
+}
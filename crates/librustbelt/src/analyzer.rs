@@ -4,9 +4,15 @@
 //! making it easy to get type hints, definitions, and other semantic
 //! information.
 
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashSet};
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock as StdRwLock, RwLockReadGuard as StdRwLockReadGuard};
+use std::time::Instant;
 
 use anyhow::Result;
+use ra_ap_base_db::salsa::Cancelled;
 use ra_ap_hir::ClosureStyle;
 use ra_ap_ide::{
     AdjustmentHints, AdjustmentHintsMode, Analysis, AnalysisHost, CallHierarchyConfig,
@@ -14,23 +20,31 @@ use ra_ap_ide::{
     CompletionItemKind as RaCompletionItemKind, DiagnosticsConfig, DiscriminantHints, FileId,
     FilePosition, FileRange, FileStructureConfig, FindAllRefsConfig, GenericParameterHints,
     GotoDefinitionConfig, GotoImplementationConfig, HoverConfig, HoverDocFormat,
-    InlayFieldsToResolve, InlayHintPosition, InlayHintsConfig, LifetimeElisionHints, LineCol,
-    LineIndex, MonikerResult, RenameConfig, SubstTyLen, TextRange, TextSize,
+    InlayFieldsToResolve, InlayHint, InlayHintPosition, InlayHintsConfig, LifetimeElisionHints,
+    LineCol, LineIndex, MonikerResult, RenameConfig, SubstTyLen, TextRange, TextSize,
 };
 use ra_ap_ide_assists::{AssistConfig, AssistResolveStrategy, assists};
-use ra_ap_ide_db::MiniCore;
 use ra_ap_ide_db::imports::insert_use::{ImportGranularity, InsertUseConfig, PrefixKind};
+use ra_ap_ide_db::source_change::FileSystemEdit as RaFileSystemEdit;
 use ra_ap_ide_db::symbol_index::Query;
 use ra_ap_ide_db::text_edit::TextEditBuilder;
-use tracing::{debug, trace, warn};
+use ra_ap_ide_db::{ChangeWithProcMacros, MiniCore, RootDatabase};
+use tokio::runtime::RuntimeFlavor;
+use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tracing::{debug, info, trace, warn};
 
+use super::config::WorkspaceConfig;
 use super::entities::{
-    AssistInfo, AssistSourceChange, CallerInfo, CompletionItem, CursorCoordinates, DefinitionInfo,
-    DiagnosticFix, DiagnosticInfo, FileChange, FileOutlineItem, MacroExpansion, ReferenceInfo,
-    RenameResult, SignatureInfo, SsrMatch, SsrResult, SymbolAnalysis, SymbolSearchResult, TextEdit,
-    TypeHint,
+    AssistInfo, AssistSourceChange, CallerInfo, CargoOptions, CompletionItem, CursorCoordinates,
+    DefinitionInfo, DiagnosticFix, DiagnosticInfo, DiagnosticSource, DiagnosticsCursor,
+    DiagnosticsOptions, DiffPreview, EditCheckResult, FileChange, FileOutlineItem, FileSystemEdit,
+    FixAllQuery, FixAllResult, InlayHintInfo, MacroExpansion, ReferenceInfo, RenameResult,
+    SignatureInfo, SsrMatch, SsrResult, SymbolAnalysis, SymbolSearchResult, TextEdit, TypeHint,
+    UnfixedDiagnostic, UnfixedReason, WorkspaceDiagnostics, WorkspaceDiagnosticsQuery,
 };
 use super::file_watcher::FileWatcher;
+use super::flycheck::{self, FlycheckConfig};
+use super::journal::EditJournal;
 use super::utils::RustAnalyzerUtils;
 
 /// Main interface to rust-analyzer functionality
@@ -41,19 +55,247 @@ use super::utils::RustAnalyzerUtils;
 /// - Symbol renaming and references
 /// - File watching for automatic updates
 ///
+/// Queries run in parallel on snapshots of the analysis database, so an
+/// analyzer can be shared between tasks, e.g. in an `Arc`. Changes to the
+/// database (file changes picked up by the watcher, overlays) wait for the
+/// running queries to finish and hold off new ones while they are applied.
+///
 /// Use RustAnalyzerishBuilder to create properly configured instances.
 #[derive(Debug)]
 pub struct RustAnalyzerish {
-    host: AnalysisHost,
-    file_watcher: FileWatcher,
+    host: Mutex<AnalysisHost>,
+    file_watcher: StdRwLock<FileWatcher>,
+    /// Held shared by queries and exclusively by changes to the database, so
+    /// changes don't cancel queries half way through
+    gate: RwLock<()>,
+    /// Number of times `cancel_queries` was called
+    cancellations: AtomicU64,
+    journal: EditJournal,
+    /// Cargo options the workspace was loaded with, also used by flycheck
+    cargo_options: CargoOptions,
+    /// Diagnostics options set on the workspace, on top of its
+    /// `rustbelt.toml`
+    diagnostics_options: StdRwLock<DiagnosticsOptions>,
 }
 
 impl RustAnalyzerish {
     /// Create a new RustAnalyzer instance with a loaded workspace
     ///
     /// This is called by RustAnalyzerishBuilder after workspace loading.
-    pub fn new(host: AnalysisHost, file_watcher: FileWatcher) -> Self {
-        Self { host, file_watcher }
+    pub fn new(
+        host: AnalysisHost,
+        file_watcher: FileWatcher,
+        journal: EditJournal,
+        cargo_options: CargoOptions,
+    ) -> Self {
+        Self {
+            host: Mutex::new(host),
+            file_watcher: StdRwLock::new(file_watcher),
+            gate: RwLock::new(()),
+            cancellations: AtomicU64::new(0),
+            journal,
+            cargo_options,
+            diagnostics_options: StdRwLock::new(DiagnosticsOptions::default()),
+        }
+    }
+
+    /// Cargo options the workspace was loaded with
+    pub fn cargo_options(&self) -> &CargoOptions {
+        &self.cargo_options
+    }
+
+    /// Set the diagnostics options of the workspace
+    ///
+    /// They apply on top of the workspace's `rustbelt.toml` and replace the
+    /// options set before.
+    pub fn set_diagnostics_options(&self, options: DiagnosticsOptions) {
+        *self.diagnostics_options.write().unwrap() = options;
+    }
+
+    /// Diagnostics options set with `set_diagnostics_options`
+    pub fn configured_diagnostics_options(&self) -> DiagnosticsOptions {
+        self.diagnostics_options.read().unwrap().clone()
+    }
+
+    /// Diagnostics options in effect for the workspace: its `rustbelt.toml`
+    /// with the options set on the workspace on top
+    ///
+    /// A `rustbelt.toml` that can't be read counts as empty, so diagnostics
+    /// keep working; see `config_error` for why it was ignored.
+    pub fn diagnostics_options(&self) -> DiagnosticsOptions {
+        let config = WorkspaceConfig::load(self.journal.workspace_root()).unwrap_or_else(|e| {
+            warn!("Ignoring the workspace configuration: {e:#}");
+            WorkspaceConfig::default()
+        });
+        config
+            .diagnostics
+            .merged(&self.configured_diagnostics_options())
+    }
+
+    /// Why the workspace's `rustbelt.toml` is ignored, if it can't be read
+    pub fn config_error(&self) -> Option<String> {
+        WorkspaceConfig::load(self.journal.workspace_root())
+            .err()
+            .map(|e| format!("{e:#}"))
+    }
+
+    /// Journal of the edits this analyzer wrote to disk, for undo and redo
+    pub fn edit_journal(&self) -> &EditJournal {
+        &self.journal
+    }
+
+    /// Check whether a file is part of this analyzer's VFS
+    pub fn contains_file(&self, path: &Path) -> bool {
+        FileWatcher::path_to_vfs_path(path)
+            .ok()
+            .and_then(|vfs_path| self.files().vfs().file_id(&vfs_path))
+            .is_some()
+    }
+
+    /// Analyze a file using in-memory contents instead of what is on disk
+    ///
+    /// All queries see the overlay text until `clear_overlay` is called.
+    /// Nothing is written to disk.
+    pub async fn set_overlay(&self, file_path: &str, text: String) -> Result<()> {
+        let _change = self.begin_change().await?;
+        let mut files = self.file_watcher.write().unwrap();
+        files.set_overlay(Path::new(file_path), text, &mut self.host.lock().unwrap())
+    }
+
+    /// Drop the overlay for a file, going back to its on-disk contents
+    ///
+    /// Returns false if the file had no overlay.
+    pub async fn clear_overlay(&self, file_path: &str) -> Result<bool> {
+        let _change = self.begin_change().await?;
+        let mut files = self.file_watcher.write().unwrap();
+        files.clear_overlay(Path::new(file_path), &mut self.host.lock().unwrap())
+    }
+
+    /// Check whether a file currently has an overlay
+    pub fn has_overlay(&self, file_path: &str) -> bool {
+        self.overlay_text(file_path).is_some()
+    }
+
+    /// Paths of all files that currently have an overlay
+    pub fn overlays(&self) -> Vec<String> {
+        self.files().overlay_paths()
+    }
+
+    /// Get the overlay text for a file, if any
+    fn overlay_text(&self, file_path: &str) -> Option<String> {
+        let vfs_path = FileWatcher::path_to_vfs_path(Path::new(file_path)).ok()?;
+        let files = self.files();
+        let (file_id, _) = files.vfs().file_id(&vfs_path)?;
+        files.overlay(file_id).map(str::to_owned)
+    }
+
+    /// Start a query, applying pending file changes first
+    ///
+    /// Changes to the database wait until the returned guard is dropped.
+    async fn begin_query(&self) -> Result<RwLockReadGuard<'_, ()>> {
+        if self.files().has_pending_changes() {
+            drop(self.begin_change().await?);
+        }
+        Ok(self.gate.read().await)
+    }
+
+    /// Start changing the database, once running queries are done
+    ///
+    /// Pending file changes are applied right away. New queries wait until
+    /// the returned guard is dropped.
+    async fn begin_change(&self) -> Result<RwLockWriteGuard<'_, ()>> {
+        let change = self.gate.write().await;
+        self.file_watcher
+            .write()
+            .unwrap()
+            .drain_and_apply_changes(&mut self.host.lock().unwrap())?;
+        Ok(change)
+    }
+
+    /// Cancel the queries running on this analyzer
+    ///
+    /// Salsa cancels all the snapshots of a database at once, so every
+    /// running query fails, not just one; compare `cancellations` before and
+    /// after a query to tell whether it failed because of a cancellation.
+    /// Blocks until the running queries are done. Queries started afterwards
+    /// run normally.
+    ///
+    /// Salsa needs the host exclusively while it waits for the snapshots to
+    /// be dropped, so callers wanting the host wait too: call this from a
+    /// blocking thread, and run queries with `run_blocking`.
+    pub fn cancel_queries(&self) {
+        self.cancellations.fetch_add(1, Ordering::SeqCst);
+        let mut host = self.host.lock().unwrap();
+        host.request_cancellation();
+    }
+
+    /// Run a query without blocking the async runtime's worker threads
+    ///
+    /// Salsa computes queries synchronously, and taking a snapshot waits
+    /// while `cancel_queries` holds the host. On a multi-threaded runtime the
+    /// query runs in a blocking section (`block_in_place`, as queries borrow
+    /// the analyzer), so tasks such as reading requests and delivering
+    /// cancellations keep running. On a current-thread runtime it runs in
+    /// place.
+    pub async fn run_blocking<T>(query: impl Future<Output = T>) -> T {
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+                tokio::task::block_in_place(|| handle.block_on(query))
+            }
+            _ => query.await,
+        }
+    }
+
+    /// Number of times the queries running on this analyzer were cancelled
+    pub fn cancellations(&self) -> u64 {
+        self.cancellations.load(Ordering::SeqCst)
+    }
+
+    /// Snapshot of the analysis database
+    ///
+    /// The host must not be locked again while holding a snapshot, as
+    /// `cancel_queries` waits for snapshots to be dropped with the lock held.
+    fn analysis(&self) -> Analysis {
+        self.host.lock().unwrap().analysis()
+    }
+
+    /// Snapshots of the analysis and raw databases, for APIs not exposed by
+    /// `Analysis`
+    fn databases(&self) -> (Analysis, RootDatabase) {
+        let host = self.host.lock().unwrap();
+        (host.analysis(), host.raw_database().clone())
+    }
+
+    /// Run code using the raw database, where salsa signals cancellation by
+    /// unwinding, turning cancellation into an error
+    fn catch_cancelled<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+        Cancelled::catch(AssertUnwindSafe(f))
+            .unwrap_or_else(|cancelled| Err(anyhow::anyhow!("Query cancelled: {cancelled}")))
+    }
+
+    /// The VFS and overlays
+    ///
+    /// Don't hold on to the guard across `.await`s.
+    fn files(&self) -> StdRwLockReadGuard<'_, FileWatcher> {
+        self.file_watcher.read().unwrap()
+    }
+
+    /// Get the VFS file ID of a path
+    fn file_id(&self, path: &Path) -> Result<FileId> {
+        self.files().get_file_id(path)
+    }
+
+    /// Get the path of a VFS file ID
+    fn file_path(&self, file_id: FileId) -> Option<String> {
+        self.files().file_path(file_id)
+    }
+
+    /// Read the current source of a file, preferring its overlay over disk
+    fn read_source(&self, file_path: &str) -> std::io::Result<String> {
+        match self.overlay_text(file_path) {
+            Some(text) => Ok(text),
+            None => std::fs::read_to_string(file_path),
+        }
     }
 
     /// Debug information about the current cursor position
@@ -147,23 +389,20 @@ impl RustAnalyzerish {
 
     /// Common setup for cursor-based operations
     ///
-    /// Prepares analysis, validates cursor, and returns common data
+    /// Prepares analysis, validates cursor, and returns common data. Callers
+    /// must have started a query with `begin_query`.
     async fn setup_cursor_analysis(
-        &mut self,
+        &self,
         raw_cursor: &CursorCoordinates,
     ) -> Result<(Analysis, FileId, TextSize, CursorCoordinates)> {
-        // Ensure file watcher changes are applied
-        self.file_watcher.drain_and_apply_changes(&mut self.host)?;
-
-        let analysis = self.host.analysis();
-        let file_id = self
-            .file_watcher
-            .get_file_id(&PathBuf::from(&raw_cursor.file_path))?;
+        let analysis = self.analysis();
+        let file_id = self.file_id(&PathBuf::from(&raw_cursor.file_path))?;
 
         // Resolve coordinates if a symbol is provided
         let resolved_cursor = if raw_cursor.symbol.is_some() {
             // Get file content for symbol resolution
-            let file_content = std::fs::read_to_string(&raw_cursor.file_path)
+            let file_content = self
+                .read_source(&raw_cursor.file_path)
                 .map_err(|e| anyhow::anyhow!("Failed to read file content: {}", e))?;
             raw_cursor.resolve_coordinates(&file_content)
         } else {
@@ -206,10 +445,8 @@ impl RustAnalyzerish {
     }
 
     /// Get type hint information at the specified cursor position
-    pub async fn get_type_hint(
-        &mut self,
-        raw_cursor: &CursorCoordinates,
-    ) -> Result<Option<TypeHint>> {
+    pub async fn get_type_hint(&self, raw_cursor: &CursorCoordinates) -> Result<Option<TypeHint>> {
+        let _query = self.begin_query().await?;
         let (analysis, file_id, offset, cursor) = self.setup_cursor_analysis(raw_cursor).await?;
 
         // Create TextRange for the hover query - use a single point range
@@ -292,9 +529,10 @@ impl RustAnalyzerish {
 
     /// Get completion suggestions at the specified cursor position
     pub async fn get_completions(
-        &mut self,
+        &self,
         raw_cursor: &CursorCoordinates,
     ) -> Result<Option<Vec<CompletionItem>>> {
+        let _query = self.begin_query().await?;
         let (analysis, file_id, offset, cursor) = self.setup_cursor_analysis(raw_cursor).await?;
 
         debug!(
@@ -406,9 +644,10 @@ impl RustAnalyzerish {
 
     /// Get definition information at the specified cursor position
     pub async fn get_definition(
-        &mut self,
+        &self,
         raw_cursor: &CursorCoordinates,
     ) -> Result<Option<Vec<DefinitionInfo>>> {
+        let _query = self.begin_query().await?;
         let (analysis, file_id, offset, cursor) = self.setup_cursor_analysis(raw_cursor).await?;
 
         debug!(
@@ -450,7 +689,7 @@ impl RustAnalyzerish {
                         let end_line_col = line_index.line_col(nav.focus_or_full_range().end());
 
                         let file_path = {
-                            if let Some(path) = self.file_watcher.file_path(nav.file_id) {
+                            if let Some(path) = self.file_path(nav.file_id) {
                                 path
                             } else {
                                 return Err(anyhow::anyhow!(
@@ -567,26 +806,68 @@ impl RustAnalyzerish {
     /// Rename a symbol at the specified cursor position and apply the changes
     /// to disk
     pub async fn rename_symbol(
-        &mut self,
+        &self,
         raw_cursor: &CursorCoordinates,
         new_name: &str,
     ) -> Result<Option<RenameResult>> {
+        // No other edit may write in between computing and applying this one
+        let transaction = self.journal.transaction().await?;
+
         // Get the rename information
         let rename_result = self.get_rename_info(raw_cursor, new_name).await?;
 
         if let Some(ref result) = rename_result {
-            // Apply the edits to disk
-            RustAnalyzerUtils::apply_rename_edits(result).await?;
+            // Apply the edits to disk and record them for undo
+            transaction
+                .apply(
+                    format!("Rename to `{new_name}`"),
+                    &result.file_changes,
+                    &result.file_system_edits,
+                )
+                .await?;
         }
 
         Ok(rename_result)
     }
 
+    /// Preview a rename as a unified diff without applying it
+    pub async fn preview_rename(
+        &self,
+        raw_cursor: &CursorCoordinates,
+        new_name: &str,
+    ) -> Result<Option<DiffPreview>> {
+        match self.get_rename_info(raw_cursor, new_name).await? {
+            Some(result) => Ok(Some(
+                self.preview_edits(&result.file_changes, &result.file_system_edits)
+                    .await?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Render edits as a unified diff against the files on disk
+    ///
+    /// Paths in the patch are relative to the workspace root. Fails, like
+    /// applying would, if a file changed since the edits were computed.
+    pub async fn preview_edits(
+        &self,
+        file_changes: &[FileChange],
+        file_system_edits: &[FileSystemEdit],
+    ) -> Result<DiffPreview> {
+        RustAnalyzerUtils::diff_source_change(
+            file_changes,
+            file_system_edits,
+            self.journal.workspace_root(),
+        )
+        .await
+    }
+
     /// Find all references to a symbol at the specified cursor position
     pub async fn find_references(
-        &mut self,
+        &self,
         raw_cursor: &CursorCoordinates,
     ) -> Result<Option<Vec<ReferenceInfo>>> {
+        let _query = self.begin_query().await?;
         let (analysis, file_id, offset, cursor) = self.setup_cursor_analysis(raw_cursor).await?;
 
         debug!(
@@ -625,7 +906,7 @@ impl RustAnalyzerish {
                 let start_line_col = decl_line_index.line_col(decl_range.start());
                 let end_line_col = decl_line_index.line_col(decl_range.end());
 
-                if let Some(decl_file_path) = self.file_watcher.file_path(declaration.nav.file_id) {
+                if let Some(decl_file_path) = self.file_path(declaration.nav.file_id) {
                     // Get the line content containing the declaration
                     let content = if let Ok(file_text) = analysis.file_text(declaration.nav.file_id)
                     {
@@ -650,7 +931,7 @@ impl RustAnalyzerish {
             // Process all references grouped by file
             for (ref_file_id, ref_ranges) in search_result.references {
                 if let Ok(ref_line_index) = analysis.file_line_index(ref_file_id)
-                    && let Some(ref_file_path) = self.file_watcher.file_path(ref_file_id)
+                    && let Some(ref_file_path) = self.file_path(ref_file_id)
                 {
                     // Get file text once for this file
                     if let Ok(file_text) = analysis.file_text(ref_file_id) {
@@ -703,12 +984,21 @@ impl RustAnalyzerish {
         RustAnalyzerUtils::get_line_content(file_text, line_number).unwrap_or_default()
     }
 
+    /// Content hash of the text edits for a file are computed against
+    fn source_hash(analysis: &Analysis, file_id: FileId) -> Option<String> {
+        analysis
+            .file_text(file_id)
+            .ok()
+            .map(|text| RustAnalyzerUtils::content_hash(&text))
+    }
+
     /// Get rename information without applying changes to disk
     pub async fn get_rename_info(
-        &mut self,
+        &self,
         raw_cursor: &CursorCoordinates,
         new_name: &str,
     ) -> Result<Option<RenameResult>> {
+        let _query = self.begin_query().await?;
         let (analysis, file_id, offset, cursor) = self.setup_cursor_analysis(raw_cursor).await?;
 
         debug!(
@@ -760,12 +1050,13 @@ impl RustAnalyzerish {
         };
 
         // Convert SourceChange to our RenameResult format
+        let file_system_edits = self.convert_file_system_edits(&source_change.file_system_edits)?;
         let mut file_changes = Vec::new();
 
         for (file_id, edit_tuple) in source_change.source_file_edits {
             // Get file path from file_id
             let file_path = {
-                if let Some(path) = self.file_watcher.file_path(file_id) {
+                if let Some(path) = self.file_path(file_id) {
                     path
                 } else {
                     return Err(anyhow::anyhow!("File ID {:?} not found in VFS", file_id));
@@ -794,7 +1085,11 @@ impl RustAnalyzerish {
                 });
             }
 
-            file_changes.push(FileChange { file_path, edits });
+            file_changes.push(FileChange {
+                file_path,
+                edits,
+                source_hash: Self::source_hash(&analysis, file_id),
+            });
         }
 
         debug!(
@@ -802,31 +1097,54 @@ impl RustAnalyzerish {
             file_changes.len()
         );
 
-        Ok(Some(RenameResult { file_changes }))
+        Ok(Some(RenameResult {
+            file_changes,
+            file_system_edits,
+        }))
     }
 
-    /// View a Rust file with inlay hints
-    pub async fn view_inlay_hints(
-        &mut self,
-        file_path: &str,
-        start_line: Option<u32>,
-        end_line: Option<u32>,
-    ) -> Result<String> {
-        let path = PathBuf::from(file_path);
-
-        // Ensure file watcher changes are applied
-        self.file_watcher.drain_and_apply_changes(&mut self.host)?;
-
-        let analysis = self.host.analysis();
-        let file_id = self.file_watcher.get_file_id(&path)?;
+    /// Convert rust-analyzer file system edits to absolute-path edits
+    fn convert_file_system_edits(
+        &self,
+        fs_edits: &[RaFileSystemEdit],
+    ) -> Result<Vec<FileSystemEdit>> {
+        let files = self.files();
+        let resolve = |path: &ra_ap_vfs::AnchoredPathBuf| {
+            files
+                .resolve_anchored_path(path)
+                .ok_or_else(|| anyhow::anyhow!("Failed to resolve path: {}", path.path))
+        };
+        let file_path = |file_id: FileId| {
+            files
+                .file_path(file_id)
+                .ok_or_else(|| anyhow::anyhow!("File ID {:?} not found in VFS", file_id))
+        };
 
-        // Get the file content
-        let file_content = analysis
-            .file_text(file_id)
-            .map_err(|_| anyhow::anyhow!("Failed to get file content for: {}", file_path))?;
+        fs_edits
+            .iter()
+            .map(|fs_edit| match fs_edit {
+                RaFileSystemEdit::CreateFile {
+                    dst,
+                    initial_contents,
+                } => Ok(FileSystemEdit::CreateFile {
+                    path: resolve(dst)?,
+                    initial_contents: initial_contents.clone(),
+                }),
+                RaFileSystemEdit::MoveFile { src, dst } => Ok(FileSystemEdit::MoveFile {
+                    src: file_path(*src)?,
+                    dst: resolve(dst)?,
+                }),
+                RaFileSystemEdit::MoveDir { src, dst, .. } => Ok(FileSystemEdit::MoveDir {
+                    src: resolve(src)?,
+                    dst: resolve(dst)?,
+                }),
+            })
+            .collect()
+    }
 
-        // Configure inlay hints to show type information
-        let inlay_config = InlayHintsConfig {
+    /// Inlay hints configuration: type and parameter name hints only
+    fn inlay_hints_config() -> InlayHintsConfig<'static> {
+        InlayHintsConfig {
             render_colons: false,
             type_hints: true,
             sized_bound: false,
@@ -866,11 +1184,80 @@ impl RustAnalyzerish {
                 resolve_label_command: false,
             },
             minicore: MiniCore::default(),
-        };
+        }
+    }
+
+    /// Text an inlay hint inserts into the source, and where it goes
+    fn inlay_hint_insertion(hint: &InlayHint) -> (TextSize, String) {
+        let hint_text = hint
+            .label
+            .parts
+            .iter()
+            .map(|part| part.text.as_str())
+            .collect::<Vec<_>>()
+            .join("");
+
+        match hint.position {
+            InlayHintPosition::After => (hint.range.end(), format!(": {}", hint_text)),
+            InlayHintPosition::Before => (hint.range.start(), format!("{}: ", hint_text)),
+        }
+    }
+
+    /// Get the inlay hints of a file, positioned where they are rendered
+    pub async fn get_inlay_hints(&self, file_path: &str) -> Result<Vec<InlayHintInfo>> {
+        let path = PathBuf::from(file_path);
+
+        // Apply file watcher changes and start the query
+        let _query = self.begin_query().await?;
+
+        let analysis = self.analysis();
+        let file_id = self.file_id(&path)?;
+
+        let line_index = analysis
+            .file_line_index(file_id)
+            .map_err(|_| anyhow::anyhow!("Failed to get line index for file: {}", file_path))?;
+        let inlay_hints = analysis
+            .inlay_hints(&Self::inlay_hints_config(), file_id, None)
+            .map_err(|_| anyhow::anyhow!("Failed to get inlay hints for file: {}", file_path))?;
+
+        Ok(inlay_hints
+            .iter()
+            .map(|hint| {
+                let (offset, label) = Self::inlay_hint_insertion(hint);
+                let line_col = line_index.line_col(offset);
+                InlayHintInfo {
+                    line: line_col.line + 1,
+                    column: line_col.col + 1,
+                    label,
+                    kind: format!("{:?}", hint.kind),
+                }
+            })
+            .collect())
+    }
+
+    /// View a Rust file with inlay hints
+    pub async fn view_inlay_hints(
+        &self,
+        file_path: &str,
+        start_line: Option<u32>,
+        end_line: Option<u32>,
+    ) -> Result<String> {
+        let path = PathBuf::from(file_path);
+
+        // Apply file watcher changes and start the query
+        let _query = self.begin_query().await?;
+
+        let analysis = self.analysis();
+        let file_id = self.file_id(&path)?;
+
+        // Get the file content
+        let file_content = analysis
+            .file_text(file_id)
+            .map_err(|_| anyhow::anyhow!("Failed to get file content for: {}", file_path))?;
 
         // Get inlay hints for the entire file
         let inlay_hints = analysis
-            .inlay_hints(&inlay_config, file_id, None)
+            .inlay_hints(&Self::inlay_hints_config(), file_id, None)
             .map_err(|_| anyhow::anyhow!("Failed to get inlay hints for file: {}", file_path))?;
 
         debug!(
@@ -884,18 +1271,7 @@ impl RustAnalyzerish {
 
         for hint in inlay_hints {
             // Create the type annotation text
-            let hint_text = hint
-                .label
-                .parts
-                .iter()
-                .map(|part| part.text.as_str())
-                .collect::<Vec<_>>()
-                .join("");
-
-            let (offset, full_hint_text) = match hint.position {
-                InlayHintPosition::After => (hint.range.end(), format!(": {}", hint_text)),
-                InlayHintPosition::Before => (hint.range.start(), format!("{}: ", hint_text)),
-            };
+            let (offset, full_hint_text) = Self::inlay_hint_insertion(&hint);
 
             trace!("Inlay hint at offset {:?}: {:?}", offset, hint);
 
@@ -927,19 +1303,18 @@ impl RustAnalyzerish {
 
     /// Get available code assists at the specified cursor position
     pub async fn get_assists(
-        &mut self,
+        &self,
         raw_cursor: &CursorCoordinates,
     ) -> Result<Option<Vec<AssistInfo>>> {
-        let cursor = raw_cursor.resolve_coordinates(
-            &std::fs::read_to_string(&raw_cursor.file_path).unwrap_or_default(),
-        );
+        let cursor = raw_cursor
+            .resolve_coordinates(&self.read_source(&raw_cursor.file_path).unwrap_or_default());
 
-        self.file_watcher.drain_and_apply_changes(&mut self.host)?;
+        let _query = self.begin_query().await?;
 
         let path = PathBuf::from(&cursor.file_path);
-        let file_id = self.file_watcher.get_file_id(&path)?;
+        let file_id = self.file_id(&path)?;
 
-        let analysis = self.host.analysis();
+        let (analysis, db) = self.databases();
 
         // Convert 1-based line/column to 0-based for rust-analyzer
         let line_col = LineCol {
@@ -985,12 +1360,14 @@ impl RustAnalyzerish {
         };
 
         // Get available assists
-        let assists_result = assists(
-            self.host.raw_database(),
-            &assist_config,
-            AssistResolveStrategy::None,
-            file_range,
-        );
+        let assists_result = Self::catch_cancelled(|| {
+            Ok(assists(
+                &db,
+                &assist_config,
+                AssistResolveStrategy::None,
+                file_range,
+            ))
+        })?;
 
         if assists_result.is_empty() {
             Ok(None)
@@ -1016,20 +1393,71 @@ impl RustAnalyzerish {
 
     /// Apply a specific code assist at the specified cursor position
     pub async fn apply_assist(
-        &mut self,
+        &self,
         raw_cursor: &CursorCoordinates,
         assist_id: &str,
     ) -> Result<Option<AssistSourceChange>> {
-        let cursor = raw_cursor.resolve_coordinates(
-            &std::fs::read_to_string(&raw_cursor.file_path).unwrap_or_default(),
-        );
+        let transaction = self.journal.transaction().await?;
+        let Some((label, assist_source_change)) =
+            self.resolve_assist(raw_cursor, assist_id).await?
+        else {
+            return Ok(None);
+        };
+
+        // Apply the changes to disk and record them for undo
+        transaction
+            .apply(
+                format!("Assist: {label}"),
+                &assist_source_change.file_changes,
+                &assist_source_change.file_system_edits,
+            )
+            .await?;
+
+        Ok(Some(assist_source_change))
+    }
+
+    /// Preview a code assist as a unified diff without applying it
+    pub async fn preview_assist(
+        &self,
+        raw_cursor: &CursorCoordinates,
+        assist_id: &str,
+    ) -> Result<Option<DiffPreview>> {
+        match self.resolve_assist(raw_cursor, assist_id).await? {
+            Some((_, change)) => Ok(Some(
+                self.preview_edits(&change.file_changes, &change.file_system_edits)
+                    .await?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Compute the source change of a code assist without applying it
+    pub async fn get_assist_source_change(
+        &self,
+        raw_cursor: &CursorCoordinates,
+        assist_id: &str,
+    ) -> Result<Option<AssistSourceChange>> {
+        Ok(self
+            .resolve_assist(raw_cursor, assist_id)
+            .await?
+            .map(|(_, change)| change))
+    }
 
-        self.file_watcher.drain_and_apply_changes(&mut self.host)?;
+    /// Compute the source change of an assist, returning it with its label
+    async fn resolve_assist(
+        &self,
+        raw_cursor: &CursorCoordinates,
+        assist_id: &str,
+    ) -> Result<Option<(String, AssistSourceChange)>> {
+        let cursor = raw_cursor
+            .resolve_coordinates(&self.read_source(&raw_cursor.file_path).unwrap_or_default());
+
+        let _query = self.begin_query().await?;
 
         let path = PathBuf::from(&cursor.file_path);
-        let file_id = self.file_watcher.get_file_id(&path)?;
+        let file_id = self.file_id(&path)?;
 
-        let analysis = self.host.analysis();
+        let (analysis, db) = self.databases();
 
         // Convert 1-based line/column to 0-based for rust-analyzer
         let line_col = LineCol {
@@ -1075,12 +1503,14 @@ impl RustAnalyzerish {
         };
 
         // Get available assists with resolved source changes
-        let assists_result = assists(
-            self.host.raw_database(),
-            &assist_config,
-            AssistResolveStrategy::All,
-            file_range,
-        );
+        let assists_result = Self::catch_cancelled(|| {
+            Ok(assists(
+                &db,
+                &assist_config,
+                AssistResolveStrategy::All,
+                file_range,
+            ))
+        })?;
 
         // Find the specific assist by ID
         let target_assist = assists_result
@@ -1090,19 +1520,22 @@ impl RustAnalyzerish {
         if let Some(assist) = target_assist {
             if let Some(source_change) = assist.source_change {
                 // Convert rust-analyzer source change to our format
-                let file_changes = source_change
+                let file_system_edits =
+                    self.convert_file_system_edits(&source_change.file_system_edits)?;
+                let file_changes: Vec<_> = source_change
                     .source_file_edits
                     .into_iter()
                     .map(|(file_id, (text_edit, _snippet_edit))| {
-                        let file_path = self
-                            .file_watcher
-                            .file_path(file_id)
-                            .unwrap_or_else(|| "unknown".to_string());
+                        let file_path = self.file_path(file_id).ok_or_else(|| {
+                            anyhow::anyhow!("File ID {:?} not found in VFS", file_id)
+                        })?;
+                        let line_index = analysis.file_line_index(file_id).map_err(|_| {
+                            anyhow::anyhow!("Failed to get line index for file {:?}", file_id)
+                        })?;
 
                         let edits = text_edit
                             .into_iter()
                             .map(|indel| {
-                                let line_index = analysis.file_line_index(file_id).unwrap();
                                 let start_line_col = line_index.line_col(indel.delete.start());
                                 let end_line_col = line_index.line_col(indel.delete.end());
 
@@ -1116,21 +1549,21 @@ impl RustAnalyzerish {
                             })
                             .collect();
 
-                        FileChange { file_path, edits }
+                        Ok(FileChange {
+                            file_path,
+                            edits,
+                            source_hash: Self::source_hash(&analysis, file_id),
+                        })
                     })
-                    .collect();
-
-                // Apply the changes to disk
-                for file_change in &file_changes {
-                    RustAnalyzerUtils::apply_file_change(file_change).await?;
-                }
+                    .collect::<Result<_>>()?;
 
                 let assist_source_change = AssistSourceChange {
                     file_changes,
+                    file_system_edits,
                     is_snippet: source_change.is_snippet,
                 };
 
-                Ok(Some(assist_source_change))
+                Ok(Some((assist.label.to_string(), assist_source_change)))
             } else {
                 Err(anyhow::anyhow!("Assist has no source change available"))
             }
@@ -1142,14 +1575,275 @@ impl RustAnalyzerish {
     // --- New agent-native tools ---
 
     /// Get diagnostics for a file, including quick-fixes
-    pub async fn get_diagnostics(&mut self, file_path: &str) -> Result<Vec<DiagnosticInfo>> {
+    pub async fn get_diagnostics(&self, file_path: &str) -> Result<Vec<DiagnosticInfo>> {
+        self.get_diagnostics_with_options(file_path, &DiagnosticsOptions::default())
+            .await
+    }
+
+    /// Get diagnostics for a file, with options of this call on top of the
+    /// workspace's
+    pub async fn get_diagnostics_with_options(
+        &self,
+        file_path: &str,
+        options: &DiagnosticsOptions,
+    ) -> Result<Vec<DiagnosticInfo>> {
+        let options = self.diagnostics_options().merged(options);
+        self.file_diagnostics(file_path, &options).await
+    }
+
+    /// Diagnostics of a file with the given effective options
+    async fn file_diagnostics(
+        &self,
+        file_path: &str,
+        options: &DiagnosticsOptions,
+    ) -> Result<Vec<DiagnosticInfo>> {
         let path = PathBuf::from(file_path);
 
-        self.file_watcher.drain_and_apply_changes(&mut self.host)?;
+        let _query = self.begin_query().await?;
+
+        let analysis = self.analysis();
+        let file_id = self.file_id(&path)?;
+
+        self.collect_diagnostics(&analysis, file_id, file_path, options, true)
+    }
+
+    /// Get diagnostics for a file, merged with those of a flycheck run
+    ///
+    /// Runs `cargo check`, `cargo clippy` or the configured command for the
+    /// package of the file, with the cargo options the workspace was loaded
+    /// with, alongside the native diagnostics. Compiler messages that
+    /// rust-analyzer already reports are left out. Flycheck sees the files on
+    /// disk, not overlays. Disabled codes are left out of both.
+    pub async fn get_diagnostics_with_flycheck(
+        &self,
+        file_path: &str,
+        config: &FlycheckConfig,
+        options: &DiagnosticsOptions,
+    ) -> Result<Vec<DiagnosticInfo>> {
+        let path = Path::new(file_path);
+        let options = self.diagnostics_options().merged(options);
+        let (native, checked) = tokio::join!(
+            self.file_diagnostics(file_path, &options),
+            flycheck::check(config, path, &self.cargo_options)
+        );
+        let native = native?;
+        let canonical = path.canonicalize()?;
+        let checked = checked?
+            .into_iter()
+            .filter(|d| Path::new(&d.file_path) == canonical && options.is_enabled(&d.code))
+            .map(|mut d| {
+                d.file_path = file_path.to_string();
+                d
+            })
+            .collect();
+        Ok(flycheck::merge(native, checked))
+    }
+
+    /// Get the diagnostics of every file of the workspace's local crates
+    ///
+    /// Files are checked in path order, from the query's cursor on, and the
+    /// diagnostics matching the query's filters are returned one page at a
+    /// time, see `WorkspaceDiagnosticsQuery`. The cursor of the next page
+    /// points into the files, so pages line up even when a deadline stops
+    /// the sweeps at different files.
+    pub async fn get_workspace_diagnostics(
+        &self,
+        query: &WorkspaceDiagnosticsQuery,
+    ) -> Result<WorkspaceDiagnostics> {
+        let options = self.diagnostics_options().merged(&query.options);
+        let changed = match &query.changed_since {
+            Some(git_ref) => Some(RustAnalyzerUtils::changed_files(
+                self.journal.workspace_root(),
+                git_ref,
+            )?),
+            None => None,
+        };
+
+        let _query = self.begin_query().await?;
+        let (analysis, db) = self.databases();
+
+        let files = self.workspace_files(&analysis, &db, query, changed.as_ref())?;
+        let cursor = query.cursor.as_ref();
+        let start = cursor.map_or(0, |cursor| {
+            files.partition_point(|(_, path)| *path < cursor.file_path)
+        });
+        let files = &files[start..];
+        debug!("Checking {} workspace files for diagnostics", files.len());
+
+        let severities: Vec<String> = query.severities.iter().map(|s| s.to_lowercase()).collect();
+        // Matching diagnostics with their index among the file's
+        let mut matching: Vec<(usize, DiagnosticInfo)> = Vec::new();
+        let mut checked_files = 0;
+        let expired = || {
+            query
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+        };
+        for (file_id, file_path) in files {
+            if expired() {
+                break;
+            }
+            let diagnostics = match self.collect_diagnostics(
+                &analysis,
+                *file_id,
+                file_path,
+                &options,
+                query.include_fixes,
+            ) {
+                Ok(diagnostics) => diagnostics,
+                // The query is cancelled once its deadline passed
+                Err(_) if expired() => break,
+                Err(e) => return Err(e),
+            };
+            checked_files += 1;
+            let skip = cursor
+                .filter(|cursor| cursor.file_path == *file_path)
+                .map_or(0, |cursor| cursor.index);
+            matching.extend(
+                diagnostics
+                    .into_iter()
+                    .filter(|d| {
+                        (severities.is_empty() || severities.contains(&d.severity.to_lowercase()))
+                            && (query.codes.is_empty() || query.codes.contains(&d.code))
+                    })
+                    .enumerate()
+                    .skip(skip),
+            );
+        }
 
-        let analysis = self.host.analysis();
-        let file_id = self.file_watcher.get_file_id(&path)?;
+        let mut severity_counts: BTreeMap<String, usize> = BTreeMap::new();
+        for (_, d) in &matching {
+            *severity_counts.entry(d.severity.clone()).or_default() += 1;
+        }
+        let total = matching.len();
+        let mut rest = matching.into_iter();
+        let diagnostics: Vec<_> = rest.by_ref().take(query.limit).map(|(_, d)| d).collect();
+        let next_cursor = match rest.next() {
+            // The page is full, resume at the next diagnostic
+            Some((index, d)) => Some(DiagnosticsCursor {
+                file_path: d.file_path,
+                index,
+            }),
+            // The deadline passed, resume at the first unchecked file
+            None => files
+                .get(checked_files)
+                .map(|(_, file_path)| DiagnosticsCursor {
+                    file_path: file_path.clone(),
+                    index: 0,
+                }),
+        };
 
+        if checked_files < files.len() {
+            info!(
+                "Workspace diagnostics stopped at the deadline after {checked_files} of {} files",
+                files.len()
+            );
+        }
+        Ok(WorkspaceDiagnostics {
+            checked_files,
+            unchecked_files: files.len() - checked_files,
+            total,
+            severity_counts,
+            diagnostics,
+            next_cursor,
+        })
+    }
+
+    /// Files of the workspace's local crates passing the file filters of a
+    /// diagnostics query, sorted by path
+    fn workspace_files(
+        &self,
+        analysis: &Analysis,
+        db: &RootDatabase,
+        query: &WorkspaceDiagnosticsQuery,
+        changed: Option<&HashSet<PathBuf>>,
+    ) -> Result<Vec<(FileId, String)>> {
+        let root = self.journal.workspace_root();
+        let candidates: Vec<(FileId, String)> = self
+            .files()
+            .vfs()
+            .iter()
+            .filter_map(|(file_id, path)| {
+                let path = path.as_path()?;
+                (path.extension() == Some("rs")).then(|| (file_id, path.to_string()))
+            })
+            .collect();
+
+        // Crate names are compared the way rustc spells them
+        let crate_filter: Vec<String> = query.crates.iter().map(|c| c.replace('-', "_")).collect();
+
+        let mut files = Vec::new();
+        for (file_id, file_path) in candidates {
+            let path = Path::new(&file_path);
+            // Git paths are canonical, VFS paths are as the workspace was
+            // loaded, e.g. through a symlink
+            if let Some(changed) = changed
+                && !changed.contains(path)
+                && !path
+                    .canonicalize()
+                    .is_ok_and(|canonical| changed.contains(&canonical))
+            {
+                continue;
+            }
+            if let Some(glob) = &query.path_glob {
+                let relative = path.strip_prefix(root).unwrap_or(path);
+                let glob_path = if Path::new(glob).is_absolute() {
+                    path
+                } else {
+                    relative
+                };
+                if !RustAnalyzerUtils::glob_matches(glob, glob_path) {
+                    continue;
+                }
+            }
+
+            let crates = analysis
+                .crates_for(file_id)
+                .map_err(|_| anyhow::anyhow!("Failed to get crates of file: {}", file_path))?;
+            let local_crates = Self::catch_cancelled(|| {
+                Ok(crates
+                    .iter()
+                    .filter(|krate| krate.data(db).origin.is_local())
+                    .map(|krate| {
+                        krate
+                            .extra_data(db)
+                            .display_name
+                            .as_ref()
+                            .map(|name| name.to_string().replace('-', "_"))
+                    })
+                    .collect::<Vec<_>>())
+            })?;
+            if local_crates.is_empty() {
+                continue;
+            }
+            if !crate_filter.is_empty()
+                && !local_crates
+                    .iter()
+                    .flatten()
+                    .any(|name| crate_filter.contains(name))
+            {
+                continue;
+            }
+            files.push((file_id, file_path));
+        }
+
+        files.sort_by(|a, b| a.1.cmp(&b.1));
+        Ok(files)
+    }
+
+    /// Compute diagnostics for a file on the given analysis snapshot
+    ///
+    /// `options` are the effective options, see `diagnostics_options`.
+    /// Without `with_fixes`, the quick-fixes aren't computed and every
+    /// diagnostic has an empty list of fixes.
+    fn collect_diagnostics(
+        &self,
+        analysis: &Analysis,
+        file_id: FileId,
+        file_path: &str,
+        options: &DiagnosticsOptions,
+        with_fixes: bool,
+    ) -> Result<Vec<DiagnosticInfo>> {
         let line_index = analysis
             .file_line_index(file_id)
             .map_err(|_| anyhow::anyhow!("Failed to get line index for file: {}", file_path))?;
@@ -1158,10 +1852,10 @@ impl RustAnalyzerish {
             enabled: true,
             proc_macros_enabled: true,
             proc_attr_macros_enabled: true,
-            disable_experimental: false,
-            disabled: Default::default(),
+            disable_experimental: !options.experimental.unwrap_or(true),
+            disabled: options.disabled.iter().cloned().collect(),
             expr_fill_default: ra_ap_ide_db::assists::ExprFillDefaultMode::Todo,
-            style_lints: false,
+            style_lints: options.style_lints.unwrap_or(false),
             snippet_cap: None,
             insert_use: InsertUseConfig {
                 granularity: ImportGranularity::Crate,
@@ -1178,8 +1872,13 @@ impl RustAnalyzerish {
             show_rename_conflicts: true,
         };
 
+        let resolve = if with_fixes {
+            AssistResolveStrategy::All
+        } else {
+            AssistResolveStrategy::None
+        };
         let ra_diagnostics = analysis
-            .full_diagnostics(&diagnostics_config, AssistResolveStrategy::All, file_id)
+            .full_diagnostics(&diagnostics_config, resolve, file_id)
             .map_err(|e| anyhow::anyhow!("Failed to get diagnostics: {:?}", e))?;
 
         let mut result = Vec::new();
@@ -1194,47 +1893,7 @@ impl RustAnalyzerish {
                 .fixes
                 .unwrap_or_default()
                 .into_iter()
-                .filter_map(|assist| {
-                    let source_change = assist.source_change?;
-                    let file_changes = source_change
-                        .source_file_edits
-                        .into_iter()
-                        .map(|(fid, (text_edit, _snippet))| {
-                            let fp = self
-                                .file_watcher
-                                .file_path(fid)
-                                .unwrap_or_else(|| "unknown".to_string());
-                            let li = analysis.file_line_index(fid).ok();
-                            let edits = text_edit
-                                .into_iter()
-                                .map(|indel| {
-                                    let (sl, sc, el, ec) = if let Some(ref li) = li {
-                                        let s = li.line_col(indel.delete.start());
-                                        let e = li.line_col(indel.delete.end());
-                                        (s.line + 1, s.col + 1, e.line + 1, e.col + 1)
-                                    } else {
-                                        (0, 0, 0, 0)
-                                    };
-                                    TextEdit {
-                                        line: sl,
-                                        column: sc,
-                                        end_line: el,
-                                        end_column: ec,
-                                        new_text: indel.insert,
-                                    }
-                                })
-                                .collect();
-                            FileChange {
-                                file_path: fp,
-                                edits,
-                            }
-                        })
-                        .collect();
-                    Some(DiagnosticFix {
-                        label: assist.label.to_string(),
-                        file_changes,
-                    })
-                })
+                .filter_map(|assist| self.diagnostic_fix(analysis, assist))
                 .collect();
 
             result.push(DiagnosticInfo {
@@ -1247,17 +1906,444 @@ impl RustAnalyzerish {
                 end_line: end.line + 1,
                 end_column: end.col + 1,
                 fixes,
+                source: DiagnosticSource::RustAnalyzer,
             });
         }
 
         Ok(result)
     }
 
+    /// Convert a fix of a diagnostic to absolute-path edits
+    ///
+    /// A fix whose edits can't be resolved to paths is kept without edits,
+    /// marked unavailable with the reason.
+    fn diagnostic_fix(
+        &self,
+        analysis: &Analysis,
+        assist: ra_ap_ide_db::assists::Assist,
+    ) -> Option<DiagnosticFix> {
+        let label = assist.label.to_string();
+        let source_change = assist.source_change?;
+        let unavailable = |reason: String| {
+            debug!("Fix `{label}` is unavailable: {reason}");
+            Some(DiagnosticFix {
+                label: label.clone(),
+                file_changes: Vec::new(),
+                file_system_edits: Vec::new(),
+                unavailable: Some(reason),
+            })
+        };
+
+        let file_system_edits =
+            match self.convert_file_system_edits(&source_change.file_system_edits) {
+                Ok(edits) => edits,
+                Err(e) => return unavailable(format!("{e:#}")),
+            };
+        let mut file_changes = Vec::new();
+        for (fid, (text_edit, _snippet)) in source_change.source_file_edits {
+            let (Some(file_path), Ok(line_index)) =
+                (self.file_path(fid), analysis.file_line_index(fid))
+            else {
+                return unavailable(format!("File ID {fid:?} not found in VFS"));
+            };
+            let edits = text_edit
+                .into_iter()
+                .map(|indel| {
+                    let start = line_index.line_col(indel.delete.start());
+                    let end = line_index.line_col(indel.delete.end());
+                    TextEdit {
+                        line: start.line + 1,
+                        column: start.col + 1,
+                        end_line: end.line + 1,
+                        end_column: end.col + 1,
+                        new_text: indel.insert,
+                    }
+                })
+                .collect();
+            file_changes.push(FileChange {
+                file_path,
+                edits,
+                source_hash: Self::source_hash(analysis, fid),
+            });
+        }
+        Some(DiagnosticFix {
+            label,
+            file_changes,
+            file_system_edits,
+            unavailable: None,
+        })
+    }
+
+    /// Check proposed edits for new or resolved diagnostics without writing them
+    ///
+    /// The diagnostics before the edits are computed on a snapshot, alongside
+    /// other queries. The edits are then applied to the analysis database in
+    /// memory, diagnostics are computed for every touched file and the
+    /// previous contents are restored afterwards. Nothing is written to disk.
+    ///
+    /// Salsa can't fork a database, so while the edited contents are checked
+    /// the analyzer is held exclusively, like for any other change: queries
+    /// started meanwhile wait for the check to finish, and the changes
+    /// invalidate cached results for the whole workspace.
+    pub async fn check_edits(&self, file_changes: &[FileChange]) -> Result<EditCheckResult> {
+        let options = self.diagnostics_options();
+
+        // Merge edits per file so they all apply to the same original text
+        let mut merged: Vec<FileChange> = Vec::new();
+        for file_change in file_changes {
+            match merged
+                .iter_mut()
+                .find(|fc| fc.file_path == file_change.file_path)
+            {
+                Some(fc) => fc.edits.extend(file_change.edits.iter().cloned()),
+                None => merged.push(file_change.clone()),
+            }
+        }
+
+        // (file_id, current text) of every touched file
+        let read = |analysis: &Analysis| {
+            merged
+                .iter()
+                .map(|file_change| {
+                    let file_id = self.file_id(Path::new(&file_change.file_path))?;
+                    let text = analysis.file_text(file_id).map_err(|_| {
+                        anyhow::anyhow!("Failed to read file text: {}", file_change.file_path)
+                    })?;
+                    Ok((file_id, text.to_string()))
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+        let diagnose = |analysis: &Analysis, files: &[(FileId, String)]| {
+            files
+                .iter()
+                .zip(&merged)
+                .map(|((file_id, _), file_change)| {
+                    self.collect_diagnostics(
+                        analysis,
+                        *file_id,
+                        &file_change.file_path,
+                        &options,
+                        true,
+                    )
+                })
+                .collect::<Result<Vec<_>>>()
+                .map(|diagnostics| diagnostics.into_iter().flatten().collect::<Vec<_>>())
+        };
+
+        let (mut originals, mut before) = {
+            let _query = self.begin_query().await?;
+            let analysis = self.analysis();
+            let originals = read(&analysis)?;
+            let before = diagnose(&analysis, &originals)?;
+            (originals, before)
+        };
+
+        // Queries must not see the edited contents
+        let _change = self.begin_change().await?;
+        {
+            let analysis = self.analysis();
+            let current = read(&analysis)?;
+            if current != originals {
+                // The files changed since the diagnostics were computed
+                before = diagnose(&analysis, &current)?;
+                originals = current;
+            }
+        }
+        let edited = originals
+            .iter()
+            .zip(&merged)
+            .map(|((_, original), file_change)| {
+                let mut edited = original.clone();
+                RustAnalyzerUtils::apply_text_edits(&mut edited, file_change)?;
+                Ok(edited)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // The snapshots above must be dropped before changing the database
+        let mut change = ChangeWithProcMacros::default();
+        for ((file_id, _), edited) in originals.iter().zip(edited) {
+            change.change_file(*file_id, Some(edited));
+        }
+        self.host.lock().unwrap().apply_change(change);
+
+        let after = diagnose(&self.analysis(), &originals);
+
+        // Always restore the previous contents, even if diagnostics failed
+        let mut change = ChangeWithProcMacros::default();
+        for (file_id, original) in &originals {
+            change.change_file(*file_id, Some(original.clone()));
+        }
+        self.host.lock().unwrap().apply_change(change);
+
+        let after = after?;
+
+        // Match diagnostics by content rather than position, since edits
+        // shift line numbers
+        let same = |a: &DiagnosticInfo, b: &DiagnosticInfo| {
+            a.file_path == b.file_path
+                && a.code == b.code
+                && a.severity == b.severity
+                && a.message == b.message
+        };
+        let mut resolved_diagnostics = before;
+        let mut new_diagnostics = Vec::new();
+        for diagnostic in after {
+            match resolved_diagnostics
+                .iter()
+                .position(|d| same(d, &diagnostic))
+            {
+                Some(index) => {
+                    resolved_diagnostics.remove(index);
+                }
+                None => new_diagnostics.push(diagnostic),
+            }
+        }
+
+        Ok(EditCheckResult {
+            checked_files: merged.into_iter().map(|fc| fc.file_path).collect(),
+            new_diagnostics,
+            resolved_diagnostics,
+        })
+    }
+
+    /// Apply one fix of the diagnostic with a code at a position
+    ///
+    /// The diagnostic is computed again on the current contents, so the fix
+    /// can't be stale. `fix` is the 0-based index of the fix among the
+    /// diagnostic's fixes or its label. The fix, file system edits included,
+    /// is written in one transaction recorded in the edit journal. Returns
+    /// the diagnostic with only the applied fix.
+    pub async fn apply_diagnostic_fix(
+        &self,
+        file_path: &str,
+        line: u32,
+        column: u32,
+        code: &str,
+        fix: &str,
+    ) -> Result<DiagnosticInfo> {
+        let transaction = self.journal.transaction().await?;
+        let diagnostic = self
+            .resolve_diagnostic_fix(file_path, line, column, code, fix)
+            .await?;
+        let applied = &diagnostic.fixes[0];
+        transaction
+            .apply(
+                format!("Fix: {}", applied.label),
+                &applied.file_changes,
+                &applied.file_system_edits,
+            )
+            .await?;
+        Ok(diagnostic)
+    }
+
+    /// Preview a diagnostic fix as a unified diff without applying it
+    pub async fn preview_diagnostic_fix(
+        &self,
+        file_path: &str,
+        line: u32,
+        column: u32,
+        code: &str,
+        fix: &str,
+    ) -> Result<DiffPreview> {
+        let diagnostic = self
+            .resolve_diagnostic_fix(file_path, line, column, code, fix)
+            .await?;
+        let fix = &diagnostic.fixes[0];
+        self.preview_edits(&fix.file_changes, &fix.file_system_edits)
+            .await
+    }
+
+    /// Find the diagnostic with a code at a position and keep only the
+    /// chosen fix
+    async fn resolve_diagnostic_fix(
+        &self,
+        file_path: &str,
+        line: u32,
+        column: u32,
+        code: &str,
+        fix: &str,
+    ) -> Result<DiagnosticInfo> {
+        let diagnostics = self.get_diagnostics(file_path).await?;
+        let position = (line, column);
+        let Some(mut diagnostic) = diagnostics.into_iter().find(|d| {
+            d.code == code
+                && (d.line, d.column) <= position
+                && position <= (d.end_line, d.end_column)
+        }) else {
+            anyhow::bail!("No `{code}` diagnostic at {file_path}:{line}:{column}");
+        };
+
+        let index = match fix.parse::<usize>() {
+            Ok(index) => Some(index).filter(|&index| index < diagnostic.fixes.len()),
+            Err(_) => diagnostic
+                .fixes
+                .iter()
+                .position(|f| f.label == fix)
+                .or_else(|| {
+                    diagnostic
+                        .fixes
+                        .iter()
+                        .position(|f| f.label.eq_ignore_ascii_case(fix))
+                }),
+        };
+        let Some(index) = index else {
+            let available: Vec<String> = diagnostic
+                .fixes
+                .iter()
+                .enumerate()
+                .map(|(i, f)| format!("{i}: {}", f.label))
+                .collect();
+            if available.is_empty() {
+                anyhow::bail!(
+                    "The `{code}` diagnostic at {file_path}:{line}:{column} has no fixes"
+                );
+            }
+            anyhow::bail!(
+                "No fix `{fix}` for the `{code}` diagnostic at {file_path}:{line}:{column}; \
+                 available fixes are {}",
+                available.join(", ")
+            );
+        };
+        let chosen = diagnostic.fixes.swap_remove(index);
+        if let Some(reason) = &chosen.unavailable {
+            anyhow::bail!("Fix `{}` can't be applied: {reason}", chosen.label);
+        }
+        diagnostic.fixes = vec![chosen];
+        Ok(diagnostic)
+    }
+
+    /// Apply every safe diagnostic fix in a file or the workspace
+    ///
+    /// A fix is safe when it is the only fix of its diagnostic, only edits
+    /// text and doesn't overlap the fixes taken before it. The fixes are
+    /// written in one transaction, recorded in the edit journal; diagnostics
+    /// left alone are reported with the reason.
+    pub async fn fix_all(&self, query: &FixAllQuery) -> Result<FixAllResult> {
+        let transaction = self.journal.transaction().await?;
+        let result = self.plan_fix_all(query).await?;
+        if !result.file_changes.is_empty() {
+            transaction
+                .apply(
+                    format!("Fix all: {} diagnostic(s)", result.fixed.len()),
+                    &result.file_changes,
+                    &[],
+                )
+                .await?;
+        }
+        Ok(result)
+    }
+
+    /// Preview `fix_all` as a unified diff without applying it
+    pub async fn preview_fix_all(
+        &self,
+        query: &FixAllQuery,
+    ) -> Result<(FixAllResult, DiffPreview)> {
+        let result = self.plan_fix_all(query).await?;
+        let preview = self.preview_edits(&result.file_changes, &[]).await?;
+        Ok((result, preview))
+    }
+
+    /// Pick the fixes `fix_all` applies, merging their edits per file
+    async fn plan_fix_all(&self, query: &FixAllQuery) -> Result<FixAllResult> {
+        let options = self.diagnostics_options();
+
+        let _query = self.begin_query().await?;
+        let (analysis, db) = self.databases();
+
+        let files = match &query.file_path {
+            Some(file_path) => vec![(self.file_id(Path::new(file_path))?, file_path.clone())],
+            None => {
+                self.workspace_files(&analysis, &db, &WorkspaceDiagnosticsQuery::default(), None)?
+            }
+        };
+
+        let mut result = FixAllResult::default();
+        for (file_id, file_path) in &files {
+            let diagnostics =
+                self.collect_diagnostics(&analysis, *file_id, file_path, &options, true)?;
+            for diagnostic in diagnostics {
+                if !query.codes.is_empty() && !query.codes.contains(&diagnostic.code) {
+                    continue;
+                }
+                let reason = match diagnostic.fixes.as_slice() {
+                    [] => Some(UnfixedReason::NoFix),
+                    [fix] if fix.unavailable.is_some() => Some(UnfixedReason::Unavailable),
+                    [fix] if !fix.file_system_edits.is_empty() => {
+                        Some(UnfixedReason::FileSystemEdits)
+                    }
+                    [fix] => (!Self::merge_fix(&mut result.file_changes, fix))
+                        .then_some(UnfixedReason::Conflict),
+                    _ => Some(UnfixedReason::SeveralFixes),
+                };
+                match reason {
+                    Some(reason) => result
+                        .remaining
+                        .push(UnfixedDiagnostic { diagnostic, reason }),
+                    None => result.fixed.push(diagnostic),
+                }
+            }
+        }
+        debug!(
+            "Fixing {} diagnostic(s) in {} file(s)",
+            result.fixed.len(),
+            result.file_changes.len()
+        );
+        Ok(result)
+    }
+
+    /// Add the edits of a fix to `file_changes`, unless one overlaps an edit
+    /// already there
+    ///
+    /// Edits identical to one already there, such as the same import added
+    /// by two fixes, are kept once. Returns whether the fix was added.
+    fn merge_fix(file_changes: &mut Vec<FileChange>, fix: &DiagnosticFix) -> bool {
+        let conflicts = fix.file_changes.iter().any(|change| {
+            file_changes
+                .iter()
+                .filter(|fc| fc.file_path == change.file_path)
+                .flat_map(|fc| &fc.edits)
+                .any(|taken| {
+                    change
+                        .edits
+                        .iter()
+                        .any(|edit| edit != taken && Self::edits_overlap(edit, taken))
+                })
+        });
+        if conflicts {
+            return false;
+        }
+
+        for change in &fix.file_changes {
+            match file_changes
+                .iter_mut()
+                .find(|fc| fc.file_path == change.file_path)
+            {
+                Some(fc) => {
+                    for edit in &change.edits {
+                        if !fc.edits.contains(edit) {
+                            fc.edits.push(edit.clone());
+                        }
+                    }
+                }
+                None => file_changes.push(change.clone()),
+            }
+        }
+        true
+    }
+
+    /// Whether two edits of the same text can't both be applied
+    ///
+    /// Insertions at the same position conflict too, as their order would be
+    /// arbitrary.
+    fn edits_overlap(a: &TextEdit, b: &TextEdit) -> bool {
+        let start = |e: &TextEdit| (e.line, e.column);
+        let end = |e: &TextEdit| (e.end_line, e.end_column);
+        (start(a) < end(b) && start(b) < end(a)) || start(a) == start(b)
+    }
+
     /// Analyze a symbol comprehensively — type, definition, implementations, callers, ref count
-    pub async fn analyze_symbol(
-        &mut self,
-        raw_cursor: &CursorCoordinates,
-    ) -> Result<SymbolAnalysis> {
+    pub async fn analyze_symbol(&self, raw_cursor: &CursorCoordinates) -> Result<SymbolAnalysis> {
+        let _query = self.begin_query().await?;
         let (analysis, file_id, offset, _cursor) = self.setup_cursor_analysis(raw_cursor).await?;
         let position = Self::create_file_position(file_id, offset);
 
@@ -1332,7 +2418,6 @@ impl RustAnalyzerish {
                 .into_iter()
                 .map(|item| {
                     let fp = self
-                        .file_watcher
                         .file_path(item.target.file_id)
                         .unwrap_or_else(|| "unknown".to_string());
                     let (line, col) = analysis
@@ -1359,7 +2444,6 @@ impl RustAnalyzerish {
                 .into_iter()
                 .map(|item| {
                     let fp = self
-                        .file_watcher
                         .file_path(item.target.file_id)
                         .unwrap_or_else(|| "unknown".to_string());
                     let (line, col) = analysis
@@ -1413,7 +2497,7 @@ impl RustAnalyzerish {
     ) -> Vec<DefinitionInfo> {
         let mut result = Vec::new();
         for nav in navs {
-            let Some(file_path) = self.file_watcher.file_path(nav.file_id) else {
+            let Some(file_path) = self.file_path(nav.file_id) else {
                 continue;
             };
             let Ok(line_index) = analysis.file_line_index(nav.file_id) else {
@@ -1457,14 +2541,34 @@ impl RustAnalyzerish {
     }
 
     /// Get the outline/structure of a file
-    pub async fn get_file_outline(&mut self, file_path: &str) -> Result<Vec<FileOutlineItem>> {
+    pub async fn get_file_outline(&self, file_path: &str) -> Result<Vec<FileOutlineItem>> {
         let path = PathBuf::from(file_path);
 
-        self.file_watcher.drain_and_apply_changes(&mut self.host)?;
+        let _query = self.begin_query().await?;
+
+        let analysis = self.analysis();
+        let file_id = self.file_id(&path)?;
 
-        let analysis = self.host.analysis();
-        let file_id = self.file_watcher.get_file_id(&path)?;
+        Self::file_outline(&analysis, file_id, file_path)
+    }
+
+    /// Get the outline/structure of a file without a loaded workspace
+    ///
+    /// The outline only depends on the syntax of the file, so it can be
+    /// computed from the file alone, e.g. while its workspace is loading.
+    pub async fn get_standalone_file_outline(file_path: &str) -> Result<Vec<FileOutlineItem>> {
+        let text = tokio::fs::read_to_string(file_path)
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", file_path, e))?;
+        let (analysis, file_id) = Analysis::from_single_file(text);
+        Self::file_outline(&analysis, file_id, file_path)
+    }
 
+    fn file_outline(
+        analysis: &Analysis,
+        file_id: FileId,
+        file_path: &str,
+    ) -> Result<Vec<FileOutlineItem>> {
         let line_index = analysis
             .file_line_index(file_id)
             .map_err(|_| anyhow::anyhow!("Failed to get line index for file: {}", file_path))?;
@@ -1506,13 +2610,13 @@ impl RustAnalyzerish {
 
     /// Search for symbols across the workspace
     pub async fn search_symbols(
-        &mut self,
+        &self,
         query_str: &str,
         limit: usize,
     ) -> Result<Vec<SymbolSearchResult>> {
-        self.file_watcher.drain_and_apply_changes(&mut self.host)?;
+        let _query = self.begin_query().await?;
 
-        let analysis = self.host.analysis();
+        let analysis = self.analysis();
         let query = Query::new(query_str.to_string());
 
         let nav_targets = analysis
@@ -1522,7 +2626,7 @@ impl RustAnalyzerish {
         let results = nav_targets
             .into_iter()
             .filter_map(|nav| {
-                let file_path = self.file_watcher.file_path(nav.file_id)?;
+                let file_path = self.file_path(nav.file_id)?;
                 let line_index = analysis.file_line_index(nav.file_id).ok()?;
                 let start = line_index.line_col(nav.focus_or_full_range().start());
 
@@ -1545,9 +2649,10 @@ impl RustAnalyzerish {
 
     /// Expand a macro at the given position
     pub async fn expand_macro(
-        &mut self,
+        &self,
         raw_cursor: &CursorCoordinates,
     ) -> Result<Option<MacroExpansion>> {
+        let _query = self.begin_query().await?;
         let (analysis, file_id, offset, _cursor) = self.setup_cursor_analysis(raw_cursor).await?;
         let position = Self::create_file_position(file_id, offset);
 
@@ -1563,9 +2668,10 @@ impl RustAnalyzerish {
 
     /// Get signature help at a call site
     pub async fn get_signature_help(
-        &mut self,
+        &self,
         raw_cursor: &CursorCoordinates,
     ) -> Result<Option<SignatureInfo>> {
+        let _query = self.begin_query().await?;
         let (analysis, file_id, offset, _cursor) = self.setup_cursor_analysis(raw_cursor).await?;
         let position = Self::create_file_position(file_id, offset);
 
@@ -1591,14 +2697,14 @@ impl RustAnalyzerish {
     ///
     /// Returns the result with file changes that need to be applied separately.
     fn ssr_sync(
-        &mut self,
+        &self,
         pattern: &str,
         context_file: Option<&str>,
     ) -> Result<(Vec<SsrMatch>, Vec<FileChange>)> {
         use ra_ap_ide_ssr::SsrRule;
         use std::str::FromStr;
 
-        let db = self.host.raw_database();
+        let (analysis, db) = self.databases();
 
         // Parse the SSR rule
         let rule = SsrRule::from_str(pattern)
@@ -1607,9 +2713,9 @@ impl RustAnalyzerish {
         // Create a MatchFinder - use context file if provided, otherwise use first file
         let mut finder = if let Some(ctx_file) = context_file {
             let path = PathBuf::from(ctx_file);
-            let file_id = self.file_watcher.get_file_id(&path)?;
+            let file_id = self.file_id(&path)?;
             ra_ap_ide_ssr::MatchFinder::in_context(
-                db,
+                &db,
                 ra_ap_ide_db::FilePosition {
                     file_id,
                     offset: TextSize::from(0),
@@ -1618,7 +2724,7 @@ impl RustAnalyzerish {
             )
             .map_err(|e| anyhow::anyhow!("Failed to create SSR context: {}", e))?
         } else {
-            ra_ap_ide_ssr::MatchFinder::at_first_file(db)
+            ra_ap_ide_ssr::MatchFinder::at_first_file(&db)
                 .map_err(|e| anyhow::anyhow!("Failed to create SSR context: {}", e))?
         };
 
@@ -1650,16 +2756,11 @@ impl RustAnalyzerish {
         let mut match_idx = 0;
 
         for (file_id, text_edit) in &edits {
-            if let Some(file_path) = self.file_watcher.file_path(*file_id)
-                && let Ok(line_index) = self.host.analysis().file_line_index(*file_id)
+            if let Some(file_path) = self.file_path(*file_id)
+                && let Ok(line_index) = analysis.file_line_index(*file_id)
             {
                 // Get original file text to extract what's being replaced
-                let file_text = self
-                    .host
-                    .analysis()
-                    .file_text(*file_id)
-                    .ok()
-                    .map(|t| t.to_string());
+                let file_text = analysis.file_text(*file_id).ok().map(|t| t.to_string());
 
                 let mut edit_items = Vec::new();
                 for edit in text_edit.iter() {
@@ -1702,6 +2803,7 @@ impl RustAnalyzerish {
                 file_changes.push(FileChange {
                     file_path,
                     edits: edit_items,
+                    source_hash: file_text.as_deref().map(RustAnalyzerUtils::content_hash),
                 });
             }
         }
@@ -1710,15 +2812,11 @@ impl RustAnalyzerish {
     }
 
     /// Search for SSR pattern matches - synchronous core
-    fn ssr_search_sync(
-        &mut self,
-        pattern: &str,
-        context_file: Option<&str>,
-    ) -> Result<Vec<SsrMatch>> {
+    fn ssr_search_sync(&self, pattern: &str, context_file: Option<&str>) -> Result<Vec<SsrMatch>> {
         use ra_ap_ide_ssr::SsrPattern;
         use std::str::FromStr;
 
-        let db = self.host.raw_database();
+        let (analysis, db) = self.databases();
 
         // Parse the search pattern (not a full rule with replacement)
         let search_pattern = SsrPattern::from_str(pattern)
@@ -1727,9 +2825,9 @@ impl RustAnalyzerish {
         // Create a MatchFinder
         let mut finder = if let Some(ctx_file) = context_file {
             let path = PathBuf::from(ctx_file);
-            let file_id = self.file_watcher.get_file_id(&path)?;
+            let file_id = self.file_id(&path)?;
             ra_ap_ide_ssr::MatchFinder::in_context(
-                db,
+                &db,
                 ra_ap_ide_db::FilePosition {
                     file_id,
                     offset: TextSize::from(0),
@@ -1738,7 +2836,7 @@ impl RustAnalyzerish {
             )
             .map_err(|e| anyhow::anyhow!("Failed to create SSR context: {}", e))?
         } else {
-            ra_ap_ide_ssr::MatchFinder::at_first_file(db)
+            ra_ap_ide_ssr::MatchFinder::at_first_file(&db)
                 .map_err(|e| anyhow::anyhow!("Failed to create SSR context: {}", e))?
         };
 
@@ -1770,9 +2868,9 @@ impl RustAnalyzerish {
         if let Ok(rule) = rule_result {
             let mut finder2 = if let Some(ctx_file) = context_file {
                 let path = PathBuf::from(ctx_file);
-                let file_id = self.file_watcher.get_file_id(&path)?;
+                let file_id = self.file_id(&path)?;
                 ra_ap_ide_ssr::MatchFinder::in_context(
-                    db,
+                    &db,
                     ra_ap_ide_db::FilePosition {
                         file_id,
                         offset: TextSize::from(0),
@@ -1781,7 +2879,7 @@ impl RustAnalyzerish {
                 )
                 .map_err(|e| anyhow::anyhow!("Failed to create SSR context: {}", e))?
             } else {
-                ra_ap_ide_ssr::MatchFinder::at_first_file(db)
+                ra_ap_ide_ssr::MatchFinder::at_first_file(&db)
                     .map_err(|e| anyhow::anyhow!("Failed to create SSR context: {}", e))?
             };
 
@@ -1792,15 +2890,10 @@ impl RustAnalyzerish {
                 let mut match_idx = 0;
 
                 for (file_id, text_edit) in &edits {
-                    if let Some(file_path) = self.file_watcher.file_path(*file_id)
-                        && let Ok(line_index) = self.host.analysis().file_line_index(*file_id)
+                    if let Some(file_path) = self.file_path(*file_id)
+                        && let Ok(line_index) = analysis.file_line_index(*file_id)
                     {
-                        let file_text = self
-                            .host
-                            .analysis()
-                            .file_text(*file_id)
-                            .ok()
-                            .map(|t| t.to_string());
+                        let file_text = analysis.file_text(*file_id).ok().map(|t| t.to_string());
 
                         for edit in text_edit.iter() {
                             let start_line_col = line_index.line_col(edit.delete.start());
@@ -1862,16 +2955,18 @@ impl RustAnalyzerish {
     ///
     /// If `dry_run` is true, returns matches without applying changes.
     pub async fn ssr(
-        &mut self,
+        &self,
         pattern: &str,
         context_file: Option<&str>,
         dry_run: bool,
     ) -> Result<SsrResult> {
-        // Ensure file watcher is up to date
-        self.file_watcher.drain_and_apply_changes(&mut self.host)?;
+        let transaction = self.journal.transaction().await?;
 
         // Run the synchronous SSR core
-        let (matches, file_changes) = self.ssr_sync(pattern, context_file)?;
+        let (matches, file_changes) = {
+            let _query = self.begin_query().await?;
+            Self::catch_cancelled(|| self.ssr_sync(pattern, context_file))?
+        };
 
         if matches.is_empty() || dry_run {
             return Ok(SsrResult {
@@ -1881,10 +2976,10 @@ impl RustAnalyzerish {
             });
         }
 
-        // Apply the changes to disk (async part)
-        for file_change in &file_changes {
-            RustAnalyzerUtils::apply_file_change(file_change).await?;
-        }
+        // Apply the changes to disk (async part) and record them for undo
+        transaction
+            .apply(format!("SSR: {pattern}"), &file_changes, &[])
+            .await?;
 
         debug!(
             "SSR applied: {} matches replaced in {} files",
@@ -1899,6 +2994,21 @@ impl RustAnalyzerish {
         })
     }
 
+    /// Preview a structural search and replace as a unified diff
+    ///
+    /// Uses the same pattern syntax as `ssr`, but nothing is written to disk.
+    pub async fn preview_ssr(
+        &self,
+        pattern: &str,
+        context_file: Option<&str>,
+    ) -> Result<DiffPreview> {
+        let (_, file_changes) = {
+            let _query = self.begin_query().await?;
+            Self::catch_cancelled(|| self.ssr_sync(pattern, context_file))?
+        };
+        self.preview_edits(&file_changes, &[]).await
+    }
+
     /// Search for SSR pattern matches without replacement
     ///
     /// Use this to find all occurrences of a pattern without modifying code.
@@ -1908,14 +3018,12 @@ impl RustAnalyzerish {
     /// - `rgba($val)` - Find all rgba() calls
     /// - `$receiver.unwrap()` - Find all .unwrap() calls
     pub async fn ssr_search(
-        &mut self,
+        &self,
         pattern: &str,
         context_file: Option<&str>,
     ) -> Result<Vec<SsrMatch>> {
-        // Ensure file watcher is up to date
-        self.file_watcher.drain_and_apply_changes(&mut self.host)?;
-
         // Run the synchronous search
-        self.ssr_search_sync(pattern, context_file)
+        let _query = self.begin_query().await?;
+        Self::catch_cancelled(|| self.ssr_search_sync(pattern, context_file))
     }
 }
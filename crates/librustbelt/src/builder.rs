@@ -4,28 +4,109 @@
 //! instances with workspace configuration, separating initialization concerns
 //! from runtime operations.
 
+use std::fmt;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
+use ra_ap_cfg::{CfgAtom, CfgDiff};
 use ra_ap_ide::AnalysisHost;
 use ra_ap_ide_db::prime_caches;
-use ra_ap_load_cargo::{LoadCargoConfig, ProcMacroServerChoice, load_workspace_at};
+use ra_ap_intern::Symbol;
+use ra_ap_load_cargo::{LoadCargoConfig, ProcMacroServerChoice, load_workspace};
 use ra_ap_profile::StopWatch;
-use ra_ap_project_model::{CargoConfig, ProjectManifest, RustLibSource};
-use ra_ap_vfs::AbsPathBuf;
-use tracing::{info, trace};
+use ra_ap_project_model::{
+    CargoConfig, CargoFeatures, ManifestPath, ProjectJson, ProjectJsonData, ProjectManifest,
+    ProjectWorkspace, RustLibSource, Sysroot,
+};
+use ra_ap_vfs::{AbsPath, AbsPathBuf};
+use tracing::{info, trace, warn};
 
 use super::analyzer::RustAnalyzerish;
+use super::cache::WorkspaceCache;
+use super::entities::{CargoOptions, LoadProgress};
 use super::file_watcher::FileWatcher;
+use super::journal::EditJournal;
 use super::utils::RustAnalyzerUtils;
 
+/// Categorized reasons why a workspace failed to load
+///
+/// Returned wrapped in an `anyhow::Error` by `RustAnalyzerishBuilder`, so
+/// callers can `downcast_ref::<WorkspaceLoadError>()` to tell failures apart.
+#[derive(Debug, Clone)]
+pub enum WorkspaceLoadError {
+    /// No project manifest could be found for the given path, or the given
+    /// manifest is not a Cargo.toml, rust-project.json or `.rs` file
+    NoManifest { path: PathBuf, reason: String },
+    /// Loading the discovered manifest failed, usually in `cargo metadata`
+    CargoMetadata { manifest: PathBuf, reason: String },
+    /// The Rust sysroot could not be discovered
+    SysrootNotFound { reason: String },
+    /// The configured proc-macro server binary could not be found
+    ProcMacroServerMissing { reason: String },
+}
+
+impl fmt::Display for WorkspaceLoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorkspaceLoadError::NoManifest { path, reason } => {
+                write!(
+                    f,
+                    "No Cargo.toml or rust-project.json found for {}: {reason}",
+                    path.display()
+                )
+            }
+            WorkspaceLoadError::CargoMetadata { manifest, reason } => write!(
+                f,
+                "Failed to run cargo metadata for {}: {reason}",
+                manifest.display()
+            ),
+            WorkspaceLoadError::SysrootNotFound { reason } => {
+                write!(f, "Rust sysroot not found: {reason}")
+            }
+            WorkspaceLoadError::ProcMacroServerMissing { reason } => {
+                write!(f, "Proc-macro server not found: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for WorkspaceLoadError {}
+
+/// Receives the progress of loading a workspace
+///
+/// Callbacks are invoked from the thread loading the workspace, and from
+/// indexing worker threads.
+#[derive(Clone)]
+pub struct ProgressCallback(Arc<dyn Fn(LoadProgress) + Send + Sync>);
+
+impl ProgressCallback {
+    pub fn new<F: Fn(LoadProgress) + Send + Sync + 'static>(callback: F) -> Self {
+        Self(Arc::new(callback))
+    }
+
+    pub fn report(&self, progress: LoadProgress) {
+        (self.0)(progress)
+    }
+}
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
 /// Builder for creating configured RustAnalyzerish instances
 #[derive(Debug)]
 pub struct RustAnalyzerishBuilder {
     project_root: Option<PathBuf>,
+    manifest: Option<ProjectManifest>,
     cargo_config: CargoConfig,
+    cargo_options: CargoOptions,
     load_config: LoadCargoConfig,
     enable_file_watching: bool,
+    cache: Option<WorkspaceCache>,
+    progress: Option<ProgressCallback>,
 }
 
 impl Default for RustAnalyzerishBuilder {
@@ -42,6 +123,7 @@ impl RustAnalyzerishBuilder {
     pub fn new() -> Self {
         Self {
             project_root: None,
+            manifest: None,
             cargo_config: CargoConfig {
                 sysroot: Some(RustLibSource::Discover),
                 all_targets: true,
@@ -49,12 +131,15 @@ impl RustAnalyzerishBuilder {
                 cfg_overrides: Default::default(),
                 ..Default::default()
             },
+            cargo_options: CargoOptions::default(),
             load_config: LoadCargoConfig {
                 load_out_dirs_from_check: true,
                 with_proc_macro_server: ProcMacroServerChoice::Sysroot,
                 prefill_caches: false, // We handle this manually to add more cores
             },
             enable_file_watching: false,
+            cache: None,
+            progress: None,
         }
     }
 
@@ -68,6 +153,22 @@ impl RustAnalyzerishBuilder {
         self
     }
 
+    /// Cache workspace metadata in a directory
+    ///
+    /// Loading an unchanged workspace again, even from another process,
+    /// then skips `cargo metadata` and building the build scripts and
+    /// proc-macros. See [`WorkspaceCache`] for what invalidates entries.
+    pub fn with_cache_dir<P: AsRef<Path>>(mut self, cache_dir: Option<P>) -> Self {
+        self.cache = cache_dir.map(WorkspaceCache::new);
+        self
+    }
+
+    /// Report loading progress, from fetching metadata to indexing crates
+    pub fn with_progress(mut self, progress: Option<ProgressCallback>) -> Self {
+        self.progress = progress;
+        self
+    }
+
     /// Set the workspace root directory
     fn with_workspace<P: AsRef<Path>>(mut self, workspace_root: P) -> Self {
         self.project_root = Some(workspace_root.as_ref().to_path_buf());
@@ -75,9 +176,27 @@ impl RustAnalyzerishBuilder {
     }
 
     /// Create a builder from a file path by finding its project root
+    ///
+    /// A `rust-project.json` in the file's directory or its parents takes
+    /// precedence over a Cargo.toml. A `.rs` file outside of any project is
+    /// loaded on its own, as a detached file.
     pub fn from_file<P: AsRef<Path>>(file_path: P) -> Result<Self> {
-        let project_root = Self::find_project_root(file_path.as_ref())?;
-        Ok(Self::new().with_workspace(project_root))
+        let manifest = Self::find_project_manifest(file_path.as_ref())?;
+        Self::from_manifest(manifest)
+    }
+
+    /// Create a builder for an explicit project manifest
+    ///
+    /// The manifest may be a `Cargo.toml`, a `rust-project.json` (as
+    /// generated by Bazel, Buck or other build systems) or a `.rs` file,
+    /// which is loaded as a cargo script or a detached file. Its directory
+    /// becomes the project root.
+    pub fn from_manifest<P: AsRef<Path>>(manifest_path: P) -> Result<Self> {
+        let manifest = Self::resolve_manifest(manifest_path.as_ref())?;
+        let project_root: PathBuf = manifest.manifest_path().parent().to_path_buf().into();
+        let mut builder = Self::new().with_workspace(project_root);
+        builder.manifest = Some(manifest);
+        Ok(builder)
     }
 
     /// Configure cargo settings
@@ -92,22 +211,136 @@ impl RustAnalyzerishBuilder {
         self
     }
 
+    /// Configure features, target, cfgs and environment at once
+    ///
+    /// The options are applied on top of the cargo settings when building,
+    /// so they also override a configuration set with `with_cargo_config`.
+    pub fn with_cargo_options(mut self, cargo_options: CargoOptions) -> Self {
+        self.cargo_options = cargo_options;
+        self
+    }
+
+    /// Enable features, in addition to the default ones unless
+    /// `with_no_default_features(true)` is used
+    pub fn with_features<I, S>(mut self, features: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.cargo_options.features = features.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Enable all features of every workspace member
+    pub fn with_all_features(mut self, enable: bool) -> Self {
+        self.cargo_options.all_features = enable;
+        self
+    }
+
+    /// Disable the default features
+    pub fn with_no_default_features(mut self, enable: bool) -> Self {
+        self.cargo_options.no_default_features = enable;
+        self
+    }
+
+    /// Analyze for a target triple instead of the host's, so code behind
+    /// `#[cfg(target_os = "...")]` and friends is active for that target
+    pub fn with_target<S: Into<String>>(mut self, target: Option<S>) -> Self {
+        self.cargo_options.target = target.map(Into::into);
+        self
+    }
+
+    /// Set extra cfg options for every crate, as `name` or `name=value`
+    ///
+    /// Options prefixed with `!` are disabled instead. Invalid options are
+    /// reported by `build`.
+    pub fn with_cfgs<I, S>(mut self, cfgs: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.cargo_options.cfgs = cfgs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Set an environment variable for cargo, build scripts and `env!`
+    pub fn with_env<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> Self {
+        self.cargo_options.env.insert(key.into(), value.into());
+        self
+    }
+
     /// Build the configured RustAnalyzerish instance
-    pub fn build(self) -> Result<RustAnalyzerish> {
+    pub fn build(mut self) -> Result<RustAnalyzerish> {
         let project_root = self
             .project_root
             .clone()
             .ok_or_else(|| anyhow::anyhow!("No workspace root specified."))?;
+        self.apply_cargo_options()?;
 
         let abs_project_root = RustAnalyzerUtils::path_to_abs_path(&project_root)?;
 
         let (analysis_host, file_watcher) = self.load_workspace(&abs_project_root)?;
 
-        Ok(RustAnalyzerish::new(analysis_host, file_watcher))
+        let journal = EditJournal::for_workspace(&abs_project_root);
+
+        Ok(RustAnalyzerish::new(
+            analysis_host,
+            file_watcher,
+            journal,
+            self.cargo_options,
+        ))
+    }
+
+    /// Apply the cargo options on top of the cargo settings
+    ///
+    /// Options left at their default keep the corresponding setting as is.
+    fn apply_cargo_options(&mut self) -> Result<()> {
+        let options = &self.cargo_options;
+        if options.all_features {
+            self.cargo_config.features = CargoFeatures::All;
+        } else if !options.features.is_empty() || options.no_default_features {
+            self.cargo_config.features = CargoFeatures::Selected {
+                features: options.features.clone(),
+                no_default_features: options.no_default_features,
+            };
+        }
+
+        if let Some(target) = &options.target {
+            self.cargo_config.target = Some(target.clone());
+        }
+
+        if !options.cfgs.is_empty() {
+            let mut enable = Vec::new();
+            let mut disable = Vec::new();
+            for cfg in &options.cfgs {
+                match cfg.strip_prefix('!') {
+                    Some(cfg) => disable.push(parse_cfg(cfg)?),
+                    None => enable.push(parse_cfg(cfg)?),
+                }
+            }
+            self.cargo_config.cfg_overrides.global = CfgDiff::new(enable, disable);
+        }
+
+        self.cargo_config.extra_env.extend(
+            options
+                .env
+                .iter()
+                .map(|(key, value)| (key.clone(), Some(value.clone()))),
+        );
+        Ok(())
+    }
+
+    /// Find the project root by looking for a rust-project.json or Cargo.toml
+    pub fn find_project_root(file_path: &Path) -> Result<PathBuf> {
+        let manifest = Self::find_project_manifest(file_path)?;
+        Ok(manifest.parent().map(Path::to_path_buf).unwrap_or(manifest))
     }
 
-    /// Find the project root by looking for Cargo.toml
-    fn find_project_root(file_path: &Path) -> Result<PathBuf> {
+    /// Find the manifest of the project containing a path
+    ///
+    /// This is the nearest rust-project.json or Cargo.toml, or the file
+    /// itself for a detached `.rs` file. Pass it to `from_manifest`.
+    pub fn find_project_manifest(file_path: &Path) -> Result<PathBuf> {
         let path = if file_path.is_absolute() {
             info!(
                 "Finding project root for absolute path: {}",
@@ -123,8 +356,174 @@ impl RustAnalyzerishBuilder {
         };
 
         let abs_path = RustAnalyzerUtils::path_to_abs_path(&path)?;
-        let root = ProjectManifest::discover_single(&abs_path)?;
-        Ok(root.manifest_path().parent().to_path_buf().into())
+        let manifest = Self::discover_manifest(&abs_path)?;
+        Ok(manifest.manifest_path().to_path_buf().into())
+    }
+
+    /// Discover the project manifest for a path
+    ///
+    /// A `.rs` file without a manifest in its parents is its own manifest.
+    fn discover_manifest(abs_path: &AbsPath) -> Result<ProjectManifest> {
+        ProjectManifest::discover_single(abs_path)
+            .or_else(|e| {
+                if abs_path.extension() == Some("rs") && Path::new(abs_path.as_str()).is_file() {
+                    ProjectManifest::from_manifest_file(abs_path.to_path_buf())
+                } else {
+                    Err(e)
+                }
+            })
+            .map_err(|e| {
+                WorkspaceLoadError::NoManifest {
+                    path: abs_path.to_path_buf().into(),
+                    reason: e.to_string(),
+                }
+                .into()
+            })
+    }
+
+    /// Check an explicitly given project manifest
+    fn resolve_manifest(manifest_path: &Path) -> Result<ProjectManifest> {
+        let no_manifest = |reason: String| WorkspaceLoadError::NoManifest {
+            path: manifest_path.to_path_buf(),
+            reason,
+        };
+        if !manifest_path.is_file() {
+            return Err(no_manifest("not a file".to_string()).into());
+        }
+        let abs_path = RustAnalyzerUtils::path_to_abs_path(manifest_path)?;
+        ProjectManifest::from_manifest_file(abs_path).map_err(|e| no_manifest(e.to_string()).into())
+    }
+
+    /// Check that the toolchain pieces needed for analysis are available
+    fn check_toolchain(&self, workspace: &ProjectWorkspace) -> Result<()> {
+        if self.cargo_config.sysroot.is_some() && workspace.sysroot.root().is_none() {
+            let reason = workspace
+                .sysroot
+                .error()
+                .unwrap_or("sysroot discovery returned no path");
+            return Err(WorkspaceLoadError::SysrootNotFound {
+                reason: reason.to_string(),
+            }
+            .into());
+        }
+
+        match &self.load_config.with_proc_macro_server {
+            ProcMacroServerChoice::Explicit(path) if std::fs::metadata(path).is_err() => {
+                return Err(WorkspaceLoadError::ProcMacroServerMissing {
+                    reason: format!("{path} does not exist"),
+                }
+                .into());
+            }
+            ProcMacroServerChoice::Sysroot => match workspace.find_sysroot_proc_macro_srv() {
+                Some(Ok(_)) => {}
+                // Analysis still works without proc-macro expansion, so only warn
+                Some(Err(e)) => warn!("Proc-macro server not found in sysroot: {e}"),
+                None => warn!("No sysroot to look up the proc-macro server in"),
+            },
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Load a rust-project.json
+    ///
+    /// Generated files often leave out the sysroot, which is then discovered
+    /// like for Cargo workspaces so the standard library resolves.
+    fn load_project_json(
+        &self,
+        manifest_path: &ManifestPath,
+        progress: &(dyn Fn(String) + Sync),
+    ) -> Result<ProjectWorkspace> {
+        let contents = std::fs::read_to_string(manifest_path)
+            .with_context(|| format!("Failed to read {manifest_path}"))?;
+        let mut data: serde_json::Value = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse {manifest_path}"))?;
+
+        let has_sysroot = ["sysroot", "sysroot_src"]
+            .iter()
+            .any(|key| data.get(key).is_some_and(|value| !value.is_null()));
+        let project_dir = manifest_path.parent();
+        let sysroot = match &self.cargo_config.sysroot {
+            _ if has_sysroot => None,
+            Some(RustLibSource::Discover) => {
+                Some(Sysroot::discover(project_dir, &self.cargo_config.extra_env))
+            }
+            Some(RustLibSource::Path(path)) => {
+                Some(Sysroot::discover_rust_lib_src_dir(path.clone()))
+            }
+            None => None,
+        };
+        if let Some(sysroot) = sysroot {
+            data["sysroot"] = sysroot.root().map(AbsPath::as_str).into();
+            data["sysroot_src"] = sysroot.rust_lib_src_root().map(AbsPath::as_str).into();
+        }
+
+        let data: ProjectJsonData = serde_json::from_value(data)
+            .with_context(|| format!("Failed to parse {manifest_path}"))?;
+        let project_json = ProjectJson::new(Some(manifest_path.clone()), project_dir, data);
+        Ok(ProjectWorkspace::load_inline(
+            project_json,
+            &self.cargo_config,
+            progress,
+        ))
+    }
+
+    /// Load a workspace from its manifest, with `cargo metadata` and build
+    /// scripts for Cargo projects, storing the result in the cache if there
+    /// is one
+    fn load_project_workspace(
+        &self,
+        abs_project_root: &AbsPathBuf,
+        manifest: ProjectManifest,
+        progress: &(dyn Fn(String) + Sync),
+    ) -> Result<ProjectWorkspace> {
+        let manifest_path: PathBuf = manifest.manifest_path().to_path_buf().into();
+        let mut workspace =
+            match manifest {
+                ProjectManifest::ProjectJson(project_json) => {
+                    self.load_project_json(&project_json, progress)?
+                }
+                manifest => ProjectWorkspace::load(manifest, &self.cargo_config, progress)
+                    .map_err(|e| WorkspaceLoadError::CargoMetadata {
+                        manifest: manifest_path.clone(),
+                        reason: format!("{e:#}"),
+                    })?,
+            };
+        self.check_toolchain(&workspace)?;
+
+        // Only complete metadata is worth caching
+        let mut cacheable = self.load_config.load_out_dirs_from_check;
+        if self.load_config.load_out_dirs_from_check {
+            let build_progress = |message: String| {
+                trace!("Build scripts progress: {}", message);
+                self.report(LoadProgress::BuildScripts { message });
+            };
+            let build_scripts = workspace
+                .run_build_scripts(&self.cargo_config, &build_progress)
+                .with_context(|| {
+                    format!(
+                        "Failed to run build scripts for {}",
+                        manifest_path.display()
+                    )
+                })?;
+            if let Some(error) = build_scripts.error() {
+                warn!(
+                    "Errors occurred while running build scripts for {}: {}",
+                    manifest_path.display(),
+                    error
+                );
+                cacheable = false;
+            }
+            workspace.set_build_scripts(build_scripts);
+        }
+
+        if let Some(cache) = self.cache.as_ref().filter(|_| cacheable)
+            && let Err(e) = cache.store(abs_project_root, &self.cargo_config, &workspace)
+        {
+            warn!("Failed to cache workspace metadata: {e:#}");
+        }
+        Ok(workspace)
     }
 
     /// Load workspace and return (AnalysisHost, FileWatcher)
@@ -132,14 +531,43 @@ impl RustAnalyzerishBuilder {
         info!("Loading workspace from: {}", abs_project_root);
         let mut stop_watch = StopWatch::start();
 
-        let (db, vfs, _proc_macro) = load_workspace_at(
-            abs_project_root.as_ref(),
-            &self.cargo_config,
-            &self.load_config,
-            &|msg| {
-                trace!("Workspace loading progress: {}", msg);
-            },
-        )?;
+        let progress = |message: String| {
+            trace!("Workspace loading progress: {}", message);
+            self.report(LoadProgress::Metadata { message });
+        };
+
+        let manifest = match &self.manifest {
+            Some(manifest) => manifest.clone(),
+            None => Self::discover_manifest(abs_project_root)?,
+        };
+        info!("Using project manifest: {manifest}");
+
+        // Only Cargo workspaces are cached, others are cheap to load
+        let cached = self
+            .cache
+            .as_ref()
+            .filter(|_| matches!(manifest, ProjectManifest::CargoToml(_)))
+            .and_then(|cache| cache.load(abs_project_root, &self.cargo_config));
+        let (workspace, library_origins) = match cached {
+            Some((project_json, library_origins)) => {
+                progress("using cached workspace metadata".to_string());
+                let workspace =
+                    ProjectWorkspace::load_inline(project_json, &self.cargo_config, &progress);
+                self.check_toolchain(&workspace)?;
+                (workspace, Some(library_origins))
+            }
+            None => (
+                self.load_project_workspace(abs_project_root, manifest, &progress)?,
+                None,
+            ),
+        };
+
+        self.report(LoadProgress::LoadingFiles);
+        let (mut db, vfs, _proc_macro) =
+            load_workspace(workspace, &self.cargo_config.extra_env, &self.load_config)?;
+        if let Some(library_origins) = library_origins {
+            library_origins.apply(&mut db, &vfs);
+        }
 
         // Create analysis host with the loaded database
         let mut host = AnalysisHost::with_database(db);
@@ -164,6 +592,16 @@ impl RustAnalyzerishBuilder {
         let threads = num_cpus::get_physical();
         prime_caches::parallel_prime_caches(host.raw_database(), threads, &|progress| {
             trace!("Cache priming progress: {:?}", progress);
+            self.report(LoadProgress::Indexing {
+                work: progress.work_type.to_string(),
+                done: progress.crates_done,
+                total: progress.crates_total,
+                crates: progress
+                    .crates_currently_indexing
+                    .iter()
+                    .map(|name| name.to_string())
+                    .collect(),
+            });
         });
 
         let elapsed = stop_watch.elapsed();
@@ -179,6 +617,39 @@ impl RustAnalyzerishBuilder {
             trace!("Loaded file in VFS: {:?} - {}", file_id, vfs_path);
         }
 
+        self.report(LoadProgress::Ready);
         Ok((host, file_watcher))
     }
+
+    fn report(&self, progress: LoadProgress) {
+        if let Some(callback) = &self.progress {
+            callback.report(progress);
+        }
+    }
+}
+
+/// Parse a cfg option given as `name`, `name=value` or `name="value"`
+fn parse_cfg(cfg: &str) -> Result<CfgAtom> {
+    let is_ident = |name: &str| {
+        name.chars()
+            .next()
+            .is_some_and(|c| c.is_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+    };
+    let atom = match cfg.split_once('=') {
+        Some((key, value)) => {
+            let key = key.trim();
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            is_ident(key).then(|| CfgAtom::KeyValue {
+                key: Symbol::intern(key),
+                value: Symbol::intern(value),
+            })
+        }
+        None => is_ident(cfg.trim()).then(|| CfgAtom::Flag(Symbol::intern(cfg.trim()))),
+    };
+    atom.ok_or_else(|| anyhow::anyhow!("Invalid cfg `{cfg}`, expected `name` or `name=value`"))
 }
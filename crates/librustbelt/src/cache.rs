@@ -0,0 +1,499 @@
+//! On-disk cache of workspace metadata
+//!
+//! Loading a Cargo workspace runs `cargo metadata` for the workspace and the
+//! sysroot, then `cargo check` to get build-script outputs (`OUT_DIR`, extra
+//! cfgs and env vars) and proc-macro dylibs. On a workspace that hasn't
+//! changed, all of that produces the same crate graph, so the cache stores
+//! the graph as a `rust-project.json` document and later loads it with
+//! [`ProjectWorkspace::load_inline`], which runs none of those commands.
+//!
+//! Entries are keyed by the project root and the cargo configuration, and
+//! are only used while the toolchain, rustbelt version and the content of
+//! `Cargo.lock`, the manifests and build scripts of members and path
+//! dependencies and toolchain/cargo config files (including those of parent
+//! directories and `$CARGO_HOME`) are unchanged, while the files cargo
+//! discovers targets from are the same, and while the build-script outputs
+//! and proc-macro dylibs they refer to still exist.
+//!
+//! rust-project.json has no notion of dependencies: every crate it lists is
+//! loaded as a local crate. The entry also records which crates came from
+//! dependencies, see [`LibraryOrigins`].
+//!
+//! Proc-macro expansions and the rest of the analysis live in
+//! rust-analyzer's in-memory database, which cannot be persisted; they are
+//! recomputed from the cached graph using the already built proc-macro
+//! dylibs.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use ra_ap_base_db::salsa::{Durability, Setter};
+use ra_ap_base_db::{CrateBuilderId, CrateGraphBuilder, CrateOrigin, ProcMacroPaths, RootQueryDb};
+use ra_ap_cfg::CfgOptions;
+use ra_ap_ide_db::RootDatabase;
+use ra_ap_intern::Symbol;
+use ra_ap_project_model::{
+    CargoConfig, ProjectJson, ProjectJsonData, ProjectWorkspace, ProjectWorkspaceKind, TargetKind,
+};
+use ra_ap_vfs::{AbsPath, AbsPathBuf, FileId, Vfs};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tracing::info;
+
+use super::utils::RustAnalyzerUtils;
+
+/// Files at the workspace root whose changes invalidate the cache
+const ROOT_INPUTS: &[&str] = &["Cargo.lock", "rust-toolchain", "rust-toolchain.toml"];
+
+/// Cargo configuration files, read from every parent directory of the
+/// workspace and from `$CARGO_HOME`
+const CONFIG_INPUTS: &[&str] = &[".cargo/config", ".cargo/config.toml"];
+
+/// Directories of a package cargo discovers targets in
+const TARGET_DIRS: &[&str] = &["src/bin", "examples", "tests", "benches"];
+
+/// Persistent cache of workspace metadata, stored in a directory
+#[derive(Debug, Clone)]
+pub struct WorkspaceCache {
+    dir: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    /// Version of rustbelt that wrote the entry
+    rustbelt_version: String,
+    /// `rustc -vV` output for the project
+    toolchain: String,
+    /// Content hashes of the files the metadata was derived from, `None`
+    /// for files that did not exist
+    inputs: BTreeMap<PathBuf, Option<String>>,
+    /// Hashes of the files cargo discovers targets from, by package directory
+    #[serde(default)]
+    targets: BTreeMap<PathBuf, String>,
+    /// The crate graph as rust-project.json data
+    project: Value,
+    /// Origins of the dependency crates in `project`
+    libraries: LibraryOrigins,
+}
+
+/// Origins of dependency crates, keyed by their root module
+///
+/// Crates loaded from rust-project.json are all local, which among other
+/// things makes cache priming index the symbols of every dependency. Applying
+/// these origins once the database is loaded makes it match a Cargo load.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LibraryOrigins(BTreeMap<PathBuf, LibraryOrigin>);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LibraryOrigin {
+    name: String,
+    repo: Option<String>,
+}
+
+impl LibraryOrigins {
+    /// Mark the crates of a database loaded from the cache as libraries
+    pub fn apply(&self, db: &mut RootDatabase, vfs: &Vfs) {
+        for &krate in db.all_crates().iter() {
+            let data = krate.data(db);
+            let Some(origin) = vfs
+                .file_path(data.root_file_id)
+                .as_path()
+                .and_then(|path| self.0.get(Path::new(path.as_str())))
+            else {
+                continue;
+            };
+            let mut data = data.clone();
+            data.origin = CrateOrigin::Library {
+                repo: origin.repo.clone(),
+                name: Symbol::intern(&origin.name),
+            };
+            krate
+                .set_data(db)
+                .with_durability(Durability::MEDIUM)
+                .to(data);
+        }
+    }
+}
+
+impl WorkspaceCache {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Cached project for a workspace root, if the entry is still valid
+    pub fn load(
+        &self,
+        project_root: &AbsPath,
+        cargo_config: &CargoConfig,
+    ) -> Option<(ProjectJson, LibraryOrigins)> {
+        let path = self.entry_path(project_root, cargo_config);
+        let entry: CacheEntry = serde_json::from_slice(&std::fs::read(&path).ok()?).ok()?;
+
+        if let Some(reason) = Self::invalidation_reason(&entry, project_root) {
+            info!("Ignoring workspace cache {}: {reason}", path.display());
+            return None;
+        }
+
+        let data: ProjectJsonData = match serde_json::from_value(entry.project) {
+            Ok(data) => data,
+            Err(e) => {
+                info!(
+                    "Ignoring unreadable workspace cache {}: {e}",
+                    path.display()
+                );
+                return None;
+            }
+        };
+        info!("Using workspace cache {}", path.display());
+        Some((ProjectJson::new(None, project_root, data), entry.libraries))
+    }
+
+    /// Store the crate graph of a freshly loaded Cargo workspace
+    ///
+    /// Other kinds of workspaces are already cheap to load, and workspaces
+    /// whose `cargo metadata` partially failed would only cache the failure,
+    /// so both are skipped.
+    pub fn store(
+        &self,
+        project_root: &AbsPath,
+        cargo_config: &CargoConfig,
+        workspace: &ProjectWorkspace,
+    ) -> Result<()> {
+        let ProjectWorkspaceKind::Cargo {
+            cargo, error: None, ..
+        } = &workspace.kind
+        else {
+            return Ok(());
+        };
+
+        let workspace_root: PathBuf = cargo.workspace_root().to_path_buf().into();
+        let mut input_paths: Vec<PathBuf> = ROOT_INPUTS
+            .iter()
+            .map(|name| workspace_root.join(name))
+            .collect();
+        input_paths.extend(config_paths(&workspace_root));
+        let mut targets = BTreeMap::new();
+        // Local packages are the members and their path dependencies
+        for package in cargo.packages().filter(|&package| cargo[package].is_local) {
+            let manifest: PathBuf = cargo[package].manifest.to_path_buf().into();
+            if let Some(dir) = manifest.parent() {
+                targets.insert(dir.to_path_buf(), target_files_hash(dir));
+            }
+            input_paths.push(manifest);
+            input_paths.extend(
+                cargo[package]
+                    .targets
+                    .iter()
+                    .filter(|&&target| cargo[target].kind == TargetKind::BuildScript)
+                    .map(|&target| cargo[target].root.to_path_buf().into()),
+            );
+        }
+        let inputs = input_paths
+            .into_iter()
+            .map(|path| {
+                let hash = file_hash(&path);
+                (path, hash)
+            })
+            .collect();
+
+        let (project, libraries) = project_json(workspace, cargo_config);
+        let entry = CacheEntry {
+            rustbelt_version: env!("CARGO_PKG_VERSION").to_string(),
+            toolchain: toolchain_version(project_root)?,
+            inputs,
+            targets,
+            project,
+            libraries,
+        };
+
+        let path = self.entry_path(project_root, cargo_config);
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create cache directory {}", self.dir.display()))?;
+        // Write to a temporary file first so concurrent readers never see a
+        // partial entry
+        let tmp_path = path.with_extension(format!("tmp{}", std::process::id()));
+        std::fs::write(&tmp_path, serde_json::to_vec(&entry)?)
+            .with_context(|| format!("Failed to write {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, &path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        info!("Stored workspace cache {}", path.display());
+        Ok(())
+    }
+
+    /// Cache file of a workspace root and cargo configuration
+    fn entry_path(&self, project_root: &AbsPath, cargo_config: &CargoConfig) -> PathBuf {
+        let name = project_root.file_name().unwrap_or("workspace").replace(
+            |c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '_',
+            "_",
+        );
+        let key = RustAnalyzerUtils::content_hash(&format!("{project_root}\n{cargo_config:?}"));
+        self.dir.join(format!("{name}-{key}.json"))
+    }
+
+    /// Why a cache entry can no longer be used, if it can't
+    fn invalidation_reason(entry: &CacheEntry, project_root: &AbsPath) -> Option<String> {
+        if entry.rustbelt_version != env!("CARGO_PKG_VERSION") {
+            return Some(format!("written by rustbelt {}", entry.rustbelt_version));
+        }
+        match toolchain_version(project_root) {
+            Ok(toolchain) if toolchain == entry.toolchain => {}
+            Ok(_) => return Some("toolchain changed".to_string()),
+            Err(e) => return Some(format!("{e:#}")),
+        }
+        if let Some((path, _)) = entry
+            .inputs
+            .iter()
+            .find(|(path, hash)| file_hash(path) != **hash)
+        {
+            return Some(format!("{} changed", path.display()));
+        }
+        if let Some((dir, _)) = entry
+            .targets
+            .iter()
+            .find(|(dir, hash)| target_files_hash(dir) != **hash)
+        {
+            return Some(format!("targets of {} changed", dir.display()));
+        }
+
+        // Build-script outputs and proc-macro dylibs are deleted by `cargo
+        // clean`, and the crate roots by removing targets
+        let crates = entry.project["crates"].as_array().into_iter().flatten();
+        for krate in crates {
+            let paths = [
+                &krate["root_module"],
+                &krate["env"]["OUT_DIR"],
+                &krate["proc_macro_dylib_path"],
+            ];
+            for path in paths.into_iter().filter_map(Value::as_str) {
+                if !Path::new(path).exists() {
+                    return Some(format!("{path} no longer exists"));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Content hash of a file, `None` if it cannot be read
+fn file_hash(path: &Path) -> Option<String> {
+    let bytes = std::fs::read(path).ok()?;
+    Some(RustAnalyzerUtils::content_hash(&String::from_utf8_lossy(
+        &bytes,
+    )))
+}
+
+/// Cargo configuration files that apply to a workspace, existing or not
+fn config_paths(workspace_root: &Path) -> Vec<PathBuf> {
+    let cargo_home = std::env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| Some(PathBuf::from(std::env::var_os("HOME")?).join(".cargo")));
+    let mut paths: Vec<PathBuf> = workspace_root
+        .ancestors()
+        .flat_map(|dir| CONFIG_INPUTS.iter().map(move |name| dir.join(name)))
+        .collect();
+    if let Some(cargo_home) = cargo_home {
+        paths.extend(["config", "config.toml"].map(|name| cargo_home.join(name)));
+    }
+    paths
+}
+
+/// Hash of the files cargo discovers targets from in a package directory
+///
+/// Adding a binary, test, example or bench, or a library or main next to
+/// existing targets, changes the targets of the package without touching its
+/// manifest.
+fn target_files_hash(package_dir: &Path) -> String {
+    let mut files: Vec<PathBuf> = ["src/lib.rs", "src/main.rs", "build.rs"]
+        .iter()
+        .map(|name| package_dir.join(name))
+        .filter(|path| path.is_file())
+        .collect();
+    for dir in TARGET_DIRS {
+        let Ok(entries) = std::fs::read_dir(package_dir.join(dir)) else {
+            continue;
+        };
+        for path in entries.filter_map(|entry| Some(entry.ok()?.path())) {
+            if path.extension().is_some_and(|ext| ext == "rs") {
+                files.push(path);
+            } else if path.join("main.rs").is_file() {
+                files.push(path.join("main.rs"));
+            }
+        }
+    }
+    files.sort();
+    let listing: Vec<_> = files
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    RustAnalyzerUtils::content_hash(&listing.join("\n"))
+}
+
+/// Version of the toolchain used for a project, honoring rust-toolchain files
+fn toolchain_version(project_root: &AbsPath) -> Result<String> {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let output = std::process::Command::new(&rustc)
+        .arg("-vV")
+        .current_dir(project_root)
+        .output()
+        .with_context(|| format!("Failed to run {rustc} -vV"))?;
+    anyhow::ensure!(
+        output.status.success(),
+        "{rustc} -vV failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Render the crate graph of a workspace as rust-project.json data, along
+/// with the origins of its dependency crates
+///
+/// Sysroot crates go in `sysroot_project`, so loading the result doesn't
+/// need `cargo metadata` for the sysroot either. Dependencies on sysroot
+/// crates are not listed, since they are injected for every crate.
+fn project_json(
+    workspace: &ProjectWorkspace,
+    cargo_config: &CargoConfig,
+) -> (Value, LibraryOrigins) {
+    let mut roots = Vec::new();
+    let mut load = |path: &AbsPath| {
+        if std::fs::metadata(path).is_err() {
+            return None;
+        }
+        roots.push(path.to_path_buf());
+        Some(FileId::from_raw(roots.len() as u32 - 1))
+    };
+    let (graph, proc_macros) = workspace.to_crate_graph(&mut load, &cargo_config.extra_env);
+
+    let is_sysroot = |origin: &CrateOrigin| matches!(origin, CrateOrigin::Lang(_));
+    let (sysroot_ids, crate_ids): (Vec<_>, Vec<_>) = graph
+        .iter()
+        .partition(|&id| is_sysroot(&graph[id].basic.origin));
+    let sysroot_crates = crates_json(&graph, &sysroot_ids, &proc_macros, &roots);
+    let crates = crates_json(&graph, &crate_ids, &proc_macros, &roots);
+
+    let mut libraries = LibraryOrigins::default();
+    for &id in &crate_ids {
+        if let CrateOrigin::Library { repo, name } = &graph[id].basic.origin {
+            let root_module = &roots[graph[id].basic.root_file_id.index() as usize];
+            libraries.0.insert(
+                root_module.to_path_buf().into(),
+                LibraryOrigin {
+                    name: name.to_string(),
+                    repo: repo.clone(),
+                },
+            );
+        }
+    }
+
+    let mut project = json!({
+        "sysroot": workspace.sysroot.root().map(AbsPath::as_str),
+        "sysroot_src": workspace.sysroot.rust_lib_src_root().map(AbsPath::as_str),
+        "crates": crates,
+    });
+    if !sysroot_crates.is_empty() {
+        project["sysroot_project"] = json!({ "crates": sysroot_crates });
+    }
+    (project, libraries)
+}
+
+/// Render some crates of a crate graph as rust-project.json crates
+///
+/// Dependencies on crates outside `ids` are dropped.
+fn crates_json(
+    graph: &CrateGraphBuilder,
+    ids: &[CrateBuilderId],
+    proc_macros: &ProcMacroPaths,
+    roots: &[AbsPathBuf],
+) -> Vec<Value> {
+    ids.iter()
+        .map(|&id| {
+            let krate = &graph[id];
+            let root_module = &roots[krate.basic.root_file_id.index() as usize];
+            let deps: Vec<Value> = krate
+                .basic
+                .dependencies
+                .iter()
+                .filter_map(|dep| {
+                    let idx = ids.iter().position(|&other| other == dep.crate_id)?;
+                    Some(json!({ "crate": idx, "name": dep.name.to_string() }))
+                })
+                .collect();
+            let env: BTreeMap<&str, &str> = krate
+                .env
+                .into_iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .collect();
+
+            // Cargo crates see their whole package, not only the directory of
+            // their root module, plus the build-script output
+            let package_dir = env
+                .get("CARGO_MANIFEST_DIR")
+                .map(|dir| dir.to_string())
+                .unwrap_or_else(|| root_module.parent().unwrap().to_string());
+            let mut include_dirs = vec![package_dir.clone()];
+            include_dirs.extend(env.get("OUT_DIR").map(|dir| dir.to_string()));
+            // Same exclusions as rust-analyzer uses for Cargo packages, the
+            // tests and examples of dependencies are never analyzed
+            let is_member = matches!(krate.basic.origin, CrateOrigin::Local { .. });
+            let excluded: &[&str] = if is_member {
+                &[".git", "target"]
+            } else {
+                &[".git", "target", "tests", "examples", "benches"]
+            };
+            let exclude_dirs: Vec<String> = excluded
+                .iter()
+                .map(|name| format!("{package_dir}/{name}"))
+                .collect();
+
+            json!({
+                "display_name": krate.extra.display_name.as_ref().map(|name| name.canonical_name().as_str()),
+                "root_module": root_module.as_str(),
+                "edition": krate.basic.edition.number().to_string(),
+                "version": krate.extra.version,
+                "deps": deps,
+                "cfg": cfg_list(&krate.cfg_options),
+                "env": env,
+                "is_workspace_member": is_member,
+                "is_proc_macro": krate.basic.is_proc_macro,
+                "proc_macro_dylib_path": proc_macros
+                    .get(&id)
+                    .and_then(|path| path.as_ref().ok())
+                    .map(|(_, path)| path.as_str()),
+                "proc_macro_cwd": krate.basic.proc_macro_cwd.as_str(),
+                "source": {
+                    "include_dirs": include_dirs,
+                    "exclude_dirs": exclude_dirs,
+                },
+            })
+        })
+        .collect()
+}
+
+/// Render cfg options in rust-project.json syntax (`flag` or `key="value"`)
+fn cfg_list(cfg_options: &CfgOptions) -> Vec<String> {
+    let mut keys: Vec<_> = cfg_options.get_cfg_keys().map(|key| key.as_str()).collect();
+    keys.sort();
+    keys.dedup();
+
+    let mut cfg = Vec::new();
+    for key in keys {
+        // Always enabled, and rejected when set explicitly
+        if key == "true" || key == "false" {
+            continue;
+        }
+        let mut values: Vec<_> = cfg_options
+            .get_cfg_values(key)
+            .map(|value| value.as_str())
+            .collect();
+        if values.is_empty() {
+            cfg.push(key.to_string());
+        } else {
+            values.sort();
+            cfg.extend(values.into_iter().map(|value| format!("{key}=\"{value}\"")));
+        }
+    }
+    cfg
+}
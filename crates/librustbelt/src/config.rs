@@ -0,0 +1,49 @@
+//! Per-workspace configuration from `rustbelt.toml`
+//!
+//! A workspace can check in a `rustbelt.toml` at its root to configure how
+//! rustbelt analyzes it:
+//!
+//! ```toml
+//! [diagnostics]
+//! disabled = ["inactive-code", "unlinked-file"]
+//! style_lints = true
+//! experimental = false
+//! ```
+//!
+//! The file is read whenever it is needed, so edits apply without reloading
+//! the workspace.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use super::entities::DiagnosticsOptions;
+
+/// Name of the configuration file at the workspace root
+pub const CONFIG_FILE: &str = "rustbelt.toml";
+
+/// Contents of a workspace's `rustbelt.toml`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorkspaceConfig {
+    /// Which diagnostics rust-analyzer reports
+    pub diagnostics: DiagnosticsOptions,
+}
+
+impl WorkspaceConfig {
+    /// Read the configuration of the workspace at `workspace_root`
+    ///
+    /// A workspace without a `rustbelt.toml` gets the default configuration.
+    pub fn load(workspace_root: &Path) -> Result<Self> {
+        let path = workspace_root.join(CONFIG_FILE);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+        toml::from_str(&text).with_context(|| format!("Invalid {}", path.display()))
+    }
+}
@@ -1,6 +1,7 @@
 use ra_ap_ide::LineCol;
 use ra_ap_ide_db::SymbolKind;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt;
 
 const TOLERANCE: u32 = 5;
@@ -126,7 +127,8 @@ impl From<&CursorCoordinates> for LineCol {
 }
 
 /// Information about a definition location
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct DefinitionInfo {
     /// Path to the file containing the definition
     pub file_path: String,
@@ -141,6 +143,11 @@ pub struct DefinitionInfo {
     /// Name of the defined symbol
     pub name: String,
     /// Kind of the symbol (function, struct, etc.)
+    #[serde(with = "symbol_kind")]
+    #[cfg_attr(
+        feature = "schemars",
+        schemars(with = "Option<symbol_kind::SymbolKindDef>")
+    )]
     pub kind: Option<SymbolKind>,
     /// Content of the definition
     pub content: String,
@@ -151,24 +158,79 @@ pub struct DefinitionInfo {
 }
 
 /// Information about a rename operation result
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct RenameResult {
     /// Files that will be changed by the rename operation
     pub file_changes: Vec<FileChange>,
+    /// Files that will be created, moved or deleted (e.g. renaming a module)
+    pub file_system_edits: Vec<FileSystemEdit>,
 }
 
 /// Information about changes to a single file during rename
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct FileChange {
     /// Path to the file that will be changed
     pub file_path: String,
     /// List of text edits to apply to this file
     pub edits: Vec<TextEdit>,
+    /// Content hash (see `RustAnalyzerUtils::content_hash`) of the file the
+    /// edits were computed against. When set, applying the change fails if
+    /// the file has changed since.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_hash: Option<String>,
+}
+
+/// A file system operation that accompanies text edits
+///
+/// When applied together with text edits, file creations run first, then the
+/// text edits (which refer to the original paths), then moves and deletions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FileSystemEdit {
+    /// Create a new file with the given contents
+    CreateFile {
+        /// Absolute path of the file to create
+        path: String,
+        /// Contents of the new file
+        initial_contents: String,
+    },
+    /// Move a file to a new location
+    MoveFile {
+        /// Absolute path of the file to move
+        src: String,
+        /// Absolute destination path
+        dst: String,
+    },
+    /// Move a directory to a new location
+    MoveDir {
+        /// Absolute path of the directory to move
+        src: String,
+        /// Absolute destination path
+        dst: String,
+    },
+    /// Delete a file
+    DeleteFile {
+        /// Absolute path of the file to delete
+        path: String,
+    },
+}
+
+impl fmt::Display for FileSystemEdit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileSystemEdit::CreateFile { path, .. } => write!(f, "create {path}"),
+            FileSystemEdit::MoveFile { src, dst } => write!(f, "move {src} → {dst}"),
+            FileSystemEdit::MoveDir { src, dst } => write!(f, "move dir {src} → {dst}"),
+            FileSystemEdit::DeleteFile { path } => write!(f, "delete {path}"),
+        }
+    }
 }
 
 /// A single text edit within a file
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct TextEdit {
     /// Line number (1-based) where the edit starts
@@ -184,7 +246,8 @@ pub struct TextEdit {
 }
 
 /// A type hint for a given symbol
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct TypeHint {
     pub file_path: String,
     /// Line number (1-based) where the edit starts
@@ -196,7 +259,8 @@ pub struct TypeHint {
 }
 
 /// A completion item for a given cursor position
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct CompletionItem {
     /// The primary name/identifier
     pub name: String,
@@ -219,7 +283,8 @@ pub struct CompletionItem {
 }
 
 /// Information about a reference location
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct ReferenceInfo {
     /// Path to the file containing the reference
     pub file_path: String,
@@ -274,6 +339,9 @@ impl std::fmt::Display for RenameResult {
         for file_change in &self.file_changes {
             writeln!(f, "{file_change}")?;
         }
+        for fs_edit in &self.file_system_edits {
+            writeln!(f, "{fs_edit}")?;
+        }
         Ok(())
     }
 }
@@ -327,7 +395,7 @@ impl std::fmt::Display for ReferenceInfo {
 }
 
 /// Information about a code assist (code action)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct AssistInfo {
     pub id: String,
@@ -344,30 +412,55 @@ impl std::fmt::Display for AssistInfo {
 }
 
 /// Source change for an assist
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct AssistSourceChange {
     pub file_changes: Vec<FileChange>,
+    pub file_system_edits: Vec<FileSystemEdit>,
     pub is_snippet: bool,
 }
 
 impl std::fmt::Display for AssistSourceChange {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Changes to {} files", self.file_changes.len())
+        write!(f, "Changes to {} files", self.file_changes.len())?;
+        for fs_edit in &self.file_system_edits {
+            write!(f, "\n{fs_edit}")?;
+        }
+        Ok(())
     }
 }
 
 // --- New agent-native entity types ---
 
 /// A single diagnostic fix with inline source changes
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct DiagnosticFix {
     pub label: String,
     pub file_changes: Vec<FileChange>,
+    pub file_system_edits: Vec<FileSystemEdit>,
+    /// Why the fix can't be applied, e.g. it edits a file outside the
+    /// workspace; it then has no edits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unavailable: Option<String>,
+}
+
+/// Where a diagnostic comes from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticSource {
+    /// rust-analyzer's own analysis
+    #[default]
+    RustAnalyzer,
+    /// Compiler messages of `cargo check`, `cargo clippy` or the configured
+    /// flycheck command
+    Flycheck,
 }
 
 /// A diagnostic with optional quick-fixes
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct DiagnosticInfo {
     pub message: String,
     pub severity: String,
@@ -378,14 +471,22 @@ pub struct DiagnosticInfo {
     pub end_line: u32,
     pub end_column: u32,
     pub fixes: Vec<DiagnosticFix>,
+    #[serde(default)]
+    pub source: DiagnosticSource,
 }
 
 impl fmt::Display for DiagnosticFix {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "  fix: {}", self.label)?;
+        if let Some(reason) = &self.unavailable {
+            write!(f, " (unavailable: {reason})")?;
+        }
         for fc in &self.file_changes {
             write!(f, "\n    {fc}")?;
         }
+        for fs_edit in &self.file_system_edits {
+            write!(f, "\n    {fs_edit}")?;
+        }
         Ok(())
     }
 }
@@ -397,6 +498,9 @@ impl fmt::Display for DiagnosticInfo {
             "[{}] {}:{}:{}: {} ({})",
             self.severity, self.file_path, self.line, self.column, self.message, self.code
         )?;
+        if self.source == DiagnosticSource::Flycheck {
+            write!(f, " [flycheck]")?;
+        }
         for fix in &self.fixes {
             write!(f, "\n{fix}")?;
         }
@@ -405,7 +509,8 @@ impl fmt::Display for DiagnosticInfo {
 }
 
 /// Compound result for analyze_symbol — everything about a symbol in one call
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct SymbolAnalysis {
     /// Type/hover info (markdown)
     pub type_info: Option<String>,
@@ -424,7 +529,8 @@ pub struct SymbolAnalysis {
 }
 
 /// A caller/callee entry
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct CallerInfo {
     pub name: String,
     pub file_path: String,
@@ -488,7 +594,8 @@ impl fmt::Display for SymbolAnalysis {
 }
 
 /// A file outline item (from file_structure)
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct FileOutlineItem {
     pub name: String,
     pub kind: String,
@@ -517,7 +624,8 @@ impl fmt::Display for FileOutlineItem {
 }
 
 /// A workspace symbol search result
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct SymbolSearchResult {
     pub name: String,
     pub kind: Option<String>,
@@ -549,7 +657,8 @@ impl fmt::Display for SymbolSearchResult {
 }
 
 /// Macro expansion result
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct MacroExpansion {
     pub name: String,
     pub expansion: String,
@@ -566,7 +675,8 @@ impl fmt::Display for MacroExpansion {
 }
 
 /// Function signature help
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct SignatureInfo {
     pub signature: String,
     pub parameters: Vec<String>,
@@ -595,10 +705,31 @@ impl fmt::Display for SignatureInfo {
     }
 }
 
+/// An inlay hint (inferred type, parameter name, ...) in a file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct InlayHintInfo {
+    /// Line number (1-based) where the hint is rendered
+    pub line: u32,
+    /// Column number (1-based) where the hint is rendered
+    pub column: u32,
+    /// Text of the hint as inserted in the source (e.g. `: Vec<u32>` or `name: `)
+    pub label: String,
+    /// Kind of the hint (Type, Parameter, ...)
+    pub kind: String,
+}
+
+impl fmt::Display for InlayHintInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{} {}", self.line, self.column, self.label)
+    }
+}
+
 // --- SSR (Structural Search and Replace) entities ---
 
 /// A single SSR match found in the codebase
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct SsrMatch {
     /// Path to the file containing the match
     pub file_path: String,
@@ -636,7 +767,8 @@ impl fmt::Display for SsrMatch {
 }
 
 /// Result of an SSR operation
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct SsrResult {
     /// All matches found
     pub matches: Vec<SsrMatch>,
@@ -686,3 +818,748 @@ impl fmt::Display for SsrResult {
         Ok(())
     }
 }
+
+/// Diagnostics delta for a set of proposed edits checked in memory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct EditCheckResult {
+    /// Files touched by the proposed edits
+    pub checked_files: Vec<String>,
+    /// Diagnostics that only appear once the edits are applied
+    pub new_diagnostics: Vec<DiagnosticInfo>,
+    /// Diagnostics that go away once the edits are applied
+    pub resolved_diagnostics: Vec<DiagnosticInfo>,
+}
+
+impl fmt::Display for EditCheckResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "## Checked {} file(s): {} new, {} resolved diagnostic(s)",
+            self.checked_files.len(),
+            self.new_diagnostics.len(),
+            self.resolved_diagnostics.len()
+        )?;
+
+        if !self.new_diagnostics.is_empty() {
+            writeln!(f, "\n### New")?;
+            for d in &self.new_diagnostics {
+                writeln!(f, "{d}")?;
+            }
+        }
+
+        if !self.resolved_diagnostics.is_empty() {
+            writeln!(f, "\n### Resolved")?;
+            for d in &self.resolved_diagnostics {
+                writeln!(f, "{d}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Which diagnostics `fix_all` fixes
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct FixAllQuery {
+    /// File to fix, or every file of the workspace's local crates if `None`
+    pub file_path: Option<String>,
+    /// Only fix diagnostics with these codes, e.g. `unused_variables`; every
+    /// code if empty
+    pub codes: Vec<String>,
+}
+
+/// Why `fix_all` left a diagnostic alone
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum UnfixedReason {
+    /// The diagnostic has no fix
+    NoFix,
+    /// The diagnostic has several fixes, so applying one is a choice
+    SeveralFixes,
+    /// The fix creates, moves or deletes files
+    FileSystemEdits,
+    /// The fix can't be applied, see `DiagnosticFix::unavailable`
+    Unavailable,
+    /// The fix overlaps the edits of a fix that was applied; fixing again
+    /// may apply it
+    Conflict,
+}
+
+impl fmt::Display for UnfixedReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self {
+            UnfixedReason::NoFix => "no fix",
+            UnfixedReason::SeveralFixes => "several fixes",
+            UnfixedReason::FileSystemEdits => "fix changes files",
+            UnfixedReason::Unavailable => "fix unavailable",
+            UnfixedReason::Conflict => "conflicts with another fix",
+        };
+        write!(f, "{reason}")
+    }
+}
+
+/// A diagnostic `fix_all` left alone
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct UnfixedDiagnostic {
+    pub diagnostic: DiagnosticInfo,
+    pub reason: UnfixedReason,
+}
+
+/// Outcome of fixing every safe diagnostic fix at once
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct FixAllResult {
+    /// Diagnostics fixed, each with the fix that was applied
+    pub fixed: Vec<DiagnosticInfo>,
+    /// Matching diagnostics left alone, and why
+    pub remaining: Vec<UnfixedDiagnostic>,
+    /// Edits of all applied fixes, one change per file
+    pub file_changes: Vec<FileChange>,
+}
+
+impl fmt::Display for FixAllResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "## Fixed {} diagnostic(s) in {} file(s), {} remaining",
+            self.fixed.len(),
+            self.file_changes.len(),
+            self.remaining.len()
+        )?;
+
+        if !self.fixed.is_empty() {
+            writeln!(f, "\n### Fixed")?;
+            for d in &self.fixed {
+                write!(
+                    f,
+                    "{}:{}:{}: {} ({})",
+                    d.file_path, d.line, d.column, d.message, d.code
+                )?;
+                match d.fixes.first() {
+                    Some(fix) => writeln!(f, " — {}", fix.label)?,
+                    None => writeln!(f)?,
+                }
+            }
+        }
+
+        if !self.remaining.is_empty() {
+            writeln!(f, "\n### Remaining")?;
+            for unfixed in &self.remaining {
+                let d = &unfixed.diagnostic;
+                writeln!(
+                    f,
+                    "{}:{}:{}: {} ({}) — {}",
+                    d.file_path, d.line, d.column, d.message, d.code, unfixed.reason
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Which diagnostics rust-analyzer reports
+///
+/// Options come in layers: the `[diagnostics]` section of the workspace's
+/// `rustbelt.toml`, options set on the loaded workspace, then options of a
+/// single call. See `merged`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct DiagnosticsOptions {
+    /// Diagnostic codes to leave out, e.g. `inactive-code` or
+    /// `unlinked-file`
+    pub disabled: Vec<String>,
+    /// Diagnostic codes to report again, when a lower layer disabled them
+    pub enabled: Vec<String>,
+    /// Report style lints, such as non-idiomatic naming (default: false)
+    pub style_lints: Option<bool>,
+    /// Report experimental diagnostics, which may be false positives
+    /// (default: true)
+    pub experimental: Option<bool>,
+}
+
+impl DiagnosticsOptions {
+    /// These options with `overrides` layered on top
+    ///
+    /// Codes disabled by either are disabled unless `overrides` enables them
+    /// again, and flags set in `overrides` win.
+    pub fn merged(&self, overrides: &DiagnosticsOptions) -> DiagnosticsOptions {
+        let mut disabled: Vec<String> = self
+            .disabled
+            .iter()
+            .filter(|code| !overrides.enabled.contains(code))
+            .cloned()
+            .collect();
+        for code in &overrides.disabled {
+            if !disabled.contains(code) {
+                disabled.push(code.clone());
+            }
+        }
+        let mut enabled: Vec<String> = self
+            .enabled
+            .iter()
+            .filter(|code| !overrides.disabled.contains(code))
+            .cloned()
+            .collect();
+        for code in &overrides.enabled {
+            if !enabled.contains(code) {
+                enabled.push(code.clone());
+            }
+        }
+        DiagnosticsOptions {
+            disabled,
+            enabled,
+            style_lints: overrides.style_lints.or(self.style_lints),
+            experimental: overrides.experimental.or(self.experimental),
+        }
+    }
+
+    /// Whether diagnostics with a code are reported
+    pub fn is_enabled(&self, code: &str) -> bool {
+        !self.disabled.iter().any(|disabled| disabled == code)
+    }
+}
+
+impl fmt::Display for DiagnosticsOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.disabled.is_empty() {
+            writeln!(f, "Disabled codes: none")?;
+        } else {
+            writeln!(f, "Disabled codes: {}", self.disabled.join(", "))?;
+        }
+        writeln!(
+            f,
+            "Style lints: {}",
+            if self.style_lints.unwrap_or(false) {
+                "on"
+            } else {
+                "off"
+            }
+        )?;
+        write!(
+            f,
+            "Experimental diagnostics: {}",
+            if self.experimental.unwrap_or(true) {
+                "on"
+            } else {
+                "off"
+            }
+        )
+    }
+}
+
+/// Filters and page of a workspace-wide diagnostics sweep
+///
+/// Empty filters match everything. Files are filtered before their
+/// diagnostics are computed, so narrowing by crate, path or git ref also
+/// makes the sweep faster.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(default)]
+pub struct WorkspaceDiagnosticsQuery {
+    /// Only report these severities: `Error`, `Warning`, `WeakWarning` or
+    /// `Allow` (case-insensitive)
+    pub severities: Vec<String>,
+    /// Only report these diagnostic codes, e.g. `E0308` or `unused_variables`
+    pub codes: Vec<String>,
+    /// Only check the files of these crates
+    pub crates: Vec<String>,
+    /// Only check files matching this glob, relative to the workspace root
+    /// unless absolute. `*` and `?` match within a path component, `**`
+    /// matches any number of components.
+    pub path_glob: Option<String>,
+    /// Only check files changed since this git ref, including uncommitted
+    /// and untracked files
+    pub changed_since: Option<String>,
+    /// Include quick-fixes, which are slower to compute
+    pub include_fixes: bool,
+    /// Diagnostics options of this sweep, on top of the workspace's
+    pub options: DiagnosticsOptions,
+    /// Where to resume, from `next_cursor` of the previous page; the sweep
+    /// starts at the first file otherwise
+    pub cursor: Option<DiagnosticsCursor>,
+    /// Maximum number of diagnostics to return
+    pub limit: usize,
+    /// Stop checking files once this passes and report what was found so far
+    #[serde(skip)]
+    pub deadline: Option<std::time::Instant>,
+}
+
+impl Default for WorkspaceDiagnosticsQuery {
+    fn default() -> Self {
+        Self {
+            severities: Vec::new(),
+            codes: Vec::new(),
+            crates: Vec::new(),
+            path_glob: None,
+            changed_since: None,
+            include_fixes: false,
+            options: DiagnosticsOptions::default(),
+            cursor: None,
+            limit: 100,
+            deadline: None,
+        }
+    }
+}
+
+/// Position in a workspace diagnostics sweep, to resume it from
+///
+/// Files are swept in path order, so a page resumes in the file it stopped
+/// in even if files are checked in different runs, e.g. after a deadline.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct DiagnosticsCursor {
+    /// File to resume in; files sorted before it are skipped
+    pub file_path: String,
+    /// Number of the file's matching diagnostics to skip
+    pub index: usize,
+}
+
+impl fmt::Display for DiagnosticsCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{{\"file_path\": {}, \"index\": {}}}",
+            serde_json::Value::from(self.file_path.as_str()),
+            self.index
+        )
+    }
+}
+
+/// A page of the diagnostics of a workspace
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct WorkspaceDiagnostics {
+    /// Number of files checked, from the cursor on
+    pub checked_files: usize,
+    /// Number of files left unchecked because the deadline passed
+    pub unchecked_files: usize,
+    /// Number of diagnostics matching the filters in the checked files, from
+    /// the cursor on
+    pub total: usize,
+    /// Matching diagnostics by severity in the checked files, from the
+    /// cursor on
+    pub severity_counts: BTreeMap<String, usize>,
+    /// Diagnostics of this page, ordered by file and position
+    pub diagnostics: Vec<DiagnosticInfo>,
+    /// Where the next page starts, if there are more diagnostics or files
+    /// left unchecked
+    pub next_cursor: Option<DiagnosticsCursor>,
+}
+
+impl fmt::Display for WorkspaceDiagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "## {} diagnostic(s) in {} checked file(s)",
+            self.total, self.checked_files
+        )?;
+        if !self.severity_counts.is_empty() {
+            let counts: Vec<String> = self
+                .severity_counts
+                .iter()
+                .map(|(severity, count)| format!("{count} {severity}"))
+                .collect();
+            write!(f, ": {}", counts.join(", "))?;
+        }
+        writeln!(f)?;
+        if self.unchecked_files > 0 {
+            writeln!(
+                f,
+                "Ran out of time, {} file(s) not checked; narrow the query to check them",
+                self.unchecked_files
+            )?;
+        }
+
+        for d in &self.diagnostics {
+            writeln!(f, "\n{d}")?;
+        }
+
+        if let Some(next_cursor) = &self.next_cursor {
+            writeln!(
+                f,
+                "\nShowing {} of {}; pass cursor {next_cursor} for the next page",
+                self.diagnostics.len(),
+                self.total
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Cargo configuration a workspace is analyzed with
+///
+/// The default analyzes the host target with the default features, like a
+/// plain `cargo check`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct CargoOptions {
+    /// Features to enable
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// Enable all features
+    #[serde(default)]
+    pub all_features: bool,
+    /// Disable the default features
+    #[serde(default)]
+    pub no_default_features: bool,
+    /// Target triple to analyze for, instead of the host's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    /// Extra cfg options for every crate, as `name` or `name=value`.
+    /// Prefix one with `!` to disable it instead.
+    #[serde(default)]
+    pub cfgs: Vec<String>,
+    /// Extra environment variables for cargo, build scripts and `env!`
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+impl CargoOptions {
+    /// Whether these are the default options
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for CargoOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if self.all_features {
+            parts.push("all features".to_string());
+        } else {
+            if self.no_default_features {
+                parts.push("no default features".to_string());
+            }
+            if !self.features.is_empty() {
+                parts.push(format!("features {}", self.features.join(",")));
+            }
+        }
+        if let Some(target) = &self.target {
+            parts.push(format!("target {target}"));
+        }
+        if !self.cfgs.is_empty() {
+            parts.push(format!("cfg {}", self.cfgs.join(",")));
+        }
+        if !self.env.is_empty() {
+            let keys: Vec<_> = self.env.keys().map(String::as_str).collect();
+            parts.push(format!("env {}", keys.join(",")));
+        }
+        if parts.is_empty() {
+            write!(f, "default features")
+        } else {
+            write!(f, "{}", parts.join(", "))
+        }
+    }
+}
+
+/// Information about a workspace loaded in a `WorkspaceRegistry`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct WorkspaceInfo {
+    /// Project root the workspace was loaded from
+    pub root: String,
+    /// Approximate memory allocated while loading the workspace, in MB
+    pub memory_mb: u64,
+    /// Seconds since the workspace was loaded
+    pub loaded_secs_ago: u64,
+    /// Seconds since the workspace was last used
+    pub idle_secs: u64,
+    /// Cargo configuration the workspace was loaded with
+    pub cargo_options: CargoOptions,
+}
+
+impl fmt::Display for WorkspaceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (~{}MB, loaded {}s ago, idle {}s",
+            self.root, self.memory_mb, self.loaded_secs_ago, self.idle_secs
+        )?;
+        if !self.cargo_options.is_default() {
+            write!(f, ", {}", self.cargo_options)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// A step of loading a workspace, as reported to progress callbacks
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(tag = "phase", rename_all = "snake_case")]
+pub enum LoadProgress {
+    /// Discovering the project and fetching its metadata, e.g. with
+    /// `cargo metadata`
+    Metadata { message: String },
+    /// Building build scripts and proc-macros
+    BuildScripts { message: String },
+    /// Loading source files and proc-macros into the database
+    LoadingFiles,
+    /// Indexing crates so semantic queries are fast
+    Indexing {
+        /// What is being computed, e.g. "Indexing"
+        work: String,
+        done: usize,
+        total: usize,
+        /// Crates currently being indexed
+        crates: Vec<String>,
+    },
+    /// The workspace is loaded
+    Ready,
+}
+
+impl LoadProgress {
+    /// Rough overall completion of the load, in percent
+    ///
+    /// Fetching metadata and building build scripts can't tell how far along
+    /// they are, so only indexing moves the value within its phase.
+    pub fn percent(&self) -> f64 {
+        match self {
+            LoadProgress::Metadata { .. } => 5.0,
+            LoadProgress::BuildScripts { .. } => 20.0,
+            LoadProgress::LoadingFiles => 40.0,
+            LoadProgress::Indexing { done, total, .. } if *total > 0 => {
+                40.0 + 60.0 * (*done).min(*total) as f64 / *total as f64
+            }
+            LoadProgress::Indexing { .. } => 40.0,
+            LoadProgress::Ready => 100.0,
+        }
+    }
+}
+
+impl fmt::Display for LoadProgress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadProgress::Metadata { message } => write!(f, "Fetching metadata: {message}"),
+            LoadProgress::BuildScripts { message } => {
+                write!(f, "Building build scripts: {message}")
+            }
+            LoadProgress::LoadingFiles => write!(f, "Loading source files and proc-macros"),
+            LoadProgress::Indexing {
+                work,
+                done,
+                total,
+                crates,
+            } => {
+                write!(f, "{work} {done}/{total} crates")?;
+                if !crates.is_empty() {
+                    write!(f, " ({})", crates.join(", "))?;
+                }
+                Ok(())
+            }
+            LoadProgress::Ready => write!(f, "Workspace ready"),
+        }
+    }
+}
+
+/// Whether a workspace known to a `WorkspaceRegistry` can be queried
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum WorkspaceState {
+    /// The workspace is being loaded, or reloaded
+    Loading,
+    /// The workspace is loaded and answers semantic queries
+    Ready,
+    /// The last attempt to load the workspace failed
+    Failed,
+}
+
+impl fmt::Display for WorkspaceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorkspaceState::Loading => write!(f, "loading"),
+            WorkspaceState::Ready => write!(f, "ready"),
+            WorkspaceState::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// Loading status of a workspace in a `WorkspaceRegistry`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct WorkspaceStatus {
+    /// Project root of the workspace
+    pub root: String,
+    pub state: WorkspaceState,
+    /// Latest loading progress, while the workspace is loading
+    pub progress: Option<LoadProgress>,
+    /// Crates indexed so far, or in total once loaded
+    pub crates_indexed: Option<usize>,
+    /// Crates to index
+    pub crates_total: Option<usize>,
+    /// Approximate memory allocated while loading the workspace, in MB, once
+    /// loaded
+    pub memory_mb: Option<u64>,
+    /// Seconds since loading started, or since the workspace was loaded
+    pub elapsed_secs: u64,
+    /// Whether semantic queries (types, references, diagnostics...) can be
+    /// answered. This stays true while a loaded workspace is reloaded, since
+    /// the previous analysis keeps answering until the reload completes.
+    pub semantic_ready: bool,
+    /// Why the last load failed
+    pub error: Option<String>,
+}
+
+impl fmt::Display for WorkspaceStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} for {}s",
+            self.root, self.state, self.elapsed_secs
+        )?;
+        if let (Some(indexed), Some(total)) = (self.crates_indexed, self.crates_total) {
+            write!(f, ", {indexed}/{total} crates indexed")?;
+        }
+        if let Some(memory_mb) = self.memory_mb {
+            write!(f, ", ~{memory_mb}MB")?;
+        }
+        if self.state != WorkspaceState::Ready {
+            let semantic = if self.semantic_ready {
+                "available"
+            } else {
+                "not available yet"
+            };
+            write!(f, ", semantic queries {semantic}")?;
+        }
+        if let Some(progress) = &self.progress {
+            write!(f, "\n  {progress}")?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, "\n  {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// An edit recorded in the undo/redo journal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct EditRecord {
+    /// Journal identifier of the edit
+    pub id: u64,
+    /// Human readable description (e.g. "Rename to `new_name`")
+    pub description: String,
+    /// Unix timestamp of when the edit was applied, in seconds
+    pub timestamp: u64,
+    /// Files created, modified, moved or deleted by the edit
+    pub files: Vec<String>,
+    /// Whether the edit has been undone (and can be redone)
+    pub undone: bool,
+}
+
+impl fmt::Display for EditRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} {}", self.id, self.description)?;
+        if self.undone {
+            write!(f, " (undone)")?;
+        }
+        writeln!(f)?;
+        for file in &self.files {
+            writeln!(f, "  - {file}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Unified diff of the changes to a single file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct FileDiff {
+    /// Path of the file before the change (absolute)
+    pub file_path: String,
+    /// Git-style unified diff, including `diff --git`, `---` and `+++` headers.
+    /// Paths in the headers are relative to the workspace root.
+    pub diff: String,
+}
+
+/// Preview of an edit as a patch, without touching the files on disk
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub struct DiffPreview {
+    /// One diff per file created, modified, moved or deleted
+    pub files: Vec<FileDiff>,
+}
+
+impl DiffPreview {
+    /// The whole patch, suitable for `git apply` from the workspace root
+    pub fn patch(&self) -> String {
+        self.files.iter().map(|file| file.diff.as_str()).collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+}
+
+impl fmt::Display for DiffPreview {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.patch())
+    }
+}
+
+/// Serde support for rust-analyzer's `SymbolKind`, which has no serde impls
+mod symbol_kind {
+    use ra_ap_ide_db::SymbolKind;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    /// Mirror of `SymbolKind`, serialized as its variant name
+    #[derive(Serialize, Deserialize)]
+    #[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+    #[serde(remote = "SymbolKind")]
+    pub enum SymbolKindDef {
+        Attribute,
+        BuiltinAttr,
+        Const,
+        ConstParam,
+        CrateRoot,
+        Derive,
+        DeriveHelper,
+        Enum,
+        Field,
+        Function,
+        Method,
+        Impl,
+        InlineAsmRegOrRegClass,
+        Label,
+        LifetimeParam,
+        Local,
+        Macro,
+        ProcMacro,
+        Module,
+        SelfParam,
+        SelfType,
+        Static,
+        Struct,
+        ToolModule,
+        Trait,
+        TypeAlias,
+        TypeParam,
+        Union,
+        ValueParam,
+        Variant,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Wrapper(#[serde(with = "SymbolKindDef")] SymbolKind);
+
+    pub fn serialize<S: Serializer>(
+        kind: &Option<SymbolKind>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        kind.map(Wrapper).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<SymbolKind>, D::Error> {
+        Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(kind)| kind))
+    }
+}
@@ -3,6 +3,7 @@
 //! This module handles file system watching and VFS synchronization,
 //! keeping the analysis host updated with file changes.
 
+use std::collections::HashMap;
 use std::path::Path;
 
 use anyhow::{Context, Result};
@@ -10,7 +11,7 @@ use crossbeam_channel::{Receiver, unbounded};
 use ra_ap_ide::{AnalysisHost, FileId};
 use ra_ap_ide_db::ChangeWithProcMacros;
 use ra_ap_vfs::loader::{Handle, Message};
-use ra_ap_vfs::{AbsPathBuf, Vfs, VfsPath};
+use ra_ap_vfs::{AbsPathBuf, AnchoredPathBuf, Vfs, VfsPath};
 use ra_ap_vfs_notify::NotifyHandle;
 use tracing::{debug, error, trace};
 
@@ -24,6 +25,8 @@ pub struct FileWatcher {
     vfs_handle: Option<NotifyHandle>,
     vfs_receiver: Option<Receiver<Message>>,
     vfs: Vfs,
+    /// In-memory contents that shadow the on-disk contents of a file
+    overlays: HashMap<FileId, String>,
 }
 
 impl Default for FileWatcher {
@@ -39,6 +42,7 @@ impl FileWatcher {
             vfs_handle: None,
             vfs_receiver: None,
             vfs: Vfs::default(),
+            overlays: HashMap::new(),
         }
     }
 
@@ -86,6 +90,13 @@ impl FileWatcher {
         Ok(())
     }
 
+    /// Check whether the file watcher sent messages that weren't drained yet
+    pub fn has_pending_changes(&self) -> bool {
+        self.vfs_receiver
+            .as_ref()
+            .is_some_and(|receiver| !receiver.is_empty())
+    }
+
     /// Drain all pending messages from the file watcher and apply changes synchronously
     ///
     /// If file watching is disabled (no receiver), this is a no-op.
@@ -123,6 +134,11 @@ impl FileWatcher {
         }
         let mut change = ChangeWithProcMacros::default();
         for (file_id, changed_file) in changed_files {
+            // Overlays take precedence over on-disk contents until cleared
+            if self.overlays.contains_key(&file_id) {
+                trace!("Skipping disk change for overlaid file: {:?}", file_id);
+                continue;
+            }
             let new_contents = match changed_file.change {
                 ra_ap_vfs::Change::Create(v, _) | ra_ap_vfs::Change::Modify(v, _) => {
                     if let Ok(text) = std::str::from_utf8(&v) {
@@ -141,6 +157,58 @@ impl FileWatcher {
         Ok(())
     }
 
+    /// Shadow the on-disk contents of a file with in-memory text
+    ///
+    /// The file must already be part of the VFS. Changes on disk are ignored
+    /// for the file until the overlay is cleared.
+    pub fn set_overlay(
+        &mut self,
+        path: &Path,
+        text: String,
+        host: &mut AnalysisHost,
+    ) -> Result<()> {
+        let file_id = self.get_file_id(path)?;
+        let mut change = ChangeWithProcMacros::default();
+        change.change_file(file_id, Some(text.clone()));
+        host.apply_change(change);
+        self.overlays.insert(file_id, text);
+        debug!("Overlay set for file: {}", path.display());
+        Ok(())
+    }
+
+    /// Remove the overlay for a file and restore its on-disk contents
+    ///
+    /// Returns false if the file had no overlay.
+    pub fn clear_overlay(&mut self, path: &Path, host: &mut AnalysisHost) -> Result<bool> {
+        let file_id = self.get_file_id(path)?;
+        if self.overlays.remove(&file_id).is_none() {
+            return Ok(false);
+        }
+
+        let disk_contents = std::fs::read_to_string(path).ok();
+        let mut change = ChangeWithProcMacros::default();
+        change.change_file(file_id, disk_contents);
+        host.apply_change(change);
+        debug!("Overlay cleared for file: {}", path.display());
+        Ok(true)
+    }
+
+    /// Get the overlay text for a file, if any
+    pub fn overlay(&self, file_id: FileId) -> Option<&str> {
+        self.overlays.get(&file_id).map(String::as_str)
+    }
+
+    /// Paths of all files that currently have an overlay
+    pub fn overlay_paths(&self) -> Vec<String> {
+        let mut paths: Vec<_> = self
+            .overlays
+            .keys()
+            .filter_map(|file_id| self.file_path(*file_id))
+            .collect();
+        paths.sort();
+        paths
+    }
+
     /// Configure VFS to watch workspace files
     fn configure_vfs_watching(&mut self, abs_project_root: AbsPathBuf) -> Result<()> {
         let Some(ref mut loader) = self.vfs_handle else {
@@ -201,6 +269,18 @@ impl FileWatcher {
         }
     }
 
+    /// Resolve a path relative to an anchor file into an absolute path
+    pub fn resolve_anchored_path(&self, path: &AnchoredPathBuf) -> Option<String> {
+        if !self.vfs.exists(path.anchor) {
+            return None;
+        }
+        let anchor = self.vfs.file_path(path.anchor);
+        anchor
+            .parent()?
+            .join(&path.path)
+            .map(|resolved| resolved.to_string())
+    }
+
     /// Get a reference to the VFS
     pub fn vfs(&self) -> &Vfs {
         &self.vfs
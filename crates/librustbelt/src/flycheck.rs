@@ -0,0 +1,400 @@
+//! Diagnostics from `cargo check` and `cargo clippy`
+//!
+//! rust-analyzer's native diagnostics miss borrow-checker errors, most
+//! trait-solver errors and every clippy lint. Like rust-analyzer's own
+//! flycheck, this runs cargo with `--message-format=json` for a package and
+//! turns the compiler messages into `DiagnosticInfo`s, with their
+//! machine-applicable suggestions as fixes.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use anyhow::{Context, Result};
+use ra_ap_ide::{LineCol, LineIndex};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::process::Command;
+use tracing::debug;
+
+use super::entities::{
+    CargoOptions, DiagnosticFix, DiagnosticInfo, DiagnosticSource, FileChange, TextEdit,
+};
+use super::utils::RustAnalyzerUtils;
+
+/// How to run the flycheck command
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlycheckConfig {
+    /// Cargo subcommand to run, e.g. `check` or `clippy`
+    pub command: String,
+    /// Extra arguments after the subcommand, e.g. `--all-targets`
+    pub extra_args: Vec<String>,
+    /// Pass `--offline`, so cargo never touches the network
+    pub offline: bool,
+    /// Command to run instead of cargo, from the package directory. It must
+    /// print compiler messages as JSON, like `cargo check
+    /// --message-format=json`.
+    pub override_command: Option<Vec<String>>,
+}
+
+impl Default for FlycheckConfig {
+    fn default() -> Self {
+        Self {
+            command: "check".to_string(),
+            extra_args: Vec::new(),
+            offline: false,
+            override_command: None,
+        }
+    }
+}
+
+impl FlycheckConfig {
+    /// The same configuration running another cargo subcommand
+    pub fn with_command(&self, command: impl Into<String>) -> Self {
+        Self {
+            command: command.into(),
+            ..self.clone()
+        }
+    }
+}
+
+/// Run the flycheck command for the package of a file
+///
+/// Features and the target come from `cargo_options`; cfgs aren't passed,
+/// as that would take `RUSTFLAGS` and rebuild every dependency. Returns the
+/// diagnostics of every workspace file the compiler reported on.
+pub async fn check(
+    config: &FlycheckConfig,
+    file_path: &Path,
+    cargo_options: &CargoOptions,
+) -> Result<Vec<DiagnosticInfo>> {
+    let manifest = package_manifest(file_path).ok_or_else(|| {
+        anyhow::anyhow!(
+            "No Cargo.toml found for {}; flycheck only supports Cargo packages",
+            file_path.display()
+        )
+    })?;
+    let package_dir = manifest.parent().unwrap_or(Path::new("."));
+    let workspace_root = workspace_root(&manifest)
+        .await
+        .unwrap_or_else(|| package_dir.to_path_buf());
+
+    let mut command = match &config.override_command {
+        Some(override_command) => {
+            let (program, args) = override_command
+                .split_first()
+                .context("The flycheck override command is empty")?;
+            let mut command = Command::new(program);
+            command.args(args);
+            command
+        }
+        None => {
+            let mut command = Command::new(cargo());
+            command
+                .arg(&config.command)
+                .arg("--message-format=json")
+                .arg("--manifest-path")
+                .arg(&manifest);
+            if config.offline {
+                command.arg("--offline");
+            }
+            if cargo_options.all_features {
+                command.arg("--all-features");
+            } else {
+                if cargo_options.no_default_features {
+                    command.arg("--no-default-features");
+                }
+                if !cargo_options.features.is_empty() {
+                    command
+                        .arg("--features")
+                        .arg(cargo_options.features.join(","));
+                }
+            }
+            if let Some(target) = &cargo_options.target {
+                command.arg("--target").arg(target);
+            }
+            command.args(&config.extra_args);
+            command
+        }
+    };
+    command
+        .current_dir(package_dir)
+        .envs(&cargo_options.env)
+        .stdin(Stdio::null())
+        .kill_on_drop(true);
+    debug!("Running flycheck: {command:?}");
+
+    let output = command
+        .output()
+        .await
+        .with_context(|| format!("Failed to run flycheck for {}", manifest.display()))?;
+
+    let mut sources = SourceFiles::default();
+    let mut diagnostics: Vec<DiagnosticInfo> = Vec::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let Ok(message) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        if message["reason"] != "compiler-message" {
+            continue;
+        }
+        let Some(diagnostic) = to_diagnostic(&message["message"], &workspace_root, &mut sources)
+        else {
+            continue;
+        };
+        // Targets sharing files (lib and tests) report the same messages
+        let duplicate = diagnostics.iter().any(|d| {
+            d.file_path == diagnostic.file_path
+                && d.line == diagnostic.line
+                && d.column == diagnostic.column
+                && d.code == diagnostic.code
+                && d.message == diagnostic.message
+        });
+        if !duplicate {
+            diagnostics.push(diagnostic);
+        }
+    }
+
+    // Cargo fails when the code doesn't compile, which is what we're after;
+    // only a failure without any message is an error
+    if diagnostics.is_empty() && !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("Flycheck failed ({}): {}", output.status, stderr.trim());
+    }
+    Ok(diagnostics)
+}
+
+/// Add flycheck diagnostics to native ones, skipping the ones rust-analyzer
+/// already reports
+///
+/// A native diagnostic without fixes takes the fixes of its flycheck twin.
+pub fn merge(mut native: Vec<DiagnosticInfo>, checked: Vec<DiagnosticInfo>) -> Vec<DiagnosticInfo> {
+    for diagnostic in checked {
+        let twin = native.iter_mut().find(|d| {
+            d.source == DiagnosticSource::RustAnalyzer
+                && d.file_path == diagnostic.file_path
+                && d.line == diagnostic.line
+                && ((!d.code.is_empty() && d.code == diagnostic.code)
+                    || d.message == diagnostic.message)
+        });
+        match twin {
+            Some(twin) => {
+                if twin.fixes.is_empty() {
+                    twin.fixes = diagnostic.fixes;
+                }
+            }
+            None => native.push(diagnostic),
+        }
+    }
+    native.sort_by_key(|d| (d.line, d.column));
+    native
+}
+
+/// Turn a rustc JSON diagnostic into a `DiagnosticInfo` on its primary span
+///
+/// Returns `None` for notes and messages without a span in the workspace,
+/// like the summary "aborting due to previous error".
+fn to_diagnostic(
+    message: &Value,
+    workspace_root: &Path,
+    sources: &mut SourceFiles,
+) -> Option<DiagnosticInfo> {
+    let severity = match message["level"].as_str()? {
+        "error" | "error: internal compiler error" => "Error",
+        "warning" => "Warning",
+        _ => return None,
+    };
+    let primary = message["spans"]
+        .as_array()?
+        .iter()
+        .find(|span| span["is_primary"] == true)?;
+    let (span, path) = workspace_span(primary, workspace_root)?;
+    let (line, column, end_line, end_column) = sources.position(&path, span)?;
+
+    let mut text = message["message"].as_str()?.to_string();
+    for child in message["children"].as_array().into_iter().flatten() {
+        if let Some(child_message) = child["message"].as_str()
+            && !child_message.is_empty()
+        {
+            let level = child["level"].as_str().unwrap_or("note");
+            text.push_str(&format!("\n{level}: {child_message}"));
+        }
+    }
+
+    let fixes = std::iter::once(message)
+        .chain(message["children"].as_array().into_iter().flatten())
+        .filter_map(|m| to_fix(m, workspace_root, sources))
+        .collect();
+
+    Some(DiagnosticInfo {
+        message: text,
+        severity: severity.to_string(),
+        code: message["code"]["code"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string(),
+        file_path: path.display().to_string(),
+        line,
+        column,
+        end_line,
+        end_column,
+        fixes,
+        source: DiagnosticSource::Flycheck,
+    })
+}
+
+/// The machine-applicable suggestions of a message as one fix
+fn to_fix(
+    message: &Value,
+    workspace_root: &Path,
+    sources: &mut SourceFiles,
+) -> Option<DiagnosticFix> {
+    let mut file_changes: Vec<FileChange> = Vec::new();
+    for span in message["spans"].as_array()? {
+        if span["suggestion_applicability"] != "MachineApplicable" {
+            continue;
+        }
+        let Some(new_text) = span["suggested_replacement"].as_str() else {
+            continue;
+        };
+        // Suggestions inside macro expansions can't be applied to the source
+        let path = in_workspace(span["file_name"].as_str()?, workspace_root)?;
+        let (line, column, end_line, end_column) = sources.position(&path, span)?;
+        let edit = TextEdit {
+            line,
+            column,
+            end_line,
+            end_column,
+            new_text: new_text.to_string(),
+        };
+        let file_path = path.display().to_string();
+        match file_changes.iter_mut().find(|fc| fc.file_path == file_path) {
+            Some(file_change) => file_change.edits.push(edit),
+            None => file_changes.push(FileChange {
+                file_path,
+                edits: vec![edit],
+                source_hash: sources.hash(&path),
+            }),
+        }
+    }
+    if file_changes.is_empty() {
+        return None;
+    }
+    Some(DiagnosticFix {
+        label: message["message"].as_str()?.to_string(),
+        file_changes,
+        file_system_edits: Vec::new(),
+        unavailable: None,
+    })
+}
+
+/// The span itself if it is in a workspace file, otherwise the first macro
+/// call site in one
+fn workspace_span<'a>(mut span: &'a Value, workspace_root: &Path) -> Option<(&'a Value, PathBuf)> {
+    loop {
+        if let Some(path) = in_workspace(span["file_name"].as_str()?, workspace_root) {
+            return Some((span, path));
+        }
+        span = span.get("expansion")?.get("span")?;
+    }
+}
+
+/// Canonical path of a file named in a compiler message, if it is an
+/// existing file of the workspace
+fn in_workspace(file_name: &str, workspace_root: &Path) -> Option<PathBuf> {
+    let path = workspace_root.join(file_name).canonicalize().ok()?;
+    (path.starts_with(workspace_root) && path.is_file()).then_some(path)
+}
+
+/// Line indexes and hashes of the files compiler messages point to
+#[derive(Default)]
+struct SourceFiles(HashMap<PathBuf, Option<SourceFile>>);
+
+struct SourceFile {
+    text: String,
+    line_index: LineIndex,
+    hash: String,
+}
+
+impl SourceFile {
+    /// 1-based line and UTF-8 column of a 1-based line and character column
+    fn column(&self, line: &Value, column: &Value) -> Option<(u32, u32)> {
+        let line = u32::try_from(line.as_u64()?.checked_sub(1)?).ok()?;
+        let chars = usize::try_from(column.as_u64()?.checked_sub(1)?).ok()?;
+        let start = self.line_index.offset(LineCol { line, col: 0 })?;
+        let text = &self.text[usize::from(start)..];
+        let text = &text[..text.find('\n').unwrap_or(text.len())];
+        let (mut taken, mut bytes) = (0, 0);
+        for c in text.chars().take(chars) {
+            taken += 1;
+            bytes += c.len_utf8();
+        }
+        // A column past the end of the line means the file changed since
+        (taken == chars).then_some((line + 1, bytes as u32 + 1))
+    }
+}
+
+impl SourceFiles {
+    fn get(&mut self, path: &Path) -> Option<&SourceFile> {
+        self.0
+            .entry(path.to_path_buf())
+            .or_insert_with(|| {
+                let text = std::fs::read_to_string(path).ok()?;
+                Some(SourceFile {
+                    line_index: LineIndex::new(&text),
+                    hash: RustAnalyzerUtils::content_hash(&text),
+                    text,
+                })
+            })
+            .as_ref()
+    }
+
+    /// 1-based start and end line and column of a span
+    ///
+    /// The byte offsets of spans are into the source as rustc normalized it,
+    /// without a BOM and with CRLF line endings turned into LF, so the lines
+    /// and columns are used instead. rustc counts columns in characters,
+    /// while the columns of rustbelt are UTF-8 byte offsets into the line.
+    fn position(&mut self, path: &Path, span: &Value) -> Option<(u32, u32, u32, u32)> {
+        let file = self.get(path)?;
+        let (line, column) = file.column(&span["line_start"], &span["column_start"])?;
+        let (end_line, end_column) = file.column(&span["line_end"], &span["column_end"])?;
+        Some((line, column, end_line, end_column))
+    }
+
+    fn hash(&mut self, path: &Path) -> Option<String> {
+        self.get(path).map(|file| file.hash.clone())
+    }
+}
+
+/// Nearest Cargo.toml above a file
+fn package_manifest(file_path: &Path) -> Option<PathBuf> {
+    let file_path = file_path.canonicalize().ok()?;
+    file_path
+        .ancestors()
+        .skip(1)
+        .map(|dir| dir.join("Cargo.toml"))
+        .find(|manifest| manifest.is_file())
+}
+
+/// Root of the Cargo workspace of a package, which paths in compiler
+/// messages are relative to
+async fn workspace_root(manifest: &Path) -> Option<PathBuf> {
+    let output = Command::new(cargo())
+        .args(["locate-project", "--workspace", "--message-format", "plain"])
+        .arg("--manifest-path")
+        .arg(manifest)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let workspace_manifest = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    workspace_manifest.parent()?.canonicalize().ok()
+}
+
+fn cargo() -> String {
+    std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string())
+}
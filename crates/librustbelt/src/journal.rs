@@ -0,0 +1,421 @@
+//! Journal of disk edits for undo and redo
+//!
+//! Every operation that writes to disk (rename, assists, SSR) records the
+//! contents of the files it touches before and after the edit. The journal is
+//! stored in the workspace's `target/rustbelt` directory so it survives across
+//! CLI invocations and server restarts, but not `cargo clean`, which erases
+//! the undo history along with the build artifacts.
+//!
+//! Transactions hold a lock on `edit-journal.lock` next to the journal, so
+//! processes editing the same workspace, like an MCP server and the CLI,
+//! take turns.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex as StdMutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tracing::debug;
+
+use super::builder::RustAnalyzerishBuilder;
+use super::entities::{EditRecord, FileChange, FileSystemEdit};
+use super::utils::RustAnalyzerUtils;
+
+/// Maximum number of undoable edits kept in the journal
+const MAX_ENTRIES: usize = 50;
+
+/// Locks serializing the transactions on each journal in this process, by
+/// journal path
+static TRANSACTION_LOCKS: LazyLock<StdMutex<HashMap<PathBuf, Arc<Mutex<()>>>>> =
+    LazyLock::new(Default::default);
+
+/// Contents of a file before and after an edit (`None` if it did not exist)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FileImage {
+    path: String,
+    before: Option<String>,
+    after: Option<String>,
+}
+
+/// A single journaled edit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JournalEntry {
+    id: u64,
+    description: String,
+    timestamp: u64,
+    files: Vec<FileImage>,
+}
+
+impl JournalEntry {
+    fn to_record(&self, undone: bool) -> EditRecord {
+        EditRecord {
+            id: self.id,
+            description: self.description.clone(),
+            timestamp: self.timestamp,
+            files: self.files.iter().map(|image| image.path.clone()).collect(),
+            undone,
+        }
+    }
+}
+
+/// On-disk state of the journal
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct JournalState {
+    next_id: u64,
+    undo: Vec<JournalEntry>,
+    redo: Vec<JournalEntry>,
+}
+
+/// Undo/redo journal for edits written to a workspace
+#[derive(Debug, Clone)]
+pub struct EditJournal {
+    root: PathBuf,
+    path: PathBuf,
+    lock_path: PathBuf,
+}
+
+impl EditJournal {
+    /// Journal stored under the given workspace root
+    pub fn for_workspace<P: AsRef<Path>>(root: P) -> Self {
+        let root = root.as_ref().to_path_buf();
+        let dir = root.join("target").join("rustbelt");
+        Self {
+            path: dir.join("edit-journal.json"),
+            lock_path: dir.join("edit-journal.lock"),
+            root,
+        }
+    }
+
+    /// Journal of the workspace containing the given file or directory
+    pub fn for_file<P: AsRef<Path>>(file_path: P) -> Result<Self> {
+        let root = RustAnalyzerishBuilder::find_project_root(file_path.as_ref())?;
+        Ok(Self::for_workspace(root))
+    }
+
+    /// Root of the workspace this journal belongs to
+    pub fn workspace_root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Start a transaction on the journal and the files it records
+    ///
+    /// Transactions on the same journal run one at a time, in this process
+    /// and across processes, so an edit can be computed, checked against the
+    /// files on disk and written without another edit interleaving.
+    pub async fn transaction(&self) -> Result<EditTransaction<'_>> {
+        let lock = TRANSACTION_LOCKS
+            .lock()
+            .unwrap()
+            .entry(self.path.clone())
+            .or_default()
+            .clone();
+        let guard = lock.lock_owned().await;
+
+        if let Some(parent) = self.lock_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let lock_path = self.lock_path.clone();
+        let lock_file = tokio::task::spawn_blocking(move || {
+            let file = File::create(&lock_path)?;
+            file.lock()?;
+            std::io::Result::Ok(file)
+        })
+        .await?
+        .with_context(|| format!("Failed to lock {}", self.lock_path.display()))?;
+
+        Ok(EditTransaction {
+            journal: self,
+            _guard: guard,
+            _lock_file: lock_file,
+        })
+    }
+
+    /// Apply text and file system edits to disk and record them, in a
+    /// transaction of their own
+    ///
+    /// Any edit recorded after an undo clears the redo history.
+    pub async fn apply(
+        &self,
+        description: impl Into<String>,
+        file_changes: &[FileChange],
+        file_system_edits: &[FileSystemEdit],
+    ) -> Result<EditRecord> {
+        self.transaction()
+            .await?
+            .apply(description, file_changes, file_system_edits)
+            .await
+    }
+
+    /// Undo the most recent edit, restoring the previous file contents
+    ///
+    /// Refuses to undo if any touched file changed since the edit was made.
+    /// Returns `None` if there is nothing to undo.
+    pub async fn undo(&self) -> Result<Option<EditRecord>> {
+        self.transaction().await?.undo().await
+    }
+
+    /// Redo the most recently undone edit
+    ///
+    /// Refuses to redo if any touched file changed since the undo. Returns
+    /// `None` if there is nothing to redo.
+    pub async fn redo(&self) -> Result<Option<EditRecord>> {
+        self.transaction().await?.redo().await
+    }
+
+    /// List journaled edits, most recent first
+    ///
+    /// Undone edits that can still be redone are included and marked as such.
+    pub async fn history(&self) -> Result<Vec<EditRecord>> {
+        let state = self.load().await?;
+        let undone = state.redo.iter().rev().map(|entry| entry.to_record(true));
+        let done = state.undo.iter().rev().map(|entry| entry.to_record(false));
+        let mut records: Vec<_> = undone.chain(done).collect();
+        records.sort_by_key(|record| std::cmp::Reverse(record.id));
+        Ok(records)
+    }
+
+    /// Check that files still have the contents recorded in the journal
+    async fn ensure_unchanged(
+        entry: &JournalEntry,
+        expected: impl Fn(&FileImage) -> &Option<String>,
+        action: &str,
+    ) -> Result<()> {
+        for image in &entry.files {
+            let current = fs::read_to_string(&image.path).await.ok();
+            if &current != expected(image) {
+                anyhow::bail!(
+                    "Cannot {} edit #{} ({}): {} changed since",
+                    action,
+                    entry.id,
+                    entry.description,
+                    image.path
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Every file path an edit may create, modify, move or delete
+    fn touched_paths(
+        file_changes: &[FileChange],
+        file_system_edits: &[FileSystemEdit],
+    ) -> Result<Vec<String>> {
+        let mut paths: Vec<String> = file_changes
+            .iter()
+            .map(|file_change| file_change.file_path.clone())
+            .collect();
+
+        for fs_edit in file_system_edits {
+            match fs_edit {
+                FileSystemEdit::CreateFile { path, .. } | FileSystemEdit::DeleteFile { path } => {
+                    paths.push(path.clone());
+                }
+                FileSystemEdit::MoveFile { src, dst } => {
+                    paths.push(src.clone());
+                    paths.push(dst.clone());
+                }
+                FileSystemEdit::MoveDir { src, dst } => {
+                    for file in RustAnalyzerUtils::files_in_dir(Path::new(src))? {
+                        let relative = file.strip_prefix(src)?;
+                        paths.push(file.display().to_string());
+                        paths.push(Path::new(dst).join(relative).display().to_string());
+                    }
+                }
+            }
+        }
+
+        paths.sort();
+        paths.dedup();
+        Ok(paths)
+    }
+
+    /// Read the current contents of files, `None` for missing files
+    async fn read_images(paths: &[String]) -> Result<Vec<Option<String>>> {
+        let mut images = Vec::with_capacity(paths.len());
+        for path in paths {
+            if !fs::try_exists(path).await.unwrap_or(false) {
+                images.push(None);
+                continue;
+            }
+            let contents = fs::read_to_string(path)
+                .await
+                .map_err(|e| anyhow::anyhow!("Failed to read file {} for journal: {}", path, e))?;
+            images.push(Some(contents));
+        }
+        Ok(images)
+    }
+
+    async fn load(&self) -> Result<JournalState> {
+        if !fs::try_exists(&self.path).await.unwrap_or(false) {
+            return Ok(JournalState::default());
+        }
+        let contents = fs::read_to_string(&self.path).await?;
+        serde_json::from_str(&contents).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to parse edit journal {}: {}",
+                self.path.display(),
+                e
+            )
+        })
+    }
+
+    async fn save(&self, state: &JournalState) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(&self.path, serde_json::to_string(state)?)
+            .await
+            .map_err(|e| {
+                anyhow::anyhow!(
+                    "Failed to write edit journal {}: {}",
+                    self.path.display(),
+                    e
+                )
+            })
+    }
+}
+
+/// Exclusive access to a journal and the files it records, held until
+/// dropped; see `EditJournal::transaction`
+#[derive(Debug)]
+pub struct EditTransaction<'a> {
+    journal: &'a EditJournal,
+    _guard: OwnedMutexGuard<()>,
+    /// Locked for other processes, unlocked when closed
+    _lock_file: File,
+}
+
+impl EditTransaction<'_> {
+    /// Apply text and file system edits to disk and record them
+    ///
+    /// Any edit recorded after an undo clears the redo history.
+    pub async fn apply(
+        &self,
+        description: impl Into<String>,
+        file_changes: &[FileChange],
+        file_system_edits: &[FileSystemEdit],
+    ) -> Result<EditRecord> {
+        // A journal that can't be read must fail the edit before it is written
+        let mut state = self.journal.load().await?;
+        let paths = EditJournal::touched_paths(file_changes, file_system_edits)?;
+        let before = EditJournal::read_images(&paths).await?;
+
+        RustAnalyzerUtils::apply_source_change(file_changes, file_system_edits).await?;
+
+        let after = EditJournal::read_images(&paths).await?;
+        let files = paths
+            .into_iter()
+            .zip(before.into_iter().zip(after))
+            .filter(|(_, (before, after))| before != after)
+            .map(|(path, (before, after))| FileImage {
+                path,
+                before,
+                after,
+            })
+            .collect();
+
+        let entry = JournalEntry {
+            id: state.next_id,
+            description: description.into(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            files,
+        };
+        let record = entry.to_record(false);
+        debug!("Recording edit #{}: {}", entry.id, entry.description);
+
+        state.next_id += 1;
+        state.undo.push(entry);
+        state.redo.clear();
+        if state.undo.len() > MAX_ENTRIES {
+            let excess = state.undo.len() - MAX_ENTRIES;
+            state.undo.drain(..excess);
+        }
+        let entry = state.undo.last().expect("just pushed");
+        self.save_or_restore(&state, entry, |image| &image.before)
+            .await?;
+
+        Ok(record)
+    }
+
+    /// Undo the most recent edit, see `EditJournal::undo`
+    pub async fn undo(&self) -> Result<Option<EditRecord>> {
+        let mut state = self.journal.load().await?;
+        let Some(entry) = state.undo.last() else {
+            return Ok(None);
+        };
+
+        EditJournal::ensure_unchanged(entry, |image| &image.after, "undo").await?;
+        let restore: Vec<_> = entry
+            .files
+            .iter()
+            .map(|image| (image.path.clone(), image.before.clone()))
+            .collect();
+        RustAnalyzerUtils::write_files(&restore).await?;
+
+        let entry = state.undo.pop().expect("checked above");
+        let record = entry.to_record(true);
+        state.redo.push(entry);
+        let entry = state.redo.last().expect("just pushed");
+        self.save_or_restore(&state, entry, |image| &image.after)
+            .await?;
+
+        Ok(Some(record))
+    }
+
+    /// Redo the most recently undone edit, see `EditJournal::redo`
+    pub async fn redo(&self) -> Result<Option<EditRecord>> {
+        let mut state = self.journal.load().await?;
+        let Some(entry) = state.redo.last() else {
+            return Ok(None);
+        };
+
+        EditJournal::ensure_unchanged(entry, |image| &image.before, "redo").await?;
+        let restore: Vec<_> = entry
+            .files
+            .iter()
+            .map(|image| (image.path.clone(), image.after.clone()))
+            .collect();
+        RustAnalyzerUtils::write_files(&restore).await?;
+
+        let entry = state.redo.pop().expect("checked above");
+        let record = entry.to_record(false);
+        state.undo.push(entry);
+        let entry = state.undo.last().expect("just pushed");
+        self.save_or_restore(&state, entry, |image| &image.before)
+            .await?;
+
+        Ok(Some(record))
+    }
+
+    /// Save the journal, or put back the files of the entry just written if
+    /// that fails, so the disk never holds an edit the journal doesn't know
+    async fn save_or_restore(
+        &self,
+        state: &JournalState,
+        entry: &JournalEntry,
+        restore: impl Fn(&FileImage) -> &Option<String>,
+    ) -> Result<()> {
+        let Err(e) = self.journal.save(state).await else {
+            return Ok(());
+        };
+        let files: Vec<_> = entry
+            .files
+            .iter()
+            .map(|image| (image.path.clone(), restore(image).clone()))
+            .collect();
+        match RustAnalyzerUtils::write_files(&files).await {
+            Ok(()) => Err(e.context("Edit reverted")),
+            Err(restore_error) => Err(e.context(format!(
+                "Failed to revert the edit, the files are left edited: {restore_error:#}"
+            ))),
+        }
+    }
+}
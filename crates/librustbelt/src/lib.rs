@@ -4,18 +4,32 @@
 
 pub mod analyzer;
 pub mod builder;
+pub mod cache;
+pub mod config;
 pub mod entities;
 pub mod file_watcher;
+pub mod flycheck;
+pub mod journal;
+pub mod registry;
 pub mod utils;
 
 pub use analyzer::RustAnalyzerish;
-pub use builder::RustAnalyzerishBuilder;
+pub use builder::{ProgressCallback, RustAnalyzerishBuilder, WorkspaceLoadError};
+pub use cache::{LibraryOrigins, WorkspaceCache};
+pub use config::WorkspaceConfig;
 pub use entities::{
-    AssistInfo, AssistSourceChange, CallerInfo, CompletionItem, CursorCoordinates, DefinitionInfo,
-    DiagnosticFix, DiagnosticInfo, FileChange, FileOutlineItem, MacroExpansion, ReferenceInfo,
-    RenameResult, SignatureInfo, SymbolAnalysis, SymbolSearchResult, TextEdit, TypeHint,
+    AssistInfo, AssistSourceChange, CallerInfo, CargoOptions, CompletionItem, CursorCoordinates,
+    DefinitionInfo, DiagnosticFix, DiagnosticInfo, DiagnosticSource, DiagnosticsCursor,
+    DiagnosticsOptions, DiffPreview, EditCheckResult, EditRecord, FileChange, FileDiff,
+    FileOutlineItem, FileSystemEdit, FixAllQuery, FixAllResult, InlayHintInfo, LoadProgress,
+    MacroExpansion, ReferenceInfo, RenameResult, SignatureInfo, SymbolAnalysis, SymbolSearchResult,
+    TextEdit, TypeHint, UnfixedDiagnostic, UnfixedReason, WorkspaceDiagnostics,
+    WorkspaceDiagnosticsQuery, WorkspaceInfo, WorkspaceState, WorkspaceStatus,
 };
-pub use utils::RustAnalyzerUtils;
+pub use flycheck::FlycheckConfig;
+pub use journal::{EditJournal, EditTransaction};
+pub use registry::{CompletedLoad, PendingLoad, WorkspaceRegistry};
+pub use utils::{RustAnalyzerUtils, StaleFileError};
 
 /// Result type alias for the library
 pub type Result<T> = anyhow::Result<T>;
@@ -0,0 +1,670 @@
+//! Registry of loaded workspaces
+//!
+//! Long-running processes (like the MCP server) are often asked about files
+//! from several Cargo workspaces in the same session. This module keeps one
+//! `RustAnalyzerish` per discovered project root, loads them lazily on first
+//! use and evicts the least recently used ones when the configured memory
+//! budget is exceeded. Workspaces can be reloaded, for instance to switch
+//! the features or target they are analyzed with.
+//!
+//! Loading a workspace takes a while, so it is split in three steps for
+//! callers that share the registry between tasks: `start_load` registers the
+//! load and returns a `PendingLoad`, which is run without borrowing the
+//! registry, and `finish_load` stores the result. The registry keeps track
+//! of the progress of pending loads in the meantime, see `status`.
+
+use std::collections::HashMap;
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Instant;
+
+use anyhow::{Result, bail};
+use ra_ap_profile::MemoryUsage;
+use tracing::{info, warn};
+
+use super::analyzer::RustAnalyzerish;
+use super::builder::{ProgressCallback, RustAnalyzerishBuilder, WorkspaceLoadError};
+use super::entities::{CargoOptions, LoadProgress, WorkspaceInfo, WorkspaceState, WorkspaceStatus};
+
+/// A loaded workspace and its bookkeeping data
+#[derive(Debug)]
+struct LoadedWorkspace {
+    /// Shared with the callers running queries, which keep it alive if the
+    /// workspace is unloaded meanwhile
+    analyzer: Arc<RustAnalyzerish>,
+    /// Manifest the workspace was loaded from
+    manifest: PathBuf,
+    cargo_options: CargoOptions,
+    /// Approximate memory allocated while loading the workspace, in MB
+    memory_mb: u64,
+    /// Number of crates indexed while loading
+    crates: Option<usize>,
+    loaded_at: Instant,
+    last_used: Instant,
+}
+
+/// A workspace being loaded
+#[derive(Debug)]
+struct LoadingWorkspace {
+    started_at: Instant,
+    state: Arc<Mutex<LoadState>>,
+}
+
+/// Latest progress of a pending load, updated from the loading thread
+#[derive(Debug, Default)]
+struct LoadState {
+    progress: Option<LoadProgress>,
+    /// Crates indexed and to index
+    crates: Option<(usize, usize)>,
+}
+
+/// Held by the load measuring its memory, see `PendingLoad::run`
+static MEASURED_LOAD: Mutex<()> = Mutex::new(());
+
+/// A workspace whose last load failed
+#[derive(Debug)]
+struct FailedLoad {
+    error: anyhow::Error,
+    failed_at: Instant,
+}
+
+/// A workspace load registered with `WorkspaceRegistry::start_load`
+///
+/// Run it, e.g. on a blocking thread, then hand the result back to
+/// `WorkspaceRegistry::finish_load`.
+#[derive(Debug)]
+pub struct PendingLoad {
+    root: PathBuf,
+    manifest: PathBuf,
+    cargo_options: CargoOptions,
+    enable_file_watching: bool,
+    cache_dir: Option<PathBuf>,
+    progress: ProgressCallback,
+    state: Arc<Mutex<LoadState>>,
+}
+
+impl PendingLoad {
+    /// Root of the workspace being loaded
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Also report the progress of this load to `progress`
+    pub fn with_progress(mut self, progress: ProgressCallback) -> Self {
+        let registry = self.progress;
+        self.progress = ProgressCallback::new(move |update| {
+            registry.report(update.clone());
+            progress.report(update);
+        });
+        self
+    }
+
+    /// Load the workspace
+    ///
+    /// This blocks while fetching metadata, running build scripts and
+    /// indexing crates.
+    ///
+    /// A workspace's memory is how much the process's allocations grow
+    /// during its load, so loads run one at a time: a load started while
+    /// another runs waits for it, instead of being charged for its memory.
+    pub fn run(self) -> CompletedLoad {
+        let _measured = MEASURED_LOAD.lock().unwrap_or_else(PoisonError::into_inner);
+
+        info!(
+            "Loading workspace into registry: {} ({})",
+            self.manifest.display(),
+            self.cargo_options
+        );
+
+        let before = MemoryUsage::now().allocated.megabytes();
+        // A panic must not leave the load pending forever, as callers may be
+        // waiting for it
+        let analyzer = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            RustAnalyzerishBuilder::from_manifest(&self.manifest)?
+                .with_file_watching(self.enable_file_watching)
+                .with_cache_dir(self.cache_dir.as_ref())
+                .with_cargo_options(self.cargo_options.clone())
+                .with_progress(Some(self.progress))
+                .build()
+        }))
+        .unwrap_or_else(|_| Err(anyhow::anyhow!("Loading the workspace panicked")));
+        let after = MemoryUsage::now().allocated.megabytes();
+        let crates = self.state.lock().unwrap().crates.map(|(_, total)| total);
+
+        CompletedLoad {
+            root: self.root,
+            manifest: self.manifest,
+            cargo_options: self.cargo_options,
+            analyzer,
+            memory_mb: after.saturating_sub(before).max(0) as u64,
+            crates,
+        }
+    }
+}
+
+/// Result of running a `PendingLoad`
+#[derive(Debug)]
+pub struct CompletedLoad {
+    root: PathBuf,
+    manifest: PathBuf,
+    cargo_options: CargoOptions,
+    analyzer: Result<RustAnalyzerish>,
+    memory_mb: u64,
+    crates: Option<usize>,
+}
+
+impl CompletedLoad {
+    /// Root of the loaded workspace
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+/// Registry of analyzers keyed by project root
+#[derive(Debug)]
+pub struct WorkspaceRegistry {
+    workspaces: HashMap<PathBuf, LoadedWorkspace>,
+    loading: HashMap<PathBuf, LoadingWorkspace>,
+    failed: HashMap<PathBuf, FailedLoad>,
+    memory_budget_mb: Option<u64>,
+    enable_file_watching: bool,
+    cache_dir: Option<PathBuf>,
+    cargo_options: CargoOptions,
+    manifest: Option<PathBuf>,
+    progress: Option<ProgressCallback>,
+}
+
+impl Default for WorkspaceRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WorkspaceRegistry {
+    /// Create an empty registry without a memory budget
+    ///
+    /// File watching is enabled for every workspace loaded by the registry,
+    /// since it is meant to be used by long-running processes.
+    pub fn new() -> Self {
+        Self {
+            workspaces: HashMap::new(),
+            loading: HashMap::new(),
+            failed: HashMap::new(),
+            memory_budget_mb: None,
+            enable_file_watching: true,
+            cache_dir: None,
+            cargo_options: CargoOptions::default(),
+            manifest: None,
+            progress: None,
+        }
+    }
+
+    /// Limit the total memory used by loaded workspaces
+    ///
+    /// When loading a workspace pushes the total over the budget, the least
+    /// recently used workspaces are unloaded. The most recently loaded
+    /// workspace is never evicted, even if it exceeds the budget on its own.
+    pub fn with_memory_budget_mb(mut self, memory_budget_mb: Option<u64>) -> Self {
+        self.memory_budget_mb = memory_budget_mb;
+        self
+    }
+
+    /// Enable or disable file watching for newly loaded workspaces
+    pub fn with_file_watching(mut self, enable: bool) -> Self {
+        self.enable_file_watching = enable;
+        self
+    }
+
+    /// Cache workspace metadata in a directory, see
+    /// [`RustAnalyzerishBuilder::with_cache_dir`]
+    pub fn with_cache_dir(mut self, cache_dir: Option<PathBuf>) -> Self {
+        self.cache_dir = cache_dir;
+        self
+    }
+
+    /// Cargo configuration newly loaded workspaces are analyzed with
+    pub fn with_cargo_options(mut self, cargo_options: CargoOptions) -> Self {
+        self.cargo_options = cargo_options;
+        self
+    }
+
+    /// Load paths under the directory of a manifest from that manifest
+    ///
+    /// This overrides discovery for the project, for instance to use a
+    /// `rust-project.json` generated in a build directory, or to pick a
+    /// `Cargo.toml` over a `rust-project.json`. See
+    /// [`RustAnalyzerishBuilder::from_manifest`] for the supported manifests.
+    pub fn with_manifest(mut self, manifest: Option<PathBuf>) -> Self {
+        self.manifest = manifest;
+        self
+    }
+
+    /// Report the progress of loading workspaces
+    pub fn with_progress(mut self, progress: Option<ProgressCallback>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Get the analyzer responsible for a path, loading its workspace if needed
+    ///
+    /// The path may be a file or a directory inside the workspace.
+    pub fn get_or_load<P: AsRef<Path>>(&mut self, path: P) -> Result<Arc<RustAnalyzerish>> {
+        let root = match self.find_loaded_root(path.as_ref()) {
+            Some(root) => root,
+            None => {
+                let (root, manifest) = self.find_project(path.as_ref())?;
+                if !self.workspaces.contains_key(&root) {
+                    self.load(&root, manifest, self.cargo_options.clone())?;
+                }
+                root
+            }
+        };
+        self.touch(&root)
+    }
+
+    /// Get the analyzer responsible for a path, if its workspace is loaded
+    pub fn get<P: AsRef<Path>>(&mut self, path: P) -> Option<Arc<RustAnalyzerish>> {
+        let root = self.find_known_root(path.as_ref())?;
+        self.touch(&root).ok()
+    }
+
+    /// Start loading the workspace containing a path
+    ///
+    /// Returns `None` if the workspace is already loaded or loading. The
+    /// returned load must be run and passed to `finish_load`.
+    pub fn start_load<P: AsRef<Path>>(&mut self, path: P) -> Result<Option<PendingLoad>> {
+        if self.find_loaded_root(path.as_ref()).is_some() {
+            return Ok(None);
+        }
+        let (root, manifest) = self.find_project(path.as_ref())?;
+        if self.workspaces.contains_key(&root) || self.loading.contains_key(&root) {
+            return Ok(None);
+        }
+        self.begin_load(&root, manifest, self.cargo_options.clone())
+            .map(Some)
+    }
+
+    /// Start reloading the workspace containing a path with a new cargo
+    /// configuration, see `reload`
+    ///
+    /// Returns `None` if the workspace is already loading. The previously
+    /// loaded analyzer keeps answering queries until `finish_load`.
+    pub fn start_reload<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        cargo_options: CargoOptions,
+    ) -> Result<Option<PendingLoad>> {
+        let (root, manifest) = self.find_loaded_or_project(path.as_ref())?;
+        if self.loading.contains_key(&root) {
+            return Ok(None);
+        }
+        self.begin_load(&root, manifest, cargo_options).map(Some)
+    }
+
+    /// Store the result of a load started with `start_load` or
+    /// `start_reload`, evicting other workspaces if over budget
+    ///
+    /// Returns `None` if the load failed. The error is then available from
+    /// `load_error` until the workspace is loaded again, and the previously
+    /// loaded analyzer, if any, is kept.
+    pub fn finish_load(&mut self, load: CompletedLoad) -> Option<WorkspaceInfo> {
+        self.loading.remove(&load.root);
+        let now = Instant::now();
+        let analyzer = match load.analyzer {
+            Ok(analyzer) => Arc::new(analyzer),
+            Err(error) => {
+                warn!(
+                    "Failed to load workspace {}: {error:#}",
+                    load.root.display()
+                );
+                self.failed.insert(
+                    load.root,
+                    FailedLoad {
+                        error,
+                        failed_at: now,
+                    },
+                );
+                return None;
+            }
+        };
+
+        // Options set on the workspace survive reloads
+        if let Some(previous) = self.workspaces.get(&load.root) {
+            analyzer.set_diagnostics_options(previous.analyzer.configured_diagnostics_options());
+        }
+
+        self.failed.remove(&load.root);
+        self.workspaces.insert(
+            load.root.clone(),
+            LoadedWorkspace {
+                analyzer,
+                manifest: load.manifest,
+                cargo_options: load.cargo_options,
+                memory_mb: load.memory_mb,
+                crates: load.crates,
+                loaded_at: now,
+                last_used: now,
+            },
+        );
+
+        self.evict_over_budget(&load.root);
+        let workspace = &self.workspaces[&load.root];
+        Some(Self::info(&load.root, workspace, now))
+    }
+
+    /// Why the last load of the workspace containing a path failed, if it did
+    pub fn load_error<P: AsRef<Path>>(&self, path: P) -> Option<&anyhow::Error> {
+        let root = self.find_known_root(path.as_ref())?;
+        self.failed.get(&root).map(|failed| &failed.error)
+    }
+
+    /// Get the analyzer responsible for a path, analyzed with the given cargo
+    /// configuration
+    ///
+    /// The workspace is loaded if needed, and reloaded if it was loaded with
+    /// a different configuration.
+    pub fn get_or_load_with<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        cargo_options: &CargoOptions,
+    ) -> Result<Arc<RustAnalyzerish>> {
+        let (root, manifest) = self.find_loaded_or_project(path.as_ref())?;
+        let up_to_date = self
+            .workspaces
+            .get(&root)
+            .is_some_and(|workspace| workspace.cargo_options == *cargo_options);
+        if !up_to_date {
+            self.load(&root, manifest, cargo_options.clone())?;
+        }
+        self.touch(&root)
+    }
+
+    /// Cargo configuration of the workspace containing a path, or the one new
+    /// workspaces are loaded with if it isn't loaded
+    pub fn cargo_options<P: AsRef<Path>>(&self, path: P) -> &CargoOptions {
+        self.find_loaded_root(path.as_ref())
+            .and_then(|root| self.workspaces.get(&root))
+            .map_or(&self.cargo_options, |workspace| &workspace.cargo_options)
+    }
+
+    /// Load the workspace containing a path again, with a new cargo
+    /// configuration
+    ///
+    /// This also picks up changes the file watcher doesn't handle, like new
+    /// dependencies. If loading fails, the previously loaded analyzer is
+    /// kept.
+    pub fn reload<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        cargo_options: CargoOptions,
+    ) -> Result<WorkspaceInfo> {
+        let (root, manifest) = self.find_loaded_or_project(path.as_ref())?;
+        self.load(&root, manifest, cargo_options)?;
+        let workspace = &self.workspaces[&root];
+        Ok(Self::info(&root, workspace, Instant::now()))
+    }
+
+    /// Loading status of every workspace that is loaded, loading or failed
+    /// to load, sorted by root
+    pub fn status(&self) -> Vec<WorkspaceStatus> {
+        let now = Instant::now();
+        let mut roots: Vec<_> = self
+            .workspaces
+            .keys()
+            .chain(self.loading.keys())
+            .chain(self.failed.keys())
+            .collect();
+        roots.sort();
+        roots.dedup();
+        roots
+            .into_iter()
+            .filter_map(|root| self.root_status(root, now))
+            .collect()
+    }
+
+    /// Loading status of the workspace containing a path, if the registry
+    /// knows about it
+    pub fn status_of<P: AsRef<Path>>(&self, path: P) -> Option<WorkspaceStatus> {
+        let root = self.find_known_root(path.as_ref())?;
+        self.root_status(&root, Instant::now())
+    }
+
+    /// Unload the workspace containing the given path
+    ///
+    /// Returns the root of the unloaded workspace, or `None` if no loaded
+    /// workspace matches the path.
+    pub fn unload<P: AsRef<Path>>(&mut self, path: P) -> Option<PathBuf> {
+        let root = self
+            .find_known_root(path.as_ref())
+            .filter(|root| self.workspaces.contains_key(root))?;
+        self.workspaces.remove(&root);
+        self.failed.remove(&root);
+        info!("Unloaded workspace: {}", root.display());
+        Some(root)
+    }
+
+    /// List all loaded workspaces, most recently used first
+    pub fn list(&self) -> Vec<WorkspaceInfo> {
+        let now = Instant::now();
+        let mut infos: Vec<_> = self
+            .workspaces
+            .iter()
+            .map(|(root, workspace)| Self::info(root, workspace, now))
+            .collect();
+        infos.sort_by_key(|info| info.idle_secs);
+        infos
+    }
+
+    /// Total approximate memory used by loaded workspaces, in MB
+    pub fn total_memory_mb(&self) -> u64 {
+        self.workspaces.values().map(|w| w.memory_mb).sum()
+    }
+
+    fn info(root: &Path, workspace: &LoadedWorkspace, now: Instant) -> WorkspaceInfo {
+        WorkspaceInfo {
+            root: root.display().to_string(),
+            memory_mb: workspace.memory_mb,
+            loaded_secs_ago: now.duration_since(workspace.loaded_at).as_secs(),
+            idle_secs: now.duration_since(workspace.last_used).as_secs(),
+            cargo_options: workspace.cargo_options.clone(),
+        }
+    }
+
+    fn root_status(&self, root: &Path, now: Instant) -> Option<WorkspaceStatus> {
+        let loaded = self.workspaces.get(root);
+        let error = self
+            .failed
+            .get(root)
+            .map(|failed| format!("{:#}", failed.error));
+        let mut status = WorkspaceStatus {
+            root: root.display().to_string(),
+            state: WorkspaceState::Ready,
+            progress: None,
+            crates_indexed: loaded.and_then(|workspace| workspace.crates),
+            crates_total: loaded.and_then(|workspace| workspace.crates),
+            memory_mb: loaded.map(|workspace| workspace.memory_mb),
+            elapsed_secs: 0,
+            semantic_ready: loaded.is_some(),
+            error,
+        };
+
+        if let Some(loading) = self.loading.get(root) {
+            let state = loading.state.lock().unwrap();
+            status.state = WorkspaceState::Loading;
+            status.progress = state.progress.clone();
+            status.crates_indexed = state.crates.map(|(done, _)| done);
+            status.crates_total = state.crates.map(|(_, total)| total);
+            status.elapsed_secs = now.duration_since(loading.started_at).as_secs();
+            status.error = None;
+        } else if let Some(workspace) = loaded {
+            status.elapsed_secs = now.duration_since(workspace.loaded_at).as_secs();
+        } else {
+            let failed = self.failed.get(root)?;
+            status.state = WorkspaceState::Failed;
+            status.elapsed_secs = now.duration_since(failed.failed_at).as_secs();
+        }
+        Some(status)
+    }
+
+    /// Mark a loaded workspace as used and return its analyzer
+    fn touch(&mut self, root: &Path) -> Result<Arc<RustAnalyzerish>> {
+        let workspace = self
+            .workspaces
+            .get_mut(root)
+            .ok_or_else(|| anyhow::anyhow!("Workspace {} is not loaded", root.display()))?;
+        workspace.last_used = Instant::now();
+        Ok(Arc::clone(&workspace.analyzer))
+    }
+
+    /// Find the root and manifest of the project containing a path, honoring
+    /// the manifest override
+    fn find_project(&self, path: &Path) -> Result<(PathBuf, PathBuf)> {
+        if let Some(manifest) = &self.manifest {
+            let manifest = manifest
+                .canonicalize()
+                .map_err(|e| WorkspaceLoadError::NoManifest {
+                    path: manifest.clone(),
+                    reason: e.to_string(),
+                })?;
+            if let Some(root) = manifest.parent()
+                && path.canonicalize().is_ok_and(|path| path.starts_with(root))
+            {
+                return Ok((root.to_path_buf(), manifest));
+            }
+        }
+        let manifest = RustAnalyzerishBuilder::find_project_manifest(path)?;
+        let root = manifest
+            .parent()
+            .map_or_else(|| manifest.clone(), Path::to_path_buf);
+        Ok((root, manifest))
+    }
+
+    /// Find the root of the loaded workspace owning a path, or of the project
+    /// containing it
+    fn find_known_root(&self, path: &Path) -> Option<PathBuf> {
+        self.find_loaded_root(path)
+            .or_else(|| self.find_project(path).ok().map(|(root, _)| root))
+    }
+
+    /// Find the root and manifest of the loaded workspace owning a path, or
+    /// of the project containing it if none does
+    fn find_loaded_or_project(&self, path: &Path) -> Result<(PathBuf, PathBuf)> {
+        match self.find_loaded_root(path) {
+            Some(root) => {
+                let manifest = self.workspaces[&root].manifest.clone();
+                Ok((root, manifest))
+            }
+            None => self.find_project(path),
+        }
+    }
+
+    /// Find an already loaded workspace owning the given path
+    ///
+    /// A workspace owns a path if the path is inside its root and the file is
+    /// part of its VFS, so workspace members resolve to an already loaded
+    /// workspace root while nested, unrelated projects do not.
+    fn find_loaded_root(&self, path: &Path) -> Option<PathBuf> {
+        let path = path.canonicalize().ok()?;
+
+        let mut candidates: Vec<_> = self
+            .workspaces
+            .iter()
+            .filter(|(root, workspace)| {
+                path.starts_with(root)
+                    && (path == **root || workspace.analyzer.contains_file(&path))
+            })
+            .map(|(root, _)| root)
+            .collect();
+
+        // Prefer the innermost root
+        candidates.sort_by_key(|root| std::cmp::Reverse(root.components().count()));
+        candidates.first().map(|root| root.to_path_buf())
+    }
+
+    /// Load a workspace, replacing it if already loaded, and evict others if
+    /// over budget
+    fn load(&mut self, root: &Path, manifest: PathBuf, cargo_options: CargoOptions) -> Result<()> {
+        let load = self.begin_load(root, manifest, cargo_options)?.run();
+        if self.finish_load(load).is_none() {
+            // The caller gets the error, so there's no need to keep it around
+            if let Some(failed) = self.failed.remove(root) {
+                return Err(failed.error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Register a load of a workspace, tracking its progress
+    fn begin_load(
+        &mut self,
+        root: &Path,
+        manifest: PathBuf,
+        cargo_options: CargoOptions,
+    ) -> Result<PendingLoad> {
+        if self.loading.contains_key(root) {
+            bail!("Workspace {} is already loading", root.display());
+        }
+
+        let state = Arc::new(Mutex::new(LoadState::default()));
+        let sink = state.clone();
+        let forward = self.progress.clone();
+        let progress = ProgressCallback::new(move |progress| {
+            {
+                let mut state = sink.lock().unwrap();
+                if let LoadProgress::Indexing { done, total, .. } = &progress {
+                    state.crates = Some((*done, *total));
+                }
+                state.progress = Some(progress.clone());
+            }
+            if let Some(forward) = &forward {
+                forward.report(progress);
+            }
+        });
+
+        self.failed.remove(root);
+        self.loading.insert(
+            root.to_path_buf(),
+            LoadingWorkspace {
+                started_at: Instant::now(),
+                state: state.clone(),
+            },
+        );
+        Ok(PendingLoad {
+            root: root.to_path_buf(),
+            manifest,
+            cargo_options,
+            enable_file_watching: self.enable_file_watching,
+            cache_dir: self.cache_dir.clone(),
+            progress,
+            state,
+        })
+    }
+
+    /// Evict least recently used workspaces until the budget is respected
+    fn evict_over_budget(&mut self, keep: &Path) {
+        let Some(budget) = self.memory_budget_mb else {
+            return;
+        };
+
+        while self.total_memory_mb() > budget {
+            let lru = self
+                .workspaces
+                .iter()
+                .filter(|(root, _)| root.as_path() != keep)
+                .min_by_key(|(_, workspace)| workspace.last_used)
+                .map(|(root, _)| root.clone());
+
+            let Some(lru) = lru else {
+                break;
+            };
+
+            info!(
+                "Evicting workspace {} to stay within {}MB memory budget",
+                lru.display(),
+                budget
+            );
+            self.workspaces.remove(&lru);
+        }
+    }
+}
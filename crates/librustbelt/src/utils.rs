@@ -3,134 +3,604 @@
 //! This module contains static utility functions that don't require
 //! an analyzer instance, such as text editing operations.
 
-use std::path::Path;
+use std::collections::HashSet;
+use std::fmt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 use anyhow::Result;
+use imara_diff::Algorithm;
+use imara_diff::intern::InternedInput;
+use imara_diff::sources::lines_with_terminator;
 use ra_ap_ide::{LineCol, LineIndex, TextRange, TextSize};
 use ra_ap_ide_db::text_edit::TextEditBuilder;
 use tokio::fs;
 
-use super::entities::{FileChange, RenameResult};
+use super::entities::{DiffPreview, FileChange, FileDiff, FileSystemEdit, RenameResult};
+
+/// Lines of unchanged context around the changes of a diff hunk
+const DIFF_CONTEXT: usize = 3;
 
 /// Utility functions for Rust analyzer operations
 pub struct RustAnalyzerUtils;
 
+/// A file changed on disk after edits to it were computed
+///
+/// Returned (wrapped in `anyhow::Error`) when applying a `FileChange` whose
+/// `source_hash` no longer matches the file. Recompute the edits against the
+/// current contents and try again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaleFileError {
+    /// File whose contents changed
+    pub path: String,
+    /// Hash of the contents the edits were computed against
+    pub expected: String,
+    /// Hash of the current contents on disk
+    pub actual: String,
+}
+
+impl fmt::Display for StaleFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} changed since the edits were computed (expected content hash {}, found {}); \
+             no changes were applied, recompute the edits and try again",
+            self.path, self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for StaleFileError {}
+
 impl RustAnalyzerUtils {
     /// Apply rename edits to files on disk using rust-analyzer's TextEditBuilder
     pub async fn apply_rename_edits(rename_result: &RenameResult) -> Result<()> {
-        for file_change in &rename_result.file_changes {
-            // Read the current file content
-            let mut content = fs::read_to_string(&file_change.file_path)
-                .await
-                .map_err(|e| {
-                    anyhow::anyhow!("Failed to read file {}: {}", file_change.file_path, e)
-                })?;
-
-            // Create TextEditBuilder to handle multiple edits atomically
-            let mut builder = TextEditBuilder::default();
-
-            // Create line index for UTF-8 safe position conversion
-            let line_index = LineIndex::new(&content);
-
-            // Add all edits to the builder (no need to sort - TextEditBuilder handles ordering)
-            for edit in &file_change.edits {
-                // Convert 1-based line/column to character offset using LineIndex for UTF-8 safety
-                let start_offset =
-                    Self::line_col_to_offset_with_index(&line_index, edit.line, edit.column)
-                        .ok_or_else(|| {
-                            anyhow::anyhow!(
-                                "Invalid start position {}:{} in file {}",
-                                edit.line,
-                                edit.column,
-                                file_change.file_path
-                            )
-                        })?;
-
-                let end_offset = Self::line_col_to_offset_with_index(
-                    &line_index,
-                    edit.end_line,
-                    edit.end_column,
-                )
-                .ok_or_else(|| {
-                    anyhow::anyhow!(
-                        "Invalid end position {}:{} in file {}",
-                        edit.end_line,
-                        edit.end_column,
-                        file_change.file_path
-                    )
-                })?;
+        Self::apply_source_change(
+            &rename_result.file_changes,
+            &rename_result.file_system_edits,
+        )
+        .await
+    }
 
-                // Create rust-analyzer TextRange
-                let range = TextRange::new(start_offset, end_offset);
+    /// Apply text edits together with file system edits as one transaction
+    ///
+    /// Every edit is validated against the current file contents and staged
+    /// in a temporary file before anything on disk is replaced. Staged files
+    /// (new files and edited files) are then renamed into place, and finally
+    /// files are moved or deleted. Text edits refer to the original paths.
+    ///
+    /// If any step fails, all completed steps are rolled back and the
+    /// original contents are restored.
+    pub async fn apply_source_change(
+        file_changes: &[FileChange],
+        file_system_edits: &[FileSystemEdit],
+    ) -> Result<()> {
+        let staged = Self::stage_writes(file_changes, file_system_edits).await?;
+        Self::commit(staged, file_system_edits).await
+    }
 
-                // Add the replacement to the builder
-                builder.replace(range, edit.new_text.clone());
+    /// Replace the contents of several files as one transaction
+    ///
+    /// A `None` content deletes the file if it exists. Used to restore
+    /// snapshots of files, e.g. when undoing an edit.
+    pub async fn write_files(files: &[(String, Option<String>)]) -> Result<()> {
+        let mut staged = Vec::new();
+        let mut deletions = Vec::new();
+        for (path, contents) in files {
+            let exists = fs::try_exists(path).await.unwrap_or(false);
+            match contents {
+                Some(contents) => {
+                    let original =
+                        if exists {
+                            Some(fs::read_to_string(path).await.map_err(|e| {
+                                anyhow::anyhow!("Failed to read file {}: {}", path, e)
+                            })?)
+                        } else {
+                            None
+                        };
+                    staged.push(StagedWrite {
+                        path: PathBuf::from(path),
+                        contents: contents.clone(),
+                        original,
+                    });
+                }
+                None if exists => deletions.push(FileSystemEdit::DeleteFile { path: path.clone() }),
+                None => {}
             }
+        }
 
-            // Build the TextEdit and apply it atomically
-            let text_edit = builder.finish();
-            text_edit.apply(&mut content);
+        Self::commit(staged, &deletions).await
+    }
+
+    /// Render text and file system edits as a git-style unified diff
+    ///
+    /// Nothing is written to disk. The edits are validated exactly as
+    /// `apply_source_change` would validate them, and paths in the patch are
+    /// relative to `base`, so it can be fed to `git apply` from there.
+    pub async fn diff_source_change(
+        file_changes: &[FileChange],
+        file_system_edits: &[FileSystemEdit],
+        base: &Path,
+    ) -> Result<DiffPreview> {
+        let staged = Self::stage_writes(file_changes, file_system_edits).await?;
+        Self::validate_file_system_edits(file_system_edits).await?;
+
+        // Where each moved file ends up, and which files are deleted
+        let mut moves: Vec<(PathBuf, PathBuf)> = Vec::new();
+        let mut deletions: Vec<PathBuf> = Vec::new();
+        for fs_edit in file_system_edits {
+            match fs_edit {
+                FileSystemEdit::CreateFile { .. } => {}
+                FileSystemEdit::MoveFile { src, dst } => {
+                    moves.push((PathBuf::from(src), PathBuf::from(dst)));
+                }
+                FileSystemEdit::MoveDir { src, dst } => {
+                    for file in Self::files_in_dir(Path::new(src))? {
+                        let moved = Path::new(dst).join(file.strip_prefix(src)?);
+                        moves.push((file, moved));
+                    }
+                }
+                FileSystemEdit::DeleteFile { path } => deletions.push(PathBuf::from(path)),
+            }
+        }
+        let moved_to = |path: &Path| {
+            moves
+                .iter()
+                .find(|(src, _)| src == path)
+                .map(|(_, dst)| dst.as_path())
+        };
+        let relative = |path: &Path| {
+            path.strip_prefix(base)
+                .unwrap_or(path)
+                .display()
+                .to_string()
+        };
 
-            // Write the modified content back to the file
-            fs::write(&file_change.file_path, content)
+        let mut files = Vec::new();
+        for write in &staged {
+            let old_path = write.original.as_ref().map(|_| relative(&write.path));
+            let new_path = relative(moved_to(&write.path).unwrap_or(&write.path));
+            let diff = Self::render_file_diff(
+                old_path.as_deref(),
+                Some(&new_path),
+                write.original.as_deref().unwrap_or_default(),
+                &write.contents,
+            );
+            files.push(FileDiff {
+                file_path: write.path.display().to_string(),
+                diff,
+            });
+        }
+        for (src, dst) in &moves {
+            if staged.iter().any(|write| &write.path == src) {
+                continue;
+            }
+            files.push(FileDiff {
+                file_path: src.display().to_string(),
+                diff: Self::render_file_diff(Some(&relative(src)), Some(&relative(dst)), "", ""),
+            });
+        }
+        for path in &deletions {
+            let contents = fs::read_to_string(path)
                 .await
-                .map_err(|e| {
-                    anyhow::anyhow!("Failed to write file {}: {}", file_change.file_path, e)
-                })?;
+                .map_err(|e| anyhow::anyhow!("Failed to read file {}: {}", path.display(), e))?;
+            files.push(FileDiff {
+                file_path: path.display().to_string(),
+                diff: Self::render_file_diff(Some(&relative(path)), None, &contents, ""),
+            });
+        }
+
+        Ok(DiffPreview { files })
+    }
+
+    /// Render the diff of a single file
+    ///
+    /// `None` as the old path marks a new file, `None` as the new path a
+    /// deleted file, and different paths a rename.
+    fn render_file_diff(
+        old_path: Option<&str>,
+        new_path: Option<&str>,
+        before: &str,
+        after: &str,
+    ) -> String {
+        let a = old_path.or(new_path).unwrap_or_default();
+        let b = new_path.or(old_path).unwrap_or_default();
+
+        let mut diff = format!("diff --git a/{a} b/{b}\n");
+        match (old_path, new_path) {
+            (None, _) => diff.push_str("new file mode 100644\n"),
+            (_, None) => diff.push_str("deleted file mode 100644\n"),
+            _ if a != b => diff.push_str(&format!("rename from {a}\nrename to {b}\n")),
+            _ => {}
+        }
+        if before == after {
+            return diff;
+        }
+
+        let old_label = old_path.map_or("/dev/null".to_string(), |path| format!("a/{path}"));
+        let new_label = new_path.map_or("/dev/null".to_string(), |path| format!("b/{path}"));
+        diff.push_str(&format!("--- {old_label}\n+++ {new_label}\n"));
+
+        diff.push_str(&Self::render_hunks(before, after));
+        diff
+    }
+
+    /// Render the hunks of a unified diff between two texts
+    ///
+    /// Lines are compared with their terminators, so a missing newline at
+    /// the end of a file is a change, marked like git does.
+    fn render_hunks(before: &str, after: &str) -> String {
+        let input = InternedInput::new(lines_with_terminator(before), lines_with_terminator(after));
+        let mut changes: Vec<(Range<usize>, Range<usize>)> = Vec::new();
+        imara_diff::diff(
+            Algorithm::Histogram,
+            &input,
+            |old: Range<u32>, new: Range<u32>| {
+                changes.push((
+                    old.start as usize..old.end as usize,
+                    new.start as usize..new.end as usize,
+                ));
+            },
+        );
+        let old_lines: Vec<&str> = input.before.iter().map(|&t| input.interner[t]).collect();
+        let new_lines: Vec<&str> = input.after.iter().map(|&t| input.interner[t]).collect();
+
+        let mut hunks = String::new();
+        let push_line = |hunks: &mut String, prefix: char, line: &str| {
+            hunks.push(prefix);
+            hunks.push_str(line);
+            if !line.ends_with('\n') {
+                hunks.push_str("\n\\ No newline at end of file\n");
+            }
+        };
+        let range = |start: usize, end: usize| match end - start {
+            0 => format!("{start},0"),
+            count => format!("{},{count}", start + 1),
+        };
+
+        let mut first = 0;
+        while first < changes.len() {
+            // Changes whose context overlaps go in the same hunk
+            let mut last = first;
+            while last + 1 < changes.len()
+                && changes[last + 1].0.start - changes[last].0.end <= 2 * DIFF_CONTEXT
+            {
+                last += 1;
+            }
+            let old_start = changes[first].0.start.saturating_sub(DIFF_CONTEXT);
+            let new_start = changes[first].1.start - (changes[first].0.start - old_start);
+            let old_end = (changes[last].0.end + DIFF_CONTEXT).min(old_lines.len());
+            let new_end = changes[last].1.end + (old_end - changes[last].0.end);
+            hunks.push_str(&format!(
+                "@@ -{} +{} @@\n",
+                range(old_start, old_end),
+                range(new_start, new_end)
+            ));
+
+            let mut line = old_start;
+            for (old, new) in &changes[first..=last] {
+                for context in &old_lines[line..old.start] {
+                    push_line(&mut hunks, ' ', context);
+                }
+                for removed in &old_lines[old.clone()] {
+                    push_line(&mut hunks, '-', removed);
+                }
+                for added in &new_lines[new.clone()] {
+                    push_line(&mut hunks, '+', added);
+                }
+                line = old.end;
+            }
+            for context in &old_lines[line..old_end] {
+                push_line(&mut hunks, ' ', context);
+            }
+            first = last + 1;
+        }
+        hunks
+    }
+
+    /// Recursively list the files in a directory
+    pub(crate) fn files_in_dir(dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                files.extend(Self::files_in_dir(&path)?);
+            } else {
+                files.push(path);
+            }
+        }
+        Ok(files)
+    }
+
+    /// Write staged files into place and run file system edits, rolling
+    /// everything back on failure
+    async fn commit(
+        mut staged: Vec<StagedWrite>,
+        file_system_edits: &[FileSystemEdit],
+    ) -> Result<()> {
+        Self::validate_file_system_edits(file_system_edits).await?;
+
+        // Write every new content to a temporary file next to its target,
+        // with the permissions of the file it replaces
+        let mut temp_paths = Vec::new();
+        for write in &mut staged {
+            let result = async {
+                // Renaming onto a symlink would replace it, so write to the
+                // file it points to instead
+                if fs::symlink_metadata(&write.path)
+                    .await
+                    .is_ok_and(|metadata| metadata.file_type().is_symlink())
+                {
+                    write.path = fs::canonicalize(&write.path).await?;
+                }
+                if let Some(parent) = write.path.parent() {
+                    fs::create_dir_all(parent).await?;
+                }
+                let temp_path = Self::temp_path_for(&write.path);
+                temp_paths.push(temp_path.clone());
+                fs::write(&temp_path, &write.contents).await?;
+                if let Ok(metadata) = fs::metadata(&write.path).await {
+                    fs::set_permissions(&temp_path, metadata.permissions()).await?;
+                }
+                Ok::<_, std::io::Error>(())
+            }
+            .await;
+            if let Err(e) = result {
+                Self::remove_temp_files(&temp_paths).await;
+                return Err(anyhow::anyhow!(
+                    "Failed to stage write for {}: {}",
+                    write.path.display(),
+                    e
+                ));
+            }
+        }
+
+        // Swap staged files into place, then run moves and deletions
+        let mut applied = Vec::new();
+        let mut result = Ok(());
+        for (index, write) in staged.into_iter().enumerate() {
+            if let Err(e) = fs::rename(&temp_paths[index], &write.path).await {
+                Self::remove_temp_files(&temp_paths[index..]).await;
+                result = Err(anyhow::anyhow!(
+                    "Failed to write file {}: {}",
+                    write.path.display(),
+                    e
+                ));
+                break;
+            }
+            applied.push(AppliedStep::Wrote {
+                path: write.path,
+                original: write.original,
+            });
+        }
+
+        if result.is_ok() {
+            for fs_edit in file_system_edits {
+                match Self::apply_file_system_edit(fs_edit).await {
+                    Ok(Some(step)) => applied.push(step),
+                    Ok(None) => {}
+                    Err(e) => {
+                        result = Err(e);
+                        break;
+                    }
+                }
+            }
+        }
+
+        if let Err(e) = result {
+            return Err(match Self::rollback(applied).await {
+                Ok(()) => e.context("All changes were rolled back"),
+                Err(rollback_error) => e.context(format!(
+                    "Rolling back changes also failed, the workspace may be partially modified: {rollback_error}"
+                )),
+            });
         }
 
         Ok(())
     }
 
-    /// Convert 1-based line/column to TextSize offset using LineIndex for UTF-8 safety
-    pub fn line_col_to_offset_with_index(
-        line_index: &LineIndex,
-        line: u32,
-        column: u32,
-    ) -> Option<TextSize> {
-        let line_col = LineCol {
-            line: line.saturating_sub(1),
-            col: column.saturating_sub(1),
+    /// Compute the new contents of every created or edited file
+    async fn stage_writes(
+        file_changes: &[FileChange],
+        file_system_edits: &[FileSystemEdit],
+    ) -> Result<Vec<StagedWrite>> {
+        let mut staged: Vec<StagedWrite> = Vec::new();
+
+        for fs_edit in file_system_edits {
+            if let FileSystemEdit::CreateFile {
+                path,
+                initial_contents,
+            } = fs_edit
+            {
+                if fs::try_exists(path).await.unwrap_or(false) {
+                    anyhow::bail!("Cannot create file {}: it already exists", path);
+                }
+                staged.push(StagedWrite {
+                    path: PathBuf::from(path),
+                    contents: initial_contents.clone(),
+                    original: None,
+                });
+            }
+        }
+
+        for file_change in file_changes {
+            let path = PathBuf::from(&file_change.file_path);
+            match staged.iter_mut().find(|write| write.path == path) {
+                Some(write) => Self::apply_text_edits(&mut write.contents, file_change)?,
+                None => {
+                    let original = fs::read_to_string(&path).await.map_err(|e| {
+                        anyhow::anyhow!("Failed to read file {}: {}", file_change.file_path, e)
+                    })?;
+                    Self::check_source_hash(file_change, &original)?;
+                    let mut contents = original.clone();
+                    Self::apply_text_edits(&mut contents, file_change)?;
+                    staged.push(StagedWrite {
+                        path,
+                        contents,
+                        original: Some(original),
+                    });
+                }
+            }
+        }
+
+        Ok(staged)
+    }
+
+    /// Refuse to apply a change computed against different file contents
+    ///
+    /// Edits are positioned relative to the text they were computed from, so
+    /// applying them to a file that changed since (e.g. saved from an editor)
+    /// would put them at the wrong offsets.
+    fn check_source_hash(file_change: &FileChange, current: &str) -> Result<()> {
+        let Some(expected) = &file_change.source_hash else {
+            return Ok(());
         };
-        line_index.offset(line_col)
+        let actual = Self::content_hash(current);
+        if *expected != actual {
+            return Err(StaleFileError {
+                path: file_change.file_path.clone(),
+                expected: expected.clone(),
+                actual,
+            }
+            .into());
+        }
+        Ok(())
     }
 
-    /// Helper method to get line content from file text
-    pub fn get_line_content(file_text: &str, line_number: usize) -> Option<String> {
-        let lines: Vec<&str> = file_text.lines().collect();
-        if line_number < lines.len() {
-            Some(lines[line_number].to_string())
-        } else {
-            None
+    /// Check that moves and deletions can be carried out
+    async fn validate_file_system_edits(file_system_edits: &[FileSystemEdit]) -> Result<()> {
+        for fs_edit in file_system_edits {
+            match fs_edit {
+                FileSystemEdit::CreateFile { .. } => {}
+                FileSystemEdit::MoveFile { src, dst } | FileSystemEdit::MoveDir { src, dst } => {
+                    if !fs::try_exists(src).await.unwrap_or(false) {
+                        anyhow::bail!("Cannot move {}: it does not exist", src);
+                    }
+                    if fs::try_exists(dst).await.unwrap_or(false) {
+                        anyhow::bail!("Cannot move {} to {}: destination exists", src, dst);
+                    }
+                }
+                FileSystemEdit::DeleteFile { path } => {
+                    if !fs::try_exists(path).await.unwrap_or(false) {
+                        anyhow::bail!("Cannot delete {}: it does not exist", path);
+                    }
+                }
+            }
         }
+        Ok(())
     }
 
-    /// Convert a PathBuf to AbsPathBuf for rust-analyzer operations
-    pub fn path_to_abs_path(path: &Path) -> Result<ra_ap_vfs::AbsPathBuf> {
-        use anyhow::Context;
-        use ra_ap_vfs::AbsPathBuf;
+    /// Run a move or deletion, returning the step needed to undo it
+    ///
+    /// File creations are staged together with text edits, so they are a
+    /// no-op here.
+    async fn apply_file_system_edit(fs_edit: &FileSystemEdit) -> Result<Option<AppliedStep>> {
+        match fs_edit {
+            FileSystemEdit::CreateFile { .. } => Ok(None),
+            FileSystemEdit::MoveFile { src, dst } | FileSystemEdit::MoveDir { src, dst } => {
+                if let Some(parent) = Path::new(dst).parent() {
+                    fs::create_dir_all(parent).await.map_err(|e| {
+                        anyhow::anyhow!("Failed to create directory {}: {}", parent.display(), e)
+                    })?;
+                }
+                fs::rename(src, dst)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Failed to move {} to {}: {}", src, dst, e))?;
+                Ok(Some(AppliedStep::Moved {
+                    src: PathBuf::from(src),
+                    dst: PathBuf::from(dst),
+                }))
+            }
+            FileSystemEdit::DeleteFile { path } => {
+                let original = fs::read(path)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Failed to read file {}: {}", path, e))?;
+                fs::remove_file(path)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Failed to delete file {}: {}", path, e))?;
+                Ok(Some(AppliedStep::Deleted {
+                    path: PathBuf::from(path),
+                    original,
+                }))
+            }
+        }
+    }
 
-        let abs_path = AbsPathBuf::assert_utf8(
-            path.canonicalize()
-                .with_context(|| format!("Failed to canonicalize path: {}", path.display()))?,
-        );
-        Ok(abs_path)
+    /// Undo completed steps in reverse order
+    async fn rollback(applied: Vec<AppliedStep>) -> Result<()> {
+        let mut errors = Vec::new();
+        for step in applied.into_iter().rev() {
+            let result = match &step {
+                AppliedStep::Wrote {
+                    path,
+                    original: Some(original),
+                } => fs::write(path, original).await,
+                AppliedStep::Wrote {
+                    path,
+                    original: None,
+                } => fs::remove_file(path).await,
+                AppliedStep::Moved { src, dst } => fs::rename(dst, src).await,
+                AppliedStep::Deleted { path, original } => fs::write(path, original).await,
+            };
+            if let Err(e) = result {
+                errors.push(format!("{step}: {e}"));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(errors.join("; ")))
+        }
     }
 
-    /// Apply a file change to disk (used by assists)
-    pub async fn apply_file_change(file_change: &FileChange) -> Result<()> {
-        // Read the current file content
-        let mut content = fs::read_to_string(&file_change.file_path)
-            .await
-            .map_err(|e| anyhow::anyhow!("Failed to read file {}: {}", file_change.file_path, e))?;
+    /// Temporary path used to stage a write next to its target
+    ///
+    /// The name is unique across processes and transactions, so concurrent
+    /// writes to the same file don't stage into each other's files.
+    fn temp_path_for(path: &Path) -> PathBuf {
+        static NEXT_TEMP_FILE: AtomicU64 = AtomicU64::new(0);
+        let file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let index = NEXT_TEMP_FILE.fetch_add(1, Ordering::Relaxed);
+        path.with_file_name(format!(
+            ".{file_name}.{}-{index}.rustbelt-tmp",
+            std::process::id()
+        ))
+    }
 
-        // Create TextEditBuilder to handle multiple edits atomically
-        let mut builder = TextEditBuilder::default();
+    /// Best-effort cleanup of staged temporary files
+    async fn remove_temp_files(temp_paths: &[PathBuf]) {
+        for temp_path in temp_paths {
+            let _ = fs::remove_file(temp_path).await;
+        }
+    }
+
+    /// Stable hash of file contents, used to detect stale edits
+    ///
+    /// This is a 64-bit FNV-1a hash rendered as hex, so it stays the same
+    /// across processes and rustbelt versions.
+    pub fn content_hash(text: &str) -> String {
+        let hash = text.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+        format!("{hash:016x}")
+    }
 
+    /// Apply the text edits of a file change to in-memory content
+    ///
+    /// Edit positions are interpreted against `content` before any edit is
+    /// applied, so all edits of a file change must be relative to the same
+    /// original text. Edits that end before they start or overlap each other
+    /// are rejected.
+    pub fn apply_text_edits(content: &mut String, file_change: &FileChange) -> Result<()> {
         // Create line index for UTF-8 safe position conversion
-        let line_index = LineIndex::new(&content);
+        let line_index = LineIndex::new(content);
 
-        // Add all edits to the builder (no need to sort - TextEditBuilder handles ordering)
+        let mut ranges = Vec::with_capacity(file_change.edits.len());
         for edit in &file_change.edits {
             // Convert 1-based line/column to character offset using LineIndex for UTF-8 safety
             let start_offset =
@@ -155,21 +625,212 @@ impl RustAnalyzerUtils {
                         )
                     })?;
 
-            let text_range = TextRange::new(start_offset, end_offset);
-            builder.replace(text_range, edit.new_text.clone());
+            if end_offset < start_offset {
+                anyhow::bail!(
+                    "Edit ends at {}:{} before its start {}:{} in file {}",
+                    edit.end_line,
+                    edit.end_column,
+                    edit.line,
+                    edit.column,
+                    file_change.file_path
+                );
+            }
+            ranges.push((TextRange::new(start_offset, end_offset), edit));
+        }
+
+        // TextEditBuilder asserts that edits don't overlap, so check first
+        ranges.sort_by_key(|(range, _)| (range.start(), range.end()));
+        for pair in ranges.windows(2) {
+            let [(first, first_edit), (second, second_edit)] = pair else {
+                unreachable!()
+            };
+            if first.end() > second.start() {
+                anyhow::bail!(
+                    "Edits at {}:{} and {}:{} overlap in file {}",
+                    first_edit.line,
+                    first_edit.column,
+                    second_edit.line,
+                    second_edit.column,
+                    file_change.file_path
+                );
+            }
+        }
+
+        // Create TextEditBuilder to handle multiple edits atomically
+        let mut builder = TextEditBuilder::default();
+        for (range, edit) in ranges {
+            builder.replace(range, edit.new_text.clone());
         }
 
         // Build the final text edit and apply it
         let text_edit = builder.finish();
-        text_edit.apply(&mut content);
-
-        // Write the modified content back to the file
-        fs::write(&file_change.file_path, content)
-            .await
-            .map_err(|e| {
-                anyhow::anyhow!("Failed to write file {}: {}", file_change.file_path, e)
-            })?;
+        text_edit.apply(content);
 
         Ok(())
     }
+
+    /// Convert 1-based line/column to TextSize offset using LineIndex for UTF-8 safety
+    pub fn line_col_to_offset_with_index(
+        line_index: &LineIndex,
+        line: u32,
+        column: u32,
+    ) -> Option<TextSize> {
+        let line_col = LineCol {
+            line: line.saturating_sub(1),
+            col: column.saturating_sub(1),
+        };
+        line_index.offset(line_col)
+    }
+
+    /// Helper method to get line content from file text
+    pub fn get_line_content(file_text: &str, line_number: usize) -> Option<String> {
+        let lines: Vec<&str> = file_text.lines().collect();
+        if line_number < lines.len() {
+            Some(lines[line_number].to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Convert a PathBuf to AbsPathBuf for rust-analyzer operations
+    pub fn path_to_abs_path(path: &Path) -> Result<ra_ap_vfs::AbsPathBuf> {
+        use anyhow::Context;
+        use ra_ap_vfs::AbsPathBuf;
+
+        let abs_path = AbsPathBuf::assert_utf8(
+            path.canonicalize()
+                .with_context(|| format!("Failed to canonicalize path: {}", path.display()))?,
+        );
+        Ok(abs_path)
+    }
+
+    /// Check whether a path matches a glob pattern
+    ///
+    /// `*` and `?` match any characters and a single character within a path
+    /// component, `**` matches any number of components.
+    pub fn glob_matches(pattern: &str, path: &Path) -> bool {
+        let pattern: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+        let path: Vec<String> = path
+            .components()
+            .filter_map(|c| match c {
+                std::path::Component::Normal(name) => Some(name.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect();
+        let path: Vec<&str> = path.iter().map(String::as_str).collect();
+        Self::glob_matches_components(&pattern, &path)
+    }
+
+    fn glob_matches_components(pattern: &[&str], path: &[&str]) -> bool {
+        match (pattern.first(), path.first()) {
+            (None, None) => true,
+            (Some(&"**"), _) => {
+                Self::glob_matches_components(&pattern[1..], path)
+                    || (!path.is_empty() && Self::glob_matches_components(pattern, &path[1..]))
+            }
+            (Some(component), Some(name)) => {
+                Self::glob_matches_name(component.as_bytes(), name.as_bytes())
+                    && Self::glob_matches_components(&pattern[1..], &path[1..])
+            }
+            _ => false,
+        }
+    }
+
+    fn glob_matches_name(pattern: &[u8], name: &[u8]) -> bool {
+        match (pattern.first(), name.first()) {
+            (None, None) => true,
+            (Some(b'*'), _) => {
+                Self::glob_matches_name(&pattern[1..], name)
+                    || (!name.is_empty() && Self::glob_matches_name(pattern, &name[1..]))
+            }
+            (Some(b'?'), Some(_)) => Self::glob_matches_name(&pattern[1..], &name[1..]),
+            (Some(p), Some(n)) if p == n => Self::glob_matches_name(&pattern[1..], &name[1..]),
+            _ => false,
+        }
+    }
+
+    /// Files of a directory changed since a git ref, as canonical paths
+    ///
+    /// Includes uncommitted changes and untracked files that aren't ignored.
+    /// Deleted files are left out. The ref comes from clients, so it is
+    /// resolved to a commit first and never passed to git as an option.
+    pub fn changed_files(dir: &Path, git_ref: &str) -> Result<HashSet<PathBuf>> {
+        use anyhow::Context;
+
+        anyhow::ensure!(
+            !git_ref.is_empty() && !git_ref.starts_with('-'),
+            "Invalid git ref `{git_ref}`"
+        );
+
+        let git = |args: &[&str]| -> Result<String> {
+            let output = std::process::Command::new("git")
+                .args(args)
+                .current_dir(dir)
+                .output()
+                .context("Failed to run git")?;
+            anyhow::ensure!(
+                output.status.success(),
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        };
+
+        let commit = git(&[
+            "rev-parse",
+            "--verify",
+            "--end-of-options",
+            &format!("{git_ref}^{{commit}}"),
+        ])
+        .with_context(|| format!("Unknown git ref `{git_ref}`"))?;
+        let changed = git(&["diff", "--name-only", "--relative", commit.trim(), "--"])?;
+        let untracked = git(&["ls-files", "--others", "--exclude-standard"])?;
+        Ok(changed
+            .lines()
+            .chain(untracked.lines())
+            .filter_map(|path| dir.join(path).canonicalize().ok())
+            .collect())
+    }
+
+    /// Apply a file change to disk (used by assists)
+    pub async fn apply_file_change(file_change: &FileChange) -> Result<()> {
+        Self::apply_source_change(std::slice::from_ref(file_change), &[]).await
+    }
+}
+
+/// New contents for a file, staged before being written
+struct StagedWrite {
+    path: PathBuf,
+    contents: String,
+    /// Contents before the write, or `None` if the file is being created
+    original: Option<String>,
+}
+
+/// A completed step of a transaction, with what is needed to undo it
+enum AppliedStep {
+    Wrote {
+        path: PathBuf,
+        original: Option<String>,
+    },
+    Moved {
+        src: PathBuf,
+        dst: PathBuf,
+    },
+    Deleted {
+        path: PathBuf,
+        original: Vec<u8>,
+    },
+}
+
+impl fmt::Display for AppliedStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppliedStep::Wrote { path, .. } => write!(f, "restore {}", path.display()),
+            AppliedStep::Moved { src, dst } => {
+                write!(f, "move {} back to {}", dst.display(), src.display())
+            }
+            AppliedStep::Deleted { path, .. } => write!(f, "recreate {}", path.display()),
+        }
+    }
 }
@@ -0,0 +1,97 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use librustbelt::{
+    RustAnalyzerishBuilder,
+    entities::{CursorCoordinates, TypeHint},
+};
+
+use common::ScratchDir;
+
+/// Copy the sample project to a scratch directory, so the test can modify it
+fn copy_sample_project(name: &str) -> ScratchDir {
+    let source = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/sample-project");
+    let dir = ScratchDir::new(name);
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    for file in ["Cargo.toml", "Cargo.lock", "src/main.rs"] {
+        std::fs::copy(source.join(file), dir.join(file)).unwrap();
+    }
+    dir
+}
+
+/// The single entry in a cache directory and its modification time
+fn cache_entry(cache_dir: &Path) -> (PathBuf, SystemTime) {
+    let entries: Vec<_> = std::fs::read_dir(cache_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(entries.len(), 1, "Expected one cache entry: {entries:?}");
+    let modified = std::fs::metadata(&entries[0]).unwrap().modified().unwrap();
+    (entries[0].clone(), modified)
+}
+
+async fn people_type(project: &Path, cache_dir: &Path) -> TypeHint {
+    let main_rs = project.join("src/main.rs");
+    let analyzer = RustAnalyzerishBuilder::from_file(&main_rs)
+        .unwrap()
+        .with_cache_dir(Some(cache_dir))
+        .build()
+        .expect("Failed to load the project");
+    analyzer
+        .get_type_hint(&CursorCoordinates {
+            file_path: main_rs.to_str().unwrap().to_string(),
+            line: 31,
+            column: 13,
+            symbol: None,
+        })
+        .await
+        .unwrap()
+        .expect("Expected a type hint")
+}
+
+#[tokio::test]
+async fn test_workspace_cache_reuse_and_invalidation() {
+    let project = copy_sample_project("cache");
+    let cache_dir = ScratchDir::new("cache-entries");
+
+    // The first load stores the crate graph
+    let uncached = people_type(&project, &cache_dir).await;
+    assert!(uncached.symbol.contains("HashMap<String, Person>"));
+    let (entry, stored_at) = cache_entry(&cache_dir);
+
+    // Loading the unchanged project uses the entry as is, and still
+    // resolves the sysroot crates
+    let cached = people_type(&project, &cache_dir).await;
+    assert_eq!(cached.symbol, uncached.symbol);
+    assert_eq!(cached.canonical_types, uncached.canonical_types);
+    assert!(
+        cached
+            .canonical_types
+            .iter()
+            .any(|ty| ty == "std::collections::hash::map::HashMap")
+    );
+    assert_eq!(cache_entry(&cache_dir), (entry.clone(), stored_at));
+
+    // Changing the manifest invalidates the entry, which is rewritten
+    let manifest = project.join("Cargo.toml");
+    let contents = std::fs::read_to_string(&manifest).unwrap();
+    std::fs::write(&manifest, format!("{contents}\n# changed\n")).unwrap();
+    assert_eq!(
+        people_type(&project, &cache_dir).await.symbol,
+        uncached.symbol
+    );
+    let (rewritten, rewritten_at) = cache_entry(&cache_dir);
+    assert_eq!(rewritten, entry);
+    assert!(rewritten_at > stored_at);
+
+    // So does adding a target cargo discovers without a manifest change
+    project.write("src/bin/extra.rs", "fn main() {}\n");
+    assert_eq!(
+        people_type(&project, &cache_dir).await.symbol,
+        uncached.symbol
+    );
+    let (_, extended_at) = cache_entry(&cache_dir);
+    assert!(extended_at > rewritten_at);
+}
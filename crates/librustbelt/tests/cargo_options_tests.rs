@@ -0,0 +1,134 @@
+mod common;
+
+use std::path::Path;
+
+use librustbelt::{
+    RustAnalyzerishBuilder, WorkspaceRegistry,
+    entities::{CargoOptions, CursorCoordinates},
+};
+
+use common::ScratchDir;
+
+/// A project whose functions return `u32` when a feature, target or cfg is
+/// active, and `bool` otherwise
+const MAIN_RS: &str = r#"#[cfg(feature = "extra")]
+fn feature_value() -> u32 { 1 }
+#[cfg(not(feature = "extra"))]
+fn feature_value() -> bool { false }
+
+#[cfg(target_os = "windows")]
+fn target_value() -> u32 { 1 }
+#[cfg(not(target_os = "windows"))]
+fn target_value() -> bool { false }
+
+#[cfg(rustbelt_test)]
+fn cfg_value() -> u32 { 1 }
+#[cfg(not(rustbelt_test))]
+fn cfg_value() -> bool { false }
+
+fn main() {
+    let from_feature = feature_value();
+    let from_target = target_value();
+    let from_cfg = cfg_value();
+}
+"#;
+
+fn create_project(name: &str) -> ScratchDir {
+    ScratchDir::cargo_package(
+        name,
+        "[package]\nname = \"configured\"\nversion = \"0.1.0\"\nedition = \"2024\"\n\n[features]\nextra = []\n",
+        MAIN_RS,
+    )
+}
+
+fn cursor(project: &Path, line: u32) -> CursorCoordinates {
+    CursorCoordinates {
+        file_path: project.join("src/main.rs").to_str().unwrap().to_string(),
+        line,
+        column: 9,
+        symbol: None,
+    }
+}
+
+/// Hover text of the `from_feature`, `from_target` and `from_cfg` bindings
+async fn binding_types(analyzer: &librustbelt::RustAnalyzerish, project: &Path) -> Vec<String> {
+    let mut types = Vec::new();
+    for line in [17, 18, 19] {
+        let hint = analyzer
+            .get_type_hint(&cursor(project, line))
+            .await
+            .unwrap()
+            .expect("Expected a type hint");
+        types.push(hint.symbol);
+    }
+    types
+}
+
+#[tokio::test]
+async fn test_builder_cargo_options() {
+    let project = create_project("cargo-options");
+
+    let analyzer = RustAnalyzerishBuilder::from_file(&project)
+        .unwrap()
+        .build()
+        .expect("Failed to load the project");
+    let types = binding_types(&analyzer, &project).await;
+    println!("Default types: {types:?}");
+    assert!(types.iter().all(|ty| ty.contains("bool")), "{types:?}");
+
+    let analyzer = RustAnalyzerishBuilder::from_file(&project)
+        .unwrap()
+        .with_features(["extra"])
+        .with_target(Some("x86_64-pc-windows-msvc"))
+        .with_cfgs(["rustbelt_test"])
+        .with_env("RUSTBELT_TEST_ENV", "1")
+        .build()
+        .expect("Failed to load the project");
+    let types = binding_types(&analyzer, &project).await;
+    println!("Configured types: {types:?}");
+    assert!(types.iter().all(|ty| ty.contains("u32")), "{types:?}");
+
+    let error = RustAnalyzerishBuilder::from_file(&project)
+        .unwrap()
+        .with_cfgs(["not a cfg"])
+        .build()
+        .expect_err("Invalid cfgs should be rejected");
+    assert!(error.to_string().contains("Invalid cfg"), "{error}");
+}
+
+#[tokio::test]
+async fn test_registry_reload_switches_cargo_options() {
+    let project = create_project("registry-reload");
+    let mut registry = WorkspaceRegistry::new().with_file_watching(false);
+
+    let analyzer = registry.get_or_load(&project).unwrap();
+    let types = binding_types(analyzer, &project).await;
+    assert!(types[0].contains("bool"), "{types:?}");
+    assert!(registry.list()[0].cargo_options.is_default());
+
+    // Reloading switches the configuration of the loaded workspace
+    let options = CargoOptions {
+        all_features: true,
+        cfgs: vec!["rustbelt_test".to_string()],
+        ..Default::default()
+    };
+    let info = registry.reload(&project, options.clone()).unwrap();
+    assert_eq!(info.cargo_options, options);
+    assert_eq!(registry.cargo_options(&project), &options);
+
+    let analyzer = registry.get_or_load(&project).unwrap();
+    let types = binding_types(analyzer, &project).await;
+    println!("Reloaded types: {types:?}");
+    assert!(types[0].contains("u32"), "{types:?}");
+    assert!(types[1].contains("bool"), "{types:?}");
+    assert!(types[2].contains("u32"), "{types:?}");
+
+    // A failed reload keeps the workspace as it was
+    let invalid = CargoOptions {
+        cfgs: vec!["=".to_string()],
+        ..Default::default()
+    };
+    assert!(registry.reload(&project, invalid).is_err());
+    assert_eq!(registry.list().len(), 1);
+    assert_eq!(registry.cargo_options(&project), &options);
+}
@@ -0,0 +1,67 @@
+//! Fixtures shared by the integration tests
+//!
+//! Each test file is its own crate and uses a different part of this module.
+#![allow(dead_code)]
+
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// Scratch directory of a test, removed once dropped, including when the
+/// test fails
+#[derive(Debug)]
+pub struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    /// Create an empty directory for the test `name`, unique to the test
+    /// process
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("rustbelt-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+
+    /// Create a Cargo package with the given manifest and `src/main.rs`
+    pub fn cargo_package(name: &str, manifest: &str, main_rs: &str) -> Self {
+        let dir = Self::new(name);
+        dir.write("Cargo.toml", manifest);
+        dir.write("src/main.rs", main_rs);
+        dir
+    }
+
+    /// Write a file in the directory, creating its parent directories, and
+    /// return its path
+    pub fn write(&self, path: &str, contents: &str) -> PathBuf {
+        let path = self.0.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Deref for ScratchDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for ScratchDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Manifest of a single-package workspace named `name`
+pub fn package_manifest(name: &str, edition: &str) -> String {
+    format!(
+        "[package]\nname = \"{name}\"\nversion = \"0.1.0\"\nedition = \"{edition}\"\n\n[workspace]\n"
+    )
+}
@@ -0,0 +1,80 @@
+mod common;
+
+use librustbelt::{RustAnalyzerishBuilder, config::WorkspaceConfig, entities::DiagnosticsOptions};
+
+use common::{ScratchDir, package_manifest};
+
+#[test]
+fn test_workspace_config_load() {
+    let dir = ScratchDir::new("config-load");
+    dir.write(
+        "rustbelt.toml",
+        "[diagnostics]\ndisabled = [\"inactive-code\"]\nstyle_lints = true\n",
+    );
+    let config = WorkspaceConfig::load(&dir).expect("Error loading rustbelt.toml");
+    assert_eq!(config.diagnostics.disabled, vec!["inactive-code"]);
+    assert_eq!(config.diagnostics.style_lints, Some(true));
+    assert_eq!(config.diagnostics.experimental, None);
+
+    let missing = ScratchDir::new("config-missing");
+    assert_eq!(
+        WorkspaceConfig::load(&missing).expect("A missing rustbelt.toml is fine"),
+        WorkspaceConfig::default()
+    );
+
+    let invalid = ScratchDir::new("config-invalid");
+    invalid.write("rustbelt.toml", "[diagnostics]\nstyle_lints = 1\n");
+    let error = WorkspaceConfig::load(&invalid).expect_err("Expected a type error");
+    assert!(format!("{error:#}").contains("rustbelt.toml"));
+}
+
+#[tokio::test]
+async fn test_invalid_config_falls_back_to_defaults() {
+    let dir = ScratchDir::cargo_package(
+        "config-fallback",
+        &package_manifest("configured", "2024"),
+        "fn main() {\n    let unused = 1;\n}\n",
+    );
+    dir.write("rustbelt.toml", "[diagnostics]\nstyle_lints = 1\n");
+    let main_rs = dir.join("src/main.rs").canonicalize().unwrap();
+    let analyzer = RustAnalyzerishBuilder::from_file(&main_rs)
+        .unwrap()
+        .with_file_watching(false)
+        .build()
+        .expect("Failed to load the project");
+
+    let error = analyzer.config_error().expect("Expected the parse error");
+    assert!(error.contains("rustbelt.toml"), "{error}");
+    assert_eq!(
+        analyzer.diagnostics_options(),
+        DiagnosticsOptions::default()
+    );
+    let diagnostics = analyzer
+        .get_diagnostics(main_rs.to_str().unwrap())
+        .await
+        .expect("Diagnostics work despite the invalid rustbelt.toml");
+    assert!(diagnostics.iter().any(|d| d.code == "unused_variables"));
+}
+
+#[test]
+fn test_diagnostics_options_merged() {
+    let workspace = DiagnosticsOptions {
+        disabled: vec!["inactive-code".to_string(), "unlinked-file".to_string()],
+        style_lints: Some(true),
+        ..Default::default()
+    };
+    let call = DiagnosticsOptions {
+        disabled: vec!["E0308".to_string()],
+        enabled: vec!["unlinked-file".to_string()],
+        experimental: Some(false),
+        ..Default::default()
+    };
+
+    let merged = workspace.merged(&call);
+
+    assert_eq!(merged.disabled, vec!["inactive-code", "E0308"]);
+    assert!(merged.is_enabled("unlinked-file"));
+    assert!(!merged.is_enabled("E0308"));
+    assert_eq!(merged.style_lints, Some(true));
+    assert_eq!(merged.experimental, Some(false));
+}
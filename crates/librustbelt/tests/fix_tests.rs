@@ -0,0 +1,108 @@
+mod common;
+
+use std::path::PathBuf;
+
+use librustbelt::{RustAnalyzerishBuilder, entities::FixAllQuery};
+
+use common::{ScratchDir, package_manifest};
+
+const MAIN_RS: &str = r#"fn main() {
+    let first = 1;
+    let second = 2;
+    let used = 3;
+    println!("{used}");
+}
+"#;
+
+/// Create a scratch project, returned with the path of its main.rs
+fn create_project(name: &str) -> (ScratchDir, PathBuf) {
+    let dir = ScratchDir::cargo_package(name, &package_manifest("fixable", "2024"), MAIN_RS);
+    let main_rs = dir.join("src/main.rs").canonicalize().unwrap();
+    (dir, main_rs)
+}
+
+#[tokio::test]
+async fn test_fix_all_unused_variables() {
+    let (_dir, main_rs) = create_project("fix-all");
+    let analyzer = RustAnalyzerishBuilder::from_file(&main_rs)
+        .unwrap()
+        .with_file_watching(false)
+        .build()
+        .expect("Failed to load the project");
+    let query = FixAllQuery {
+        file_path: Some(main_rs.to_str().unwrap().to_string()),
+        codes: vec!["unused_variables".to_string()],
+    };
+
+    let (planned, preview) = analyzer
+        .preview_fix_all(&query)
+        .await
+        .expect("Error previewing fixes");
+    println!("{planned}\n{preview}");
+    assert_eq!(planned.fixed.len(), 2);
+    assert!(preview.patch().contains("+    let _first = 1;"));
+    assert_eq!(std::fs::read_to_string(&main_rs).unwrap(), MAIN_RS);
+
+    let result = analyzer.fix_all(&query).await.expect("Error fixing");
+    println!("{result}");
+    assert_eq!(result.fixed.len(), 2);
+    assert!(result.remaining.is_empty());
+    assert!(result.fixed.iter().all(|d| d.code == "unused_variables"));
+    assert_eq!(result.file_changes.len(), 1);
+
+    let fixed = std::fs::read_to_string(&main_rs).unwrap();
+    assert!(fixed.contains("let _first = 1;"));
+    assert!(fixed.contains("let _second = 2;"));
+    assert!(fixed.contains("let used = 3;"));
+
+    // All fixes are one edit in the journal
+    analyzer
+        .edit_journal()
+        .undo()
+        .await
+        .expect("Error undoing fixes")
+        .expect("Expected an edit to undo");
+    assert_eq!(std::fs::read_to_string(&main_rs).unwrap(), MAIN_RS);
+}
+
+#[tokio::test]
+async fn test_apply_diagnostic_fix() {
+    let (_dir, main_rs) = create_project("apply-fix");
+    let file_path = main_rs.to_str().unwrap();
+    let analyzer = RustAnalyzerishBuilder::from_file(&main_rs)
+        .unwrap()
+        .with_file_watching(false)
+        .build()
+        .expect("Failed to load the project");
+
+    let error = analyzer
+        .apply_diagnostic_fix(file_path, 2, 9, "unused_variables", "No such fix")
+        .await
+        .expect_err("Expected an unknown fix to fail");
+    assert!(
+        error.to_string().contains("available fixes are 0: "),
+        "{error}"
+    );
+    let error = analyzer
+        .apply_diagnostic_fix(file_path, 4, 9, "unused_variables", "0")
+        .await
+        .expect_err("Expected no diagnostic on a used variable");
+    assert!(
+        error
+            .to_string()
+            .contains("No `unused_variables` diagnostic")
+    );
+    assert_eq!(std::fs::read_to_string(&main_rs).unwrap(), MAIN_RS);
+
+    let fixed = analyzer
+        .apply_diagnostic_fix(file_path, 2, 9, "unused_variables", "0")
+        .await
+        .expect("Error applying fix");
+    println!("{fixed}");
+    assert_eq!(fixed.line, 2);
+    assert_eq!(fixed.fixes.len(), 1);
+
+    let contents = std::fs::read_to_string(&main_rs).unwrap();
+    assert!(contents.contains("let _first = 1;"));
+    assert!(contents.contains("let second = 2;"));
+}
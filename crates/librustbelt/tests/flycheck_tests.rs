@@ -0,0 +1,137 @@
+mod common;
+
+use std::path::PathBuf;
+
+use librustbelt::{
+    entities::{CargoOptions, DiagnosticInfo, DiagnosticSource},
+    flycheck::{self, FlycheckConfig},
+};
+
+use common::{ScratchDir, package_manifest};
+
+/// Create a scratch Cargo package with the given main.rs, returned with the
+/// path of its main.rs
+fn scratch_package(name: &str, main_rs: &str) -> (ScratchDir, PathBuf) {
+    let dir = ScratchDir::cargo_package(name, &package_manifest(name, "2024"), main_rs);
+    let main_path = dir.join("src/main.rs").canonicalize().unwrap();
+    (dir, main_path)
+}
+
+fn diagnostic(source: DiagnosticSource, line: u32, code: &str) -> DiagnosticInfo {
+    DiagnosticInfo {
+        message: format!("{code} message"),
+        severity: "Error".to_string(),
+        code: code.to_string(),
+        file_path: "/src/main.rs".to_string(),
+        line,
+        column: 5,
+        end_line: line,
+        end_column: 10,
+        fixes: Vec::new(),
+        source,
+    }
+}
+
+#[tokio::test]
+async fn test_flycheck_reports_borrowck_errors_and_fixes() {
+    let (_dir, main_path) = scratch_package(
+        "flycheck-borrowck",
+        "fn main() {\n    let mut unused = 1;\n    println!(\"{unused}\");\n    moved();\n}\n\n\
+         fn moved() {\n    let s = String::new();\n    let t = s;\n    println!(\"{s} {t}\");\n}\n",
+    );
+    let config = FlycheckConfig {
+        offline: true,
+        ..Default::default()
+    };
+
+    let diagnostics = flycheck::check(&config, &main_path, &CargoOptions::default())
+        .await
+        .expect("Error running flycheck");
+    for d in &diagnostics {
+        println!("{d}");
+    }
+
+    // Use after move is only caught by the borrow checker
+    let moved = diagnostics
+        .iter()
+        .find(|d| d.code == "E0382")
+        .expect("Expected a borrow-checker error");
+    assert_eq!(moved.severity, "Error");
+    assert_eq!(moved.source, DiagnosticSource::Flycheck);
+    assert_eq!(moved.line, 10);
+    assert_eq!(moved.file_path, main_path.display().to_string());
+
+    let unused_mut = diagnostics
+        .iter()
+        .find(|d| d.code == "unused_mut")
+        .expect("Expected an unused_mut warning");
+    assert_eq!(unused_mut.severity, "Warning");
+    let fix = unused_mut
+        .fixes
+        .first()
+        .expect("unused_mut has a machine-applicable fix");
+    let edit = &fix.file_changes[0].edits[0];
+    assert_eq!((edit.line, edit.column), (2, 9));
+    assert!(edit.new_text.is_empty());
+    assert!(fix.file_changes[0].source_hash.is_some());
+}
+
+#[tokio::test]
+async fn test_flycheck_positions_in_crlf_files() {
+    // rustc normalizes CRLF to LF, so its byte offsets don't match the file
+    let (_dir, main_path) = scratch_package(
+        "flycheck-crlf",
+        "// h\u{e9}llo\r\nfn main() {\r\n    let mut unused = 1;\r\n    println!(\"{unused}\");\r\n\
+         \x20   let s = String::new();\r\n    let t = s;\r\n    let _ = (\"\u{e9}\", s, t);\r\n}\r\n",
+    );
+    let config = FlycheckConfig {
+        offline: true,
+        ..Default::default()
+    };
+
+    let diagnostics = flycheck::check(&config, &main_path, &CargoOptions::default())
+        .await
+        .expect("Error running flycheck");
+
+    // Columns are UTF-8 byte offsets, and é takes two bytes
+    let moved = diagnostics
+        .iter()
+        .find(|d| d.code == "E0382")
+        .expect("Expected a borrow-checker error");
+    assert_eq!(
+        (moved.line, moved.column, moved.end_line, moved.end_column),
+        (7, 20, 7, 21)
+    );
+
+    let unused_mut = diagnostics
+        .iter()
+        .find(|d| d.code == "unused_mut")
+        .expect("Expected an unused_mut warning");
+    let edit = &unused_mut.fixes[0].file_changes[0].edits[0];
+    assert_eq!((edit.line, edit.column, edit.end_column), (3, 9, 13));
+}
+
+#[test]
+fn test_flycheck_merge_skips_native_diagnostics() {
+    let native = vec![diagnostic(DiagnosticSource::RustAnalyzer, 3, "E0308")];
+    let mut twin = diagnostic(DiagnosticSource::Flycheck, 3, "E0308");
+    twin.fixes.push(librustbelt::entities::DiagnosticFix {
+        label: "Convert".to_string(),
+        file_changes: Vec::new(),
+        file_system_edits: Vec::new(),
+        unavailable: None,
+    });
+    let checked = vec![twin, diagnostic(DiagnosticSource::Flycheck, 1, "E0382")];
+
+    let merged = flycheck::merge(native, checked);
+
+    assert_eq!(merged.len(), 2);
+    assert_eq!(merged[0].code, "E0382");
+    assert_eq!(merged[0].source, DiagnosticSource::Flycheck);
+    assert_eq!(merged[1].source, DiagnosticSource::RustAnalyzer);
+    assert_eq!(
+        merged[1].fixes.len(),
+        1,
+        "Native diagnostic takes the fixes"
+    );
+}
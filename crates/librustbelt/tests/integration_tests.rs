@@ -4,7 +4,12 @@ use std::{
 };
 
 use librustbelt::{
-    analyzer::RustAnalyzerish, builder::RustAnalyzerishBuilder, entities::CursorCoordinates,
+    analyzer::RustAnalyzerish,
+    builder::RustAnalyzerishBuilder,
+    entities::{
+        CursorCoordinates, DefinitionInfo, DiagnosticsOptions, FileChange, TextEdit,
+        WorkspaceDiagnosticsQuery,
+    },
 };
 use ra_ap_ide::SymbolKind;
 use tokio::sync::Mutex;
@@ -36,7 +41,7 @@ fn get_sample_file_path() -> PathBuf {
 #[tokio::test]
 async fn test_type_hint_simple_variable() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Test type hint for 'people' variable on line 31 (HashMap<String, Person>)
@@ -67,7 +72,7 @@ async fn test_type_hint_simple_variable() {
 #[ignore = "Requires extracting function signatures"]
 async fn test_type_hint_function_call() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Test type hint for function call result on line 35 (f64)
@@ -93,7 +98,7 @@ async fn test_type_hint_function_call() {
 #[tokio::test]
 async fn test_type_hint_complex_generic() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Test type hint for complex generic type on line 46
@@ -129,7 +134,7 @@ async fn test_type_hint_complex_generic() {
 #[tokio::test]
 async fn test_get_definition_struct() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Test get definition for Person struct usage on line 33
@@ -158,10 +163,38 @@ async fn test_get_definition_struct() {
     assert!(has_person_def, "Should find Person struct definition");
 }
 
+#[tokio::test]
+async fn test_definition_json_round_trip() {
+    let analyzer = get_shared_analyzer().await;
+    let analyzer = analyzer.lock().await;
+    let sample_path = get_sample_file_path();
+
+    let definitions = analyzer
+        .get_definition(&CursorCoordinates {
+            file_path: sample_path.to_str().unwrap().to_string(),
+            line: 33,
+            column: 18,
+            symbol: None,
+        })
+        .await
+        .expect("Error getting definition")
+        .expect("Expected to find definition for Person struct");
+
+    let json = serde_json::to_value(&definitions[0]).expect("Error serializing definition");
+    println!("Definition JSON: {json}");
+    assert_eq!(json["name"], "Person");
+    assert_eq!(json["kind"], "Struct");
+
+    let round_trip: DefinitionInfo =
+        serde_json::from_value(json).expect("Error deserializing definition");
+    assert_eq!(round_trip.kind, Some(SymbolKind::Struct));
+    assert_eq!(round_trip.line, definitions[0].line);
+}
+
 #[tokio::test]
 async fn test_get_external_definition_function() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Test get definition for function call on line 35
@@ -197,7 +230,7 @@ async fn test_get_external_definition_function() {
 #[tokio::test]
 async fn test_get_definition_method() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Test get definition for method call on line 33 (.with_email)
@@ -225,7 +258,7 @@ async fn test_get_definition_method() {
 #[tokio::test]
 async fn test_error_handling_invalid_position() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Test with invalid line/column (way beyond file bounds)
@@ -245,7 +278,7 @@ async fn test_error_handling_invalid_position() {
 #[tokio::test]
 async fn test_error_handling_nonexistent_file() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
 
     // Test with non-existent file
     let result = analyzer
@@ -264,7 +297,7 @@ async fn test_error_handling_nonexistent_file() {
 #[tokio::test]
 async fn test_no_definition_available() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Test get definition on a comment or whitespace (should return None or empty)
@@ -293,7 +326,7 @@ async fn test_no_definition_available() {
 #[tokio::test]
 async fn test_multiple_usages_same_analyzer() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Test multiple operations with the same analyzer to ensure state consistency
@@ -337,7 +370,7 @@ async fn test_multiple_usages_same_analyzer() {
 #[tokio::test]
 async fn test_analyzer_workspace_loading() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // This test ensures the analyzer can properly load and work with the workspace
@@ -369,7 +402,7 @@ async fn test_analyzer_workspace_loading() {
 #[tokio::test]
 async fn test_type_hint_variable_with_name() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Test type hint for 'doubled' variable on line 42 (should show "let doubled:
@@ -407,7 +440,7 @@ async fn test_type_hint_variable_with_name() {
 #[tokio::test]
 async fn test_get_completions_basic() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Test getting completions at a position where we expect some completions
@@ -445,7 +478,7 @@ async fn test_get_completions_basic() {
 #[tokio::test]
 async fn test_get_completions_method_chaining() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Test getting completions after a dot (method completions)
@@ -487,7 +520,7 @@ async fn test_get_completions_method_chaining() {
 #[tokio::test]
 async fn test_view_inlay_hints() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Test getting completions after a dot (method completions)
@@ -529,10 +562,37 @@ async fn test_view_inlay_hints() {
     );
 }
 
+#[tokio::test]
+async fn test_get_inlay_hints() {
+    let analyzer = get_shared_analyzer().await;
+    let analyzer = analyzer.lock().await;
+    let sample_path = get_sample_file_path();
+
+    let hints = analyzer
+        .get_inlay_hints(sample_path.to_str().unwrap())
+        .await
+        .expect("Error getting inlay hints");
+
+    // `let person = ...` on line 33, hint rendered right after `person`
+    let person_hint = hints
+        .iter()
+        .find(|hint| hint.line == 33 && hint.label == ": Person")
+        .expect("Should have a type hint for person");
+    assert_eq!(person_hint.column, 15);
+    assert_eq!(person_hint.kind, "Type");
+
+    assert!(
+        hints
+            .iter()
+            .any(|hint| hint.line == 33 && hint.label == "name: " && hint.kind == "Parameter"),
+        "Should have a parameter hint for Person::new"
+    );
+}
+
 #[tokio::test]
 async fn test_view_inlay_hints_with_line_range() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Test with line range from lines 30-45 (includes main function start to line 45)
@@ -591,7 +651,7 @@ async fn test_view_inlay_hints_with_line_range() {
 #[tokio::test]
 async fn test_find_references() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Test finding references to the Person struct definition
@@ -684,7 +744,7 @@ async fn test_find_references() {
 #[tokio::test]
 async fn test_find_references_variable() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Test finding references to a variable like 'people'
@@ -756,7 +816,7 @@ async fn test_find_references_variable() {
 #[tokio::test]
 async fn test_symbol_resolution() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Test finding a symbol with approximate coordinates
@@ -798,7 +858,7 @@ async fn test_symbol_resolution() {
 #[tokio::test]
 async fn test_get_diagnostics() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Get diagnostics for our sample file
@@ -843,10 +903,266 @@ async fn test_get_diagnostics() {
     }
 }
 
+#[tokio::test]
+async fn test_overlay_diagnostics() {
+    let analyzer = get_shared_analyzer().await;
+    let analyzer = analyzer.lock().await;
+    let sample_path = get_sample_file_path();
+    let file_path = sample_path.to_str().unwrap();
+
+    let on_disk = std::fs::read_to_string(&sample_path).unwrap();
+    let speculative = format!("{on_disk}\nfn overlay_only() -> u32 {{\n    \"not a number\"\n}}\n");
+
+    analyzer
+        .set_overlay(file_path, speculative)
+        .await
+        .expect("Error setting overlay");
+    assert!(analyzer.has_overlay(file_path));
+    assert_eq!(analyzer.overlays(), vec![file_path.to_string()]);
+
+    let diagnostics = analyzer
+        .get_diagnostics(file_path)
+        .await
+        .expect("Error getting diagnostics with overlay");
+    let cleared = analyzer
+        .clear_overlay(file_path)
+        .await
+        .expect("Error clearing overlay");
+    let restored = analyzer
+        .get_diagnostics(file_path)
+        .await
+        .expect("Error getting diagnostics after clearing overlay");
+
+    for d in &diagnostics {
+        println!("  - {}", d);
+    }
+    assert!(
+        diagnostics.iter().any(|d| d.severity == "Error"),
+        "Overlay content should produce a type error"
+    );
+    assert!(cleared, "Overlay should have been cleared");
+    assert!(!analyzer.has_overlay(file_path));
+    assert!(
+        restored.iter().all(|d| d.severity != "Error"),
+        "Diagnostics should come from disk again after clearing the overlay"
+    );
+    assert_eq!(
+        std::fs::read_to_string(&sample_path).unwrap(),
+        on_disk,
+        "Overlays must not touch the file on disk"
+    );
+}
+
+#[tokio::test]
+async fn test_check_edits() {
+    let analyzer = get_shared_analyzer().await;
+    let analyzer = analyzer.lock().await;
+    let sample_path = get_sample_file_path();
+    let file_path = sample_path.to_str().unwrap();
+    let on_disk = std::fs::read_to_string(&sample_path).unwrap();
+
+    // Pass a string where `Person::new` expects an age (line 33)
+    let file_changes = vec![FileChange {
+        file_path: file_path.to_string(),
+        edits: vec![TextEdit {
+            line: 33,
+            column: 51,
+            end_line: 33,
+            end_column: 53,
+            new_text: "\"25\"".to_string(),
+        }],
+        source_hash: None,
+    }];
+
+    let result = analyzer
+        .check_edits(&file_changes)
+        .await
+        .expect("Error checking edits");
+    println!("{result}");
+
+    assert_eq!(result.checked_files, vec![file_path.to_string()]);
+    assert!(
+        result
+            .new_diagnostics
+            .iter()
+            .any(|d| d.severity == "Error" && d.line == 33),
+        "Expected a new type error on line 33"
+    );
+    assert!(result.resolved_diagnostics.is_empty());
+
+    // The database and the file on disk are left untouched
+    let diagnostics = analyzer
+        .get_diagnostics(file_path)
+        .await
+        .expect("Error getting diagnostics");
+    assert!(diagnostics.iter().all(|d| d.severity != "Error"));
+    assert_eq!(std::fs::read_to_string(&sample_path).unwrap(), on_disk);
+}
+
+#[tokio::test]
+async fn test_get_workspace_diagnostics() {
+    let analyzer = get_shared_analyzer().await;
+    let analyzer = analyzer.lock().await;
+    let sample_path = get_sample_file_path();
+    let file_path = sample_path.to_str().unwrap();
+
+    // Two type errors, only in memory
+    let on_disk = std::fs::read_to_string(&sample_path).unwrap();
+    let speculative = format!(
+        "{on_disk}\nfn overlay_one() -> u32 {{\n    \"one\"\n}}\n\
+         \nfn overlay_two() -> u32 {{\n    \"two\"\n}}\n"
+    );
+    analyzer
+        .set_overlay(file_path, speculative)
+        .await
+        .expect("Error setting overlay");
+
+    let errors = WorkspaceDiagnosticsQuery {
+        severities: vec!["error".to_string()],
+        limit: 1,
+        ..Default::default()
+    };
+    let first_page = analyzer.get_workspace_diagnostics(&errors).await;
+    let cursor = first_page
+        .as_ref()
+        .ok()
+        .and_then(|page| page.next_cursor.clone());
+    let second_page = analyzer
+        .get_workspace_diagnostics(&WorkspaceDiagnosticsQuery {
+            cursor,
+            ..errors.clone()
+        })
+        .await;
+    // A sweep out of time resumes at the first file it didn't check
+    let expired = analyzer
+        .get_workspace_diagnostics(&WorkspaceDiagnosticsQuery {
+            deadline: Some(std::time::Instant::now()),
+            ..errors.clone()
+        })
+        .await;
+    let cursor = expired
+        .as_ref()
+        .ok()
+        .and_then(|page| page.next_cursor.clone());
+    let resumed = analyzer
+        .get_workspace_diagnostics(&WorkspaceDiagnosticsQuery {
+            cursor,
+            limit: 10,
+            ..errors.clone()
+        })
+        .await;
+    let other_crate = analyzer
+        .get_workspace_diagnostics(&WorkspaceDiagnosticsQuery {
+            crates: vec!["not_in_workspace".to_string()],
+            ..Default::default()
+        })
+        .await;
+    let other_files = analyzer
+        .get_workspace_diagnostics(&WorkspaceDiagnosticsQuery {
+            path_glob: Some("tests/**".to_string()),
+            ..Default::default()
+        })
+        .await;
+    analyzer
+        .clear_overlay(file_path)
+        .await
+        .expect("Error clearing overlay");
+
+    let first_page = first_page.expect("Error getting workspace diagnostics");
+    println!("{first_page}");
+    assert_eq!(first_page.checked_files, 1);
+    assert_eq!(first_page.total, 2);
+    assert_eq!(first_page.severity_counts.get("Error"), Some(&2));
+    assert_eq!(first_page.diagnostics.len(), 1);
+    let next_cursor = first_page
+        .next_cursor
+        .as_ref()
+        .expect("Expected a second page");
+    assert_eq!(next_cursor.file_path, first_page.diagnostics[0].file_path);
+    assert_eq!(next_cursor.index, 1);
+    assert!(first_page.diagnostics[0].fixes.is_empty());
+
+    let second_page = second_page.expect("Error getting second page");
+    assert_eq!(second_page.total, 1);
+    assert_eq!(second_page.diagnostics.len(), 1);
+    assert_eq!(second_page.next_cursor, None);
+    assert!(second_page.diagnostics[0].line > first_page.diagnostics[0].line);
+
+    let expired = expired.expect("Error getting expired page");
+    assert_eq!(expired.checked_files, 0);
+    assert_eq!(expired.unchecked_files, 1);
+    assert_eq!(expired.next_cursor.as_ref().map(|c| c.index), Some(0));
+    let resumed = resumed.expect("Error resuming the sweep");
+    assert_eq!(resumed.checked_files, 1);
+    assert_eq!(resumed.diagnostics.len(), 2);
+    assert_eq!(resumed.next_cursor, None);
+
+    let other_crate = other_crate.expect("Error filtering by crate");
+    assert_eq!(other_crate.checked_files, 0);
+    assert_eq!(other_crate.total, 0);
+
+    let other_files = other_files.expect("Error filtering by path");
+    assert_eq!(other_files.checked_files, 0);
+}
+
+#[tokio::test]
+async fn test_diagnostics_options() {
+    let analyzer = get_shared_analyzer().await;
+    let analyzer = analyzer.lock().await;
+    let sample_path = get_sample_file_path();
+    let file_path = sample_path.to_str().unwrap();
+
+    let on_disk = std::fs::read_to_string(&sample_path).unwrap();
+    let speculative = format!("{on_disk}\nfn overlay_mismatch() -> u32 {{\n    \"one\"\n}}\n");
+    analyzer
+        .set_overlay(file_path, speculative)
+        .await
+        .expect("Error setting overlay");
+    let mismatch = DiagnosticsOptions {
+        disabled: vec!["E0308".to_string()],
+        ..Default::default()
+    };
+
+    let all = analyzer.get_diagnostics(file_path).await;
+    let disabled_for_call = analyzer
+        .get_diagnostics_with_options(file_path, &mismatch)
+        .await;
+    analyzer.set_diagnostics_options(mismatch.clone());
+    let disabled_for_workspace = analyzer.get_diagnostics(file_path).await;
+    let enabled_for_call = analyzer
+        .get_diagnostics_with_options(
+            file_path,
+            &DiagnosticsOptions {
+                enabled: vec!["E0308".to_string()],
+                ..Default::default()
+            },
+        )
+        .await;
+    analyzer.set_diagnostics_options(DiagnosticsOptions::default());
+    analyzer
+        .clear_overlay(file_path)
+        .await
+        .expect("Error clearing overlay");
+
+    let has_mismatch = |diagnostics: &[librustbelt::entities::DiagnosticInfo]| {
+        diagnostics.iter().any(|d| d.code == "E0308")
+    };
+    assert!(has_mismatch(&all.expect("Error getting diagnostics")));
+    assert!(!has_mismatch(
+        &disabled_for_call.expect("Error getting diagnostics")
+    ));
+    assert!(!has_mismatch(
+        &disabled_for_workspace.expect("Error getting diagnostics")
+    ));
+    assert!(has_mismatch(
+        &enabled_for_call.expect("Error getting diagnostics")
+    ));
+}
+
 #[tokio::test]
 async fn test_analyze_symbol_struct() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Analyze the Person struct
@@ -896,7 +1212,7 @@ async fn test_analyze_symbol_struct() {
 #[tokio::test]
 async fn test_analyze_symbol_function() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Analyze the calculate_average_age function
@@ -934,7 +1250,7 @@ async fn test_analyze_symbol_function() {
 #[tokio::test]
 async fn test_get_file_outline() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     let outline = analyzer
@@ -994,7 +1310,7 @@ async fn test_get_file_outline() {
 #[tokio::test]
 async fn test_search_symbols() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
 
     // Search for "Person"
     let results = analyzer
@@ -1028,7 +1344,7 @@ async fn test_search_symbols() {
 #[tokio::test]
 async fn test_search_symbols_fuzzy() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
 
     // Fuzzy search for "calc" should find calculate_average_age
     let results = analyzer
@@ -1053,7 +1369,7 @@ async fn test_search_symbols_fuzzy() {
 #[tokio::test]
 async fn test_expand_macro_derive() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Try to expand the #[derive(Debug, Clone)] on line 4
@@ -1083,7 +1399,7 @@ async fn test_expand_macro_derive() {
 #[tokio::test]
 async fn test_expand_macro_vec() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Try to expand vec! macro on line 41
@@ -1111,7 +1427,7 @@ async fn test_expand_macro_vec() {
 #[tokio::test]
 async fn test_get_signature_help() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Test signature help inside Person::new() call on line 33
@@ -1152,7 +1468,7 @@ async fn test_get_signature_help() {
 #[tokio::test]
 async fn test_get_signature_help_insert() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Test signature help inside people.insert() call on line 35
@@ -1178,7 +1494,7 @@ async fn test_get_signature_help_insert() {
 #[tokio::test]
 async fn test_get_diagnostics_nonexistent_file() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
 
     let result = analyzer.get_diagnostics("/nonexistent/file.rs").await;
     assert!(result.is_err(), "Should error for nonexistent file");
@@ -1187,7 +1503,7 @@ async fn test_get_diagnostics_nonexistent_file() {
 #[tokio::test]
 async fn test_get_file_outline_nonexistent_file() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
 
     let result = analyzer.get_file_outline("/nonexistent/file.rs").await;
     assert!(result.is_err(), "Should error for nonexistent file");
@@ -1198,7 +1514,7 @@ async fn test_get_file_outline_nonexistent_file() {
 #[tokio::test]
 async fn test_ssr_search_method_call() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Search for .to_string() calls
@@ -1234,7 +1550,7 @@ async fn test_ssr_search_method_call() {
 #[tokio::test]
 async fn test_ssr_search_collect_call() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Search for .collect() calls
@@ -1266,7 +1582,7 @@ async fn test_ssr_search_collect_call() {
 #[tokio::test]
 async fn test_ssr_search_pattern_with_placeholder() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Search for Option field access - email field is Option<String>
@@ -1299,7 +1615,7 @@ async fn test_ssr_search_pattern_with_placeholder() {
 #[tokio::test]
 async fn test_ssr_dry_run() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Test SSR with dry_run=true (preview replacement without applying)
@@ -1343,10 +1659,45 @@ async fn test_ssr_dry_run() {
     }
 }
 
+#[tokio::test]
+async fn test_ssr_preview() {
+    let analyzer = get_shared_analyzer().await;
+    let analyzer = analyzer.lock().await;
+    let sample_path = get_sample_file_path();
+    let on_disk = std::fs::read_to_string(&sample_path).unwrap();
+
+    let preview = analyzer
+        .preview_ssr(
+            "$receiver.clone() ==>> $receiver.to_owned()",
+            Some(sample_path.to_str().unwrap()),
+        )
+        .await
+        .expect("Error previewing SSR");
+
+    let patch = preview.patch();
+    println!("SSR preview:\n{patch}");
+    assert_eq!(preview.files.len(), 1);
+    assert!(patch.starts_with("diff --git a/src/main.rs b/src/main.rs\n"));
+    assert!(patch.contains("--- a/src/main.rs\n+++ b/src/main.rs\n@@ -"));
+    assert!(
+        patch
+            .lines()
+            .any(|l| l.starts_with('-') && l.contains(".clone()"))
+    );
+    assert!(
+        patch
+            .lines()
+            .any(|l| l.starts_with('+') && l.contains(".to_owned()"))
+    );
+
+    // Nothing is written
+    assert_eq!(std::fs::read_to_string(&sample_path).unwrap(), on_disk);
+}
+
 #[tokio::test]
 async fn test_ssr_search_no_matches() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Search for a pattern that doesn't exist in the sample file
@@ -1369,7 +1720,7 @@ async fn test_ssr_search_no_matches() {
 #[tokio::test]
 async fn test_ssr_complex_pattern() {
     let analyzer = get_shared_analyzer().await;
-    let mut analyzer = analyzer.lock().await;
+    let analyzer = analyzer.lock().await;
     let sample_path = get_sample_file_path();
 
     // Search for iterator chain pattern: .iter().map(...)
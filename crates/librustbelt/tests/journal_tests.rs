@@ -0,0 +1,157 @@
+mod common;
+
+use std::path::PathBuf;
+
+use librustbelt::{
+    EditJournal,
+    entities::{FileChange, FileSystemEdit, TextEdit},
+};
+
+use common::ScratchDir;
+
+#[tokio::test]
+async fn test_journal_undo_redo() {
+    let dir = ScratchDir::new("journal");
+    let lib_rs = dir.join("lib.rs");
+    let foo_rs = dir.join("foo.rs");
+    let bar_rs = dir.join("bar.rs");
+    std::fs::write(&lib_rs, "mod foo;\n").unwrap();
+    std::fs::write(&foo_rs, "pub fn foo() {}\n").unwrap();
+
+    let journal = EditJournal::for_workspace(&dir);
+    assert!(journal.undo().await.unwrap().is_none());
+
+    let file_changes = vec![FileChange {
+        file_path: lib_rs.to_string_lossy().to_string(),
+        edits: vec![TextEdit {
+            line: 1,
+            column: 5,
+            end_line: 1,
+            end_column: 8,
+            new_text: "bar".to_string(),
+        }],
+        source_hash: None,
+    }];
+    let file_system_edits = vec![FileSystemEdit::MoveFile {
+        src: foo_rs.to_string_lossy().to_string(),
+        dst: bar_rs.to_string_lossy().to_string(),
+    }];
+    let record = journal
+        .apply("Rename to `bar`", &file_changes, &file_system_edits)
+        .await
+        .expect("Error applying edit");
+    assert_eq!(record.files.len(), 3);
+    assert_eq!(std::fs::read_to_string(&lib_rs).unwrap(), "mod bar;\n");
+    assert!(bar_rs.exists() && !foo_rs.exists());
+
+    // Undo restores every touched file, including the moved one
+    let undone = journal.undo().await.unwrap().expect("Expected an undo");
+    assert!(undone.undone);
+    assert_eq!(std::fs::read_to_string(&lib_rs).unwrap(), "mod foo;\n");
+    assert_eq!(
+        std::fs::read_to_string(&foo_rs).unwrap(),
+        "pub fn foo() {}\n"
+    );
+    assert!(!bar_rs.exists());
+
+    let history = journal.history().await.unwrap();
+    println!("History: {history:?}");
+    assert_eq!(history.len(), 1);
+    assert!(history[0].undone);
+
+    // Redo re-applies it
+    journal.redo().await.unwrap().expect("Expected a redo");
+    assert_eq!(std::fs::read_to_string(&lib_rs).unwrap(), "mod bar;\n");
+    assert!(bar_rs.exists() && !foo_rs.exists());
+    assert!(journal.redo().await.unwrap().is_none());
+
+    // Files modified after the edit are not overwritten
+    std::fs::write(&lib_rs, "mod bar;\nmod baz;\n").unwrap();
+    let error = journal.undo().await.expect_err("Undo should be refused");
+    println!("Error: {error:#}");
+    assert_eq!(
+        std::fs::read_to_string(&lib_rs).unwrap(),
+        "mod bar;\nmod baz;\n"
+    );
+}
+
+#[tokio::test]
+async fn test_journal_concurrent_edits() {
+    let dir = ScratchDir::new("journal-concurrent");
+    let paths: Vec<PathBuf> = (0..8).map(|i| dir.join(format!("file{i}.rs"))).collect();
+    for path in &paths {
+        std::fs::write(path, "fn old() {}\n").unwrap();
+    }
+
+    // Separate journals on the same workspace, like concurrent tool calls
+    let mut edits = tokio::task::JoinSet::new();
+    for path in paths.clone() {
+        let journal = EditJournal::for_workspace(&dir);
+        edits.spawn(async move {
+            let file_changes = vec![FileChange {
+                file_path: path.to_string_lossy().to_string(),
+                edits: vec![TextEdit {
+                    line: 1,
+                    column: 4,
+                    end_line: 1,
+                    end_column: 7,
+                    new_text: "new".to_string(),
+                }],
+                source_hash: None,
+            }];
+            journal.apply("Rename to `new`", &file_changes, &[]).await
+        });
+    }
+    while let Some(result) = edits.join_next().await {
+        result.unwrap().expect("Error applying edit");
+    }
+
+    // Every edit is recorded, none overwrote another's entry
+    let history = EditJournal::for_workspace(&dir).history().await.unwrap();
+    assert_eq!(history.len(), paths.len());
+    let mut ids: Vec<u64> = history.iter().map(|record| record.id).collect();
+    ids.dedup();
+    assert_eq!(ids.len(), paths.len());
+}
+
+#[tokio::test]
+async fn test_journal_locks_across_processes() {
+    let dir = ScratchDir::new("journal-lock");
+    let journal = EditJournal::for_workspace(&dir);
+    drop(journal.transaction().await.unwrap());
+
+    // Another process holding the lock file keeps transactions waiting
+    let lock_path = dir.join("target/rustbelt/edit-journal.lock");
+    let other = std::fs::File::open(&lock_path).unwrap();
+    other.lock().unwrap();
+    let waiting =
+        tokio::time::timeout(std::time::Duration::from_millis(200), journal.transaction()).await;
+    assert!(waiting.is_err(), "Transaction started while locked");
+
+    other.unlock().unwrap();
+    journal.transaction().await.unwrap();
+}
+
+#[tokio::test]
+async fn test_journal_unreadable_leaves_files_alone() {
+    let dir = ScratchDir::new("journal-unreadable");
+    let lib_rs = dir.join("lib.rs");
+    std::fs::write(&lib_rs, "fn old() {}\n").unwrap();
+    // A directory where the journal file should be can't be read or written
+    std::fs::create_dir_all(dir.join("target/rustbelt/edit-journal.json")).unwrap();
+
+    let file_changes = vec![FileChange {
+        file_path: lib_rs.to_string_lossy().to_string(),
+        edits: vec![TextEdit {
+            line: 1,
+            column: 4,
+            end_line: 1,
+            end_column: 7,
+            new_text: "new".to_string(),
+        }],
+        source_hash: None,
+    }];
+    let journal = EditJournal::for_workspace(&dir);
+    assert!(journal.apply("Rename", &file_changes, &[]).await.is_err());
+    assert_eq!(std::fs::read_to_string(&lib_rs).unwrap(), "fn old() {}\n");
+}
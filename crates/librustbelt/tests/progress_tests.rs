@@ -0,0 +1,43 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use librustbelt::{ProgressCallback, RustAnalyzerishBuilder, entities::LoadProgress};
+
+#[test]
+fn test_load_progress_phases() {
+    let sample_project = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/sample-project");
+    let updates = Arc::new(Mutex::new(Vec::new()));
+    let sink = updates.clone();
+
+    RustAnalyzerishBuilder::from_file(&sample_project)
+        .unwrap()
+        .with_progress(Some(ProgressCallback::new(move |progress| {
+            sink.lock().unwrap().push(progress)
+        })))
+        .build()
+        .expect("Failed to load the sample project");
+
+    let updates = updates.lock().unwrap();
+    println!("{} progress updates", updates.len());
+    assert!(matches!(updates[0], LoadProgress::Metadata { .. }));
+    assert_eq!(updates.last(), Some(&LoadProgress::Ready));
+
+    // Phases are reported in order
+    let phase = |progress: &LoadProgress| match progress {
+        LoadProgress::Metadata { .. } => 0,
+        LoadProgress::BuildScripts { .. } => 1,
+        LoadProgress::LoadingFiles => 2,
+        LoadProgress::Indexing { .. } => 3,
+        LoadProgress::Ready => 4,
+    };
+    assert!(
+        updates
+            .windows(2)
+            .all(|pair| phase(&pair[0]) <= phase(&pair[1]))
+    );
+    assert!(updates.contains(&LoadProgress::LoadingFiles));
+    assert!(updates.iter().any(|progress| {
+        matches!(progress, LoadProgress::Indexing { done, total, .. } if done <= total)
+    }));
+    assert_eq!(LoadProgress::Ready.percent(), 100.0);
+}
@@ -0,0 +1,121 @@
+mod common;
+
+use std::path::Path;
+
+use librustbelt::{
+    RustAnalyzerish, RustAnalyzerishBuilder, WorkspaceLoadError, WorkspaceRegistry,
+    entities::CursorCoordinates,
+};
+
+use common::ScratchDir;
+
+/// Returns `u32` when built by the generated rust-project.json, which sets
+/// the `bazel_build` cfg, and `bool` when built by cargo
+const MAIN_RS: &str = r#"#[cfg(bazel_build)]
+fn value() -> u32 { 1 }
+#[cfg(not(bazel_build))]
+fn value() -> bool { false }
+
+fn main() {
+    let built_by = value();
+}
+"#;
+
+const RUST_PROJECT_JSON: &str = r#"{
+    "crates": [
+        {
+            "display_name": "generated",
+            "root_module": "src/main.rs",
+            "edition": "2021",
+            "deps": [],
+            "cfg": ["bazel_build"],
+            "is_workspace_member": true
+        }
+    ]
+}
+"#;
+
+const STANDALONE_RS: &str = r#"fn main() {
+    let names: Vec<String> = Vec::new();
+    let count = names.len();
+}
+"#;
+
+/// A project with both a Cargo.toml and a generated rust-project.json
+fn create_project(name: &str) -> ScratchDir {
+    let dir = ScratchDir::cargo_package(
+        name,
+        "[package]\nname = \"generated\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+        MAIN_RS,
+    );
+    dir.write("rust-project.json", RUST_PROJECT_JSON);
+    dir
+}
+
+async fn type_at(analyzer: &RustAnalyzerish, file: &Path, line: u32, column: u32) -> String {
+    analyzer
+        .get_type_hint(&CursorCoordinates {
+            file_path: file.to_str().unwrap().to_string(),
+            line,
+            column,
+            symbol: None,
+        })
+        .await
+        .unwrap()
+        .expect("Expected a type hint")
+        .symbol
+}
+
+#[tokio::test]
+async fn test_rust_project_json_and_manifest_override() {
+    let project = create_project("rust-project-json");
+    let main_rs = project.join("src/main.rs");
+
+    // Discovery prefers the rust-project.json
+    let analyzer = RustAnalyzerishBuilder::from_file(&main_rs)
+        .unwrap()
+        .build()
+        .expect("Failed to load the rust-project.json");
+    let built_by = type_at(&analyzer, &main_rs, 7, 9).await;
+    assert!(built_by.contains("u32"), "{built_by}");
+
+    // An explicit manifest overrides it
+    let analyzer = RustAnalyzerishBuilder::from_manifest(project.join("Cargo.toml"))
+        .unwrap()
+        .build()
+        .expect("Failed to load the Cargo.toml");
+    let built_by = type_at(&analyzer, &main_rs, 7, 9).await;
+    assert!(built_by.contains("bool"), "{built_by}");
+
+    // So does the registry's manifest override
+    let mut registry = WorkspaceRegistry::new()
+        .with_file_watching(false)
+        .with_manifest(Some(project.join("Cargo.toml")));
+    let analyzer = registry.get_or_load(&main_rs).unwrap();
+    let built_by = type_at(analyzer, &main_rs, 7, 9).await;
+    assert!(built_by.contains("bool"), "{built_by}");
+
+    let error = RustAnalyzerishBuilder::from_manifest(main_rs.with_extension("toml"))
+        .expect_err("Missing manifests should be rejected");
+    assert!(matches!(
+        error.downcast_ref::<WorkspaceLoadError>(),
+        Some(WorkspaceLoadError::NoManifest { .. })
+    ));
+}
+
+#[tokio::test]
+async fn test_detached_file() {
+    let dir = ScratchDir::new("detached");
+    let script = dir.write("standalone.rs", STANDALONE_RS);
+
+    assert_eq!(
+        RustAnalyzerishBuilder::find_project_manifest(&script).unwrap(),
+        script.canonicalize().unwrap()
+    );
+    let analyzer = RustAnalyzerishBuilder::from_file(&script)
+        .unwrap()
+        .build()
+        .expect("Failed to load the detached file");
+    let count = type_at(&analyzer, &script, 3, 9).await;
+    assert!(count.contains("usize"), "{count}");
+}
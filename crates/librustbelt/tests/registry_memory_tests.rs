@@ -0,0 +1,61 @@
+//! Memory accounting of workspace loads
+//!
+//! Loads are measured by the growth of the process's allocations, so this
+//! test has its own binary, where no other test allocates meanwhile.
+
+mod common;
+
+use librustbelt::WorkspaceRegistry;
+use ra_ap_profile::MemoryUsage;
+
+use common::{ScratchDir, package_manifest};
+
+#[test]
+fn test_concurrent_loads_are_charged_their_own_memory() {
+    let first = ScratchDir::cargo_package(
+        "memory-first",
+        &package_manifest("first", "2024"),
+        "fn main() {}\n",
+    );
+    let second = ScratchDir::cargo_package(
+        "memory-second",
+        &package_manifest("second", "2024"),
+        "fn main() {}\n",
+    );
+    let mut registry = WorkspaceRegistry::new().with_file_watching(false);
+    let loads = [&first, &second].map(|dir| {
+        registry
+            .start_load(dir.join("src/main.rs"))
+            .unwrap()
+            .expect("Expected a new load")
+    });
+
+    let before = MemoryUsage::now().allocated.megabytes();
+    let completed: Vec<_> = std::thread::scope(|scope| {
+        let handles: Vec<_> = loads
+            .into_iter()
+            .map(|load| scope.spawn(move || load.run()))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+    let grown = MemoryUsage::now()
+        .allocated
+        .megabytes()
+        .saturating_sub(before)
+        .max(0) as u64;
+    for load in completed {
+        registry
+            .finish_load(load)
+            .expect("Failed to load the workspace");
+    }
+
+    // Each load only counts its own allocations, so together they account
+    // for no more than the growth across both, give or take rounding
+    let charged = registry.total_memory_mb();
+    println!("{charged}MB charged for {grown}MB allocated");
+    assert!(grown > 2, "Loading should allocate");
+    assert!(charged <= grown + 2, "{charged}MB charged for {grown}MB");
+}
@@ -0,0 +1,208 @@
+mod common;
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use librustbelt::{
+    WorkspaceLoadError, WorkspaceRegistry,
+    entities::{CursorCoordinates, WorkspaceState},
+};
+
+use common::ScratchDir;
+
+/// Get the path to our sample project main.rs file
+fn get_sample_file_path() -> PathBuf {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("tests/sample-project/src/main.rs");
+    path
+}
+
+#[tokio::test]
+async fn test_registry_reuses_loaded_workspace() {
+    let mut registry = WorkspaceRegistry::new().with_file_watching(false);
+    let sample_path = get_sample_file_path();
+    let sample_root = sample_path.parent().unwrap().parent().unwrap();
+
+    let analyzer = registry
+        .get_or_load(&sample_path)
+        .expect("Failed to load sample workspace");
+    let type_info = analyzer
+        .get_type_hint(&CursorCoordinates {
+            file_path: sample_path.to_str().unwrap().to_string(),
+            line: 31,
+            column: 13,
+            symbol: None,
+        })
+        .await
+        .expect("Error getting type hint");
+    assert!(
+        type_info.is_some(),
+        "Expected type info from registry analyzer"
+    );
+
+    // Files and directories inside the same workspace reuse the analyzer
+    registry
+        .get_or_load(sample_root)
+        .expect("Failed to get sample workspace by root");
+
+    let workspaces = registry.list();
+    println!("Loaded workspaces: {workspaces:?}");
+    assert_eq!(workspaces.len(), 1);
+    assert_eq!(
+        PathBuf::from(&workspaces[0].root),
+        sample_root.canonicalize().unwrap()
+    );
+
+    let unloaded = registry.unload(&sample_path);
+    assert_eq!(unloaded, Some(sample_root.canonicalize().unwrap()));
+    assert!(registry.list().is_empty());
+    assert_eq!(registry.unload(&sample_path), None);
+}
+
+#[tokio::test]
+async fn test_registry_reports_missing_manifest() {
+    let mut registry = WorkspaceRegistry::new().with_file_watching(false);
+    let dir = ScratchDir::new("no-manifest");
+
+    let error = registry
+        .get_or_load(&dir)
+        .expect_err("Loading a directory without Cargo.toml should fail");
+    println!("Load error: {error:#}");
+    assert!(matches!(
+        error.downcast_ref::<WorkspaceLoadError>(),
+        Some(WorkspaceLoadError::NoManifest { .. })
+    ));
+    assert!(registry.list().is_empty());
+}
+
+#[tokio::test]
+async fn test_registry_background_load_status() {
+    let mut registry = WorkspaceRegistry::new().with_file_watching(false);
+    let sample_path = get_sample_file_path();
+
+    let load = registry
+        .start_load(&sample_path)
+        .expect("Failed to start loading sample workspace")
+        .expect("Sample workspace should not be loaded yet");
+    assert!(
+        registry.start_load(&sample_path).unwrap().is_none(),
+        "A workspace should only be loaded once at a time"
+    );
+
+    // Nothing can be queried until the load is finished
+    assert!(registry.get(&sample_path).is_none());
+    let status = registry.status_of(&sample_path).expect("Expected a status");
+    assert_eq!(status.state, WorkspaceState::Loading);
+    assert!(!status.semantic_ready);
+
+    let load = std::thread::spawn(move || load.run()).join().unwrap();
+    let workspace = registry
+        .finish_load(load)
+        .expect("Failed to load sample workspace");
+    println!("Loaded workspace: {workspace}");
+
+    let statuses = registry.status();
+    println!("Workspace status: {statuses:?}");
+    assert_eq!(statuses.len(), 1);
+    assert_eq!(statuses[0].state, WorkspaceState::Ready);
+    assert!(statuses[0].semantic_ready);
+    assert!(statuses[0].crates_total.is_some_and(|total| total > 0));
+    assert!(registry.get(&sample_path).is_some());
+    assert!(registry.start_load(&sample_path).unwrap().is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_registry_analyzer_concurrent_queries() {
+    let mut registry = WorkspaceRegistry::new().with_file_watching(false);
+    let sample_path = get_sample_file_path();
+    let file_path = sample_path.to_str().unwrap().to_string();
+
+    let analyzer = registry
+        .get_or_load(&sample_path)
+        .expect("Failed to load sample workspace");
+
+    // Queries share the analyzer, so they run on several tasks at once
+    let queries: Vec<_> = (0..8)
+        .map(|_| {
+            let analyzer = Arc::clone(&analyzer);
+            let cursor = CursorCoordinates {
+                file_path: file_path.clone(),
+                line: 31,
+                column: 13,
+                symbol: None,
+            };
+            tokio::spawn(async move { analyzer.get_type_hint(&cursor).await })
+        })
+        .collect();
+
+    // Changes wait for running queries instead of cancelling them
+    let on_disk = std::fs::read_to_string(&sample_path).unwrap();
+    analyzer
+        .set_overlay(&file_path, on_disk)
+        .await
+        .expect("Error setting overlay");
+    assert!(
+        analyzer
+            .clear_overlay(&file_path)
+            .await
+            .expect("Error clearing overlay")
+    );
+
+    // Unloading the workspace doesn't pull the analyzer from under queries
+    registry.unload(&sample_path);
+    for query in queries {
+        let type_info = query
+            .await
+            .expect("Query task panicked")
+            .expect("Error getting type hint");
+        assert!(type_info.is_some(), "Expected type info from every query");
+    }
+    let outline = analyzer
+        .get_file_outline(&file_path)
+        .await
+        .expect("Error getting outline");
+    assert!(!outline.is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_registry_analyzer_cancel_queries() {
+    let mut registry = WorkspaceRegistry::new().with_file_watching(false);
+    let sample_path = get_sample_file_path();
+    let cursor = CursorCoordinates {
+        file_path: sample_path.to_str().unwrap().to_string(),
+        line: 31,
+        column: 13,
+        symbol: None,
+    };
+
+    let analyzer = registry
+        .get_or_load(&sample_path)
+        .expect("Failed to load sample workspace");
+    assert_eq!(analyzer.cancellations(), 0);
+
+    // Queries started after a cancellation run normally
+    analyzer.cancel_queries();
+    assert_eq!(analyzer.cancellations(), 1);
+    let type_info = analyzer
+        .get_type_hint(&cursor)
+        .await
+        .expect("Error getting type hint after cancellation");
+    assert!(type_info.is_some());
+
+    // A query running while cancelled either finishes or fails, the task
+    // doesn't panic
+    let query = tokio::spawn({
+        let analyzer = Arc::clone(&analyzer);
+        let cursor = cursor.clone();
+        async move { analyzer.find_references(&cursor).await }
+    });
+    tokio::task::spawn_blocking({
+        let analyzer = Arc::clone(&analyzer);
+        move || analyzer.cancel_queries()
+    })
+    .await
+    .unwrap();
+    let references = query.await.expect("Query task panicked");
+    println!("References while cancelled: {references:?}");
+    assert_eq!(analyzer.cancellations(), 2);
+}
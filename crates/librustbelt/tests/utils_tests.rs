@@ -0,0 +1,444 @@
+mod common;
+
+use librustbelt::{
+    RustAnalyzerUtils, StaleFileError,
+    entities::{FileChange, FileSystemEdit, TextEdit},
+};
+
+use common::ScratchDir;
+
+#[tokio::test]
+async fn test_apply_source_change_with_file_system_edits() {
+    let dir = ScratchDir::new("fs-edits");
+    let lib_rs = dir.join("lib.rs");
+    let foo_rs = dir.join("foo.rs");
+    let bar_rs = dir.join("bar.rs");
+    let new_rs = dir.join("nested/new.rs");
+    std::fs::write(&lib_rs, "mod foo;\n").unwrap();
+    std::fs::write(&foo_rs, "pub fn foo() {}\n").unwrap();
+
+    // Edits refer to the original paths, the move happens last
+    let file_changes = vec![
+        FileChange {
+            file_path: lib_rs.to_string_lossy().to_string(),
+            edits: vec![TextEdit {
+                line: 1,
+                column: 5,
+                end_line: 1,
+                end_column: 8,
+                new_text: "bar".to_string(),
+            }],
+            source_hash: None,
+        },
+        FileChange {
+            file_path: foo_rs.to_string_lossy().to_string(),
+            edits: vec![TextEdit {
+                line: 1,
+                column: 8,
+                end_line: 1,
+                end_column: 11,
+                new_text: "bar".to_string(),
+            }],
+            source_hash: None,
+        },
+    ];
+    let file_system_edits = vec![
+        FileSystemEdit::MoveFile {
+            src: foo_rs.to_string_lossy().to_string(),
+            dst: bar_rs.to_string_lossy().to_string(),
+        },
+        FileSystemEdit::CreateFile {
+            path: new_rs.to_string_lossy().to_string(),
+            initial_contents: "// new\n".to_string(),
+        },
+    ];
+
+    RustAnalyzerUtils::apply_source_change(&file_changes, &file_system_edits)
+        .await
+        .expect("Error applying source change");
+
+    assert_eq!(std::fs::read_to_string(&lib_rs).unwrap(), "mod bar;\n");
+    assert!(!foo_rs.exists(), "foo.rs should have been moved");
+    assert_eq!(
+        std::fs::read_to_string(&bar_rs).unwrap(),
+        "pub fn bar() {}\n"
+    );
+    assert_eq!(std::fs::read_to_string(&new_rs).unwrap(), "// new\n");
+}
+
+#[tokio::test]
+async fn test_apply_source_change_rejects_invalid_edit() {
+    let dir = ScratchDir::new("invalid-edit");
+    let a_rs = dir.join("a.rs");
+    let b_rs = dir.join("b.rs");
+    std::fs::write(&a_rs, "fn a() {}\n").unwrap();
+    std::fs::write(&b_rs, "fn b() {}\n").unwrap();
+
+    let file_changes = vec![
+        FileChange {
+            file_path: a_rs.to_string_lossy().to_string(),
+            edits: vec![TextEdit {
+                line: 1,
+                column: 4,
+                end_line: 1,
+                end_column: 5,
+                new_text: "renamed".to_string(),
+            }],
+            source_hash: None,
+        },
+        // Position out of bounds: nothing should be written
+        FileChange {
+            file_path: b_rs.to_string_lossy().to_string(),
+            edits: vec![TextEdit {
+                line: 10,
+                column: 1,
+                end_line: 10,
+                end_column: 2,
+                new_text: "x".to_string(),
+            }],
+            source_hash: None,
+        },
+    ];
+
+    let result = RustAnalyzerUtils::apply_source_change(&file_changes, &[]).await;
+    assert!(result.is_err(), "Out of bounds edit should fail");
+    assert_eq!(std::fs::read_to_string(&a_rs).unwrap(), "fn a() {}\n");
+    assert_eq!(std::fs::read_to_string(&b_rs).unwrap(), "fn b() {}\n");
+}
+
+#[test]
+fn test_apply_text_edits_rejects_reversed_and_overlapping_edits() {
+    let edit = |column: u32, end_column: u32, new_text: &str| TextEdit {
+        line: 1,
+        column,
+        end_line: 1,
+        end_column,
+        new_text: new_text.to_string(),
+    };
+    let apply = |edits: Vec<TextEdit>| {
+        let mut content = "fn a() {}\n".to_string();
+        let file_change = FileChange {
+            file_path: "a.rs".to_string(),
+            edits,
+            source_hash: None,
+        };
+        RustAnalyzerUtils::apply_text_edits(&mut content, &file_change).map(|()| content)
+    };
+
+    // Edits that touch, in any order, apply
+    assert_eq!(
+        apply(vec![edit(5, 7, "(x)"), edit(4, 5, "b")]).unwrap(),
+        "fn b(x) {}\n"
+    );
+
+    let error = apply(vec![edit(5, 4, "b")]).expect_err("Reversed edit should fail");
+    assert!(error.to_string().contains("before its start"), "{error}");
+
+    let error =
+        apply(vec![edit(5, 7, ""), edit(4, 6, "b")]).expect_err("Overlapping edits should fail");
+    assert!(error.to_string().contains("overlap"), "{error}");
+}
+
+#[tokio::test]
+async fn test_apply_source_change_rolls_back_on_failure() {
+    let dir = ScratchDir::new("rollback");
+    let lib_rs = dir.join("lib.rs");
+    let foo_rs = dir.join("foo.rs");
+    let created_rs = dir.join("created.rs");
+    std::fs::write(&lib_rs, "mod foo;\n").unwrap();
+    std::fs::write(&foo_rs, "pub fn foo() {}\n").unwrap();
+
+    let file_changes = vec![FileChange {
+        file_path: lib_rs.to_string_lossy().to_string(),
+        edits: vec![TextEdit {
+            line: 1,
+            column: 5,
+            end_line: 1,
+            end_column: 8,
+            new_text: "bar".to_string(),
+        }],
+        source_hash: None,
+    }];
+    // Moving into a path below a regular file fails after the edits are written
+    let file_system_edits = vec![
+        FileSystemEdit::CreateFile {
+            path: created_rs.to_string_lossy().to_string(),
+            initial_contents: "// created\n".to_string(),
+        },
+        FileSystemEdit::MoveFile {
+            src: foo_rs.to_string_lossy().to_string(),
+            dst: lib_rs.join("bar.rs").to_string_lossy().to_string(),
+        },
+    ];
+
+    let error = RustAnalyzerUtils::apply_source_change(&file_changes, &file_system_edits)
+        .await
+        .expect_err("Move below a file should fail");
+    println!("Error: {error:#}");
+
+    assert_eq!(std::fs::read_to_string(&lib_rs).unwrap(), "mod foo;\n");
+    assert_eq!(
+        std::fs::read_to_string(&foo_rs).unwrap(),
+        "pub fn foo() {}\n"
+    );
+    assert!(!created_rs.exists(), "Created file should be removed");
+    let leftovers: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(leftovers.len(), 2, "No temporary files should remain");
+}
+
+#[tokio::test]
+async fn test_apply_source_change_rejects_stale_file() {
+    let dir = ScratchDir::new("stale-file");
+    let a_rs = dir.join("a.rs");
+    let computed_against = "fn a() {}\n";
+    std::fs::write(&a_rs, computed_against).unwrap();
+
+    let file_change = FileChange {
+        file_path: a_rs.to_string_lossy().to_string(),
+        edits: vec![TextEdit {
+            line: 1,
+            column: 4,
+            end_line: 1,
+            end_column: 5,
+            new_text: "renamed".to_string(),
+        }],
+        source_hash: Some(RustAnalyzerUtils::content_hash(computed_against)),
+    };
+
+    // Someone saves the file after the edits were computed
+    std::fs::write(&a_rs, "// header\nfn a() {}\n").unwrap();
+    let error = RustAnalyzerUtils::apply_file_change(&file_change)
+        .await
+        .expect_err("Stale edit should be refused");
+    println!("Error: {error:#}");
+    let stale = error
+        .downcast_ref::<StaleFileError>()
+        .expect("Expected a StaleFileError");
+    assert_eq!(stale.path, file_change.file_path);
+    assert_eq!(
+        std::fs::read_to_string(&a_rs).unwrap(),
+        "// header\nfn a() {}\n"
+    );
+
+    // Once the file matches again the edit applies
+    std::fs::write(&a_rs, computed_against).unwrap();
+    RustAnalyzerUtils::apply_file_change(&file_change)
+        .await
+        .expect("Error applying up-to-date edit");
+    assert_eq!(std::fs::read_to_string(&a_rs).unwrap(), "fn renamed() {}\n");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_write_files_keeps_symlinks_and_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = ScratchDir::new("write-symlink");
+    let real = dir.join("real.rs");
+    let link = dir.join("link.rs");
+    std::fs::write(&real, "fn old() {}\n").unwrap();
+    std::fs::set_permissions(&real, std::fs::Permissions::from_mode(0o754)).unwrap();
+    std::os::unix::fs::symlink(&real, &link).unwrap();
+
+    let files = [(
+        link.to_string_lossy().to_string(),
+        Some("fn new() {}\n".to_string()),
+    )];
+    RustAnalyzerUtils::write_files(&files)
+        .await
+        .expect("Error writing through symlink");
+
+    assert!(link.symlink_metadata().unwrap().file_type().is_symlink());
+    assert_eq!(std::fs::read_to_string(&real).unwrap(), "fn new() {}\n");
+    let mode = real.metadata().unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o754);
+    // No staged files are left behind
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+}
+
+#[tokio::test]
+async fn test_diff_source_change() {
+    let dir = ScratchDir::new("diff");
+    let lib_rs = dir.join("lib.rs");
+    let foo_rs = dir.join("foo.rs");
+    let old_rs = dir.join("old.rs");
+    std::fs::write(&lib_rs, "mod foo;\nmod old;\n").unwrap();
+    std::fs::write(&foo_rs, "pub fn foo() {}\n").unwrap();
+    std::fs::write(&old_rs, "// old\n").unwrap();
+
+    let file_changes = vec![FileChange {
+        file_path: lib_rs.to_string_lossy().to_string(),
+        edits: vec![TextEdit {
+            line: 2,
+            column: 1,
+            end_line: 3,
+            end_column: 1,
+            new_text: "mod new;\n".to_string(),
+        }],
+        source_hash: None,
+    }];
+    let file_system_edits = vec![
+        FileSystemEdit::MoveFile {
+            src: foo_rs.to_string_lossy().to_string(),
+            dst: dir.join("bar.rs").to_string_lossy().to_string(),
+        },
+        FileSystemEdit::CreateFile {
+            path: dir.join("new.rs").to_string_lossy().to_string(),
+            initial_contents: "// new\n".to_string(),
+        },
+        FileSystemEdit::DeleteFile {
+            path: old_rs.to_string_lossy().to_string(),
+        },
+    ];
+
+    let preview = RustAnalyzerUtils::diff_source_change(&file_changes, &file_system_edits, &dir)
+        .await
+        .expect("Error computing diff");
+    let patch = preview.patch();
+    println!("Patch:\n{patch}");
+
+    assert_eq!(preview.files.len(), 4);
+    assert!(patch.contains(
+        "diff --git a/lib.rs b/lib.rs\n--- a/lib.rs\n+++ b/lib.rs\n@@ -1,2 +1,2 @@\n mod foo;\n-mod old;\n+mod new;\n"
+    ));
+    assert!(patch.contains(
+        "diff --git a/new.rs b/new.rs\nnew file mode 100644\n--- /dev/null\n+++ b/new.rs\n@@ -0,0 +1,1 @@\n+// new\n"
+    ));
+    assert!(patch.contains("diff --git a/foo.rs b/bar.rs\nrename from foo.rs\nrename to bar.rs\n"));
+    assert!(patch.contains(
+        "diff --git a/old.rs b/old.rs\ndeleted file mode 100644\n--- a/old.rs\n+++ /dev/null\n@@ -1,1 +0,0 @@\n-// old\n"
+    ));
+
+    // Nothing is written
+    assert_eq!(
+        std::fs::read_to_string(&lib_rs).unwrap(),
+        "mod foo;\nmod old;\n"
+    );
+    assert!(foo_rs.exists() && old_rs.exists());
+}
+
+#[tokio::test]
+async fn test_diff_without_trailing_newline_applies() {
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    let dir = ScratchDir::new("diff-no-newline");
+    let lib_rs = dir.join("lib.rs");
+    std::fs::write(&lib_rs, "fn a() {}\nfn b() {}").unwrap();
+    let git = |args: &[&str]| {
+        let status = Command::new("git")
+            .args(args)
+            .current_dir(&dir)
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {args:?} failed");
+    };
+    git(&["init", "-q"]);
+
+    let file_changes = vec![FileChange {
+        file_path: lib_rs.to_string_lossy().to_string(),
+        edits: vec![TextEdit {
+            line: 2,
+            column: 4,
+            end_line: 2,
+            end_column: 5,
+            new_text: "c".to_string(),
+        }],
+        source_hash: None,
+    }];
+    let file_system_edits = vec![FileSystemEdit::CreateFile {
+        path: dir.join("new.rs").to_string_lossy().to_string(),
+        initial_contents: "// new".to_string(),
+    }];
+    let preview = RustAnalyzerUtils::diff_source_change(&file_changes, &file_system_edits, &dir)
+        .await
+        .expect("Error computing diff");
+    let patch = preview.patch();
+    println!("Patch:\n{patch}");
+
+    assert!(patch.contains(
+        "-fn b() {}\n\\ No newline at end of file\n+fn c() {}\n\\ No newline at end of file\n"
+    ));
+    assert!(patch.contains("+// new\n\\ No newline at end of file\n"));
+
+    let mut apply = Command::new("git")
+        .args(["apply", "--check", "-"])
+        .current_dir(&dir)
+        .stdin(Stdio::piped())
+        .spawn()
+        .unwrap();
+    apply
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(patch.as_bytes())
+        .unwrap();
+    assert!(
+        apply.wait().unwrap().success(),
+        "git apply rejected the patch"
+    );
+}
+
+#[test]
+fn test_glob_matches() {
+    let path = std::path::Path::new("crates/core/src/parser/lexer.rs");
+    assert!(RustAnalyzerUtils::glob_matches("crates/core/**", path));
+    assert!(RustAnalyzerUtils::glob_matches("**/*.rs", path));
+    assert!(RustAnalyzerUtils::glob_matches(
+        "crates/*/src/**/lex?r.rs",
+        path
+    ));
+    assert!(RustAnalyzerUtils::glob_matches(
+        "crates/core/src/parser/lexer.rs",
+        path
+    ));
+    assert!(!RustAnalyzerUtils::glob_matches("crates/*/lexer.rs", path));
+    assert!(!RustAnalyzerUtils::glob_matches("crates/cli/**", path));
+    assert!(!RustAnalyzerUtils::glob_matches("**/*.toml", path));
+
+    // Absolute patterns match absolute paths
+    assert!(RustAnalyzerUtils::glob_matches(
+        "/work/**/src/*.rs",
+        std::path::Path::new("/work/crate/src/main.rs")
+    ));
+}
+
+#[test]
+fn test_changed_files_rejects_options() {
+    let dir = ScratchDir::new("changed-files");
+    let run = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(args)
+            .current_dir(&dir)
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {args:?} failed");
+    };
+    run(&["init", "--quiet"]);
+    std::fs::write(dir.join("lib.rs"), "pub fn lib() {}\n").unwrap();
+    run(&["add", "lib.rs"]);
+    run(&[
+        "-c",
+        "user.name=test",
+        "-c",
+        "user.email=test@example.com",
+        "commit",
+        "--quiet",
+        "-m",
+        "Initial",
+    ]);
+    std::fs::write(dir.join("lib.rs"), "pub fn changed() {}\n").unwrap();
+
+    let changed = RustAnalyzerUtils::changed_files(&dir, "HEAD").expect("Error diffing HEAD");
+    assert!(changed.contains(&dir.join("lib.rs").canonicalize().unwrap()));
+
+    let output = dir.join("overwritten");
+    let injected = format!("--output={}", output.display());
+    assert!(RustAnalyzerUtils::changed_files(&dir, &injected).is_err());
+    assert!(RustAnalyzerUtils::changed_files(&dir, "no-such-ref").is_err());
+    assert!(!output.exists());
+}
@@ -0,0 +1,347 @@
+//! Conversions between rustbelt entities and LSP types
+//!
+//! rustbelt positions are 1-based lines and 1-based byte columns. LSP
+//! positions are 0-based, and their columns are counted in the position
+//! encoding negotiated with the client (UTF-16 unless the client accepts
+//! UTF-8), so converting columns may require the text of the line.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, anyhow};
+use librustbelt::entities::{
+    CompletionItem, DiagnosticInfo, FileChange, FileSystemEdit, InlayHintInfo, SignatureInfo,
+};
+use lsp_types::{
+    CompletionItemKind, CompletionItemLabelDetails, CompletionItemTag, CreateFile, DeleteFile,
+    DiagnosticSeverity, DocumentChangeOperation, DocumentChanges, Documentation, InlayHintKind,
+    InlayHintLabel, MarkupContent, MarkupKind, NumberOrString, OneOf,
+    OptionalVersionedTextDocumentIdentifier, ParameterInformation, ParameterLabel, Position,
+    PositionEncodingKind, Range, RenameFile, ResourceOp, SignatureHelp, SignatureInformation,
+    TextDocumentEdit, Url, WorkspaceEdit,
+};
+
+/// Column encoding negotiated with the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PositionEncoding {
+    Utf8,
+    Utf16,
+}
+
+impl PositionEncoding {
+    /// Pick UTF-8 if the client supports it, UTF-16 (the LSP default) otherwise
+    pub fn negotiate(offered: Option<&[PositionEncodingKind]>) -> Self {
+        match offered {
+            Some(kinds) if kinds.contains(&PositionEncodingKind::UTF8) => Self::Utf8,
+            _ => Self::Utf16,
+        }
+    }
+
+    pub fn kind(self) -> PositionEncodingKind {
+        match self {
+            Self::Utf8 => PositionEncodingKind::UTF8,
+            Self::Utf16 => PositionEncodingKind::UTF16,
+        }
+    }
+}
+
+/// Converts positions, reading file contents only when UTF-16 columns
+/// must be computed
+///
+/// Open documents take precedence over the files on disk. Files read from
+/// disk are cached for the lifetime of the converter, which is a single
+/// request.
+pub struct PositionConverter<'a> {
+    encoding: PositionEncoding,
+    documents: &'a HashMap<PathBuf, String>,
+    disk: HashMap<PathBuf, String>,
+}
+
+impl<'a> PositionConverter<'a> {
+    pub fn new(encoding: PositionEncoding, documents: &'a HashMap<PathBuf, String>) -> Self {
+        Self {
+            encoding,
+            documents,
+            disk: HashMap::new(),
+        }
+    }
+
+    /// Text of a 0-based line of a file, without the line terminator
+    fn line_text(&mut self, path: &Path, line: u32) -> &str {
+        let text = match self.documents.get(path) {
+            Some(text) => text.as_str(),
+            None => self
+                .disk
+                .entry(path.to_path_buf())
+                .or_insert_with(|| std::fs::read_to_string(path).unwrap_or_default())
+                .as_str(),
+        };
+        text.split('\n').nth(line as usize).unwrap_or_default()
+    }
+
+    /// Convert a 1-based line and byte column to an LSP position
+    pub fn position(&mut self, path: &Path, line: u32, column: u32) -> Position {
+        let line = line.saturating_sub(1);
+        let byte_col = column.saturating_sub(1);
+        let character = match self.encoding {
+            PositionEncoding::Utf8 => byte_col,
+            PositionEncoding::Utf16 => {
+                let text = self.line_text(path, line);
+                let mut end = (byte_col as usize).min(text.len());
+                while !text.is_char_boundary(end) {
+                    end -= 1;
+                }
+                text[..end].encode_utf16().count() as u32
+            }
+        };
+        Position { line, character }
+    }
+
+    /// Convert 1-based start and end coordinates to an LSP range
+    pub fn range(
+        &mut self,
+        path: &Path,
+        (line, column): (u32, u32),
+        (end_line, end_column): (u32, u32),
+    ) -> Range {
+        Range {
+            start: self.position(path, line, column),
+            end: self.position(path, end_line, end_column),
+        }
+    }
+
+    /// Convert an LSP position to a 1-based line and byte column
+    pub fn coordinates(&mut self, path: &Path, position: Position) -> (u32, u32) {
+        let byte_col = match self.encoding {
+            PositionEncoding::Utf8 => position.character,
+            PositionEncoding::Utf16 => {
+                let text = self.line_text(path, position.line);
+                let mut units = 0;
+                let mut bytes = text.len();
+                for (idx, c) in text.char_indices() {
+                    if units >= position.character {
+                        bytes = idx;
+                        break;
+                    }
+                    units += c.len_utf16() as u32;
+                }
+                bytes as u32
+            }
+        };
+        (position.line + 1, byte_col + 1)
+    }
+
+    /// Convert rustbelt file changes and file system edits to a workspace edit
+    ///
+    /// Text edits come first since they refer to the files before they are
+    /// moved or deleted.
+    pub fn workspace_edit(
+        &mut self,
+        file_changes: &[FileChange],
+        file_system_edits: &[FileSystemEdit],
+    ) -> Result<WorkspaceEdit> {
+        let mut operations = Vec::new();
+        for change in file_changes {
+            let path = PathBuf::from(&change.file_path);
+            let edits = change
+                .edits
+                .iter()
+                .map(|edit| {
+                    OneOf::Left(lsp_types::TextEdit {
+                        range: self.range(
+                            &path,
+                            (edit.line, edit.column),
+                            (edit.end_line, edit.end_column),
+                        ),
+                        new_text: edit.new_text.clone(),
+                    })
+                })
+                .collect();
+            operations.push(DocumentChangeOperation::Edit(TextDocumentEdit {
+                text_document: OptionalVersionedTextDocumentIdentifier {
+                    uri: file_url(&path)?,
+                    version: None,
+                },
+                edits,
+            }));
+        }
+
+        for edit in file_system_edits {
+            match edit {
+                FileSystemEdit::CreateFile {
+                    path,
+                    initial_contents,
+                } => {
+                    let uri = file_url(Path::new(path))?;
+                    operations.push(DocumentChangeOperation::Op(ResourceOp::Create(
+                        CreateFile {
+                            uri: uri.clone(),
+                            options: None,
+                            annotation_id: None,
+                        },
+                    )));
+                    operations.push(DocumentChangeOperation::Edit(TextDocumentEdit {
+                        text_document: OptionalVersionedTextDocumentIdentifier {
+                            uri,
+                            version: None,
+                        },
+                        edits: vec![OneOf::Left(lsp_types::TextEdit {
+                            range: Range::default(),
+                            new_text: initial_contents.clone(),
+                        })],
+                    }));
+                }
+                FileSystemEdit::MoveFile { src, dst } | FileSystemEdit::MoveDir { src, dst } => {
+                    operations.push(DocumentChangeOperation::Op(ResourceOp::Rename(
+                        RenameFile {
+                            old_uri: file_url(Path::new(src))?,
+                            new_uri: file_url(Path::new(dst))?,
+                            options: None,
+                            annotation_id: None,
+                        },
+                    )));
+                }
+                FileSystemEdit::DeleteFile { path } => {
+                    operations.push(DocumentChangeOperation::Op(ResourceOp::Delete(
+                        DeleteFile {
+                            uri: file_url(Path::new(path))?,
+                            options: None,
+                        },
+                    )));
+                }
+            }
+        }
+
+        Ok(WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Operations(operations)),
+            ..Default::default()
+        })
+    }
+
+    pub fn diagnostic(&mut self, diagnostic: &DiagnosticInfo) -> lsp_types::Diagnostic {
+        let path = PathBuf::from(&diagnostic.file_path);
+        lsp_types::Diagnostic {
+            range: self.range(
+                &path,
+                (diagnostic.line, diagnostic.column),
+                (diagnostic.end_line, diagnostic.end_column),
+            ),
+            severity: Some(diagnostic_severity(&diagnostic.severity)),
+            code: Some(NumberOrString::String(diagnostic.code.clone())),
+            source: Some("rustbelt".to_string()),
+            message: diagnostic.message.clone(),
+            ..Default::default()
+        }
+    }
+
+    pub fn inlay_hint(&mut self, path: &Path, hint: &InlayHintInfo) -> lsp_types::InlayHint {
+        lsp_types::InlayHint {
+            position: self.position(path, hint.line, hint.column),
+            label: InlayHintLabel::String(hint.label.clone()),
+            kind: match hint.kind.as_str() {
+                "Type" => Some(InlayHintKind::TYPE),
+                "Parameter" => Some(InlayHintKind::PARAMETER),
+                _ => None,
+            },
+            text_edits: None,
+            tooltip: None,
+            padding_left: None,
+            padding_right: None,
+            data: None,
+        }
+    }
+}
+
+/// Path of a `file://` URI
+pub fn file_path(uri: &Url) -> Result<PathBuf> {
+    uri.to_file_path()
+        .map_err(|_| anyhow!("Not a file URI: {uri}"))
+}
+
+/// `file://` URI of an absolute path
+pub fn file_url(path: &Path) -> Result<Url> {
+    Url::from_file_path(path).map_err(|_| anyhow!("Not an absolute path: {}", path.display()))
+}
+
+/// Map a rust-analyzer severity name to an LSP severity
+fn diagnostic_severity(severity: &str) -> DiagnosticSeverity {
+    match severity {
+        "Error" => DiagnosticSeverity::ERROR,
+        "Warning" => DiagnosticSeverity::WARNING,
+        "WeakWarning" => DiagnosticSeverity::HINT,
+        _ => DiagnosticSeverity::INFORMATION,
+    }
+}
+
+pub fn completion_item(item: &CompletionItem) -> lsp_types::CompletionItem {
+    lsp_types::CompletionItem {
+        label: item.name.clone(),
+        label_details: item
+            .required_import
+            .as_ref()
+            .map(|import| CompletionItemLabelDetails {
+                detail: None,
+                description: Some(import.clone()),
+            }),
+        kind: item.kind.as_deref().and_then(completion_item_kind),
+        detail: item.signature.clone(),
+        documentation: item.documentation.as_ref().map(|doc| {
+            Documentation::MarkupContent(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: doc.clone(),
+            })
+        }),
+        tags: item.deprecated.then(|| vec![CompletionItemTag::DEPRECATED]),
+        ..Default::default()
+    }
+}
+
+/// Map a rust-analyzer completion or symbol kind name to an LSP kind
+fn completion_item_kind(kind: &str) -> Option<CompletionItemKind> {
+    Some(match kind {
+        "Function" => CompletionItemKind::FUNCTION,
+        "Method" => CompletionItemKind::METHOD,
+        "Field" => CompletionItemKind::FIELD,
+        "Local" | "Binding" | "ValueParam" | "SelfParam" => CompletionItemKind::VARIABLE,
+        "Struct" | "Union" | "BuiltinType" | "SelfType" => CompletionItemKind::STRUCT,
+        "Enum" => CompletionItemKind::ENUM,
+        "Variant" => CompletionItemKind::ENUM_MEMBER,
+        "Trait" => CompletionItemKind::INTERFACE,
+        "Module" | "ToolModule" => CompletionItemKind::MODULE,
+        "Const" | "ConstParam" | "Static" => CompletionItemKind::CONSTANT,
+        "Macro" | "ProcMacro" | "Derive" | "Attribute" | "BuiltinAttr" => {
+            CompletionItemKind::FUNCTION
+        }
+        "TypeAlias" | "TypeParam" | "InferredType" => CompletionItemKind::TYPE_PARAMETER,
+        "Keyword" => CompletionItemKind::KEYWORD,
+        "Snippet" => CompletionItemKind::SNIPPET,
+        _ => return None,
+    })
+}
+
+pub fn signature_help(signature: &SignatureInfo) -> SignatureHelp {
+    let parameters = signature
+        .parameters
+        .iter()
+        .map(|param| ParameterInformation {
+            label: ParameterLabel::Simple(param.clone()),
+            documentation: None,
+        })
+        .collect();
+    let active_parameter = signature.active_parameter.map(|idx| idx as u32);
+    SignatureHelp {
+        signatures: vec![SignatureInformation {
+            label: signature.signature.clone(),
+            documentation: signature.documentation.as_ref().map(|doc| {
+                Documentation::MarkupContent(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: doc.clone(),
+                })
+            }),
+            parameters: Some(parameters),
+            active_parameter,
+        }],
+        active_signature: Some(0),
+        active_parameter,
+    }
+}
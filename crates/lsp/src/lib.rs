@@ -0,0 +1,601 @@
+//! rustbelt Language Server
+//!
+//! This crate serves the `RustAnalyzerish` operations over the Language
+//! Server Protocol on stdio: hover, go to definition, references, rename,
+//! completion, signature help, code actions, inlay hints and diagnostics.
+//! Workspaces are loaded lazily, like in the MCP server, so the same
+//! analyzer configuration is used by both front-ends.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, anyhow};
+use librustbelt::{
+    WorkspaceRegistry,
+    entities::{CargoOptions, CursorCoordinates},
+};
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+    LogMessage, Notification as _, PublishDiagnostics,
+};
+use lsp_types::request::{
+    CodeActionRequest, CodeActionResolveRequest, Completion, GotoDefinition, HoverRequest,
+    InlayHintRequest, References, Rename, Request as _, SignatureHelpRequest,
+};
+use lsp_types::{
+    CodeAction, CodeActionDisabled, CodeActionKind, CodeActionOptions, CodeActionOrCommand,
+    CodeActionParams, CodeActionProviderCapability, CompletionOptions, CompletionParams,
+    CompletionResponse, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, DidSaveTextDocumentParams, GotoDefinitionParams,
+    GotoDefinitionResponse, Hover, HoverContents, HoverParams, HoverProviderCapability,
+    InitializeParams, InitializeResult, InlayHint, InlayHintParams, Location, LogMessageParams,
+    MarkupContent, MarkupKind, MessageType, OneOf, PublishDiagnosticsParams, ReferenceParams,
+    RenameParams, ServerCapabilities, ServerInfo, SignatureHelp, SignatureHelpOptions,
+    SignatureHelpParams, TextDocumentPositionParams, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextDocumentSyncOptions, TextDocumentSyncSaveOptions, Url, WorkspaceEdit,
+};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::Handle;
+use tracing::{info, warn};
+
+use convert::{PositionConverter, PositionEncoding, file_path, file_url};
+
+mod convert;
+
+/// Configuration for the language server
+#[derive(Debug, Clone, Default)]
+pub struct ServerConfig {
+    /// Memory budget in MB for loaded workspaces; least recently used
+    /// workspaces are unloaded when it is exceeded
+    pub memory_budget_mb: Option<u64>,
+    /// Directory to cache workspace metadata in
+    pub cache_dir: Option<PathBuf>,
+    /// Manifest to load projects under its directory from, instead of
+    /// discovering theirs
+    pub manifest_path: Option<PathBuf>,
+    /// Cargo configuration workspaces are loaded with
+    pub cargo_options: CargoOptions,
+}
+
+/// Where an assist was offered, so `codeAction/resolve` can compute its edit
+#[derive(Debug, Serialize, Deserialize)]
+struct AssistData {
+    file_path: String,
+    line: u32,
+    column: u32,
+    assist_id: String,
+}
+
+/// Language server state
+struct LanguageServer {
+    connection: Connection,
+    workspaces: WorkspaceRegistry,
+    /// Contents of the documents opened by the client
+    documents: HashMap<PathBuf, String>,
+    encoding: PositionEncoding,
+    /// Whether the client resolves code action edits lazily
+    resolve_code_actions: bool,
+    /// Errors in `rustbelt.toml` files already shown to the client
+    config_errors: HashSet<String>,
+    runtime: Handle,
+}
+
+/// Serve the language server on stdin/stdout until the client exits
+pub async fn serve_stdio(config: ServerConfig) -> Result<()> {
+    let runtime = Handle::current();
+    // lsp-server does blocking IO, keep it off the async workers
+    tokio::task::spawn_blocking(move || {
+        let (connection, io_threads) = Connection::stdio();
+        LanguageServer::initialize(connection, &config, runtime)?.run()?;
+        io_threads.join()?;
+        Ok(())
+    })
+    .await?
+}
+
+impl LanguageServer {
+    /// Perform the initialize handshake and negotiate capabilities
+    fn initialize(connection: Connection, config: &ServerConfig, runtime: Handle) -> Result<Self> {
+        let (id, params) = connection.initialize_start()?;
+        let params: InitializeParams = serde_json::from_value(params)?;
+
+        let encoding = PositionEncoding::negotiate(
+            params
+                .capabilities
+                .general
+                .as_ref()
+                .and_then(|general| general.position_encodings.as_deref()),
+        );
+        let resolve_code_actions = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|text_document| text_document.code_action.as_ref())
+            .and_then(|code_action| code_action.resolve_support.as_ref())
+            .is_some_and(|support| support.properties.iter().any(|p| p == "edit"));
+
+        let result = InitializeResult {
+            capabilities: Self::capabilities(encoding),
+            server_info: Some(ServerInfo {
+                name: "rustbelt".to_string(),
+                version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            }),
+        };
+        connection.initialize_finish(id, serde_json::to_value(result)?)?;
+        info!("Language server initialized ({encoding:?} positions)");
+
+        Ok(Self {
+            connection,
+            workspaces: WorkspaceRegistry::new()
+                .with_memory_budget_mb(config.memory_budget_mb)
+                .with_cache_dir(config.cache_dir.clone())
+                .with_manifest(config.manifest_path.clone())
+                .with_cargo_options(config.cargo_options.clone()),
+            documents: HashMap::new(),
+            encoding,
+            resolve_code_actions,
+            config_errors: HashSet::new(),
+            runtime,
+        })
+    }
+
+    fn capabilities(encoding: PositionEncoding) -> ServerCapabilities {
+        ServerCapabilities {
+            position_encoding: Some(encoding.kind()),
+            text_document_sync: Some(TextDocumentSyncCapability::Options(
+                TextDocumentSyncOptions {
+                    open_close: Some(true),
+                    change: Some(TextDocumentSyncKind::FULL),
+                    save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                    ..Default::default()
+                },
+            )),
+            hover_provider: Some(HoverProviderCapability::Simple(true)),
+            definition_provider: Some(OneOf::Left(true)),
+            references_provider: Some(OneOf::Left(true)),
+            rename_provider: Some(OneOf::Left(true)),
+            completion_provider: Some(CompletionOptions {
+                trigger_characters: Some(vec![".".to_string(), ":".to_string()]),
+                ..Default::default()
+            }),
+            signature_help_provider: Some(SignatureHelpOptions {
+                trigger_characters: Some(vec!["(".to_string(), ",".to_string()]),
+                ..Default::default()
+            }),
+            code_action_provider: Some(CodeActionProviderCapability::Options(CodeActionOptions {
+                code_action_kinds: Some(vec![CodeActionKind::QUICKFIX, CodeActionKind::REFACTOR]),
+                resolve_provider: Some(true),
+                ..Default::default()
+            })),
+            inlay_hint_provider: Some(OneOf::Left(true)),
+            ..Default::default()
+        }
+    }
+
+    /// Handle messages until the client sends `exit`
+    fn run(mut self) -> Result<()> {
+        while let Ok(message) = self.connection.receiver.recv() {
+            match message {
+                Message::Request(request) => {
+                    if self.connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+                    let response = self.handle_request(request);
+                    self.connection.sender.send(Message::Response(response))?;
+                }
+                Message::Notification(notification) => {
+                    // A bad notification, e.g. for an untitled document,
+                    // must not take the server down
+                    let method = notification.method.clone();
+                    if let Err(e) = self.handle_notification(notification) {
+                        warn!("{method} failed: {e:#}");
+                        self.log_message(MessageType::WARNING, format!("{method} failed: {e:#}"))?;
+                    }
+                }
+                Message::Response(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_request(&mut self, request: Request) -> Response {
+        let Request { id, method, params } = request;
+        let result = match method.as_str() {
+            HoverRequest::METHOD => self.dispatch::<HoverRequest>(params, Self::hover),
+            GotoDefinition::METHOD => self.dispatch::<GotoDefinition>(params, Self::definition),
+            References::METHOD => self.dispatch::<References>(params, Self::references),
+            Rename::METHOD => self.dispatch::<Rename>(params, Self::rename),
+            Completion::METHOD => self.dispatch::<Completion>(params, Self::completion),
+            SignatureHelpRequest::METHOD => {
+                self.dispatch::<SignatureHelpRequest>(params, Self::signature_help)
+            }
+            CodeActionRequest::METHOD => {
+                self.dispatch::<CodeActionRequest>(params, Self::code_action)
+            }
+            CodeActionResolveRequest::METHOD => {
+                self.dispatch::<CodeActionResolveRequest>(params, Self::resolve_code_action)
+            }
+            InlayHintRequest::METHOD => {
+                self.dispatch::<InlayHintRequest>(params, Self::inlay_hints)
+            }
+            _ => {
+                return Response::new_err(
+                    id,
+                    ErrorCode::MethodNotFound as i32,
+                    format!("Unsupported request: {method}"),
+                );
+            }
+        };
+
+        match result {
+            Ok(value) => Response::new_ok(id, value),
+            Err(e) => {
+                warn!("{method} failed: {e:#}");
+                Response::new_err(id, ErrorCode::RequestFailed as i32, format!("{e:#}"))
+            }
+        }
+    }
+
+    /// Deserialize the params of a request, run its handler and serialize
+    /// the result
+    fn dispatch<R: lsp_types::request::Request>(
+        &mut self,
+        params: serde_json::Value,
+        handler: fn(&mut Self, R::Params) -> Result<R::Result>,
+    ) -> Result<serde_json::Value> {
+        let params = serde_json::from_value(params)
+            .map_err(|e| anyhow!("Invalid params for {}: {e}", R::METHOD))?;
+        Ok(serde_json::to_value(handler(self, params)?)?)
+    }
+
+    fn handle_notification(&mut self, notification: Notification) -> Result<()> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                let uri = params.text_document.uri;
+                self.set_document(&uri, params.text_document.text)?;
+                self.publish_diagnostics(uri)?;
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: DidChangeTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                // Full sync: the last change holds the whole document
+                if let Some(change) = params.content_changes.into_iter().last() {
+                    let uri = params.text_document.uri;
+                    self.set_document(&uri, change.text)?;
+                    self.publish_diagnostics(uri)?;
+                }
+            }
+            DidSaveTextDocument::METHOD => {
+                let params: DidSaveTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                self.publish_diagnostics(params.text_document.uri)?;
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams =
+                    serde_json::from_value(notification.params)?;
+                let uri = params.text_document.uri;
+                let path = file_path(&uri)?;
+                self.documents.remove(&path);
+                // Only loaded workspaces can hold an overlay
+                if let Some(analyzer) = self.workspaces.get(&path) {
+                    self.runtime
+                        .block_on(analyzer.clear_overlay(&path.to_string_lossy()))?;
+                }
+                self.send_diagnostics(uri, Vec::new())?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Shadow a file with the contents of an open document
+    fn set_document(&mut self, uri: &Url, text: String) -> Result<()> {
+        let path = file_path(uri)?;
+        match self.workspaces.get_or_load(&path) {
+            Ok(analyzer) => self
+                .runtime
+                .block_on(analyzer.set_overlay(&path.to_string_lossy(), text.clone()))?,
+            Err(e) => warn!("Failed to load workspace for {}: {e:#}", path.display()),
+        }
+        self.documents.insert(path, text);
+        Ok(())
+    }
+
+    fn publish_diagnostics(&mut self, uri: Url) -> Result<()> {
+        let path = file_path(&uri)?;
+        let diagnostics = match self.workspaces.get_or_load(&path) {
+            Ok(analyzer) => {
+                // Diagnostics fall back to the defaults, tell the user once
+                if let Some(error) = analyzer.config_error()
+                    && self.config_errors.insert(error.clone())
+                {
+                    self.log_message(
+                        MessageType::WARNING,
+                        format!("Ignoring rustbelt.toml: {error}"),
+                    )?;
+                }
+                self.runtime
+                    .block_on(analyzer.get_diagnostics(&path.to_string_lossy()))
+            }
+            Err(e) => Err(e),
+        };
+        match diagnostics {
+            Ok(diagnostics) => {
+                let mut converter = self.converter();
+                let diagnostics = diagnostics
+                    .iter()
+                    .map(|d| converter.diagnostic(d))
+                    .collect();
+                self.send_diagnostics(uri, diagnostics)
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to compute diagnostics for {}: {e:#}",
+                    path.display()
+                );
+                Ok(())
+            }
+        }
+    }
+
+    fn send_diagnostics(&self, uri: Url, diagnostics: Vec<lsp_types::Diagnostic>) -> Result<()> {
+        let params = PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version: None,
+        };
+        self.connection
+            .sender
+            .send(Message::Notification(Notification::new(
+                PublishDiagnostics::METHOD.to_string(),
+                params,
+            )))?;
+        Ok(())
+    }
+
+    /// Show a message in the client's log
+    fn log_message(&self, typ: MessageType, message: String) -> Result<()> {
+        self.connection
+            .sender
+            .send(Message::Notification(Notification::new(
+                LogMessage::METHOD.to_string(),
+                LogMessageParams { typ, message },
+            )))?;
+        Ok(())
+    }
+
+    fn converter(&self) -> PositionConverter<'_> {
+        PositionConverter::new(self.encoding, &self.documents)
+    }
+
+    /// Convert an LSP document position to rustbelt cursor coordinates
+    fn cursor(&self, position: &TextDocumentPositionParams) -> Result<CursorCoordinates> {
+        let path = file_path(&position.text_document.uri)?;
+        let (line, column) = self.converter().coordinates(&path, position.position);
+        Ok(CursorCoordinates {
+            file_path: path.to_string_lossy().into_owned(),
+            line,
+            column,
+            symbol: None,
+        })
+    }
+
+    fn hover(&mut self, params: HoverParams) -> Result<Option<Hover>> {
+        let cursor = self.cursor(&params.text_document_position_params)?;
+        let analyzer = self.workspaces.get_or_load(Path::new(&cursor.file_path))?;
+        let type_hint = self.runtime.block_on(analyzer.get_type_hint(&cursor))?;
+        Ok(type_hint.map(|hint| Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                // Hovers are rendered as plain text
+                kind: MarkupKind::PlainText,
+                value: hint.symbol,
+            }),
+            range: None,
+        }))
+    }
+
+    fn definition(
+        &mut self,
+        params: GotoDefinitionParams,
+    ) -> Result<Option<GotoDefinitionResponse>> {
+        let cursor = self.cursor(&params.text_document_position_params)?;
+        let analyzer = self.workspaces.get_or_load(Path::new(&cursor.file_path))?;
+        let Some(definitions) = self.runtime.block_on(analyzer.get_definition(&cursor))? else {
+            return Ok(None);
+        };
+
+        let mut converter = self.converter();
+        let locations = definitions
+            .iter()
+            .map(|def| {
+                let path = PathBuf::from(&def.file_path);
+                Ok(Location {
+                    uri: file_url(&path)?,
+                    range: converter.range(
+                        &path,
+                        (def.line, def.column),
+                        (def.end_line, def.end_column),
+                    ),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(GotoDefinitionResponse::Array(locations)))
+    }
+
+    fn references(&mut self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        let cursor = self.cursor(&params.text_document_position)?;
+        let analyzer = self.workspaces.get_or_load(Path::new(&cursor.file_path))?;
+        let Some(references) = self.runtime.block_on(analyzer.find_references(&cursor))? else {
+            return Ok(None);
+        };
+
+        let mut converter = self.converter();
+        let locations = references
+            .iter()
+            .filter(|reference| params.context.include_declaration || !reference.is_definition)
+            .map(|reference| {
+                let path = PathBuf::from(&reference.file_path);
+                Ok(Location {
+                    uri: file_url(&path)?,
+                    range: converter.range(
+                        &path,
+                        (reference.line, reference.column),
+                        (reference.end_line, reference.end_column),
+                    ),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(locations))
+    }
+
+    fn rename(&mut self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
+        let cursor = self.cursor(&params.text_document_position)?;
+        let analyzer = self.workspaces.get_or_load(Path::new(&cursor.file_path))?;
+        let Some(rename) = self
+            .runtime
+            .block_on(analyzer.get_rename_info(&cursor, &params.new_name))?
+        else {
+            return Ok(None);
+        };
+        self.converter()
+            .workspace_edit(&rename.file_changes, &rename.file_system_edits)
+            .map(Some)
+    }
+
+    fn completion(&mut self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
+        let cursor = self.cursor(&params.text_document_position)?;
+        let analyzer = self.workspaces.get_or_load(Path::new(&cursor.file_path))?;
+        let completions = self.runtime.block_on(analyzer.get_completions(&cursor))?;
+        Ok(completions.map(|completions| {
+            CompletionResponse::Array(completions.iter().map(convert::completion_item).collect())
+        }))
+    }
+
+    fn signature_help(&mut self, params: SignatureHelpParams) -> Result<Option<SignatureHelp>> {
+        let cursor = self.cursor(&params.text_document_position_params)?;
+        let analyzer = self.workspaces.get_or_load(Path::new(&cursor.file_path))?;
+        let signature = self
+            .runtime
+            .block_on(analyzer.get_signature_help(&cursor))?;
+        Ok(signature.as_ref().map(convert::signature_help))
+    }
+
+    /// Diagnostic quick-fixes overlapping the range, followed by the assists
+    /// available at its start
+    fn code_action(
+        &mut self,
+        params: CodeActionParams,
+    ) -> Result<Option<Vec<CodeActionOrCommand>>> {
+        let path = file_path(&params.text_document.uri)?;
+        let file_path = path.to_string_lossy().into_owned();
+        let cursor = self.cursor(&TextDocumentPositionParams {
+            text_document: params.text_document.clone(),
+            position: params.range.start,
+        })?;
+
+        let analyzer = self.workspaces.get_or_load(&path)?;
+        let diagnostics = self
+            .runtime
+            .block_on(analyzer.get_diagnostics(&file_path))?;
+        let assists = self
+            .runtime
+            .block_on(analyzer.get_assists(&cursor))?
+            .unwrap_or_default();
+
+        let mut actions = Vec::new();
+        let mut converter = self.converter();
+        for diagnostic in &diagnostics {
+            let lsp_diagnostic = converter.diagnostic(diagnostic);
+            let range = lsp_diagnostic.range;
+            if range.end < params.range.start || params.range.end < range.start {
+                continue;
+            }
+            for fix in &diagnostic.fixes {
+                let (edit, disabled) = match &fix.unavailable {
+                    Some(reason) => (
+                        None,
+                        Some(CodeActionDisabled {
+                            reason: reason.clone(),
+                        }),
+                    ),
+                    None => (
+                        Some(converter.workspace_edit(&fix.file_changes, &fix.file_system_edits)?),
+                        None,
+                    ),
+                };
+                actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                    title: fix.label.clone(),
+                    kind: Some(CodeActionKind::QUICKFIX),
+                    diagnostics: Some(vec![lsp_diagnostic.clone()]),
+                    edit,
+                    disabled,
+                    ..Default::default()
+                }));
+            }
+        }
+
+        for assist in assists {
+            let mut action = CodeAction {
+                title: assist.label,
+                kind: Some(CodeActionKind::REFACTOR),
+                data: Some(serde_json::to_value(AssistData {
+                    file_path: file_path.clone(),
+                    line: cursor.line,
+                    column: cursor.column,
+                    assist_id: assist.id,
+                })?),
+                ..Default::default()
+            };
+            if !self.resolve_code_actions {
+                action = self.resolve_code_action(action)?;
+            }
+            actions.push(CodeActionOrCommand::CodeAction(action));
+        }
+
+        Ok(Some(actions))
+    }
+
+    /// Compute the edit of an assist offered by `code_action`
+    fn resolve_code_action(&mut self, mut action: CodeAction) -> Result<CodeAction> {
+        let Some(data) = action.data.take() else {
+            return Ok(action);
+        };
+        let data: AssistData = serde_json::from_value(data)?;
+        let cursor = CursorCoordinates {
+            file_path: data.file_path,
+            line: data.line,
+            column: data.column,
+            symbol: None,
+        };
+
+        let analyzer = self.workspaces.get_or_load(Path::new(&cursor.file_path))?;
+        let change = self
+            .runtime
+            .block_on(analyzer.get_assist_source_change(&cursor, &data.assist_id))?
+            .ok_or_else(|| anyhow!("Assist '{}' is no longer available", data.assist_id))?;
+        action.edit = Some(
+            self.converter()
+                .workspace_edit(&change.file_changes, &change.file_system_edits)?,
+        );
+        Ok(action)
+    }
+
+    fn inlay_hints(&mut self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
+        let path = file_path(&params.text_document.uri)?;
+        let analyzer = self.workspaces.get_or_load(&path)?;
+        let hints = self
+            .runtime
+            .block_on(analyzer.get_inlay_hints(&path.to_string_lossy()))?;
+
+        let mut converter = self.converter();
+        let hints = hints
+            .iter()
+            .map(|hint| converter.inlay_hint(&path, hint))
+            .filter(|hint| params.range.start <= hint.position && hint.position <= params.range.end)
+            .collect();
+        Ok(Some(hints))
+    }
+}
@@ -0,0 +1,307 @@
+//! Integration tests for the language server
+//!
+//! These tests spawn `rustbelt lsp` and talk to it over stdio with a minimal
+//! JSON-RPC client.
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use serde_json::{Value, json};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command as TokioCommand};
+use tokio::time::timeout;
+
+fn workspace_root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent() // crates
+        .unwrap()
+        .parent() // workspace root
+        .unwrap()
+}
+
+fn sample_file() -> PathBuf {
+    workspace_root().join("crates/librustbelt/tests/sample-project/src/main.rs")
+}
+
+fn file_uri(path: &Path) -> String {
+    format!("file://{}", path.display())
+}
+
+/// Minimal LSP client over the stdio of a `rustbelt lsp` process
+struct LspClient {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: i64,
+    /// Notifications received while waiting for responses
+    notifications: Vec<Value>,
+}
+
+impl LspClient {
+    async fn spawn() -> Self {
+        // First ensure the binary is built
+        let output = Command::new("cargo")
+            .current_dir(workspace_root())
+            .args(["build"])
+            .output()
+            .expect("Failed to build rustbelt");
+        if !output.status.success() {
+            panic!(
+                "Failed to build rustbelt: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        let profile = if cfg!(debug_assertions) {
+            "debug"
+        } else {
+            "release"
+        };
+        let binary_path = workspace_root()
+            .join("target")
+            .join(profile)
+            .join("rustbelt");
+
+        let mut child = TokioCommand::new(binary_path)
+            .arg("lsp")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .expect("Failed to spawn rustbelt lsp");
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+
+        Self {
+            child,
+            stdin,
+            stdout,
+            next_id: 1,
+            notifications: Vec::new(),
+        }
+    }
+
+    async fn send(&mut self, message: Value) {
+        let body = message.to_string();
+        let frame = format!("Content-Length: {}\r\n\r\n{body}", body.len());
+        self.stdin.write_all(frame.as_bytes()).await.unwrap();
+        self.stdin.flush().await.unwrap();
+    }
+
+    async fn receive(&mut self) -> Value {
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            self.stdout.read_line(&mut header).await.unwrap();
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(length) = header.strip_prefix("Content-Length: ") {
+                content_length = length.parse().unwrap();
+            }
+        }
+        let mut body = vec![0; content_length];
+        self.stdout.read_exact(&mut body).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// Send a request and wait for its response, keeping the notifications
+    /// received in the meantime
+    async fn request(&mut self, method: &str, params: Value) -> Value {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}))
+            .await;
+        loop {
+            let message = timeout(Duration::from_secs(120), self.receive())
+                .await
+                .unwrap_or_else(|_| panic!("Timeout waiting for {method} response"));
+            if message["id"] == id {
+                return message;
+            }
+            self.notifications.push(message);
+        }
+    }
+
+    async fn notify(&mut self, method: &str, params: Value) {
+        self.send(json!({"jsonrpc": "2.0", "method": method, "params": params}))
+            .await;
+    }
+
+    async fn initialize(&mut self) -> Value {
+        let response = self
+            .request(
+                "initialize",
+                json!({
+                    "processId": null,
+                    "rootUri": file_uri(&workspace_root().join("crates/librustbelt/tests/sample-project")),
+                    "capabilities": {},
+                }),
+            )
+            .await;
+        self.notify("initialized", json!({})).await;
+        response
+    }
+
+    async fn shutdown(mut self) {
+        let response = self.request("shutdown", Value::Null).await;
+        assert!(response.get("error").is_none());
+        self.notify("exit", Value::Null).await;
+        let status = timeout(Duration::from_secs(10), self.child.wait())
+            .await
+            .expect("Timeout waiting for the server to exit")
+            .unwrap();
+        assert!(status.success());
+    }
+}
+
+#[tokio::test]
+async fn test_lsp_initialize() {
+    let mut client = LspClient::spawn().await;
+
+    let response = client.initialize().await;
+    let capabilities = &response["result"]["capabilities"];
+    assert_eq!(capabilities["positionEncoding"], "utf-16");
+    assert_eq!(capabilities["hoverProvider"], true);
+    assert_eq!(capabilities["definitionProvider"], true);
+    assert_eq!(capabilities["referencesProvider"], true);
+    assert_eq!(capabilities["renameProvider"], true);
+    assert_eq!(capabilities["inlayHintProvider"], true);
+    assert!(capabilities["completionProvider"].is_object());
+    assert!(capabilities["signatureHelpProvider"].is_object());
+    assert!(capabilities["codeActionProvider"].is_object());
+    assert_eq!(response["result"]["serverInfo"]["name"], "rustbelt");
+
+    // Unknown requests are rejected without killing the server
+    let response = client.request("textDocument/unknown", json!({})).await;
+    assert_eq!(response["error"]["code"], -32601);
+
+    // So are notifications that can't be handled, like untitled documents
+    client
+        .notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": {
+                    "uri": "untitled:Untitled-1",
+                    "languageId": "rust",
+                    "version": 1,
+                    "text": "fn main() {}",
+                }
+            }),
+        )
+        .await;
+    client
+        .notify(
+            "textDocument/didClose",
+            json!({"textDocument": {"uri": "untitled:Untitled-1"}}),
+        )
+        .await;
+    let response = client.request("textDocument/unknown", json!({})).await;
+    assert_eq!(response["error"]["code"], -32601);
+    assert!(
+        client
+            .notifications
+            .iter()
+            .any(|message| message["method"] == "window/logMessage")
+    );
+
+    client.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_lsp_text_document_requests() {
+    let mut client = LspClient::spawn().await;
+    client.initialize().await;
+
+    let sample_file = sample_file();
+    let uri = file_uri(&sample_file);
+    client
+        .notify(
+            "textDocument/didOpen",
+            json!({
+                "textDocument": {
+                    "uri": uri,
+                    "languageId": "rust",
+                    "version": 1,
+                    "text": std::fs::read_to_string(&sample_file).unwrap(),
+                }
+            }),
+        )
+        .await;
+
+    // Hover on `people` in `let mut people: HashMap<String, Person>`
+    let response = client
+        .request(
+            "textDocument/hover",
+            json!({"textDocument": {"uri": uri}, "position": {"line": 30, "character": 12}}),
+        )
+        .await;
+    assert_eq!(response["result"]["contents"]["kind"], "plaintext");
+    let hover = response["result"]["contents"]["value"]
+        .as_str()
+        .expect("Expected hover markup");
+    assert!(hover.contains("HashMap"), "Unexpected hover: {hover}");
+
+    // Go to the definition of `Person` in `Person::new(...)`
+    let response = client
+        .request(
+            "textDocument/definition",
+            json!({"textDocument": {"uri": uri}, "position": {"line": 32, "character": 17}}),
+        )
+        .await;
+    let locations = response["result"].as_array().expect("Expected locations");
+    assert!(locations.iter().any(|location| {
+        location["uri"] == uri.as_str() && location["range"]["start"]["line"] == 4
+    }));
+
+    // References to `people`, with and without the declaration
+    let mut counts = Vec::new();
+    for include_declaration in [true, false] {
+        let response = client
+            .request(
+                "textDocument/references",
+                json!({
+                    "textDocument": {"uri": uri},
+                    "position": {"line": 30, "character": 12},
+                    "context": {"includeDeclaration": include_declaration},
+                }),
+            )
+            .await;
+        counts.push(response["result"].as_array().unwrap().len());
+    }
+    assert_eq!(counts[0], counts[1] + 1);
+
+    // Rename returns a workspace edit without touching the file
+    let response = client
+        .request(
+            "textDocument/rename",
+            json!({
+                "textDocument": {"uri": uri},
+                "position": {"line": 30, "character": 12},
+                "newName": "folks",
+            }),
+        )
+        .await;
+    let operations = response["result"]["documentChanges"]
+        .as_array()
+        .expect("Expected document changes");
+    let edits = operations[0]["edits"].as_array().unwrap();
+    assert_eq!(edits.len(), counts[0]);
+    assert!(edits.iter().all(|edit| edit["newText"] == "folks"));
+    assert!(
+        std::fs::read_to_string(&sample_file)
+            .unwrap()
+            .contains("let mut people")
+    );
+
+    // Opening the document published its diagnostics
+    assert!(client.notifications.iter().any(|notification| {
+        notification["method"] == "textDocument/publishDiagnostics"
+            && notification["params"]["uri"] == uri.as_str()
+    }));
+
+    client.shutdown().await;
+}
@@ -0,0 +1,350 @@
+//! Cancellation and deadlines of tool calls
+//!
+//! Queries can take a while on large workspaces, e.g. `find_references` on a
+//! common name or a workspace-wide `ssr`. They are stopped when the client
+//! cancels the call with `notifications/cancelled`, or when they run past
+//! the tool's deadline, by triggering salsa cancellation on the analyzer of
+//! the workspace.
+//!
+//! Salsa cancels every query running on the analyzer at once, and the other
+//! ones are then run again. A cancelled call whose analyzer is busy with
+//! other calls is therefore left to finish and its response dropped; only
+//! deadlines, which must return on time, always cancel.
+//!
+//! tmcp neither tells tools that their request was cancelled nor which
+//! request they serve, so like progress tokens, cancellations are picked up
+//! by a [`MessageHandler`]. It adds the JSON-RPC id of every tool call to its
+//! arguments as [`CALL_ID_ARGUMENT`], which tools read into a [`CallId`] and
+//! register their query with.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use librustbelt::RustAnalyzerish;
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::{debug, info};
+
+use crate::interceptor::{ClientSender, MessageHandler, Verdict};
+
+/// Argument holding the JSON-RPC id of a tool call, added by the tracker
+pub const CALL_ID_ARGUMENT: &str = "_call_id";
+
+/// JSON-RPC id of the tool call a tool serves, read from the
+/// [`CALL_ID_ARGUMENT`] of its parameters
+///
+/// Parameters declare it as
+/// `#[serde(default, rename = "_call_id")] #[schemars(skip)] call: CallId`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct CallId(Option<String>);
+
+impl CallId {
+    /// JSON-RPC id of the call, unless the tool was called without one,
+    /// e.g. by a test
+    pub fn as_str(&self) -> Option<&str> {
+        self.0.as_deref()
+    }
+}
+
+/// Deadlines of tool calls
+#[derive(Debug, Clone, Default)]
+pub struct ToolTimeouts {
+    /// Deadline of the tools without one of their own
+    pub default: Option<Duration>,
+    /// Deadlines by tool name
+    pub tools: BTreeMap<String, Duration>,
+}
+
+impl ToolTimeouts {
+    /// Deadline of a tool, if it has one
+    pub fn get(&self, tool: &str) -> Option<Duration> {
+        self.tools.get(tool).copied().or(self.default)
+    }
+}
+
+/// Why a query was stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    /// The client cancelled the tool call
+    Cancelled,
+    /// The query ran past the tool's deadline
+    TimedOut(Duration),
+}
+
+impl StopReason {
+    /// Tool error returned for the stopped call
+    pub fn to_tool_error(self, tool: &str) -> tmcp::ToolError {
+        match self {
+            StopReason::Cancelled => {
+                tmcp::ToolError::new("CANCELLED", format!("{tool} was cancelled by the client"))
+            }
+            StopReason::TimedOut(timeout) => tmcp::ToolError::new(
+                "TIMEOUT",
+                format!(
+                    "{tool} did not finish within {}s; narrow the query or raise the tool's timeout",
+                    timeout.as_secs_f64()
+                ),
+            ),
+        }
+    }
+}
+
+/// Tool calls in flight on a connection and the queries running for them
+#[derive(Debug, Clone, Default)]
+pub struct CallTracker {
+    inner: Arc<Mutex<TrackerState>>,
+}
+
+#[derive(Debug, Default)]
+struct TrackerState {
+    /// Tool calls in flight, by JSON-RPC request id
+    requests: HashMap<String, ToolCall>,
+    /// Queries running, to tell whether cancelling one disturbs others
+    queries: Vec<Arc<QueryState>>,
+}
+
+#[derive(Debug)]
+struct ToolCall {
+    tool: String,
+    cancelled: bool,
+    /// Query running for the call, once it started
+    query: Option<Arc<QueryState>>,
+}
+
+/// State of a running query, shared with whatever stops it
+#[derive(Debug)]
+struct QueryState {
+    analyzer: Arc<RustAnalyzerish>,
+    stop: Mutex<Option<StopReason>>,
+    stopped: Notify,
+}
+
+impl QueryState {
+    /// Stop the query, cancelling the queries running on its analyzer
+    ///
+    /// Cancelling blocks until the running queries of the analyzer are
+    /// done, so it runs on a blocking thread.
+    fn stop(&self, reason: StopReason) {
+        if self.mark_stopped(reason) {
+            let analyzer = self.analyzer.clone();
+            tokio::task::spawn_blocking(move || analyzer.cancel_queries());
+        }
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stop.lock().unwrap().is_some()
+    }
+
+    /// Record why the query is stopped, for queries that didn't start yet
+    ///
+    /// Returns false if it was already stopped.
+    fn mark_stopped(&self, reason: StopReason) -> bool {
+        {
+            let mut stop = self.stop.lock().unwrap();
+            if stop.is_some() {
+                return false;
+            }
+            *stop = Some(reason);
+        }
+        self.stopped.notify_waiters();
+        true
+    }
+}
+
+/// A query registered with `CallTracker::start`
+///
+/// Dropping it unregisters the query.
+#[derive(Debug)]
+pub struct RunningQuery {
+    tracker: CallTracker,
+    state: Arc<QueryState>,
+    call: CallId,
+    deadline: Option<JoinHandle<()>>,
+}
+
+impl RunningQuery {
+    /// Why the query was stopped, if it was
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        *self.state.stop.lock().unwrap()
+    }
+
+    /// Wait until the query is stopped
+    pub async fn stopped(&self) {
+        let stopped = self.state.stopped.notified();
+        if self.stop_reason().is_none() {
+            stopped.await;
+        }
+    }
+}
+
+impl Drop for RunningQuery {
+    fn drop(&mut self) {
+        if let Some(deadline) = self.deadline.take() {
+            deadline.abort();
+        }
+        let mut state = self.tracker.inner.lock().unwrap();
+        state
+            .queries
+            .retain(|query| !Arc::ptr_eq(query, &self.state));
+        if let Some(id) = &self.call.0
+            && let Some(call) = state.requests.get_mut(id)
+        {
+            call.query = None;
+        }
+    }
+}
+
+impl CallTracker {
+    /// Register a query of a tool call on an analyzer
+    ///
+    /// The query is stopped when the client cancels the call, or once
+    /// `timeout` elapsed.
+    pub fn start(
+        &self,
+        call: &CallId,
+        analyzer: Arc<RustAnalyzerish>,
+        timeout: Option<Duration>,
+    ) -> RunningQuery {
+        let state = Arc::new(QueryState {
+            analyzer,
+            stop: Mutex::new(None),
+            stopped: Notify::new(),
+        });
+
+        {
+            let mut tracker = self.inner.lock().unwrap();
+            tracker.queries.push(state.clone());
+            if let Some(id) = &call.0
+                && let Some(call) = tracker.requests.get_mut(id)
+            {
+                call.query = Some(state.clone());
+                if call.cancelled {
+                    state.mark_stopped(StopReason::Cancelled);
+                }
+            }
+        }
+
+        let deadline = timeout.map(|timeout| {
+            let state = state.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(timeout).await;
+                info!("Query timed out after {}s", timeout.as_secs_f64());
+                state.stop(StopReason::TimedOut(timeout));
+            })
+        });
+
+        RunningQuery {
+            tracker: self.clone(),
+            state,
+            call: call.clone(),
+            deadline,
+        }
+    }
+
+    /// Record an incoming tool call, adding its id to its arguments, or stop
+    /// the query of a cancelled one
+    fn track(&self, message: &mut Value) -> Verdict {
+        match message["method"].as_str() {
+            Some("tools/call") => {
+                let (Some(id), Some(tool)) =
+                    (message.get("id"), message["params"]["name"].as_str())
+                else {
+                    return Verdict::Pass;
+                };
+                let id = id.to_string();
+                let tool = tool.to_string();
+                let Some(params) = message["params"].as_object_mut() else {
+                    return Verdict::Pass;
+                };
+                let arguments = params
+                    .entry("arguments")
+                    .or_insert_with(|| Value::Object(Default::default()));
+                let Some(arguments) = arguments.as_object_mut() else {
+                    return Verdict::Pass;
+                };
+                arguments.insert(CALL_ID_ARGUMENT.to_string(), Value::from(id.clone()));
+
+                self.inner.lock().unwrap().requests.insert(
+                    id,
+                    ToolCall {
+                        tool,
+                        cancelled: false,
+                        query: None,
+                    },
+                );
+                return Verdict::Rewrite;
+            }
+            Some("notifications/cancelled") => {
+                if let Some(id) = message["params"].get("requestId") {
+                    self.cancel(&id.to_string());
+                }
+            }
+            _ => {}
+        }
+        Verdict::Pass
+    }
+
+    /// Stop the query of a cancelled tool call
+    ///
+    /// Salsa cancellation would also cancel the other queries running on
+    /// the analyzer, so a query that isn't alone is left to finish instead;
+    /// its response is dropped all the same.
+    fn cancel(&self, id: &str) {
+        let mut state = self.inner.lock().unwrap();
+        let TrackerState { requests, queries } = &mut *state;
+        let Some(call) = requests.get_mut(id) else {
+            return;
+        };
+        debug!("Client cancelled {} call {id}", call.tool);
+        call.cancelled = true;
+        let Some(query) = &call.query else {
+            return;
+        };
+        let alone = !queries.iter().any(|other| {
+            !Arc::ptr_eq(other, query)
+                && Arc::ptr_eq(&other.analyzer, &query.analyzer)
+                && !other.is_stopped()
+        });
+        if alone {
+            query.stop(StopReason::Cancelled);
+        } else {
+            debug!("Letting cancelled call {id} finish, other queries share its analyzer");
+            query.mark_stopped(StopReason::Cancelled);
+        }
+    }
+
+    /// Forget a tool call once it has been answered
+    ///
+    /// Returns false for the responses to cancelled calls, which aren't sent
+    /// to the client.
+    fn untrack(&self, message: &Value) -> bool {
+        let is_response = message.get("result").is_some() || message.get("error").is_some();
+        let Some(id) = message.get("id").filter(|_| is_response) else {
+            return true;
+        };
+        let mut state = self.inner.lock().unwrap();
+        !state
+            .requests
+            .remove(&id.to_string())
+            .is_some_and(|call| call.cancelled)
+    }
+}
+
+impl MessageHandler for CallTracker {
+    fn incoming(&self, message: &mut Value, _client: &ClientSender) -> Verdict {
+        self.track(message)
+    }
+
+    fn outgoing(&self, message: &mut Value) -> Verdict {
+        // Cancelled requests must not be answered
+        if self.untrack(message) {
+            Verdict::Pass
+        } else {
+            Verdict::Drop
+        }
+    }
+}
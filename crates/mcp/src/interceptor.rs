@@ -0,0 +1,169 @@
+//! Interception of the JSON-RPC messages of a connection
+//!
+//! tmcp doesn't expose everything rustbelt needs from the protocol: the
+//! `_meta` and id of requests, cancellations, or requests to the client.
+//! The connection's streams are therefore wrapped by an [`Interceptor`],
+//! which passes every message through a list of [`MessageHandler`]s on its
+//! way between the client and tmcp.
+
+use std::sync::Arc;
+
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, DuplexStream};
+use tokio::sync::mpsc;
+use tracing::warn;
+
+/// Size of the buffers between the client streams and the server
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// What to do with a message after a handler saw it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    /// Pass the message on unchanged
+    Pass,
+    /// Pass the message on, the handler changed it
+    Rewrite,
+    /// Drop the message, the handlers after this one don't see it
+    Drop,
+}
+
+/// Handler of the messages of a connection
+///
+/// Handlers run on the tasks reading from the client and from tmcp, so they
+/// should hand long work off to another task. Messages that aren't JSON are
+/// passed on without being handled.
+pub trait MessageHandler: Send + Sync + 'static {
+    /// Handle a message from the client, before tmcp gets it
+    fn incoming(&self, _message: &mut Value, _client: &ClientSender) -> Verdict {
+        Verdict::Pass
+    }
+
+    /// Handle a message from tmcp, before the client gets it
+    fn outgoing(&self, _message: &mut Value) -> Verdict {
+        Verdict::Pass
+    }
+}
+
+/// Sends messages of the handlers' own to the client
+#[derive(Debug, Clone)]
+pub struct ClientSender(mpsc::UnboundedSender<String>);
+
+impl ClientSender {
+    /// Send a message to the client, after the messages tmcp already wrote
+    pub fn send(&self, message: &Value) {
+        // Only fails once the connection is closed
+        let _ = self.0.send(message.to_string());
+    }
+}
+
+/// Passes the messages of a connection through handlers
+#[derive(Default, Clone)]
+pub struct Interceptor {
+    handlers: Vec<Arc<dyn MessageHandler>>,
+}
+
+impl std::fmt::Debug for Interceptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Interceptor")
+            .field("handlers", &self.handlers.len())
+            .finish()
+    }
+}
+
+impl Interceptor {
+    /// Add a handler, which sees messages after the ones added before it in
+    /// both directions
+    pub fn with(mut self, handler: impl MessageHandler) -> Self {
+        self.handlers.push(Arc::new(handler));
+        self
+    }
+
+    /// Wrap the streams of a connection
+    ///
+    /// Returns the reader and writer to serve the connection with. They are
+    /// connected to the given streams by tasks that stop when the client or
+    /// the server closes its side.
+    pub fn wrap<R, W>(self, reader: R, writer: W) -> (DuplexStream, DuplexStream)
+    where
+        R: AsyncRead + Send + Unpin + 'static,
+        W: AsyncWrite + Send + Unpin + 'static,
+    {
+        let (server_reader, mut incoming) = tokio::io::duplex(BUFFER_SIZE);
+        let (server_writer, outgoing) = tokio::io::duplex(BUFFER_SIZE);
+        let (sender, mut own_messages) = mpsc::unbounded_channel::<String>();
+        let client = ClientSender(sender);
+
+        let handlers = self.handlers.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let Some(line) = filter(line, &handlers, |handler, message| {
+                    handler.incoming(message, &client)
+                }) else {
+                    continue;
+                };
+                if incoming.write_all(line.as_bytes()).await.is_err()
+                    || incoming.write_all(b"\n").await.is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        let handlers = self.handlers;
+        tokio::spawn(async move {
+            let mut writer = writer;
+            let mut lines = BufReader::new(outgoing).lines();
+            loop {
+                // The handlers' own messages aren't handled again
+                let line = tokio::select! {
+                    line = lines.next_line() => match line {
+                        Ok(Some(line)) => {
+                            match filter(line, &handlers, |handler, message| {
+                                handler.outgoing(message)
+                            }) {
+                                Some(line) => line,
+                                None => continue,
+                            }
+                        }
+                        _ => break,
+                    },
+                    Some(message) = own_messages.recv() => message,
+                };
+                let written = async {
+                    writer.write_all(line.as_bytes()).await?;
+                    writer.write_all(b"\n").await?;
+                    writer.flush().await
+                };
+                if let Err(e) = written.await {
+                    warn!("Failed to write to the client: {e}");
+                    break;
+                }
+            }
+        });
+
+        (server_reader, server_writer)
+    }
+}
+
+/// Run a line through the handlers
+///
+/// Returns the line to pass on, or None if a handler dropped it.
+fn filter(
+    line: String,
+    handlers: &[Arc<dyn MessageHandler>],
+    handle: impl Fn(&dyn MessageHandler, &mut Value) -> Verdict,
+) -> Option<String> {
+    let Ok(mut message) = serde_json::from_str::<Value>(&line) else {
+        return Some(line);
+    };
+    let mut rewritten = false;
+    for handler in handlers {
+        match handle(handler.as_ref(), &mut message) {
+            Verdict::Pass => {}
+            Verdict::Rewrite => rewritten = true,
+            Verdict::Drop => return None,
+        }
+    }
+    Some(if rewritten { message.to_string() } else { line })
+}
@@ -4,15 +4,37 @@
 //! Protocol (MCP). It exposes IDE capabilities like type hints,
 //! go-to-definition, and more as MCP tools.
 
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Instant;
 
+use cancellation::{CallId, CallTracker, StopReason, ToolTimeouts};
+use interceptor::Interceptor;
 use libruskel::Ruskel;
-use librustbelt::{RustAnalyzerish, builder::RustAnalyzerishBuilder, entities::CursorCoordinates};
-use serde::Deserialize;
+use librustbelt::{
+    EditJournal, FlycheckConfig, PendingLoad, RustAnalyzerish, WorkspaceLoadError,
+    WorkspaceRegistry,
+    entities::{
+        CargoOptions, CursorCoordinates, DiagnosticsCursor, DiagnosticsOptions, DiffPreview,
+        EditCheckResult, FileChange, FixAllQuery, SymbolAnalysis, WorkspaceDiagnostics,
+        WorkspaceDiagnosticsQuery, WorkspaceInfo,
+    },
+};
+use output::*;
+use progress::ProgressTracker;
+use roots::RootsWatcher;
+use serde::{Deserialize, Serialize};
 use tmcp::{Result, ServerCtx, ToolResult, mcp_server, schema::CallToolResult, tool};
-use tokio::sync::Mutex;
-use tracing::info;
+use tokio::sync::{Mutex, Notify};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+pub mod cancellation;
+pub mod interceptor;
+pub mod output;
+pub mod progress;
+pub mod roots;
 
 pub const VERSION: &str = concat!(
     env!("CARGO_PKG_VERSION"),
@@ -39,6 +61,14 @@ pub struct RenameParams {
     pub symbol: Option<String>,
     /// New name for the symbol
     pub new_name: String,
+    /// If true, return the rename as a unified diff without writing to disk
+    /// (default: false)
+    #[serde(default)]
+    pub preview: bool,
+    /// Id of the tool call, added by the server, see `CallId`
+    #[serde(default, rename = "_call_id")]
+    #[schemars(skip)]
+    pub call: CallId,
 }
 
 /// Parameters for the ruskel tool
@@ -69,6 +99,10 @@ pub struct ViewInlayHintsParams {
     pub start_line: Option<u32>,
     /// Optional ending line number (1-based, inclusive)
     pub end_line: Option<u32>,
+    /// Id of the tool call, added by the server, see `CallId`
+    #[serde(default, rename = "_call_id")]
+    #[schemars(skip)]
+    pub call: CallId,
 }
 
 /// Parameters for the apply_assist tool
@@ -87,6 +121,14 @@ pub struct ApplyAssistParams {
     pub symbol: Option<String>,
     /// ID of the assist to apply
     pub assist_id: String,
+    /// If true, return the change as a unified diff without writing to disk
+    /// (default: false)
+    #[serde(default)]
+    pub preview: bool,
+    /// Id of the tool call, added by the server, see `CallId`
+    #[serde(default, rename = "_call_id")]
+    #[schemars(skip)]
+    pub call: CallId,
 }
 
 /// Parameters for file-based tools (no cursor position needed)
@@ -94,6 +136,51 @@ pub struct ApplyAssistParams {
 pub struct FileParams {
     /// Absolute path to the Rust source file
     pub file_path: String,
+    /// Id of the tool call, added by the server, see `CallId`
+    #[serde(default, rename = "_call_id")]
+    #[schemars(skip)]
+    pub call: CallId,
+}
+
+/// Parameters for file diagnostics
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct DiagnosticsParams {
+    /// Absolute path to the Rust source file
+    pub file_path: String,
+    /// Also run `cargo check` (or the server's configured flycheck command)
+    /// for the file's package, to catch borrow-checker and trait errors
+    /// rust-analyzer misses (default: false, slower)
+    #[serde(default)]
+    pub flycheck: bool,
+    /// Cargo subcommand to run for flycheck instead of the configured one,
+    /// e.g. `clippy` for lints. Implies `flycheck`.
+    pub flycheck_command: Option<String>,
+    /// Diagnostic codes to leave out of this call, e.g. `inactive-code`
+    #[serde(default)]
+    pub disabled_codes: Vec<String>,
+    /// Diagnostic codes the workspace disables to report in this call
+    #[serde(default)]
+    pub enabled_codes: Vec<String>,
+    /// Report style lints in this call, e.g. non-idiomatic naming
+    pub style_lints: Option<bool>,
+    /// Report experimental diagnostics in this call
+    pub experimental: Option<bool>,
+    /// Id of the tool call, added by the server, see `CallId`
+    #[serde(default, rename = "_call_id")]
+    #[schemars(skip)]
+    pub call: CallId,
+}
+
+impl DiagnosticsParams {
+    /// Diagnostics options of this call
+    fn options(&self) -> DiagnosticsOptions {
+        DiagnosticsOptions {
+            disabled: self.disabled_codes.clone(),
+            enabled: self.enabled_codes.clone(),
+            style_lints: self.style_lints,
+            experimental: self.experimental,
+        }
+    }
 }
 
 /// Parameters for symbol search
@@ -104,6 +191,13 @@ pub struct SearchSymbolsParams {
     /// Maximum number of results to return (default: 50)
     #[serde(default = "default_search_limit")]
     pub limit: usize,
+    /// Optional path to a file or directory inside the workspace to search.
+    /// If not provided, uses the current directory.
+    pub workspace_path: Option<String>,
+    /// Id of the tool call, added by the server, see `CallId`
+    #[serde(default, rename = "_call_id")]
+    #[schemars(skip)]
+    pub call: CallId,
 }
 
 fn default_search_limit() -> usize {
@@ -123,6 +217,10 @@ pub struct CursorParams {
     /// If provided, will search for this symbol within a tolerance box
     /// of +/- 5 lines/columns around the given coordinates.
     pub symbol: Option<String>,
+    /// Id of the tool call, added by the server, see `CallId`
+    #[serde(default, rename = "_call_id")]
+    #[schemars(skip)]
+    pub call: CallId,
 }
 
 /// Parameters for structural search and replace
@@ -142,6 +240,14 @@ pub struct SsrParams {
     /// If true, only show matches without applying changes (default: false)
     #[serde(default)]
     pub dry_run: bool,
+    /// If true, return the replacements as a unified diff without writing to
+    /// disk (default: false)
+    #[serde(default)]
+    pub preview: bool,
+    /// Id of the tool call, added by the server, see `CallId`
+    #[serde(default, rename = "_call_id")]
+    #[schemars(skip)]
+    pub call: CallId,
 }
 
 /// Parameters for SSR search (find matches without replacement)
@@ -156,41 +262,539 @@ pub struct SsrSearchParams {
     pub pattern: String,
     /// Optional file path for name resolution context.
     pub context_file: Option<String>,
+    /// Id of the tool call, added by the server, see `CallId`
+    #[serde(default, rename = "_call_id")]
+    #[schemars(skip)]
+    pub call: CallId,
+}
+
+/// Parameters for checking proposed edits
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct CheckEditsParams {
+    /// Proposed changes, with 1-based line/column positions relative to the
+    /// current contents of each file
+    pub file_changes: Vec<FileChange>,
+    /// Id of the tool call, added by the server, see `CallId`
+    #[serde(default, rename = "_call_id")]
+    #[schemars(skip)]
+    pub call: CallId,
+}
+
+/// Parameters for a workspace-wide diagnostics sweep
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct WorkspaceDiagnosticsParams {
+    /// Path to a file or directory inside the workspace to check.
+    /// If not provided, uses the current directory.
+    pub workspace_path: Option<String>,
+    /// Only report these severities: `Error`, `Warning`, `WeakWarning` or
+    /// `Allow` (case-insensitive)
+    #[serde(default)]
+    pub severities: Vec<String>,
+    /// Only report these diagnostic codes, e.g. `E0308` or `unused_variables`
+    #[serde(default)]
+    pub codes: Vec<String>,
+    /// Only check the files of these crates
+    #[serde(default)]
+    pub crates: Vec<String>,
+    /// Only check files matching this glob, relative to the workspace root
+    /// unless absolute, e.g. `crates/core/**` or `**/tests/*.rs`
+    pub path_glob: Option<String>,
+    /// Only check files changed since this git ref (e.g. `HEAD` or `main`),
+    /// including uncommitted and untracked files
+    pub changed_since: Option<String>,
+    /// Include quick-fixes for each diagnostic (default: false, slower)
+    #[serde(default)]
+    pub include_fixes: bool,
+    /// Where to resume, from `next_cursor` of the previous page (default:
+    /// the first file)
+    pub cursor: Option<DiagnosticsCursor>,
+    /// Maximum number of diagnostics to return (default: 100)
+    #[serde(default = "default_diagnostics_limit")]
+    pub limit: usize,
+    /// Diagnostic codes to leave out of this sweep, e.g. `inactive-code`
+    #[serde(default)]
+    pub disabled_codes: Vec<String>,
+    /// Diagnostic codes the workspace disables to report in this sweep
+    #[serde(default)]
+    pub enabled_codes: Vec<String>,
+    /// Report style lints in this sweep, e.g. non-idiomatic naming
+    pub style_lints: Option<bool>,
+    /// Report experimental diagnostics in this sweep
+    pub experimental: Option<bool>,
+    /// Id of the tool call, added by the server, see `CallId`
+    #[serde(default, rename = "_call_id")]
+    #[schemars(skip)]
+    pub call: CallId,
+}
+
+fn default_diagnostics_limit() -> usize {
+    WorkspaceDiagnosticsQuery::default().limit
+}
+
+impl From<WorkspaceDiagnosticsParams> for WorkspaceDiagnosticsQuery {
+    fn from(params: WorkspaceDiagnosticsParams) -> Self {
+        Self {
+            severities: params.severities,
+            codes: params.codes,
+            crates: params.crates,
+            path_glob: params.path_glob,
+            changed_since: params.changed_since,
+            include_fixes: params.include_fixes,
+            options: DiagnosticsOptions {
+                disabled: params.disabled_codes,
+                enabled: params.enabled_codes,
+                style_lints: params.style_lints,
+                experimental: params.experimental,
+            },
+            cursor: params.cursor,
+            limit: params.limit,
+        }
+    }
+}
+
+/// Parameters for applying one diagnostic fix
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ApplyDiagnosticFixParams {
+    /// Absolute path to the Rust source file
+    pub file_path: String,
+    /// Line number (1-based) inside the diagnostic's range
+    pub line: u32,
+    /// Column number (1-based) inside the diagnostic's range
+    pub column: u32,
+    /// Code of the diagnostic, as reported by `get_diagnostics`
+    pub code: String,
+    /// The fix to apply: its 0-based index in the diagnostic's `fixes`, or
+    /// its label
+    pub fix: String,
+    /// If true, return the fix as a unified diff without writing to disk
+    /// (default: false)
+    #[serde(default)]
+    pub preview: bool,
+    /// Id of the tool call, added by the server, see `CallId`
+    #[serde(default, rename = "_call_id")]
+    #[schemars(skip)]
+    pub call: CallId,
+}
+
+/// Parameters for applying every safe diagnostic fix
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct FixAllParams {
+    /// Absolute path of the file to fix. If not provided, fixes every file
+    /// of the workspace at `workspace_path`.
+    pub file_path: Option<String>,
+    /// Path to a file or directory inside the workspace to fix, when
+    /// `file_path` isn't given. If not provided, uses the current directory.
+    pub workspace_path: Option<String>,
+    /// Only fix diagnostics with these codes, e.g. `unused_variables` or
+    /// `remove-unnecessary-else`; every code if empty
+    #[serde(default)]
+    pub codes: Vec<String>,
+    /// If true, return the fixes as a unified diff without writing to disk
+    /// (default: false)
+    #[serde(default)]
+    pub preview: bool,
+    /// Id of the tool call, added by the server, see `CallId`
+    #[serde(default, rename = "_call_id")]
+    #[schemars(skip)]
+    pub call: CallId,
+}
+
+/// Parameters for configuring a workspace's diagnostics
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ConfigureDiagnosticsParams {
+    /// Workspace root, or any file or directory inside the workspace
+    pub path: String,
+    /// Diagnostic codes to leave out, e.g. `inactive-code` or `unlinked-file`
+    #[serde(default)]
+    pub disabled_codes: Vec<String>,
+    /// Diagnostic codes disabled in `rustbelt.toml` to report again
+    #[serde(default)]
+    pub enabled_codes: Vec<String>,
+    /// Report style lints, e.g. non-idiomatic naming (default: false)
+    pub style_lints: Option<bool>,
+    /// Report experimental diagnostics, which may be false positives
+    /// (default: true)
+    pub experimental: Option<bool>,
+    /// Id of the tool call, added by the server, see `CallId`
+    #[serde(default, rename = "_call_id")]
+    #[schemars(skip)]
+    pub call: CallId,
+}
+
+/// Parameters for opening an in-memory buffer
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct OpenBufferParams {
+    /// Absolute path to the Rust source file
+    pub file_path: String,
+    /// Initial buffer contents. If not provided, the current file contents on
+    /// disk are used.
+    pub content: Option<String>,
+    /// Id of the tool call, added by the server, see `CallId`
+    #[serde(default, rename = "_call_id")]
+    #[schemars(skip)]
+    pub call: CallId,
+}
+
+/// Parameters for updating an in-memory buffer
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct UpdateBufferParams {
+    /// Absolute path to the Rust source file
+    pub file_path: String,
+    /// New buffer contents, replacing the whole file
+    pub content: String,
+    /// Id of the tool call, added by the server, see `CallId`
+    #[serde(default, rename = "_call_id")]
+    #[schemars(skip)]
+    pub call: CallId,
+}
+
+/// Parameters for unloading a workspace
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct UnloadWorkspaceParams {
+    /// Workspace root, or any file or directory inside the workspace
+    pub path: String,
+}
+
+/// Parameters for loading a workspace in the background
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct LoadWorkspaceParams {
+    /// Workspace root, or any file or directory inside the workspace
+    pub path: String,
+}
+
+/// Parameters for the workspace_status tool
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct WorkspaceStatusParams {
+    /// Workspace root, or any file or directory inside the workspace. If not
+    /// provided, reports every workspace the server knows about.
+    pub path: Option<String>,
+}
+
+/// Parameters for reloading a workspace
+///
+/// Omitted configuration fields keep the value the workspace is currently
+/// loaded with.
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct ReloadWorkspaceParams {
+    /// Workspace root, or any file or directory inside the workspace
+    pub path: String,
+    /// Features to enable
+    pub features: Option<Vec<String>>,
+    /// Enable all features
+    pub all_features: Option<bool>,
+    /// Disable the default features
+    pub no_default_features: Option<bool>,
+    /// Target triple to analyze for, e.g. `x86_64-pc-windows-msvc`. Pass an
+    /// empty string to go back to the host target.
+    pub target: Option<String>,
+    /// Extra cfg options, as `name` or `name=value`. Prefix one with `!` to
+    /// disable it instead.
+    pub cfgs: Option<Vec<String>>,
+    /// Extra environment variables for cargo, build scripts and `env!`
+    pub env: Option<BTreeMap<String, String>>,
+    /// Id of the tool call, added by the server, see `CallId`
+    #[serde(default, rename = "_call_id")]
+    #[schemars(skip)]
+    pub call: CallId,
+}
+
+impl ReloadWorkspaceParams {
+    /// Apply the given fields on top of the current configuration
+    fn merge_into(self, mut options: CargoOptions) -> CargoOptions {
+        if let Some(features) = self.features {
+            options.features = features;
+        }
+        if let Some(all_features) = self.all_features {
+            options.all_features = all_features;
+        }
+        if let Some(no_default_features) = self.no_default_features {
+            options.no_default_features = no_default_features;
+        }
+        if let Some(target) = self.target {
+            options.target = Some(target).filter(|target| !target.is_empty());
+        }
+        if let Some(cfgs) = self.cfgs {
+            options.cfgs = cfgs;
+        }
+        if let Some(env) = self.env {
+            options.env = env;
+        }
+        options
+    }
+}
+
+/// Parameters for the undo/redo edit journal tools
+#[derive(Debug, Deserialize, schemars::JsonSchema)]
+pub struct EditJournalParams {
+    /// Workspace root, or any file or directory inside the workspace
+    pub path: String,
+}
+
+/// Configuration for the MCP server
+#[derive(Debug, Clone, Default)]
+pub struct ServerConfig {
+    /// Memory budget in MB for loaded workspaces. When exceeded, the least
+    /// recently used workspaces are unloaded.
+    pub memory_budget_mb: Option<u64>,
+    /// Directory to cache workspace metadata in, so restarts skip `cargo
+    /// metadata` and building build scripts for unchanged workspaces
+    pub cache_dir: Option<PathBuf>,
+    /// Manifest (Cargo.toml, rust-project.json or `.rs` file) to load
+    /// projects under its directory from, instead of discovering theirs
+    pub manifest_path: Option<PathBuf>,
+    /// Cargo configuration workspaces are loaded with, until changed with
+    /// the reload_workspace tool
+    pub cargo_options: CargoOptions,
+    /// Deadlines of the tools querying workspaces
+    pub tool_timeouts: ToolTimeouts,
+    /// How get_diagnostics runs cargo when asked for flycheck diagnostics
+    pub flycheck: FlycheckConfig,
 }
 
 /// Rust-Analyzer MCP server connection
 #[derive(Debug, Clone)]
 pub struct Rustbelt {
-    analyzer: Arc<Mutex<Option<RustAnalyzerish>>>,
+    /// Only locked to look up analyzers and start loads; tools run their
+    /// queries on the shared analyzer after releasing it, in parallel
+    workspaces: Arc<Mutex<WorkspaceRegistry>>,
+    /// Notified whenever a background workspace load finishes
+    loaded: Arc<Notify>,
+    progress: ProgressTracker,
+    calls: CallTracker,
+    tool_timeouts: ToolTimeouts,
+    flycheck: FlycheckConfig,
 }
 
 impl Rustbelt {
-    fn new() -> Self {
+    fn new(config: &ServerConfig, progress: ProgressTracker, calls: CallTracker) -> Self {
+        // File watching is enabled for the long-running MCP server
+        let registry = WorkspaceRegistry::new()
+            .with_file_watching(true)
+            .with_memory_budget_mb(config.memory_budget_mb)
+            .with_cache_dir(config.cache_dir.clone())
+            .with_manifest(config.manifest_path.clone())
+            .with_cargo_options(config.cargo_options.clone());
         Self {
-            analyzer: Arc::new(Mutex::new(None)),
+            workspaces: Arc::new(Mutex::new(registry)),
+            loaded: Arc::new(Notify::new()),
+            progress,
+            calls,
+            tool_timeouts: config.tool_timeouts.clone(),
+            flycheck: config.flycheck.clone(),
         }
     }
 
-    /// Initialize the analyzer if it hasn't been created yet
+    /// Get the analyzer for the workspace containing the given path,
+    /// waiting for the workspace to load if it isn't loaded yet
+    ///
+    /// Workspaces load in the background, so calls touching other workspaces
+    /// aren't blocked meanwhile. Loading progress is sent to the calling tool
+    /// call if it asked for it.
     async fn ensure_analyzer<P: AsRef<Path>>(
         &self,
+        ctx: &ServerCtx,
+        call: &CallId,
+        file_path: P,
+    ) -> std::result::Result<Arc<RustAnalyzerish>, tmcp::ToolError> {
+        let path = file_path.as_ref();
+        let mut wait = None;
+        let mut waited = false;
+        loop {
+            // Created before looking at the registry, so a load finishing
+            // in between still wakes us up
+            let loaded = self.loaded.notified();
+            let mut workspaces = self.workspaces.lock().await;
+            if let Some(analyzer) = workspaces.get(path) {
+                return Ok(analyzer);
+            }
+            // Only report failures of the load we waited for; otherwise retry
+            if waited && let Some(e) = workspaces.load_error(path) {
+                return Err(load_error_to_tool_error(path, e));
+            }
+            let load = workspaces
+                .start_load(path)
+                .map_err(|e| load_error_to_tool_error(path, &e))?;
+            // Registered before the load starts so no progress is missed
+            if wait.is_none() {
+                let root = match &load {
+                    Some(load) => Some(load.root().to_path_buf()),
+                    None => workspaces
+                        .status_of(path)
+                        .map(|status| PathBuf::from(status.root)),
+                };
+                wait = root.map(|root| self.progress.wait(call, ctx, &root));
+            }
+            if let Some(load) = load {
+                self.spawn_load(load);
+            }
+            drop(workspaces);
+            loaded.await;
+            waited = true;
+        }
+    }
+
+    /// Get the analyzer for the workspace containing the given path if it is
+    /// loaded, starting to load it in the background otherwise
+    async fn ready_analyzer<P: AsRef<Path>>(
+        &self,
+        file_path: P,
+    ) -> std::result::Result<Option<Arc<RustAnalyzerish>>, tmcp::ToolError> {
+        let path = file_path.as_ref();
+        let mut workspaces = self.workspaces.lock().await;
+        if let Some(analyzer) = workspaces.get(path) {
+            return Ok(Some(analyzer));
+        }
+        if let Some(load) = workspaces
+            .start_load(path)
+            .map_err(|e| load_error_to_tool_error(path, &e))?
+        {
+            self.spawn_load(load);
+        }
+        Ok(None)
+    }
+
+    /// Run a query of a tool call on the analyzer of the workspace containing
+    /// the given path, see `ensure_analyzer`
+    ///
+    /// The query is stopped when the client cancels the call or it runs past
+    /// the tool's deadline. Salsa cancels all the queries running on the
+    /// workspace at once, so the other ones are run again.
+    async fn query<T, P: AsRef<Path>>(
+        &self,
+        ctx: &ServerCtx,
+        call: &CallId,
+        tool: &str,
+        file_path: P,
+        query: impl AsyncFn(&RustAnalyzerish) -> anyhow::Result<T>,
+    ) -> std::result::Result<anyhow::Result<T>, tmcp::ToolError> {
+        self.query_until(ctx, call, tool, file_path, async |analyzer, _| {
+            query(analyzer).await
+        })
+        .await
+    }
+
+    /// Run a query like `query`, passing it the tool's deadline
+    ///
+    /// Queries that can stop early return what they found by the deadline,
+    /// which is then the tool's result instead of a timeout error.
+    async fn query_until<T, P: AsRef<Path>>(
+        &self,
+        ctx: &ServerCtx,
+        call: &CallId,
+        tool: &str,
         file_path: P,
-    ) -> std::result::Result<(), tmcp::ToolError> {
-        let mut analyzer_guard = self.analyzer.lock().await;
-        if analyzer_guard.is_none() {
-            // Create analyzer with file watching enabled for the long-running MCP server
-            let analyzer = RustAnalyzerishBuilder::from_file(file_path)
-                .expect("Failed to find root workspace from given file")
-                .with_file_watching(true)
-                .build()
-                .expect("Failed to create analyzer with current directory");
-
-            *analyzer_guard = Some(analyzer);
+        query: impl AsyncFn(&RustAnalyzerish, Option<Instant>) -> anyhow::Result<T>,
+    ) -> std::result::Result<anyhow::Result<T>, tmcp::ToolError> {
+        let path = file_path.as_ref();
+        let analyzer = self.ensure_analyzer(ctx, call, path).await?;
+        let timeout = self.tool_timeouts.get(tool);
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let running = self.calls.start(call, analyzer.clone(), timeout);
+        loop {
+            let cancellations = analyzer.cancellations();
+            let result = tokio::select! {
+                biased;
+                () = running.stopped() => Err(anyhow::anyhow!("{tool} was stopped")),
+                result = RustAnalyzerish::run_blocking(query(analyzer.as_ref(), deadline)) => result,
+            };
+            match running.stop_reason() {
+                Some(StopReason::TimedOut(_)) if result.is_ok() => return Ok(result),
+                Some(reason) => return Err(reason.to_tool_error(tool)),
+                None if result.is_ok() => return Ok(result),
+                None => {}
+            }
+            if analyzer.cancellations() == cancellations {
+                return Ok(result);
+            }
+            debug!("{tool} was cancelled along with another query, running it again");
+        }
+    }
+
+    /// Start loading the workspaces of the given roots in the background
+    ///
+    /// Roots that aren't inside a Rust project are skipped.
+    async fn load_roots(&self, roots: Vec<PathBuf>) {
+        let mut workspaces = self.workspaces.lock().await;
+        for root in roots {
+            match workspaces.start_load(&root) {
+                Ok(Some(load)) => {
+                    info!("Loading workspace of root {}", root.display());
+                    self.spawn_load(load);
+                }
+                Ok(None) => {}
+                Err(e) => debug!("Not loading root {}: {e:#}", root.display()),
+            }
+        }
+    }
+
+    /// Run a workspace load on a blocking thread, without holding the
+    /// registry lock, and wake up the tool calls waiting for it once done
+    ///
+    /// The progress of the load is reported to the tool calls waiting for
+    /// it, see `ProgressTracker::wait`.
+    fn spawn_load(&self, load: PendingLoad) -> JoinHandle<Option<WorkspaceInfo>> {
+        let progress = self.progress.callback(load.root());
+        let load = load.with_progress(progress);
+        let workspaces = self.workspaces.clone();
+        let loaded = self.loaded.clone();
+        tokio::task::spawn_blocking(move || {
+            let load = load.run();
+            let workspace = workspaces.blocking_lock().finish_load(load);
+            loaded.notify_waiters();
+            workspace
+        })
+    }
+}
+
+/// Tool result carrying both the text rendering and the structured payload
+/// described by the tool's output schema
+///
+/// A payload that fails to serialize is a bug; the call then fails instead
+/// of returning a result without the structured content its schema promises.
+fn output_result(text: impl Into<String>, output: impl Serialize) -> CallToolResult {
+    match serde_json::to_value(output) {
+        Ok(value) => CallToolResult::new()
+            .with_text_content(text)
+            .with_structured_content(value),
+        Err(e) => {
+            warn!("Failed to serialize tool output: {e}");
+            CallToolResult::new()
+                .with_text_content(format!("Failed to serialize the tool's output: {e}"))
+                .mark_as_error()
         }
-        Ok(())
     }
 }
 
+/// Text rendering of a diff preview: the raw patch, so it can be piped into
+/// `git apply --check`
+fn preview_text(preview: &DiffPreview) -> String {
+    if preview.is_empty() {
+        "No changes.".to_string()
+    } else {
+        preview.patch()
+    }
+}
+
+/// Convert a workspace load failure into a categorized tool error
+///
+/// The registry is left untouched on failure, so the next call can retry
+/// with a different path.
+fn load_error_to_tool_error(path: &Path, error: &anyhow::Error) -> tmcp::ToolError {
+    let cause = match error.downcast_ref::<WorkspaceLoadError>() {
+        Some(WorkspaceLoadError::NoManifest { .. }) => "NO_MANIFEST",
+        Some(WorkspaceLoadError::CargoMetadata { .. }) => "CARGO_METADATA_FAILED",
+        Some(WorkspaceLoadError::SysrootNotFound { .. }) => "SYSROOT_NOT_FOUND",
+        Some(WorkspaceLoadError::ProcMacroServerMissing { .. }) => "PROC_MACRO_SERVER_MISSING",
+        None => tmcp::TOOL_ERROR_INTERNAL,
+    };
+    let message = format!("Failed to load workspace for {}: {error:#}", path.display());
+    tmcp::ToolError::new(cause, message.clone()).with_structured(serde_json::json!({
+        "cause": cause,
+        "path": path.display().to_string(),
+        "message": message,
+    }))
+}
+
 #[mcp_server]
 impl Rustbelt {
     /// Generate a Rust code skeleton for a crate, showing its public API structure
@@ -216,7 +820,7 @@ impl Rustbelt {
     ///
     /// - Use deep module paths (e.g. `tokio::sync::mpsc`) to keep output small.
     /// - Pass `all_features=true` or `features=[…]` for feature-gated symbols.
-    #[tool]
+    #[tool(output_schema = SkeletonOutput)]
     async fn ruskel(&self, _ctx: &ServerCtx, params: RuskelParams) -> ToolResult {
         let ruskel = Ruskel::new();
 
@@ -227,7 +831,7 @@ impl Rustbelt {
             params.features.to_vec(),
             params.private,
         ) {
-            Ok(skeleton) => Ok(CallToolResult::new().with_text_content(skeleton)),
+            Ok(skeleton) => Ok(output_result(skeleton.clone(), SkeletonOutput { skeleton })),
             Err(e) => Ok(CallToolResult::new()
                 .with_text_content(format!("Error generating skeleton: {e}"))
                 .mark_as_error()),
@@ -251,29 +855,31 @@ impl Rustbelt {
     /// - Type is obvious: `let s = String::new()`, `let n: u32 = 5`.
     /// - You need the full API signature — use `ruskel` instead.
     /// - You need the definition location — use `get_definition` instead.
-    #[tool]
-    async fn get_type_hint(&self, _ctx: &ServerCtx, params: CursorParams) -> ToolResult {
+    #[tool(output_schema = TypeHintOutput)]
+    async fn get_type_hint(&self, ctx: &ServerCtx, params: CursorParams) -> ToolResult {
         let cursor = CursorCoordinates {
             file_path: params.file_path,
             line: params.line,
             column: params.column,
             symbol: params.symbol,
         };
-        self.ensure_analyzer(&cursor.file_path).await?;
         match self
-            .analyzer
-            .lock()
-            .await
-            .as_mut()
-            .unwrap()
-            .get_type_hint(&cursor)
-            .await
+            .query(
+                ctx,
+                &params.call,
+                "get_type_hint",
+                &cursor.file_path,
+                async |analyzer| analyzer.get_type_hint(&cursor).await,
+            )
+            .await?
         {
-            Ok(Some(type_info)) => {
-                Ok(CallToolResult::new().with_text_content(type_info.to_string()))
+            Ok(type_hint) => {
+                let text = match &type_hint {
+                    Some(type_info) => type_info.to_string(),
+                    None => "No type information available at this position".to_string(),
+                };
+                Ok(output_result(text, TypeHintOutput { type_hint }))
             }
-            Ok(None) => Ok(CallToolResult::new()
-                .with_text_content("No type information available at this position")),
             Err(e) => Ok(CallToolResult::new()
                 .with_text_content(format!("Error getting type hint: {e}"))
                 .mark_as_error()),
@@ -296,23 +902,23 @@ impl Rustbelt {
     /// - You need the full API surface — use `ruskel` instead.
     /// - You need the *type*, not the location — use `get_type_hint`.
     /// - You need all *usages* — use `find_references`.
-    #[tool]
-    async fn get_definition(&self, _ctx: &ServerCtx, params: CursorParams) -> ToolResult {
+    #[tool(output_schema = DefinitionsOutput)]
+    async fn get_definition(&self, ctx: &ServerCtx, params: CursorParams) -> ToolResult {
         let cursor = CursorCoordinates {
             file_path: params.file_path,
             line: params.line,
             column: params.column,
             symbol: params.symbol,
         };
-        self.ensure_analyzer(&cursor.file_path).await?;
         match self
-            .analyzer
-            .lock()
-            .await
-            .as_mut()
-            .unwrap()
-            .get_definition(&cursor)
-            .await
+            .query(
+                ctx,
+                &params.call,
+                "get_definition",
+                &cursor.file_path,
+                async |analyzer| analyzer.get_definition(&cursor).await,
+            )
+            .await?
         {
             Ok(Some(definitions)) => {
                 let result_text = definitions
@@ -321,11 +927,17 @@ impl Rustbelt {
                     .collect::<Vec<_>>()
                     .join("\n");
 
-                Ok(CallToolResult::new().with_text_content(result_text))
+                Ok(output_result(
+                    result_text,
+                    DefinitionsOutput { definitions },
+                ))
             }
-            Ok(None) => Ok(
-                CallToolResult::new().with_text_content("No definitions found at this position")
-            ),
+            Ok(None) => Ok(output_result(
+                "No definitions found at this position",
+                DefinitionsOutput {
+                    definitions: Vec::new(),
+                },
+            )),
             Err(e) => Ok(CallToolResult::new()
                 .with_text_content(format!("Error getting definitions: {e}"))
                 .mark_as_error()),
@@ -348,23 +960,23 @@ impl Rustbelt {
     /// - You already know the symbol name — just write the code.
     /// - You need the full API with signatures — use `ruskel` instead.
     /// - You need the type of a specific symbol — use `get_type_hint`.
-    #[tool]
-    async fn get_completions(&self, _ctx: &ServerCtx, params: CursorParams) -> ToolResult {
+    #[tool(output_schema = CompletionsOutput)]
+    async fn get_completions(&self, ctx: &ServerCtx, params: CursorParams) -> ToolResult {
         let cursor = CursorCoordinates {
             file_path: params.file_path,
             line: params.line,
             column: params.column,
             symbol: params.symbol,
         };
-        self.ensure_analyzer(&cursor.file_path).await?;
         match self
-            .analyzer
-            .lock()
-            .await
-            .as_mut()
-            .unwrap()
-            .get_completions(&cursor)
-            .await
+            .query(
+                ctx,
+                &params.call,
+                "get_completions",
+                &cursor.file_path,
+                async |analyzer| analyzer.get_completions(&cursor).await,
+            )
+            .await?
         {
             Ok(Some(completions)) => {
                 let result_text = completions
@@ -373,11 +985,17 @@ impl Rustbelt {
                     .collect::<Vec<_>>()
                     .join("\n");
 
-                Ok(CallToolResult::new().with_text_content(result_text))
+                Ok(output_result(
+                    result_text,
+                    CompletionsOutput { completions },
+                ))
             }
-            Ok(None) => Ok(
-                CallToolResult::new().with_text_content("No completions found at this position")
-            ),
+            Ok(None) => Ok(output_result(
+                "No completions found at this position",
+                CompletionsOutput {
+                    completions: Vec::new(),
+                },
+            )),
             Err(e) => Ok(CallToolResult::new()
                 .with_text_content(format!("Error getting completions: {e}"))
                 .mark_as_error()),
@@ -388,7 +1006,9 @@ impl Rustbelt {
     ///
     /// Performs workspace-wide symbol renaming that updates all references. Works with
     /// functions, types, variables, struct fields, enum variants, modules, and macros.
-    /// Writes changes to disk immediately.
+    /// Writes changes to disk immediately, unless `preview` is set, in which case
+    /// the change is returned as a unified diff (relative to the workspace root,
+    /// usable with `git apply`) and nothing is written.
     ///
     /// ## When to use
     ///
@@ -401,29 +1021,66 @@ impl Rustbelt {
     /// - Symbol is used in one place — just edit directly.
     /// - Renaming files/directories — use shell commands.
     /// - Only renames semantic references, not string literals or comments.
-    #[tool]
-    async fn rename_symbol(&self, _ctx: &ServerCtx, params: RenameParams) -> ToolResult {
+    #[tool(output_schema = RenameOutput)]
+    async fn rename_symbol(&self, ctx: &ServerCtx, params: RenameParams) -> ToolResult {
         let cursor = CursorCoordinates {
             file_path: params.file_path,
             line: params.line,
             column: params.column,
             symbol: params.symbol,
         };
-        self.ensure_analyzer(&cursor.file_path).await?;
+        if params.preview {
+            let preview = self
+                .query(
+                    ctx,
+                    &params.call,
+                    "rename_symbol",
+                    &cursor.file_path,
+                    async |analyzer| analyzer.preview_rename(&cursor, &params.new_name).await,
+                )
+                .await?;
+            return match preview {
+                Ok(preview) => {
+                    let text = match &preview {
+                        Some(preview) => preview_text(preview),
+                        None => "Symbol cannot be renamed at this position".to_string(),
+                    };
+                    Ok(output_result(
+                        text,
+                        RenameOutput {
+                            result: None,
+                            preview,
+                        },
+                    ))
+                }
+                Err(e) => Ok(CallToolResult::new()
+                    .with_text_content(format!("Error previewing rename: {e}"))
+                    .mark_as_error()),
+            };
+        }
         match self
-            .analyzer
-            .lock()
-            .await
-            .as_mut()
-            .unwrap()
-            .rename_symbol(&cursor, &params.new_name)
-            .await
+            .query(
+                ctx,
+                &params.call,
+                "rename_symbol",
+                &cursor.file_path,
+                async |analyzer| analyzer.rename_symbol(&cursor, &params.new_name).await,
+            )
+            .await?
         {
-            Ok(Some(rename_result)) => {
-                Ok(CallToolResult::new().with_text_content(rename_result.to_string()))
+            Ok(result) => {
+                let text = match &result {
+                    Some(rename_result) => rename_result.to_string(),
+                    None => "Symbol cannot be renamed at this position".to_string(),
+                };
+                Ok(output_result(
+                    text,
+                    RenameOutput {
+                        result,
+                        preview: None,
+                    },
+                ))
             }
-            Ok(None) => Ok(CallToolResult::new()
-                .with_text_content("Symbol cannot be renamed at this position")),
             Err(e) => Ok(CallToolResult::new()
                 .with_text_content(format!("Error performing rename: {e}"))
                 .mark_as_error()),
@@ -447,19 +1104,26 @@ impl Rustbelt {
     ///
     /// - Types are obvious from context: `Cli::parse()`, `format!(...)`, nearby enum patterns.
     /// - Simple `let` bindings where the RHS makes the type self-evident.
-    #[tool]
-    async fn view_inlay_hints(&self, _ctx: &ServerCtx, params: ViewInlayHintsParams) -> ToolResult {
-        self.ensure_analyzer(&params.file_path).await?;
+    #[tool(output_schema = InlayHintsOutput)]
+    async fn view_inlay_hints(&self, ctx: &ServerCtx, params: ViewInlayHintsParams) -> ToolResult {
         match self
-            .analyzer
-            .lock()
-            .await
-            .as_mut()
-            .unwrap()
-            .view_inlay_hints(&params.file_path, params.start_line, params.end_line)
-            .await
+            .query(
+                ctx,
+                &params.call,
+                "view_inlay_hints",
+                &params.file_path,
+                async |analyzer| {
+                    analyzer
+                        .view_inlay_hints(&params.file_path, params.start_line, params.end_line)
+                        .await
+                },
+            )
+            .await?
         {
-            Ok(annotated_content) => Ok(CallToolResult::new().with_text_content(annotated_content)),
+            Ok(annotated_source) => Ok(output_result(
+                annotated_source.clone(),
+                InlayHintsOutput { annotated_source },
+            )),
             Err(e) => Ok(CallToolResult::new()
                 .with_text_content(format!("Error viewing inlay hints: {e}"))
                 .mark_as_error()),
@@ -482,23 +1146,23 @@ impl Rustbelt {
     /// - You just need the definition location — use `get_definition`.
     /// - Searching for a string pattern, not a semantic symbol — use grep instead.
     /// - Symbol is obviously local (loop variable, short function) — just read the code.
-    #[tool]
-    async fn find_references(&self, _ctx: &ServerCtx, params: CursorParams) -> ToolResult {
+    #[tool(output_schema = ReferencesOutput)]
+    async fn find_references(&self, ctx: &ServerCtx, params: CursorParams) -> ToolResult {
         let cursor = CursorCoordinates {
             file_path: params.file_path,
             line: params.line,
             column: params.column,
             symbol: params.symbol,
         };
-        self.ensure_analyzer(&cursor.file_path).await?;
         match self
-            .analyzer
-            .lock()
-            .await
-            .as_mut()
-            .unwrap()
-            .find_references(&cursor)
-            .await
+            .query(
+                ctx,
+                &params.call,
+                "find_references",
+                &cursor.file_path,
+                async |analyzer| analyzer.find_references(&cursor).await,
+            )
+            .await?
         {
             Ok(Some(references)) => {
                 let result_text = references
@@ -507,11 +1171,14 @@ impl Rustbelt {
                     .collect::<Vec<_>>()
                     .join("\n");
 
-                Ok(CallToolResult::new().with_text_content(result_text))
-            }
-            Ok(None) => {
-                Ok(CallToolResult::new().with_text_content("No references found at this position"))
+                Ok(output_result(result_text, ReferencesOutput { references }))
             }
+            Ok(None) => Ok(output_result(
+                "No references found at this position",
+                ReferencesOutput {
+                    references: Vec::new(),
+                },
+            )),
             Err(e) => Ok(CallToolResult::new()
                 .with_text_content(format!("Error finding references: {e}"))
                 .mark_as_error()),
@@ -536,23 +1203,23 @@ impl Rustbelt {
     ///
     /// - You already know the assist ID — skip to `apply_assist`.
     /// - Simple text edits — just edit the file directly.
-    #[tool]
-    async fn get_assists(&self, _ctx: &ServerCtx, params: CursorParams) -> ToolResult {
+    #[tool(output_schema = AssistsOutput)]
+    async fn get_assists(&self, ctx: &ServerCtx, params: CursorParams) -> ToolResult {
         let cursor = CursorCoordinates {
             file_path: params.file_path,
             line: params.line,
             column: params.column,
             symbol: params.symbol,
         };
-        self.ensure_analyzer(&cursor.file_path).await?;
         match self
-            .analyzer
-            .lock()
-            .await
-            .as_mut()
-            .unwrap()
-            .get_assists(&cursor)
-            .await
+            .query(
+                ctx,
+                &params.call,
+                "get_assists",
+                &cursor.file_path,
+                async |analyzer| analyzer.get_assists(&cursor).await,
+            )
+            .await?
         {
             Ok(Some(assists)) => {
                 let result_text = assists
@@ -561,11 +1228,14 @@ impl Rustbelt {
                     .collect::<Vec<_>>()
                     .join("\n");
 
-                Ok(CallToolResult::new().with_text_content(result_text))
+                Ok(output_result(result_text, AssistsOutput { assists }))
             }
-            Ok(None) => Ok(
-                CallToolResult::new().with_text_content("No assists available at this position")
-            ),
+            Ok(None) => Ok(output_result(
+                "No assists available at this position",
+                AssistsOutput {
+                    assists: Vec::new(),
+                },
+            )),
             Err(e) => Ok(CallToolResult::new()
                 .with_text_content(format!("Error getting assists: {e}"))
                 .mark_as_error()),
@@ -575,7 +1245,9 @@ impl Rustbelt {
     /// Apply a specific code assist (code action) at a position in Rust code
     ///
     /// Applies a code transformation identified by an assist ID from `get_assists`.
-    /// Writes changes to disk immediately. Two-step workflow:
+    /// Writes changes to disk immediately, unless `preview` is set, in which case
+    /// the change is returned as a unified diff and nothing is written.
+    /// Two-step workflow:
     /// 1. `get_assists` at a position → discover available assist IDs.
     /// 2. `apply_assist` with the chosen ID → apply the change.
     ///
@@ -587,31 +1259,72 @@ impl Rustbelt {
     ///
     /// - Don't guess assist IDs — always call `get_assists` first.
     /// - Simple text edits — just edit the file directly.
-    #[tool]
-    async fn apply_assist(&self, _ctx: &ServerCtx, params: ApplyAssistParams) -> ToolResult {
+    #[tool(output_schema = ApplyAssistOutput)]
+    async fn apply_assist(&self, ctx: &ServerCtx, params: ApplyAssistParams) -> ToolResult {
         let cursor = CursorCoordinates {
             file_path: params.file_path,
             line: params.line,
             column: params.column,
             symbol: params.symbol,
         };
-        self.ensure_analyzer(&cursor.file_path).await?;
+        if params.preview {
+            let preview = self
+                .query(
+                    ctx,
+                    &params.call,
+                    "apply_assist",
+                    &cursor.file_path,
+                    async |analyzer| analyzer.preview_assist(&cursor, &params.assist_id).await,
+                )
+                .await?;
+            return match preview {
+                Ok(preview) => {
+                    let text = match &preview {
+                        Some(preview) => preview_text(preview),
+                        None => format!(
+                            "Assist '{}' not available at this position",
+                            params.assist_id
+                        ),
+                    };
+                    Ok(output_result(
+                        text,
+                        ApplyAssistOutput {
+                            source_change: None,
+                            preview,
+                        },
+                    ))
+                }
+                Err(e) => Ok(CallToolResult::new()
+                    .with_text_content(format!("Error previewing assist: {e}"))
+                    .mark_as_error()),
+            };
+        }
         match self
-            .analyzer
-            .lock()
-            .await
-            .as_mut()
-            .unwrap()
-            .apply_assist(&cursor, &params.assist_id)
-            .await
+            .query(
+                ctx,
+                &params.call,
+                "apply_assist",
+                &cursor.file_path,
+                async |analyzer| analyzer.apply_assist(&cursor, &params.assist_id).await,
+            )
+            .await?
         {
-            Ok(Some(source_change)) => {
-                Ok(CallToolResult::new().with_text_content(source_change.to_string()))
+            Ok(source_change) => {
+                let text = match &source_change {
+                    Some(source_change) => source_change.to_string(),
+                    None => format!(
+                        "Assist '{}' not available at this position",
+                        params.assist_id
+                    ),
+                };
+                Ok(output_result(
+                    text,
+                    ApplyAssistOutput {
+                        source_change,
+                        preview: None,
+                    },
+                ))
             }
-            Ok(None) => Ok(CallToolResult::new().with_text_content(format!(
-                "Assist '{}' not available at this position",
-                params.assist_id
-            ))),
             Err(e) => Ok(CallToolResult::new()
                 .with_text_content(format!("Error applying assist: {e}"))
                 .mark_as_error()),
@@ -624,6 +1337,12 @@ impl Rustbelt {
     /// AFTER making edits to verify correctness. Each diagnostic includes inline
     /// fix suggestions so you can fix issues without additional tool calls.
     ///
+    /// rust-analyzer misses borrow-checker errors, some trait errors and all
+    /// clippy lints. Pass `flycheck: true` to also run `cargo check` for the
+    /// file's package, or `flycheck_command: "clippy"` for lints; those
+    /// diagnostics are marked `[flycheck]` and their machine-applicable
+    /// suggestions come as fixes.
+    ///
     /// ## When to use
     ///
     /// - After editing Rust code to check for compile errors.
@@ -633,31 +1352,46 @@ impl Rustbelt {
     /// ## When NOT to use
     ///
     /// - For full `cargo build` diagnostics across the entire project — use `cargo check` via shell.
-    /// - This only analyzes a single file at a time.
-    #[tool]
-    async fn get_diagnostics(&self, _ctx: &ServerCtx, params: FileParams) -> ToolResult {
-        self.ensure_analyzer(&params.file_path).await?;
+    /// - Checking many files at once — use `get_workspace_diagnostics`.
+    #[tool(output_schema = DiagnosticsOutput)]
+    async fn get_diagnostics(&self, ctx: &ServerCtx, params: DiagnosticsParams) -> ToolResult {
+        let flycheck = match &params.flycheck_command {
+            Some(command) => Some(self.flycheck.with_command(command)),
+            None => params.flycheck.then(|| self.flycheck.clone()),
+        };
+        let options = params.options();
         match self
-            .analyzer
-            .lock()
-            .await
-            .as_mut()
-            .unwrap()
-            .get_diagnostics(&params.file_path)
-            .await
+            .query(
+                ctx,
+                &params.call,
+                "get_diagnostics",
+                &params.file_path,
+                async |analyzer| match &flycheck {
+                    Some(config) => {
+                        analyzer
+                            .get_diagnostics_with_flycheck(&params.file_path, config, &options)
+                            .await
+                    }
+                    None => {
+                        analyzer
+                            .get_diagnostics_with_options(&params.file_path, &options)
+                            .await
+                    }
+                },
+            )
+            .await?
         {
             Ok(diagnostics) => {
-                if diagnostics.is_empty() {
-                    Ok(CallToolResult::new()
-                        .with_text_content("No diagnostics — code looks clean."))
+                let text = if diagnostics.is_empty() {
+                    "No diagnostics — code looks clean.".to_string()
                 } else {
-                    let text = diagnostics
+                    diagnostics
                         .iter()
                         .map(|d| d.to_string())
                         .collect::<Vec<_>>()
-                        .join("\n\n");
-                    Ok(CallToolResult::new().with_text_content(text))
-                }
+                        .join("\n\n")
+                };
+                Ok(output_result(text, DiagnosticsOutput { diagnostics }))
             }
             Err(e) => Ok(CallToolResult::new()
                 .with_text_content(format!("Error getting diagnostics: {e}"))
@@ -665,6 +1399,304 @@ impl Rustbelt {
         }
     }
 
+    /// Check a whole workspace for errors and warnings in one call
+    ///
+    /// Runs diagnostics on every file of the workspace's own crates (not
+    /// dependencies) and returns the matching ones a page at a time, with
+    /// counts by severity from the page on. Use it to verify a refactor that
+    /// touched many files instead of calling `get_diagnostics` per file.
+    ///
+    /// ## When to use
+    ///
+    /// - After a multi-file change, with `severities: ["Error"]` to see
+    ///   whether everything still compiles.
+    /// - With `changed_since: "HEAD"` to only check the files you changed.
+    /// - To find every occurrence of a warning, filtered by `codes`.
+    ///
+    /// ## When NOT to use
+    ///
+    /// - For a single file — use `get_diagnostics`, which includes fixes.
+    /// - On very large workspaces without filters; narrow by `crates`,
+    ///   `path_glob` or `changed_since` first.
+    ///
+    /// Pass `next_cursor` back as `cursor` to get the next page, or to resume
+    /// a sweep that ran out of time.
+    #[tool(output_schema = WorkspaceDiagnostics)]
+    async fn get_workspace_diagnostics(
+        &self,
+        ctx: &ServerCtx,
+        params: WorkspaceDiagnosticsParams,
+    ) -> ToolResult {
+        let workspace_path = params.workspace_path.clone().unwrap_or_else(|| {
+            std::env::current_dir()
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|_| ".".to_string())
+        });
+        let call = params.call.clone();
+        let query = WorkspaceDiagnosticsQuery::from(params);
+        match self
+            .query_until(
+                ctx,
+                &call,
+                "get_workspace_diagnostics",
+                &workspace_path,
+                async |analyzer, deadline| {
+                    let query = WorkspaceDiagnosticsQuery {
+                        deadline,
+                        ..query.clone()
+                    };
+                    analyzer.get_workspace_diagnostics(&query).await
+                },
+            )
+            .await?
+        {
+            Ok(result) => {
+                let text = if result.total == 0 && result.unchecked_files == 0 {
+                    format!(
+                        "No diagnostics in {} checked file(s) — code looks clean.",
+                        result.checked_files
+                    )
+                } else {
+                    result.to_string()
+                };
+                Ok(output_result(text, result))
+            }
+            Err(e) => Ok(CallToolResult::new()
+                .with_text_content(format!("Error getting workspace diagnostics: {e}"))
+                .mark_as_error()),
+        }
+    }
+
+    /// Choose which diagnostics a workspace reports
+    ///
+    /// Disables diagnostic codes, or turns style lints and experimental
+    /// diagnostics on or off, for every later `get_diagnostics` and
+    /// `get_workspace_diagnostics` call on the workspace. The options apply
+    /// on top of the workspace's `rustbelt.toml` and replace the ones set by
+    /// earlier calls; call it without options to go back to the file's.
+    /// Returns the options now in effect.
+    ///
+    /// ## When to use
+    ///
+    /// - Diagnostics are cluttered by codes that don't matter for the task,
+    ///   like `inactive-code` or a noisy experimental check.
+    /// - Style lints (naming conventions) should be reported.
+    ///
+    /// ## When NOT to use
+    ///
+    /// - For a single call — pass `disabled_codes` and friends to the
+    ///   diagnostics tools instead.
+    #[tool(output_schema = DiagnosticsConfigOutput)]
+    async fn configure_diagnostics(
+        &self,
+        ctx: &ServerCtx,
+        params: ConfigureDiagnosticsParams,
+    ) -> ToolResult {
+        let options = DiagnosticsOptions {
+            disabled: params.disabled_codes,
+            enabled: params.enabled_codes,
+            style_lints: params.style_lints,
+            experimental: params.experimental,
+        };
+        match self
+            .query(
+                ctx,
+                &params.call,
+                "configure_diagnostics",
+                &params.path,
+                async |analyzer| {
+                    analyzer.set_diagnostics_options(options.clone());
+                    Ok((analyzer.diagnostics_options(), analyzer.config_error()))
+                },
+            )
+            .await?
+        {
+            Ok((effective, config_error)) => {
+                let mut text = effective.to_string();
+                if let Some(error) = &config_error {
+                    text.push_str(&format!("\n\nIgnoring rustbelt.toml: {error}"));
+                }
+                Ok(output_result(
+                    text,
+                    DiagnosticsConfigOutput {
+                        options,
+                        effective,
+                        config_error,
+                    },
+                ))
+            }
+            Err(e) => Ok(CallToolResult::new()
+                .with_text_content(format!("Error configuring diagnostics: {e}"))
+                .mark_as_error()),
+        }
+    }
+
+    /// Apply one quick-fix of a diagnostic reported by `get_diagnostics`
+    ///
+    /// Identifies the diagnostic by its code and a position inside its
+    /// range, computes it again on the current code and applies the chosen
+    /// fix, including any files it creates, moves or deletes. Writes changes
+    /// to disk immediately, unless `preview` is set, in which case the change
+    /// is returned as a unified diff and nothing is written.
+    ///
+    /// ## When to use
+    ///
+    /// - After `get_diagnostics` listed a fix you want, instead of copying its
+    ///   edits by hand.
+    /// - To pick one of several fixes, which `fix_all` leaves alone.
+    ///
+    /// ## When NOT to use
+    ///
+    /// - Many diagnostics with unambiguous fixes — use `fix_all`.
+    /// - Flycheck diagnostics — only rust-analyzer's own fixes are applied.
+    #[tool(output_schema = DiagnosticFixOutput)]
+    async fn apply_diagnostic_fix(
+        &self,
+        ctx: &ServerCtx,
+        params: ApplyDiagnosticFixParams,
+    ) -> ToolResult {
+        if params.preview {
+            let preview = self
+                .query(
+                    ctx,
+                    &params.call,
+                    "apply_diagnostic_fix",
+                    &params.file_path,
+                    async |analyzer| {
+                        analyzer
+                            .preview_diagnostic_fix(
+                                &params.file_path,
+                                params.line,
+                                params.column,
+                                &params.code,
+                                &params.fix,
+                            )
+                            .await
+                    },
+                )
+                .await?;
+            return match preview {
+                Ok(preview) => Ok(output_result(
+                    preview_text(&preview),
+                    DiagnosticFixOutput {
+                        fixed: None,
+                        preview: Some(preview),
+                    },
+                )),
+                Err(e) => Ok(CallToolResult::new()
+                    .with_text_content(format!("Error previewing fix: {e}"))
+                    .mark_as_error()),
+            };
+        }
+        match self
+            .query(
+                ctx,
+                &params.call,
+                "apply_diagnostic_fix",
+                &params.file_path,
+                async |analyzer| {
+                    analyzer
+                        .apply_diagnostic_fix(
+                            &params.file_path,
+                            params.line,
+                            params.column,
+                            &params.code,
+                            &params.fix,
+                        )
+                        .await
+                },
+            )
+            .await?
+        {
+            Ok(fixed) => Ok(output_result(
+                format!("Applied fix:\n{fixed}"),
+                DiagnosticFixOutput {
+                    fixed: Some(fixed),
+                    preview: None,
+                },
+            )),
+            Err(e) => Ok(CallToolResult::new()
+                .with_text_content(format!("Error applying fix: {e}"))
+                .mark_as_error()),
+        }
+    }
+
+    /// Apply every safe quick-fix in a file or a whole workspace at once
+    ///
+    /// Fixes the diagnostics with the given codes (or all of them) whose
+    /// quick-fix is unambiguous: the only fix of its diagnostic, editing text
+    /// only and not overlapping another fix. All fixes are written in one
+    /// transaction that `undo_last_edit` reverts, unless `preview` is set, in
+    /// which case they are returned as a unified diff and nothing is written.
+    /// Reports which diagnostics were fixed and which remain, with the reason.
+    ///
+    /// ## When to use
+    ///
+    /// - Clearing many mechanical warnings, e.g. `codes: ["unused_variables"]`
+    ///   or `["remove-unnecessary-else"]`, without applying each fix by hand.
+    /// - After a refactor, to fix everything rust-analyzer can fix on its own.
+    ///
+    /// ## When NOT to use
+    ///
+    /// - Diagnostics with several fixes — pick one from `get_diagnostics`.
+    /// - Remaining `conflict` diagnostics — call `fix_all` again, as their
+    ///   fixes are computed on the updated code.
+    #[tool(output_schema = FixAllOutput)]
+    async fn fix_all(&self, ctx: &ServerCtx, params: FixAllParams) -> ToolResult {
+        let path = params
+            .file_path
+            .clone()
+            .or(params.workspace_path)
+            .unwrap_or_else(|| {
+                std::env::current_dir()
+                    .map(|p| p.display().to_string())
+                    .unwrap_or_else(|_| ".".to_string())
+            });
+        let query = FixAllQuery {
+            file_path: params.file_path,
+            codes: params.codes,
+        };
+        if params.preview {
+            let preview = self
+                .query(ctx, &params.call, "fix_all", &path, async |analyzer| {
+                    analyzer.preview_fix_all(&query).await
+                })
+                .await?;
+            return match preview {
+                Ok((result, preview)) => {
+                    let text = format!("{result}\n{}", preview_text(&preview));
+                    Ok(output_result(
+                        text,
+                        FixAllOutput {
+                            result,
+                            preview: Some(preview),
+                        },
+                    ))
+                }
+                Err(e) => Ok(CallToolResult::new()
+                    .with_text_content(format!("Error previewing fixes: {e}"))
+                    .mark_as_error()),
+            };
+        }
+        match self
+            .query(ctx, &params.call, "fix_all", &path, async |analyzer| {
+                analyzer.fix_all(&query).await
+            })
+            .await?
+        {
+            Ok(result) => Ok(output_result(
+                result.to_string(),
+                FixAllOutput {
+                    result,
+                    preview: None,
+                },
+            )),
+            Err(e) => Ok(CallToolResult::new()
+                .with_text_content(format!("Error applying fixes: {e}"))
+                .mark_as_error()),
+        }
+    }
+
     /// Understand a symbol completely — type, definition, implementations, callers, reference count
     ///
     /// Returns everything about a symbol in one call: its type, where it's defined,
@@ -682,25 +1714,25 @@ impl Rustbelt {
     ///
     /// - You only need the type — use `get_type_hint` (lighter weight).
     /// - You need the full list of references — use `find_references`.
-    #[tool]
-    async fn analyze_symbol(&self, _ctx: &ServerCtx, params: CursorParams) -> ToolResult {
+    #[tool(output_schema = SymbolAnalysis)]
+    async fn analyze_symbol(&self, ctx: &ServerCtx, params: CursorParams) -> ToolResult {
         let cursor = CursorCoordinates {
             file_path: params.file_path,
             line: params.line,
             column: params.column,
             symbol: params.symbol,
         };
-        self.ensure_analyzer(&cursor.file_path).await?;
         match self
-            .analyzer
-            .lock()
-            .await
-            .as_mut()
-            .unwrap()
-            .analyze_symbol(&cursor)
-            .await
+            .query(
+                ctx,
+                &params.call,
+                "analyze_symbol",
+                &cursor.file_path,
+                async |analyzer| analyzer.analyze_symbol(&cursor).await,
+            )
+            .await?
         {
-            Ok(analysis) => Ok(CallToolResult::new().with_text_content(analysis.to_string())),
+            Ok(analysis) => Ok(output_result(analysis.to_string(), analysis)),
             Err(e) => Ok(CallToolResult::new()
                 .with_text_content(format!("Error analyzing symbol: {e}"))
                 .mark_as_error()),
@@ -723,32 +1755,28 @@ impl Rustbelt {
     ///
     /// - You need the full source code — use the Read tool.
     /// - You need the public API of an external crate — use `ruskel`.
-    #[tool]
+    ///
+    /// Answers right away while the workspace is still loading.
+    #[tool(output_schema = OutlineOutput)]
     async fn get_file_outline(&self, _ctx: &ServerCtx, params: FileParams) -> ToolResult {
-        self.ensure_analyzer(&params.file_path).await?;
-        match self
-            .analyzer
-            .lock()
-            .await
-            .as_mut()
-            .unwrap()
-            .get_file_outline(&params.file_path)
-            .await
-        {
+        let outline = match self.ready_analyzer(&params.file_path).await? {
+            Some(analyzer) => analyzer.get_file_outline(&params.file_path).await,
+            // The outline only needs the syntax of the file, so don't wait
+            // for the workspace to load
+            None => RustAnalyzerish::get_standalone_file_outline(&params.file_path).await,
+        };
+        match outline {
             Ok(items) => {
-                if items.is_empty() {
-                    Ok(
-                        CallToolResult::new()
-                            .with_text_content("No structure items found in file."),
-                    )
+                let text = if items.is_empty() {
+                    "No structure items found in file.".to_string()
                 } else {
-                    let text = items
+                    items
                         .iter()
                         .map(|item| item.to_string())
                         .collect::<Vec<_>>()
-                        .join("\n");
-                    Ok(CallToolResult::new().with_text_content(text))
-                }
+                        .join("\n")
+                };
+                Ok(output_result(text, OutlineOutput { items }))
             }
             Err(e) => Ok(CallToolResult::new()
                 .with_text_content(format!("Error getting file outline: {e}"))
@@ -772,34 +1800,35 @@ impl Rustbelt {
     ///
     /// - Searching for string literals or comments — use grep.
     /// - You know the exact file — use `get_file_outline` or read the file.
-    #[tool]
-    async fn search_symbols(&self, _ctx: &ServerCtx, params: SearchSymbolsParams) -> ToolResult {
-        // We need a file path to initialize the analyzer - use current dir
-        let cwd = std::env::current_dir()
-            .map(|p| p.display().to_string())
-            .unwrap_or_else(|_| ".".to_string());
-        self.ensure_analyzer(&cwd).await?;
+    #[tool(output_schema = SymbolSearchOutput)]
+    async fn search_symbols(&self, ctx: &ServerCtx, params: SearchSymbolsParams) -> ToolResult {
+        // We need a path to pick the workspace - default to current dir
+        let workspace_path = params.workspace_path.clone().unwrap_or_else(|| {
+            std::env::current_dir()
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|_| ".".to_string())
+        });
         match self
-            .analyzer
-            .lock()
-            .await
-            .as_mut()
-            .unwrap()
-            .search_symbols(&params.query, params.limit)
-            .await
+            .query(
+                ctx,
+                &params.call,
+                "search_symbols",
+                &workspace_path,
+                async |analyzer| analyzer.search_symbols(&params.query, params.limit).await,
+            )
+            .await?
         {
-            Ok(results) => {
-                if results.is_empty() {
-                    Ok(CallToolResult::new()
-                        .with_text_content(format!("No symbols found matching '{}'", params.query)))
+            Ok(symbols) => {
+                let text = if symbols.is_empty() {
+                    format!("No symbols found matching '{}'", params.query)
                 } else {
-                    let text = results
+                    symbols
                         .iter()
                         .map(|r| r.to_string())
                         .collect::<Vec<_>>()
-                        .join("\n");
-                    Ok(CallToolResult::new().with_text_content(text))
-                }
+                        .join("\n")
+                };
+                Ok(output_result(text, SymbolSearchOutput { symbols }))
             }
             Err(e) => Ok(CallToolResult::new()
                 .with_text_content(format!("Error searching symbols: {e}"))
@@ -823,29 +1852,31 @@ impl Rustbelt {
     ///
     /// - The macro is simple and well-known (e.g., `println!`, `vec!`).
     /// - You need to modify the macro itself — read the macro definition instead.
-    #[tool]
-    async fn expand_macro(&self, _ctx: &ServerCtx, params: CursorParams) -> ToolResult {
+    #[tool(output_schema = MacroExpansionOutput)]
+    async fn expand_macro(&self, ctx: &ServerCtx, params: CursorParams) -> ToolResult {
         let cursor = CursorCoordinates {
             file_path: params.file_path,
             line: params.line,
             column: params.column,
             symbol: params.symbol,
         };
-        self.ensure_analyzer(&cursor.file_path).await?;
         match self
-            .analyzer
-            .lock()
-            .await
-            .as_mut()
-            .unwrap()
-            .expand_macro(&cursor)
-            .await
+            .query(
+                ctx,
+                &params.call,
+                "expand_macro",
+                &cursor.file_path,
+                async |analyzer| analyzer.expand_macro(&cursor).await,
+            )
+            .await?
         {
-            Ok(Some(expansion)) => {
-                Ok(CallToolResult::new().with_text_content(expansion.to_string()))
+            Ok(expansion) => {
+                let text = match &expansion {
+                    Some(expansion) => expansion.to_string(),
+                    None => "No macro found at this position to expand.".to_string(),
+                };
+                Ok(output_result(text, MacroExpansionOutput { expansion }))
             }
-            Ok(None) => Ok(CallToolResult::new()
-                .with_text_content("No macro found at this position to expand.")),
             Err(e) => Ok(CallToolResult::new()
                 .with_text_content(format!("Error expanding macro: {e}"))
                 .mark_as_error()),
@@ -867,27 +1898,31 @@ impl Rustbelt {
     ///
     /// - You need the full function definition — use `get_definition` or `analyze_symbol`.
     /// - You need the full API of a type — use `ruskel`.
-    #[tool]
-    async fn get_signature_help(&self, _ctx: &ServerCtx, params: CursorParams) -> ToolResult {
+    #[tool(output_schema = SignatureHelpOutput)]
+    async fn get_signature_help(&self, ctx: &ServerCtx, params: CursorParams) -> ToolResult {
         let cursor = CursorCoordinates {
             file_path: params.file_path,
             line: params.line,
             column: params.column,
             symbol: params.symbol,
         };
-        self.ensure_analyzer(&cursor.file_path).await?;
         match self
-            .analyzer
-            .lock()
-            .await
-            .as_mut()
-            .unwrap()
-            .get_signature_help(&cursor)
-            .await
+            .query(
+                ctx,
+                &params.call,
+                "get_signature_help",
+                &cursor.file_path,
+                async |analyzer| analyzer.get_signature_help(&cursor).await,
+            )
+            .await?
         {
-            Ok(Some(sig_info)) => Ok(CallToolResult::new().with_text_content(sig_info.to_string())),
-            Ok(None) => Ok(CallToolResult::new()
-                .with_text_content("No signature help available at this position.")),
+            Ok(signature) => {
+                let text = match &signature {
+                    Some(sig_info) => sig_info.to_string(),
+                    None => "No signature help available at this position.".to_string(),
+                };
+                Ok(output_result(text, SignatureHelpOutput { signature }))
+            }
             Err(e) => Ok(CallToolResult::new()
                 .with_text_content(format!("Error getting signature help: {e}"))
                 .mark_as_error()),
@@ -898,6 +1933,8 @@ impl Rustbelt {
     ///
     /// Searches for code patterns using AST matching and optionally replaces them.
     /// Much more powerful than text-based find/replace because it understands Rust syntax.
+    /// Set `dry_run` to only list the matches, or `preview` to get the replacements
+    /// as a unified diff without writing to disk.
     ///
     /// ## Pattern Syntax
     ///
@@ -933,8 +1970,8 @@ impl Rustbelt {
     /// - Simple text find/replace — use your editor
     /// - Renaming a single symbol — use `rename_symbol`
     /// - The pattern is purely textual with no structure
-    #[tool]
-    async fn ssr(&self, _ctx: &ServerCtx, params: SsrParams) -> ToolResult {
+    #[tool(output_schema = SsrOutput)]
+    async fn ssr(&self, ctx: &ServerCtx, params: SsrParams) -> ToolResult {
         // Get context file for analyzer initialization
         let init_path = params.context_file.as_deref().unwrap_or_else(|| {
             std::env::current_dir()
@@ -942,22 +1979,48 @@ impl Rustbelt {
                 .unwrap_or_else(|_| ".".to_string())
                 .leak()
         });
-        self.ensure_analyzer(init_path).await?;
+
+        if params.preview {
+            let preview = self
+                .query(ctx, &params.call, "ssr", init_path, async |analyzer| {
+                    analyzer
+                        .preview_ssr(&params.pattern, params.context_file.as_deref())
+                        .await
+                })
+                .await?;
+            return match preview {
+                Ok(preview) => Ok(output_result(
+                    preview_text(&preview),
+                    SsrOutput {
+                        result: None,
+                        preview: Some(preview),
+                    },
+                )),
+                Err(e) => Ok(CallToolResult::new()
+                    .with_text_content(format!("SSR error: {e}"))
+                    .mark_as_error()),
+            };
+        }
 
         match self
-            .analyzer
-            .lock()
-            .await
-            .as_mut()
-            .unwrap()
-            .ssr(
-                &params.pattern,
-                params.context_file.as_deref(),
-                params.dry_run,
-            )
-            .await
+            .query(ctx, &params.call, "ssr", init_path, async |analyzer| {
+                analyzer
+                    .ssr(
+                        &params.pattern,
+                        params.context_file.as_deref(),
+                        params.dry_run,
+                    )
+                    .await
+            })
+            .await?
         {
-            Ok(result) => Ok(CallToolResult::new().with_text_content(result.to_string())),
+            Ok(result) => Ok(output_result(
+                result.to_string(),
+                SsrOutput {
+                    result: Some(result),
+                    preview: None,
+                },
+            )),
             Err(e) => Ok(CallToolResult::new()
                 .with_text_content(format!("SSR error: {e}"))
                 .mark_as_error()),
@@ -1001,33 +2064,34 @@ impl Rustbelt {
     /// - Finding a single symbol's usages — use `find_references` instead
     /// - Searching for text strings or comments — use grep
     /// - You already know you want to replace — use `ssr` directly with `dry_run: true`
-    #[tool]
-    async fn ssr_search(&self, _ctx: &ServerCtx, params: SsrSearchParams) -> ToolResult {
+    #[tool(output_schema = SsrSearchOutput)]
+    async fn ssr_search(&self, ctx: &ServerCtx, params: SsrSearchParams) -> ToolResult {
         let init_path = params.context_file.as_deref().unwrap_or_else(|| {
             std::env::current_dir()
                 .map(|p| p.display().to_string())
                 .unwrap_or_else(|_| ".".to_string())
                 .leak()
         });
-        self.ensure_analyzer(init_path).await?;
 
         match self
-            .analyzer
-            .lock()
-            .await
-            .as_mut()
-            .unwrap()
-            .ssr_search(&params.pattern, params.context_file.as_deref())
-            .await
+            .query(
+                ctx,
+                &params.call,
+                "ssr_search",
+                init_path,
+                async |analyzer| {
+                    analyzer
+                        .ssr_search(&params.pattern, params.context_file.as_deref())
+                        .await
+                },
+            )
+            .await?
         {
             Ok(matches) => {
-                if matches.is_empty() {
-                    Ok(CallToolResult::new().with_text_content(format!(
-                        "No matches found for pattern: {}",
-                        params.pattern
-                    )))
+                let text = if matches.is_empty() {
+                    format!("No matches found for pattern: {}", params.pattern)
                 } else {
-                    let text = format!(
+                    format!(
                         "## Found {} matches\n\n{}",
                         matches.len(),
                         matches
@@ -1035,24 +2099,522 @@ impl Rustbelt {
                             .map(|m| m.to_string())
                             .collect::<Vec<_>>()
                             .join("\n")
-                    );
-                    Ok(CallToolResult::new().with_text_content(text))
-                }
+                    )
+                };
+                Ok(output_result(text, SsrSearchOutput { matches }))
             }
             Err(e) => Ok(CallToolResult::new()
                 .with_text_content(format!("SSR search error: {e}"))
                 .mark_as_error()),
         }
     }
+
+    /// Check whether proposed edits introduce or fix diagnostics, without writing them
+    ///
+    /// Applies the edits in memory, runs diagnostics on every touched file and
+    /// returns the delta against the current state: diagnostics that would be
+    /// introduced and diagnostics that would be resolved. Nothing is written
+    /// to disk, but the edits are checked on the workspace's own analysis:
+    /// other calls on the workspace wait while it runs, like for a buffer
+    /// update.
+    ///
+    /// ## When to use
+    ///
+    /// - Before writing an edit, to make sure it does not break the build.
+    /// - Comparing alternative fixes for the same error.
+    ///
+    /// All files must belong to the same workspace.
+    #[tool(output_schema = EditCheckResult)]
+    async fn check_edits(&self, ctx: &ServerCtx, params: CheckEditsParams) -> ToolResult {
+        let Some(first) = params.file_changes.first() else {
+            return Err(tmcp::ToolError::invalid_input(
+                "file_changes must contain at least one file",
+            ));
+        };
+
+        match self
+            .query(
+                ctx,
+                &params.call,
+                "check_edits",
+                &first.file_path,
+                async |analyzer| analyzer.check_edits(&params.file_changes).await,
+            )
+            .await?
+        {
+            Ok(result) => Ok(output_result(result.to_string(), result)),
+            Err(e) => Ok(CallToolResult::new()
+                .with_text_content(format!("Error checking edits: {e}"))
+                .mark_as_error()),
+        }
+    }
+
+    /// Open an in-memory buffer that shadows a file on disk
+    ///
+    /// Until the buffer is closed, every tool (`get_diagnostics`,
+    /// `get_type_hint`, `get_completions`, ...) analyzes the buffer contents
+    /// instead of the file on disk. Nothing is written to disk.
+    ///
+    /// ## When to use
+    ///
+    /// - Checking whether a speculative edit compiles before writing it.
+    /// - Asking for types or completions in code you have not saved yet.
+    ///
+    /// Call `close_buffer` when done so the file is read from disk again.
+    #[tool(output_schema = BufferOutput)]
+    async fn open_buffer(&self, ctx: &ServerCtx, params: OpenBufferParams) -> ToolResult {
+        let content = match params.content {
+            Some(content) => content,
+            None => match tokio::fs::read_to_string(&params.file_path).await {
+                Ok(content) => content,
+                Err(e) => {
+                    return Ok(CallToolResult::new()
+                        .with_text_content(format!("Error reading file: {e}"))
+                        .mark_as_error());
+                }
+            },
+        };
+
+        match self
+            .ensure_analyzer(ctx, &params.call, &params.file_path)
+            .await?
+            .set_overlay(&params.file_path, content)
+            .await
+        {
+            Ok(()) => Ok(output_result(
+                format!("Opened buffer: {}", params.file_path),
+                BufferOutput {
+                    file_path: params.file_path,
+                    open: true,
+                },
+            )),
+            Err(e) => Ok(CallToolResult::new()
+                .with_text_content(format!("Error opening buffer: {e}"))
+                .mark_as_error()),
+        }
+    }
+
+    /// Replace the contents of an open in-memory buffer
+    ///
+    /// The buffer must have been opened with `open_buffer` first. Subsequent
+    /// tool calls analyze the new contents. Nothing is written to disk.
+    #[tool(output_schema = BufferOutput)]
+    async fn update_buffer(&self, ctx: &ServerCtx, params: UpdateBufferParams) -> ToolResult {
+        let analyzer = self
+            .ensure_analyzer(ctx, &params.call, &params.file_path)
+            .await?;
+        if !analyzer.has_overlay(&params.file_path) {
+            return Ok(CallToolResult::new()
+                .with_text_content(format!(
+                    "No open buffer for {}. Call open_buffer first.",
+                    params.file_path
+                ))
+                .mark_as_error());
+        }
+
+        match analyzer
+            .set_overlay(&params.file_path, params.content)
+            .await
+        {
+            Ok(()) => Ok(output_result(
+                format!("Updated buffer: {}", params.file_path),
+                BufferOutput {
+                    file_path: params.file_path,
+                    open: true,
+                },
+            )),
+            Err(e) => Ok(CallToolResult::new()
+                .with_text_content(format!("Error updating buffer: {e}"))
+                .mark_as_error()),
+        }
+    }
+
+    /// Close an in-memory buffer and go back to the file on disk
+    #[tool(output_schema = BufferOutput)]
+    async fn close_buffer(&self, ctx: &ServerCtx, params: FileParams) -> ToolResult {
+        match self
+            .ensure_analyzer(ctx, &params.call, &params.file_path)
+            .await?
+            .clear_overlay(&params.file_path)
+            .await
+        {
+            Ok(closed) => {
+                let text = if closed {
+                    format!("Closed buffer: {}", params.file_path)
+                } else {
+                    format!("No open buffer for {}", params.file_path)
+                };
+                Ok(output_result(
+                    text,
+                    BufferOutput {
+                        file_path: params.file_path,
+                        open: false,
+                    },
+                ))
+            }
+            Err(e) => Ok(CallToolResult::new()
+                .with_text_content(format!("Error closing buffer: {e}"))
+                .mark_as_error()),
+        }
+    }
+
+    /// List the workspaces currently loaded by the server
+    ///
+    /// Each Cargo workspace touched by a tool call is loaded lazily and kept in
+    /// memory. Returns every loaded workspace root with its approximate memory
+    /// usage and how long it has been idle.
+    ///
+    /// ## When to use
+    ///
+    /// - Checking which projects are loaded when working across several repos.
+    /// - Deciding which workspace to unload to free memory.
+    #[tool(output_schema = WorkspacesOutput)]
+    async fn list_workspaces(&self, _ctx: &ServerCtx) -> ToolResult {
+        let workspaces = self.workspaces.lock().await;
+        let loaded = workspaces.list();
+        let total_memory_mb = workspaces.total_memory_mb();
+        let text = if loaded.is_empty() {
+            "No workspaces loaded.".to_string()
+        } else {
+            format!(
+                "## {} workspace(s) loaded (~{}MB)\n\n{}",
+                loaded.len(),
+                total_memory_mb,
+                loaded
+                    .iter()
+                    .map(|w| w.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            )
+        };
+        Ok(output_result(
+            text,
+            WorkspacesOutput {
+                workspaces: loaded,
+                total_memory_mb,
+            },
+        ))
+    }
+
+    /// Unload a workspace to free its memory
+    ///
+    /// Drops the analyzer for the workspace containing the given path. The
+    /// workspace is loaded again on the next tool call that touches it.
+    ///
+    /// ## When to use
+    ///
+    /// - You are done with a project and want to free memory.
+    /// - Forcing a full reload after changing `Cargo.toml` or the toolchain.
+    #[tool(output_schema = UnloadOutput)]
+    async fn unload_workspace(
+        &self,
+        _ctx: &ServerCtx,
+        params: UnloadWorkspaceParams,
+    ) -> ToolResult {
+        let unloaded = self.workspaces.lock().await.unload(&params.path);
+        let text = match &unloaded {
+            Some(root) => format!("Unloaded workspace: {}", root.display()),
+            None => format!("No loaded workspace contains: {}", params.path),
+        };
+        Ok(output_result(
+            text,
+            UnloadOutput {
+                unloaded: unloaded.map(|root| root.display().to_string()),
+            },
+        ))
+    }
+
+    /// Reload a workspace, optionally switching its cargo configuration
+    ///
+    /// Loads the workspace containing the given path again, analyzing it with
+    /// different features, another target triple, extra cfg options or
+    /// environment variables. Omitted fields keep their current value. If the
+    /// new configuration fails to load, the workspace stays loaded as before.
+    ///
+    /// ## When to use
+    ///
+    /// - Code behind `#[cfg(feature = "...")]` shows as inactive or unresolved:
+    ///   reload with `features` or `all_features`.
+    /// - Working on platform specific code, e.g. `#[cfg(windows)]`: reload
+    ///   with `target: "x86_64-pc-windows-msvc"`.
+    /// - After adding dependencies or changing `Cargo.toml`.
+    #[tool(output_schema = ReloadOutput)]
+    async fn reload_workspace(&self, ctx: &ServerCtx, params: ReloadWorkspaceParams) -> ToolResult {
+        let path = PathBuf::from(&params.path);
+        let call = params.call.clone();
+        let load = {
+            let mut workspaces = self.workspaces.lock().await;
+            let cargo_options = params.merge_into(workspaces.cargo_options(&path).clone());
+            workspaces
+                .start_reload(&path, cargo_options)
+                .map_err(|e| load_error_to_tool_error(&path, &e))?
+        };
+        let Some(load) = load else {
+            return Err(tmcp::ToolError::new(
+                "WORKSPACE_LOADING",
+                format!(
+                    "The workspace containing {} is still loading, check workspace_status",
+                    path.display()
+                ),
+            ));
+        };
+
+        // The previous analyzer keeps answering other calls meanwhile
+        let root = load.root().to_path_buf();
+        let _wait = self.progress.wait(&call, ctx, &root);
+        let workspace = self.spawn_load(load).await.map_err(|e| {
+            tmcp::ToolError::internal(format!("Failed to reload {}: {e}", root.display()))
+        })?;
+        match workspace {
+            Some(workspace) => Ok(output_result(
+                format!("Reloaded workspace: {workspace}"),
+                ReloadOutput { workspace },
+            )),
+            None => {
+                let workspaces = self.workspaces.lock().await;
+                Err(match workspaces.load_error(&root) {
+                    Some(e) => load_error_to_tool_error(&path, e),
+                    None => tmcp::ToolError::internal(format!(
+                        "Failed to reload workspace for {}",
+                        path.display()
+                    )),
+                })
+            }
+        }
+    }
+
+    /// Start loading a workspace in the background
+    ///
+    /// Returns right away with the status of the workspace. Semantic tools
+    /// called on the workspace wait for the load to finish, while
+    /// `get_file_outline` answers immediately. Workspaces also load on the
+    /// first tool call touching them, and from the roots the client
+    /// advertises.
+    ///
+    /// ## When to use
+    ///
+    /// - At the start of a session, so the workspace is ready by the time
+    ///   semantic queries are needed.
+    /// - Before switching to another project.
+    #[tool(output_schema = LoadWorkspaceOutput)]
+    async fn load_workspace(&self, _ctx: &ServerCtx, params: LoadWorkspaceParams) -> ToolResult {
+        let path = Path::new(&params.path);
+        let mut workspaces = self.workspaces.lock().await;
+        if let Some(load) = workspaces
+            .start_load(path)
+            .map_err(|e| load_error_to_tool_error(path, &e))?
+        {
+            self.spawn_load(load);
+        }
+        let Some(workspace) = workspaces.status_of(path) else {
+            return Err(tmcp::ToolError::internal(format!(
+                "No workspace found for {}",
+                path.display()
+            )));
+        };
+        Ok(output_result(
+            workspace.to_string(),
+            LoadWorkspaceOutput { workspace },
+        ))
+    }
+
+    /// Report whether workspaces are loaded and ready for semantic queries
+    ///
+    /// Returns the state of each workspace (loading, ready or failed), the
+    /// current loading phase, crates indexed so far, approximate memory usage
+    /// and whether semantic tools (types, references, diagnostics...) answer
+    /// without waiting.
+    ///
+    /// ## When to use
+    ///
+    /// - After `load_workspace`, to know when the workspace is ready.
+    /// - A semantic tool call is taking long: check whether it waits for a
+    ///   workspace to load.
+    #[tool(output_schema = WorkspaceStatusOutput)]
+    async fn workspace_status(
+        &self,
+        _ctx: &ServerCtx,
+        params: WorkspaceStatusParams,
+    ) -> ToolResult {
+        let workspaces = self.workspaces.lock().await;
+        let statuses = match &params.path {
+            Some(path) => workspaces.status_of(path).into_iter().collect(),
+            None => workspaces.status(),
+        };
+        let text = if statuses.is_empty() {
+            match &params.path {
+                Some(path) => format!("No workspace loaded or loading for {path}"),
+                None => "No workspaces loaded or loading.".to_string(),
+            }
+        } else {
+            statuses
+                .iter()
+                .map(|status| status.to_string())
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        Ok(output_result(
+            text,
+            WorkspaceStatusOutput {
+                workspaces: statuses,
+            },
+        ))
+    }
+
+    /// Undo the last edit rustbelt wrote to disk
+    ///
+    /// Renames, applied assists and SSR replacements are recorded in a
+    /// per-workspace journal. This restores every file touched by the most
+    /// recent edit (including created, moved or deleted files). The undo is
+    /// refused if any of those files changed since the edit was made.
+    ///
+    /// ## When to use
+    ///
+    /// - A rename, assist or SSR produced an unwanted result.
+    /// - Reverting an experiment without relying on version control.
+    #[tool(output_schema = EditOutput)]
+    async fn undo_last_edit(&self, _ctx: &ServerCtx, params: EditJournalParams) -> ToolResult {
+        let result = match EditJournal::for_file(&params.path) {
+            Ok(journal) => journal.undo().await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(edit) => {
+                let text = match &edit {
+                    Some(record) => format!("Undid edit {record}"),
+                    None => "Nothing to undo.".to_string(),
+                };
+                Ok(output_result(text, EditOutput { edit }))
+            }
+            Err(e) => Ok(CallToolResult::new()
+                .with_text_content(format!("Error undoing edit: {e}"))
+                .mark_as_error()),
+        }
+    }
+
+    /// Redo the last edit undone with `undo_last_edit`
+    ///
+    /// Re-applies the most recently undone edit. The redo is refused if any of
+    /// the touched files changed since the undo. Recording a new edit clears
+    /// the redo history.
+    ///
+    /// ## When to use
+    ///
+    /// - An edit was undone by mistake.
+    #[tool(output_schema = EditOutput)]
+    async fn redo_last_edit(&self, _ctx: &ServerCtx, params: EditJournalParams) -> ToolResult {
+        let result = match EditJournal::for_file(&params.path) {
+            Ok(journal) => journal.redo().await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(edit) => {
+                let text = match &edit {
+                    Some(record) => format!("Redid edit {record}"),
+                    None => "Nothing to redo.".to_string(),
+                };
+                Ok(output_result(text, EditOutput { edit }))
+            }
+            Err(e) => Ok(CallToolResult::new()
+                .with_text_content(format!("Error redoing edit: {e}"))
+                .mark_as_error()),
+        }
+    }
+
+    /// List the edits recorded in the workspace's undo/redo journal
+    ///
+    /// Returns the journaled edits, most recent first, with the files each one
+    /// touched. Undone edits that can still be redone are marked as such.
+    ///
+    /// ## When to use
+    ///
+    /// - Reviewing what rustbelt changed on disk before undoing.
+    #[tool(output_schema = EditHistoryOutput)]
+    async fn list_edit_history(&self, _ctx: &ServerCtx, params: EditJournalParams) -> ToolResult {
+        let result = match EditJournal::for_file(&params.path) {
+            Ok(journal) => journal.history().await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(edits) => {
+                let text = if edits.is_empty() {
+                    "No edits recorded.".to_string()
+                } else {
+                    format!(
+                        "## {} edit(s) recorded\n\n{}",
+                        edits.len(),
+                        edits
+                            .iter()
+                            .map(|r| r.to_string())
+                            .collect::<Vec<_>>()
+                            .join("")
+                    )
+                };
+                Ok(output_result(text, EditHistoryOutput { edits }))
+            }
+            Err(e) => Ok(CallToolResult::new()
+                .with_text_content(format!("Error reading edit history: {e}"))
+                .mark_as_error()),
+        }
+    }
 }
 
-pub async fn serve_stdio() -> Result<()> {
-    tmcp::Server::new(Rustbelt::new).serve_stdio().await
+pub async fn serve_stdio(config: ServerConfig) -> Result<()> {
+    let progress = ProgressTracker::default();
+    let calls = CallTracker::default();
+    let server = Rustbelt::new(&config, progress.clone(), calls.clone());
+    let (reader, writer) =
+        intercept(&server, progress, calls).wrap(tokio::io::stdin(), tokio::io::stdout());
+    tmcp::Server::new(move || server.clone())
+        .serve_stream(reader, writer)
+        .await
 }
 
-pub async fn serve_tcp(addr: String) -> Result<()> {
-    info!("Starting Rustbelt MCP server on {}", addr);
+pub async fn serve_tcp(addr: String, config: ServerConfig) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    info!("Starting Rustbelt MCP server on {}", listener.local_addr()?);
+
+    loop {
+        let (stream, peer_addr) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("Failed to accept connection: {e}");
+                continue;
+            }
+        };
+        info!("New connection from {peer_addr}");
+
+        let config = config.clone();
+        tokio::spawn(async move {
+            let progress = ProgressTracker::default();
+            let calls = CallTracker::default();
+            let server = Rustbelt::new(&config, progress.clone(), calls.clone());
+            let (reader, writer) = stream.into_split();
+            let (reader, writer) = intercept(&server, progress, calls).wrap(reader, writer);
+            let served = tmcp::Server::new(move || server.clone())
+                .serve_stream(reader, writer)
+                .await;
+            match served {
+                Ok(()) => info!("Connection from {peer_addr} closed"),
+                Err(e) => warn!("Error handling connection from {peer_addr}: {e}"),
+            }
+        });
+    }
+}
 
-    tmcp::Server::new(Rustbelt::new).serve_tcp(addr).await?;
-    Ok(())
+/// Handlers of the messages of a connection to a server
+///
+/// Roots are handled first, so the other handlers don't see the responses
+/// to the `roots/list` requests. Progress tokens are forgotten before the
+/// responses to cancelled calls are dropped.
+fn intercept(server: &Rustbelt, progress: ProgressTracker, calls: CallTracker) -> Interceptor {
+    let server = server.clone();
+    let roots = RootsWatcher::new(move |roots| {
+        let server = server.clone();
+        tokio::spawn(async move { server.load_roots(roots).await });
+    });
+    Interceptor::default()
+        .with(roots)
+        .with(progress)
+        .with(calls)
 }
@@ -0,0 +1,228 @@
+//! Structured tool outputs
+//!
+//! Every tool returns its text rendering for LLMs alongside a JSON
+//! `structuredContent` payload. These are the payload types; each tool
+//! publishes the schema of its payload as the tool's `outputSchema`.
+//!
+//! MCP requires structured content to be a JSON object, so lists and
+//! optional results are wrapped in a named field.
+
+use librustbelt::entities::{
+    AssistInfo, AssistSourceChange, CompletionItem, DefinitionInfo, DiagnosticInfo,
+    DiagnosticsOptions, DiffPreview, EditRecord, FileOutlineItem, FixAllResult, MacroExpansion,
+    ReferenceInfo, RenameResult, SignatureInfo, SsrMatch, SsrResult, SymbolSearchResult, TypeHint,
+    WorkspaceInfo, WorkspaceStatus,
+};
+use serde::{Deserialize, Serialize};
+
+/// Output of the ruskel tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct SkeletonOutput {
+    /// Rust source listing the requested API with all bodies stripped
+    pub skeleton: String,
+}
+
+/// Output of the get_type_hint tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct TypeHintOutput {
+    /// Type of the symbol, or null if none is available at the position
+    pub type_hint: Option<TypeHint>,
+}
+
+/// Output of the get_definition tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct DefinitionsOutput {
+    /// Definitions of the symbol at the position
+    pub definitions: Vec<DefinitionInfo>,
+}
+
+/// Output of the get_completions tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct CompletionsOutput {
+    /// Completions available at the position
+    pub completions: Vec<CompletionItem>,
+}
+
+/// Output of the rename_symbol tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct RenameOutput {
+    /// Changes written to disk, or null in preview mode or if the symbol
+    /// cannot be renamed
+    pub result: Option<RenameResult>,
+    /// Diff of the rename, only set in preview mode
+    pub preview: Option<DiffPreview>,
+}
+
+/// Output of the view_inlay_hints tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct InlayHintsOutput {
+    /// Source code with the inlay hints embedded
+    pub annotated_source: String,
+}
+
+/// Output of the find_references tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ReferencesOutput {
+    /// References to the symbol at the position, including its definition
+    pub references: Vec<ReferenceInfo>,
+}
+
+/// Output of the get_assists tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct AssistsOutput {
+    /// Assists available at the position
+    pub assists: Vec<AssistInfo>,
+}
+
+/// Output of the apply_assist tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ApplyAssistOutput {
+    /// Changes written to disk, or null in preview mode or if the assist is
+    /// not available
+    pub source_change: Option<AssistSourceChange>,
+    /// Diff of the assist, only set in preview mode
+    pub preview: Option<DiffPreview>,
+}
+
+/// Output of the get_diagnostics tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct DiagnosticsOutput {
+    /// Diagnostics reported for the file
+    pub diagnostics: Vec<DiagnosticInfo>,
+}
+
+/// Output of the configure_diagnostics tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct DiagnosticsConfigOutput {
+    /// Options set on the workspace by this call
+    pub options: DiagnosticsOptions,
+    /// Options in effect: the workspace's `rustbelt.toml` with `options` on
+    /// top
+    pub effective: DiagnosticsOptions,
+    /// Why the workspace's `rustbelt.toml` is ignored, if it is invalid
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_error: Option<String>,
+}
+
+/// Output of the apply_diagnostic_fix tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct DiagnosticFixOutput {
+    /// The diagnostic with the fix written to disk, or null in preview mode
+    pub fixed: Option<DiagnosticInfo>,
+    /// Diff of the fix, only set in preview mode
+    pub preview: Option<DiffPreview>,
+}
+
+/// Output of the fix_all tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct FixAllOutput {
+    /// Fixed and remaining diagnostics, and the changes of the fixes
+    pub result: FixAllResult,
+    /// Diff of the fixes, only set in preview mode
+    pub preview: Option<DiffPreview>,
+}
+
+/// Output of the get_file_outline tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct OutlineOutput {
+    /// Items declared in the file, in source order
+    pub items: Vec<FileOutlineItem>,
+}
+
+/// Output of the search_symbols tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct SymbolSearchOutput {
+    /// Symbols matching the query
+    pub symbols: Vec<SymbolSearchResult>,
+}
+
+/// Output of the expand_macro tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct MacroExpansionOutput {
+    /// Expansion of the macro, or null if there is no macro at the position
+    pub expansion: Option<MacroExpansion>,
+}
+
+/// Output of the get_signature_help tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct SignatureHelpOutput {
+    /// Signature of the call, or null if there is no call at the position
+    pub signature: Option<SignatureInfo>,
+}
+
+/// Output of the ssr tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct SsrOutput {
+    /// Matches and changes of the replacement, null in preview mode
+    pub result: Option<SsrResult>,
+    /// Diff of the replacement, only set in preview mode
+    pub preview: Option<DiffPreview>,
+}
+
+/// Output of the ssr_search tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct SsrSearchOutput {
+    /// Code matching the pattern
+    pub matches: Vec<SsrMatch>,
+}
+
+/// Output of the open_buffer, update_buffer and close_buffer tools
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct BufferOutput {
+    /// Path of the file shadowed by the buffer
+    pub file_path: String,
+    /// Whether an in-memory buffer is open for the file after the call
+    pub open: bool,
+}
+
+/// Output of the list_workspaces tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct WorkspacesOutput {
+    /// Loaded workspaces
+    pub workspaces: Vec<WorkspaceInfo>,
+    /// Approximate memory used by all loaded workspaces, in MB
+    pub total_memory_mb: u64,
+}
+
+/// Output of the unload_workspace tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct UnloadOutput {
+    /// Root of the unloaded workspace, or null if none contained the path
+    pub unloaded: Option<String>,
+}
+
+/// Output of the reload_workspace tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct ReloadOutput {
+    /// The reloaded workspace and the configuration it now uses
+    pub workspace: WorkspaceInfo,
+}
+
+/// Output of the load_workspace tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct LoadWorkspaceOutput {
+    /// Status of the workspace, loading in the background unless it was
+    /// already loaded
+    pub workspace: WorkspaceStatus,
+}
+
+/// Output of the workspace_status tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct WorkspaceStatusOutput {
+    /// Workspaces that are loaded, loading or failed to load
+    pub workspaces: Vec<WorkspaceStatus>,
+}
+
+/// Output of the undo_last_edit and redo_last_edit tools
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct EditOutput {
+    /// Edit that was undone or redone, or null if there was nothing to do
+    pub edit: Option<EditRecord>,
+}
+
+/// Output of the list_edit_history tool
+#[derive(Debug, Clone, Serialize, Deserialize, schemars::JsonSchema)]
+pub struct EditHistoryOutput {
+    /// Journaled edits, most recent first
+    pub edits: Vec<EditRecord>,
+}
@@ -0,0 +1,188 @@
+//! Progress notifications for tool calls
+//!
+//! The first tool call in a workspace blocks while the workspace loads,
+//! which takes tens of seconds for large ones. Clients that pass a
+//! `progressToken` in the `_meta` of a `tools/call` request get
+//! `notifications/progress` for the load in the meantime.
+//!
+//! tmcp doesn't hand the `_meta` of a request to tools, so a
+//! [`MessageHandler`] picks the tokens up on the way in and forgets them
+//! once the response goes out. Tools waiting for a workspace load register
+//! with the tracker, which reports the progress of each load to the calls
+//! waiting for it, through their own context.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use librustbelt::{ProgressCallback, entities::LoadProgress};
+use serde_json::Value;
+use tmcp::ServerCtx;
+use tmcp::schema::{ProgressToken, ServerNotification};
+use tracing::debug;
+
+use crate::cancellation::CallId;
+use crate::interceptor::{ClientSender, MessageHandler, Verdict};
+
+/// Minimum time between two indexing updates; indexing reports every crate,
+/// which would flood clients and tmcp's notification queue
+const INDEXING_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Progress tokens of the tool calls in flight on a connection
+#[derive(Debug, Clone, Default)]
+pub struct ProgressTracker {
+    inner: Arc<Mutex<TrackerState>>,
+}
+
+#[derive(Debug, Default)]
+struct TrackerState {
+    /// Tool calls that asked for progress, by JSON-RPC request id
+    requests: HashMap<String, TrackedCall>,
+    /// Last indexing update sent, by workspace root
+    last_sent: HashMap<PathBuf, Instant>,
+}
+
+struct TrackedCall {
+    token: ProgressToken,
+    /// Last reported progress
+    last: f64,
+    /// Workspace load the call waits for, and the context to report its
+    /// progress with
+    waiting: Option<(PathBuf, ServerCtx)>,
+}
+
+impl std::fmt::Debug for TrackedCall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TrackedCall")
+            .field("last", &self.last)
+            .field("waiting", &self.waiting.as_ref().map(|(root, _)| root))
+            .finish_non_exhaustive()
+    }
+}
+
+/// A tool call waiting for a workspace load, see `ProgressTracker::wait`
+///
+/// The call stops getting the progress of the load once dropped.
+#[derive(Debug)]
+pub struct LoadWait {
+    tracker: ProgressTracker,
+    call: CallId,
+}
+
+impl Drop for LoadWait {
+    fn drop(&mut self) {
+        let mut state = self.tracker.inner.lock().unwrap();
+        if let Some(id) = self.call.as_str()
+            && let Some(call) = state.requests.get_mut(id)
+        {
+            call.waiting = None;
+        }
+    }
+}
+
+impl ProgressTracker {
+    /// Report the progress of loading the workspace at `root` to a tool call,
+    /// if it asked for progress, until the returned guard is dropped
+    pub fn wait(&self, call: &CallId, ctx: &ServerCtx, root: &Path) -> LoadWait {
+        if let Some(id) = call.as_str() {
+            let mut state = self.inner.lock().unwrap();
+            if let Some(call) = state.requests.get_mut(id) {
+                call.waiting = Some((root.to_path_buf(), ctx.clone()));
+            }
+        }
+        LoadWait {
+            tracker: self.clone(),
+            call: call.clone(),
+        }
+    }
+
+    /// Callback reporting the progress of loading the workspace at `root` to
+    /// the tool calls waiting for it
+    pub fn callback(&self, root: &Path) -> ProgressCallback {
+        let tracker = self.clone();
+        let root = root.to_path_buf();
+        ProgressCallback::new(move |progress| tracker.report(&root, &progress))
+    }
+
+    fn report(&self, root: &Path, progress: &LoadProgress) {
+        let mut state = self.inner.lock().unwrap();
+        let now = Instant::now();
+        let throttled = matches!(progress, LoadProgress::Indexing { .. })
+            && state
+                .last_sent
+                .get(root)
+                .is_some_and(|last| now.duration_since(*last) < INDEXING_INTERVAL);
+        if throttled {
+            return;
+        }
+        if matches!(progress, LoadProgress::Ready) {
+            state.last_sent.remove(root);
+        } else {
+            state.last_sent.insert(root.to_path_buf(), now);
+        }
+        let message = progress.to_string();
+        for call in state.requests.values_mut() {
+            let Some((_, ctx)) = call.waiting.as_ref().filter(|(waited, _)| waited == root) else {
+                continue;
+            };
+            // Progress must increase with every notification
+            let value = progress.percent().max(call.last + 0.01);
+            call.last = value;
+            let notification = ServerNotification::progress(
+                call.token.clone(),
+                value,
+                Some(100.0),
+                Some(message.clone()),
+            );
+            if let Err(e) = ctx.notify(notification) {
+                debug!("Failed to send progress notification: {e}");
+            }
+        }
+    }
+
+    /// Record the progress token of an incoming tool call
+    fn track(&self, message: &Value) {
+        if message["method"] != "tools/call" {
+            return;
+        }
+        let (Some(id), Some(token)) = (
+            message.get("id"),
+            message["params"]["_meta"].get("progressToken"),
+        ) else {
+            return;
+        };
+        if let Ok(token) = serde_json::from_value::<ProgressToken>(token.clone()) {
+            let mut state = self.inner.lock().unwrap();
+            state.requests.insert(
+                id.to_string(),
+                TrackedCall {
+                    token,
+                    last: 0.0,
+                    waiting: None,
+                },
+            );
+        }
+    }
+
+    /// Forget the progress token of a tool call once it has been answered
+    fn untrack(&self, message: &Value) {
+        let is_response = message.get("result").is_some() || message.get("error").is_some();
+        if let Some(id) = message.get("id").filter(|_| is_response) {
+            let mut state = self.inner.lock().unwrap();
+            state.requests.remove(&id.to_string());
+        }
+    }
+}
+
+impl MessageHandler for ProgressTracker {
+    fn incoming(&self, message: &mut Value, _client: &ClientSender) -> Verdict {
+        self.track(message);
+        Verdict::Pass
+    }
+
+    fn outgoing(&self, message: &mut Value) -> Verdict {
+        self.untrack(message);
+        Verdict::Pass
+    }
+}
@@ -0,0 +1,110 @@
+//! Workspace roots advertised by the client
+//!
+//! Clients supporting MCP roots tell the server which directories they work
+//! in, which is the earliest the server learns about the workspaces it will
+//! be asked about. Loading them right away means the first semantic tool
+//! call doesn't have to wait for the whole load.
+//!
+//! tmcp doesn't expose roots to tools, so like progress tokens, they are
+//! picked up by a [`MessageHandler`]: it asks the client for its roots with
+//! `roots/list` once the connection is initialized and whenever the client
+//! reports they changed, and keeps the answers to itself.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use serde_json::{Value, json};
+use tracing::debug;
+use url::Url;
+
+use crate::interceptor::{ClientSender, MessageHandler, Verdict};
+
+/// Prefix of the ids of the `roots/list` requests sent by the watcher, to
+/// tell their responses apart from the ones meant for tmcp
+const REQUEST_ID_PREFIX: &str = "rustbelt-roots-";
+
+/// Calls back with the client's roots whenever they are listed
+#[derive(Clone)]
+pub struct RootsWatcher {
+    on_roots: Arc<dyn Fn(Vec<PathBuf>) + Send + Sync>,
+    /// Whether the client supports roots
+    supported: Arc<AtomicBool>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl std::fmt::Debug for RootsWatcher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("RootsWatcher")
+    }
+}
+
+impl RootsWatcher {
+    /// Call `on_roots` with the local directories of the client's roots
+    ///
+    /// The callback runs on the task reading from the client, so it should
+    /// hand long work off to another task.
+    pub fn new<F: Fn(Vec<PathBuf>) + Send + Sync + 'static>(on_roots: F) -> Self {
+        Self {
+            on_roots: Arc::new(on_roots),
+            supported: Arc::default(),
+            next_id: Arc::default(),
+        }
+    }
+
+    /// Handle the response to a `roots/list` request sent by the watcher
+    ///
+    /// Returns false if the message isn't one, so it is passed on to tmcp.
+    fn handle_response(&self, message: &Value) -> bool {
+        let is_ours = message["id"]
+            .as_str()
+            .is_some_and(|id| id.starts_with(REQUEST_ID_PREFIX));
+        if !is_ours || message.get("method").is_some() {
+            return false;
+        }
+        if let Some(error) = message.get("error") {
+            debug!("Client failed to list its roots: {error}");
+            return true;
+        }
+
+        let roots: Vec<PathBuf> = message["result"]["roots"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|root| root["uri"].as_str())
+            .filter_map(|uri| Url::parse(uri).ok()?.to_file_path().ok())
+            .collect();
+        debug!("Client roots: {roots:?}");
+        if !roots.is_empty() {
+            (self.on_roots)(roots);
+        }
+        true
+    }
+}
+
+impl MessageHandler for RootsWatcher {
+    fn incoming(&self, message: &mut Value, client: &ClientSender) -> Verdict {
+        match message["method"].as_str() {
+            Some("initialize") => {
+                let supported = message["params"]["capabilities"].get("roots").is_some();
+                self.supported.store(supported, Ordering::Relaxed);
+            }
+            Some("notifications/initialized" | "notifications/roots/list_changed")
+                if self.supported.load(Ordering::Relaxed) =>
+            {
+                let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+                client.send(&json!({
+                    "jsonrpc": "2.0",
+                    "id": format!("{REQUEST_ID_PREFIX}{id}"),
+                    "method": "roots/list",
+                }));
+            }
+            _ => {}
+        }
+        if self.handle_response(message) {
+            Verdict::Drop
+        } else {
+            Verdict::Pass
+        }
+    }
+}
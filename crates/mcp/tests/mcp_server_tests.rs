@@ -13,8 +13,8 @@ use tokio::{
     time::{sleep, timeout},
 };
 
-/// Helper to create a test MCP client connected to the rustbelt server process
-async fn create_test_client() -> Result<(Client<()>, SpawnedServer)> {
+/// Build the rustbelt binary and return its path
+fn rustbelt_binary() -> std::path::PathBuf {
     // Get the workspace root - this is the current project directory
     let manifest_dir = env!("CARGO_MANIFEST_DIR");
     let workspace_root = std::path::Path::new(manifest_dir)
@@ -45,12 +45,15 @@ async fn create_test_client() -> Result<(Client<()>, SpawnedServer)> {
     } else {
         "release"
     };
-    let binary_path = target_dir.join(profile).join("rustbelt");
+    target_dir.join(profile).join("rustbelt")
+}
 
+/// Helper to create a test MCP client connected to the rustbelt server process
+async fn create_test_client() -> Result<(Client<()>, SpawnedServer)> {
     // Create client and connect to process
     let mut client = Client::new("test-client", "1.0.0");
 
-    let mut cmd = TokioCommand::new(binary_path);
+    let mut cmd = TokioCommand::new(rustbelt_binary());
     cmd.arg("mcp");
 
     let spawned = client.connect_process(cmd).await?;
@@ -84,7 +87,7 @@ async fn test_mcp_server_list_tools() {
         .expect("Failed to list tools");
 
     // Verify response
-    assert_eq!(result.tools.len(), 17);
+    assert_eq!(result.tools.len(), 33);
     let tool_names: Vec<&str> = result.tools.iter().map(|t| t.name.as_str()).collect();
     assert!(tool_names.contains(&"get_type_hint"));
     assert!(tool_names.contains(&"get_definition"));
@@ -96,6 +99,10 @@ async fn test_mcp_server_list_tools() {
     assert!(tool_names.contains(&"get_assists"));
     assert!(tool_names.contains(&"apply_assist"));
     assert!(tool_names.contains(&"get_diagnostics"));
+    assert!(tool_names.contains(&"get_workspace_diagnostics"));
+    assert!(tool_names.contains(&"configure_diagnostics"));
+    assert!(tool_names.contains(&"fix_all"));
+    assert!(tool_names.contains(&"apply_diagnostic_fix"));
     assert!(tool_names.contains(&"analyze_symbol"));
     assert!(tool_names.contains(&"get_file_outline"));
     assert!(tool_names.contains(&"search_symbols"));
@@ -103,6 +110,27 @@ async fn test_mcp_server_list_tools() {
     assert!(tool_names.contains(&"get_signature_help"));
     assert!(tool_names.contains(&"ssr"));
     assert!(tool_names.contains(&"ssr_search"));
+    assert!(tool_names.contains(&"check_edits"));
+    assert!(tool_names.contains(&"open_buffer"));
+    assert!(tool_names.contains(&"update_buffer"));
+    assert!(tool_names.contains(&"close_buffer"));
+    assert!(tool_names.contains(&"list_workspaces"));
+    assert!(tool_names.contains(&"unload_workspace"));
+    assert!(tool_names.contains(&"reload_workspace"));
+    assert!(tool_names.contains(&"load_workspace"));
+    assert!(tool_names.contains(&"workspace_status"));
+    assert!(tool_names.contains(&"undo_last_edit"));
+    assert!(tool_names.contains(&"redo_last_edit"));
+    assert!(tool_names.contains(&"list_edit_history"));
+
+    // Every tool publishes the schema of its structured content
+    for tool in &result.tools {
+        assert!(
+            tool.output_schema.is_some(),
+            "{} has no output schema",
+            tool.name
+        );
+    }
 
     // Clean up
     let _ = spawned.process.kill().await;
@@ -314,3 +342,429 @@ async fn test_mcp_get_completions_tool() {
     // Clean up
     let _ = spawned.process.kill().await;
 }
+
+#[tokio::test]
+async fn test_mcp_structured_content() {
+    let (mut client, mut spawned) = create_test_client()
+        .await
+        .expect("Failed to create test client");
+
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let workspace_root = std::path::Path::new(manifest_dir)
+        .parent() // crates
+        .unwrap()
+        .parent() // workspace root
+        .unwrap();
+    let sample_file = workspace_root.join("crates/librustbelt/tests/sample-project/src/main.rs");
+
+    let arguments = HashMap::from([(
+        "file_path".to_string(),
+        Value::from(sample_file.to_string_lossy()),
+    )]);
+    let result = timeout(
+        Duration::from_secs(60),
+        client.call_tool("get_file_outline", arguments),
+    )
+    .await
+    .expect("Timeout during get_file_outline call")
+    .expect("Failed to call get_file_outline tool");
+
+    assert!(!result.is_error.unwrap_or(false));
+    // The text rendering is kept next to the structured content
+    let text = result.text().expect("Expected text content").to_string();
+    let structured = result
+        .structured_content
+        .expect("Expected structured content");
+    let items = structured["items"]
+        .as_array()
+        .expect("Expected items array");
+    assert!(!items.is_empty());
+    assert!(items.iter().any(|item| item["name"] == "main"));
+    assert!(text.contains("main"));
+
+    // Clean up
+    let _ = spawned.process.kill().await;
+}
+
+#[tokio::test]
+async fn test_mcp_workspace_load_error() {
+    let (mut client, mut spawned) = create_test_client()
+        .await
+        .expect("Failed to create test client");
+
+    // A file outside of any project that isn't a detached `.rs` file should
+    // fail without killing the server
+    let dir = std::env::temp_dir().join("rustbelt-mcp-no-manifest");
+    std::fs::create_dir_all(&dir).unwrap();
+    let orphan_file = dir.join("orphan.txt");
+    std::fs::write(&orphan_file, "fn main() {}\n").unwrap();
+
+    let arguments = HashMap::from([(
+        "file_path".to_string(),
+        Value::from(orphan_file.to_string_lossy()),
+    )]);
+    let result = timeout(
+        Duration::from_secs(30),
+        client.call_tool("get_file_outline", arguments),
+    )
+    .await
+    .expect("Timeout during get_file_outline call")
+    .expect("Failed to call get_file_outline tool");
+
+    println!("Load error result: {:?}", result.content);
+    assert!(result.is_error.unwrap_or(false));
+    let structured = result
+        .structured_content
+        .expect("Expected structured error content");
+    assert_eq!(structured["cause"], "NO_MANIFEST");
+
+    // The server should still be usable afterwards
+    let result = timeout(Duration::from_secs(10), client.list_tools(None))
+        .await
+        .expect("Timeout listing tools after load error")
+        .expect("Failed to list tools after load error");
+    assert!(!result.tools.is_empty());
+
+    // A detached `.rs` file is analyzed on its own
+    let detached_file = dir.join("detached.rs");
+    std::fs::write(&detached_file, "fn main() {}\n").unwrap();
+    let arguments = HashMap::from([(
+        "file_path".to_string(),
+        Value::from(detached_file.to_string_lossy()),
+    )]);
+    let result = timeout(
+        Duration::from_secs(60),
+        client.call_tool("get_file_outline", arguments),
+    )
+    .await
+    .expect("Timeout during get_file_outline call")
+    .expect("Failed to call get_file_outline tool");
+    assert!(!result.is_error.unwrap_or(false), "{:?}", result.content);
+
+    // Clean up
+    let _ = spawned.process.kill().await;
+}
+
+#[tokio::test]
+async fn test_mcp_reload_workspace() {
+    let (mut client, mut spawned) = create_test_client()
+        .await
+        .expect("Failed to create test client");
+
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let workspace_root = std::path::Path::new(manifest_dir)
+        .parent() // crates
+        .unwrap()
+        .parent() // workspace root
+        .unwrap();
+    let sample_project = workspace_root.join("crates/librustbelt/tests/sample-project");
+
+    let arguments = HashMap::from([
+        (
+            "path".to_string(),
+            Value::from(sample_project.to_string_lossy()),
+        ),
+        ("cfgs".to_string(), serde_json::json!(["rustbelt_test"])),
+    ]);
+    let result = timeout(
+        Duration::from_secs(120),
+        client.call_tool("reload_workspace", arguments),
+    )
+    .await
+    .expect("Timeout during reload_workspace call")
+    .expect("Failed to call reload_workspace tool");
+
+    assert!(!result.is_error.unwrap_or(false));
+    let structured = result
+        .structured_content
+        .expect("Expected structured content");
+    assert_eq!(
+        structured["workspace"]["cargo_options"]["cfgs"],
+        serde_json::json!(["rustbelt_test"])
+    );
+
+    // Omitted fields keep the current configuration
+    let arguments = HashMap::from([
+        (
+            "path".to_string(),
+            Value::from(sample_project.to_string_lossy()),
+        ),
+        ("no_default_features".to_string(), Value::from(true)),
+    ]);
+    let result = timeout(
+        Duration::from_secs(120),
+        client.call_tool("reload_workspace", arguments),
+    )
+    .await
+    .expect("Timeout during reload_workspace call")
+    .expect("Failed to call reload_workspace tool");
+    let structured = result
+        .structured_content
+        .expect("Expected structured content");
+    let cargo_options = &structured["workspace"]["cargo_options"];
+    assert_eq!(cargo_options["cfgs"], serde_json::json!(["rustbelt_test"]));
+    assert_eq!(cargo_options["no_default_features"], true);
+
+    // Clean up
+    let _ = spawned.process.kill().await;
+}
+
+#[tokio::test]
+async fn test_mcp_load_progress_notifications() {
+    use std::process::Stdio;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    // The tmcp client can't set `_meta`, so talk JSON-RPC directly
+    let mut process = TokioCommand::new(rustbelt_binary())
+        .arg("mcp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("Failed to spawn rustbelt");
+    let mut stdin = process.stdin.take().unwrap();
+    let mut lines = BufReader::new(process.stdout.take().unwrap()).lines();
+
+    let sample_file = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../librustbelt/tests/sample-project/src/main.rs");
+    let messages = [
+        serde_json::json!({
+            "jsonrpc": "2.0", "id": 1, "method": "initialize",
+            "params": {
+                "protocolVersion": "2025-11-25",
+                "capabilities": {},
+                "clientInfo": {"name": "test-client", "version": "1.0.0"},
+            },
+        }),
+        serde_json::json!({"jsonrpc": "2.0", "method": "notifications/initialized"}),
+        serde_json::json!({
+            "jsonrpc": "2.0", "id": 2, "method": "tools/call",
+            "params": {
+                "name": "get_diagnostics",
+                "arguments": {"file_path": sample_file.to_string_lossy()},
+                "_meta": {"progressToken": "load"},
+            },
+        }),
+    ];
+    for message in messages {
+        stdin
+            .write_all(format!("{message}\n").as_bytes())
+            .await
+            .unwrap();
+    }
+
+    let mut progress = Vec::new();
+    let response = timeout(Duration::from_secs(120), async {
+        loop {
+            let line = lines.next_line().await.unwrap().expect("Server exited");
+            let message: Value = serde_json::from_str(&line).unwrap();
+            if message["method"] == "notifications/progress" {
+                progress.push(message["params"].clone());
+            } else if message["id"] == 2 {
+                break message;
+            }
+        }
+    })
+    .await
+    .expect("Timeout waiting for get_diagnostics");
+
+    assert!(response["result"]["isError"] != true, "{response}");
+    println!("Progress notifications: {}", progress.len());
+    assert!(!progress.is_empty());
+    assert!(
+        progress
+            .iter()
+            .all(|params| params["progressToken"] == "load")
+    );
+    let values: Vec<f64> = progress
+        .iter()
+        .map(|params| params["progress"].as_f64().unwrap())
+        .collect();
+    assert!(
+        values.windows(2).all(|pair| pair[0] < pair[1]),
+        "{values:?}"
+    );
+    let messages: Vec<&str> = progress
+        .iter()
+        .map(|params| params["message"].as_str().unwrap())
+        .collect();
+    assert!(messages[0].starts_with("Fetching metadata"), "{messages:?}");
+    assert!(
+        messages.contains(&"Loading source files and proc-macros"),
+        "{messages:?}"
+    );
+
+    let _ = process.kill().await;
+}
+
+#[tokio::test]
+async fn test_mcp_background_workspace_loading() {
+    let (mut client, mut spawned) = create_test_client()
+        .await
+        .expect("Failed to create test client");
+
+    let sample_project = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../librustbelt/tests/sample-project")
+        .canonicalize()
+        .unwrap();
+    let sample_file = sample_project.join("src/main.rs");
+
+    let arguments = HashMap::from([(
+        "path".to_string(),
+        Value::from(sample_project.to_string_lossy()),
+    )]);
+    let result = timeout(
+        Duration::from_secs(10),
+        client.call_tool("load_workspace", arguments),
+    )
+    .await
+    .expect("load_workspace should return without waiting for the load")
+    .expect("Failed to call load_workspace tool");
+    assert!(!result.is_error.unwrap_or(false), "{:?}", result.content);
+    let structured = result
+        .structured_content
+        .expect("Expected structured content");
+    assert_eq!(structured["workspace"]["state"], "loading");
+    assert_eq!(structured["workspace"]["semantic_ready"], false);
+
+    // Outlines don't wait for the workspace
+    let arguments = HashMap::from([(
+        "file_path".to_string(),
+        Value::from(sample_file.to_string_lossy()),
+    )]);
+    let result = timeout(
+        Duration::from_secs(10),
+        client.call_tool("get_file_outline", arguments.clone()),
+    )
+    .await
+    .expect("get_file_outline should answer while the workspace loads")
+    .expect("Failed to call get_file_outline tool");
+    assert!(!result.is_error.unwrap_or(false), "{:?}", result.content);
+    let structured = result
+        .structured_content
+        .expect("Expected structured content");
+    assert!(!structured["items"].as_array().unwrap().is_empty());
+
+    // Semantic tools wait for the load to finish
+    let result = timeout(
+        Duration::from_secs(120),
+        client.call_tool("get_diagnostics", arguments),
+    )
+    .await
+    .expect("Timeout during get_diagnostics call")
+    .expect("Failed to call get_diagnostics tool");
+    assert!(!result.is_error.unwrap_or(false), "{:?}", result.content);
+
+    let result = timeout(
+        Duration::from_secs(10),
+        client.call_tool("workspace_status", HashMap::<String, Value>::new()),
+    )
+    .await
+    .expect("Timeout during workspace_status call")
+    .expect("Failed to call workspace_status tool");
+    println!("Workspace status: {:?}", result.content);
+    let structured = result
+        .structured_content
+        .expect("Expected structured content");
+    let workspaces = structured["workspaces"].as_array().unwrap();
+    assert_eq!(workspaces.len(), 1);
+    assert_eq!(
+        workspaces[0]["root"],
+        sample_project.to_string_lossy().as_ref()
+    );
+    assert_eq!(workspaces[0]["state"], "ready");
+    assert_eq!(workspaces[0]["semantic_ready"], true);
+
+    // Clean up
+    let _ = spawned.process.kill().await;
+}
+
+#[tokio::test]
+async fn test_mcp_cancelled_tool_call() {
+    use std::process::Stdio;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    // The tmcp client can't cancel requests, so talk JSON-RPC directly
+    let mut process = TokioCommand::new(rustbelt_binary())
+        .arg("mcp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("Failed to spawn rustbelt");
+    let mut stdin = process.stdin.take().unwrap();
+    let mut lines = BufReader::new(process.stdout.take().unwrap()).lines();
+
+    let sample_file = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../librustbelt/tests/sample-project/src/main.rs");
+    let cursor = serde_json::json!({
+        "file_path": sample_file.to_string_lossy(),
+        "line": 31,
+        "column": 13,
+    });
+    let messages = [
+        serde_json::json!({
+            "jsonrpc": "2.0", "id": 1, "method": "initialize",
+            "params": {
+                "protocolVersion": "2025-11-25",
+                "capabilities": {},
+                "clientInfo": {"name": "test-client", "version": "1.0.0"},
+            },
+        }),
+        serde_json::json!({"jsonrpc": "2.0", "method": "notifications/initialized"}),
+        // Identical calls, of which only the second one is cancelled
+        serde_json::json!({
+            "jsonrpc": "2.0", "id": 2, "method": "tools/call",
+            "params": {"name": "find_references", "arguments": cursor},
+        }),
+        serde_json::json!({
+            "jsonrpc": "2.0", "id": 3, "method": "tools/call",
+            "params": {"name": "find_references", "arguments": cursor},
+        }),
+        serde_json::json!({
+            "jsonrpc": "2.0", "method": "notifications/cancelled",
+            "params": {"requestId": 3, "reason": "test"},
+        }),
+        serde_json::json!({
+            "jsonrpc": "2.0", "id": 4, "method": "tools/call",
+            "params": {"name": "get_type_hint", "arguments": cursor},
+        }),
+    ];
+    for message in messages {
+        stdin
+            .write_all(format!("{message}\n").as_bytes())
+            .await
+            .unwrap();
+    }
+
+    let mut responses: Vec<Value> = Vec::new();
+    timeout(Duration::from_secs(120), async {
+        while !(responses.iter().any(|message| message["id"] == 2)
+            && responses.iter().any(|message| message["id"] == 4))
+        {
+            let line = lines.next_line().await.unwrap().expect("Server exited");
+            responses.push(serde_json::from_str(&line).unwrap());
+        }
+    })
+    .await
+    .expect("Timeout waiting for find_references and get_type_hint");
+    for id in [2, 4] {
+        let response = responses.iter().find(|message| message["id"] == id);
+        let response = response.unwrap();
+        assert!(response["result"]["isError"] != true, "{response}");
+    }
+
+    // Give a late response to the cancelled call a chance to show up
+    let _ = timeout(Duration::from_secs(2), async {
+        while let Ok(Some(line)) = lines.next_line().await {
+            responses.push(serde_json::from_str(&line).unwrap());
+        }
+    })
+    .await;
+    assert!(
+        responses.iter().all(|message| message["id"] != 3),
+        "Cancelled call was answered: {responses:?}"
+    );
+
+    let _ = process.kill().await;
+}